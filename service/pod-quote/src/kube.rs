@@ -12,6 +12,7 @@ use kube::api::Api;
 use kube::Client;
 
 use std::env;
+use tracing::debug;
 
 const POD_NAME: &str = "POD_NAME";
 const POD_NAMESPACE: &str = "POD_NAMESPACE";
@@ -33,17 +34,12 @@ pub async fn get_cur_pod_images_info() -> Result<String, Error> {
             let image_id = container_status.image_id.clone();
             pod_data_array.push(image_id);
         }
-        println!("pod quote data array:");
-        // Print out the quote data of pod.
-        for item in &pod_data_array {
-            println!("{}", item);
-        }
+        debug!(images = ?pod_data_array, "pod quote data");
 
         // Concat all pod quote data into one String.
         let pod_image_id_data = pod_data_array.join(SEPARATOR);
         return Ok(pod_image_id_data);
     } else {
-        println!("Pod {pod_name} in {namespace} not found.");
         let error_message = format!("Pod '{}' in '{}' not found.", pod_name, namespace);
         return Err(anyhow!(error_message));
     }
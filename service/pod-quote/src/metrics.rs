@@ -0,0 +1,229 @@
+/*
+* Copyright (c) 2023, Intel Corporation. All rights reserved.<BR>
+* SPDX-License-Identifier: Apache-2.0
+*/
+
+use hyper::header::CONTENT_TYPE;
+use hyper::service::service_fn;
+use hyper::{Body, Method, Request, Response, StatusCode};
+use prometheus::{
+    Encoder, GaugeVec, HistogramOpts, HistogramVec, IntCounterVec, IntGauge, IntGaugeVec, Opts,
+    Registry, TextEncoder,
+};
+use std::convert::Infallible;
+use std::env;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::{TcpListener, UnixListener};
+use tracing::{info, warn};
+
+const METRICS_PATH: &str = "/metrics";
+
+// Environment variables selecting where metrics are served, pod-quote takes
+// no options
+const METRICS_ADDRESS: &str = "METRICS_ADDRESS";
+const METRICS_SOCKET: &str = "METRICS_SOCKET";
+
+const LATENCY_BUCKETS: &[f64] = &[
+    0.001, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0,
+];
+
+// Prometheus metrics of the pod quote server
+#[derive(Clone)]
+pub struct Metrics {
+    registry: Registry,
+    tee: String,
+    requests: IntCounterVec,
+    request_duration: HistogramVec,
+    quote_duration: HistogramVec,
+    last_success: GaugeVec,
+    in_flight: IntGauge,
+}
+
+impl Metrics {
+    pub fn new(tee: String) -> Self {
+        let registry = Registry::new();
+        let histogram = |name: &str, help: &str, labels: &[&str]| {
+            let opts = HistogramOpts::new(name, help).buckets(LATENCY_BUCKETS.to_vec());
+            register(&registry, HistogramVec::new(opts, labels).unwrap())
+        };
+        let metrics = Metrics {
+            requests: register(
+                &registry,
+                IntCounterVec::new(
+                    Opts::new(
+                        "pod_quote_requests_total",
+                        "Requests by path and HTTP status",
+                    ),
+                    &["path", "tee", "code"],
+                )
+                .unwrap(),
+            ),
+            request_duration: histogram(
+                "pod_quote_request_duration_seconds",
+                "Total latency of requests, including the pod lookup",
+                &["path", "tee"],
+            ),
+            quote_duration: histogram(
+                "pod_quote_quote_generation_duration_seconds",
+                "Latency of quote generation, the TDREPORT and the QGS round-trip",
+                &["tee"],
+            ),
+            last_success: register(
+                &registry,
+                GaugeVec::new(
+                    Opts::new(
+                        "pod_quote_last_success_timestamp_seconds",
+                        "Unix time of the last quote served",
+                    ),
+                    &["tee"],
+                )
+                .unwrap(),
+            ),
+            in_flight: register(
+                &registry,
+                IntGauge::new("pod_quote_requests_in_flight", "Requests being served").unwrap(),
+            ),
+            registry,
+            tee,
+        };
+
+        let build_info = register(
+            &metrics.registry,
+            IntGaugeVec::new(
+                Opts::new("pod_quote_build_info", "Version of the pod quote server"),
+                &["version"],
+            )
+            .unwrap(),
+        );
+        build_info
+            .with_label_values(&[env!("CARGO_PKG_VERSION")])
+            .set(1);
+        let tee_info = register(
+            &metrics.registry,
+            IntGaugeVec::new(
+                Opts::new("pod_quote_tee_info", "TEE type of the node"),
+                &["tee"],
+            )
+            .unwrap(),
+        );
+        tee_info.with_label_values(&[&metrics.tee]).set(1);
+        metrics
+    }
+
+    // Mark a request as started, returning its start time
+    pub fn start_request(&self) -> Instant {
+        self.in_flight.inc();
+        Instant::now()
+    }
+
+    // Count a finished request by its HTTP status and observe its latency
+    pub fn finish_request(&self, path: &str, start: Instant, status: StatusCode) {
+        self.in_flight.dec();
+        // unknown paths are not labeled individually to bound the cardinality
+        let path = if path == "/quote" { path } else { "other" };
+        self.requests
+            .with_label_values(&[path, &self.tee, status.as_str()])
+            .inc();
+        self.request_duration
+            .with_label_values(&[path, &self.tee])
+            .observe(start.elapsed().as_secs_f64());
+        if path == "/quote" && status.is_success() {
+            let now = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default();
+            self.last_success
+                .with_label_values(&[&self.tee])
+                .set(now.as_secs_f64());
+        }
+    }
+
+    // Run a quote generation and observe its latency
+    pub fn time_quote<T>(&self, f: impl FnOnce() -> T) -> T {
+        let start = Instant::now();
+        let result = f();
+        self.quote_duration
+            .with_label_values(&[&self.tee])
+            .observe(start.elapsed().as_secs_f64());
+        result
+    }
+
+    pub fn render(&self) -> String {
+        let mut buffer = Vec::new();
+        if let Err(e) = TextEncoder::new().encode(&self.registry.gather(), &mut buffer) {
+            warn!(error = %e, "failed to encode metrics");
+        }
+        String::from_utf8(buffer).unwrap_or_default()
+    }
+
+    // Serve the metrics on METRICS_ADDRESS and METRICS_SOCKET, if set
+    pub async fn serve_from_env(&self) -> anyhow::Result<()> {
+        if let Ok(address) = env::var(METRICS_ADDRESS) {
+            let address: SocketAddr = address.parse()?;
+            let listener = TcpListener::bind(address).await?;
+            info!(%address, "serving metrics");
+            let metrics = self.clone();
+            tokio::spawn(async move {
+                loop {
+                    match listener.accept().await {
+                        Ok((stream, _)) => {
+                            tokio::spawn(connection(stream, metrics.clone()));
+                        }
+                        Err(e) => warn!(error = %e, "failed to accept metrics connection"),
+                    }
+                }
+            });
+        }
+        if let Ok(path) = env::var(METRICS_SOCKET) {
+            let path = PathBuf::from(path);
+            let _ = std::fs::remove_file(&path);
+            let listener = UnixListener::bind(&path)?;
+            info!(path = %path.display(), "serving metrics");
+            let metrics = self.clone();
+            tokio::spawn(async move {
+                loop {
+                    match listener.accept().await {
+                        Ok((stream, _)) => {
+                            tokio::spawn(connection(stream, metrics.clone()));
+                        }
+                        Err(e) => warn!(error = %e, "failed to accept metrics connection"),
+                    }
+                }
+            });
+        }
+        Ok(())
+    }
+}
+
+fn register<C: prometheus::core::Collector + Clone + 'static>(registry: &Registry, c: C) -> C {
+    registry.register(Box::new(c.clone())).unwrap();
+    c
+}
+
+async fn connection<S>(stream: S, metrics: Metrics)
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let service = service_fn(move |request: Request<Body>| {
+        let metrics = metrics.clone();
+        async move {
+            if request.method() != Method::GET || request.uri().path() != METRICS_PATH {
+                let mut response = Response::new(Body::from("Not Found"));
+                *response.status_mut() = StatusCode::NOT_FOUND;
+                return Ok::<_, Infallible>(response);
+            }
+            let mut response = Response::new(Body::from(metrics.render()));
+            response.headers_mut().insert(
+                CONTENT_TYPE,
+                TextEncoder::new().format_type().parse().unwrap(),
+            );
+            Ok(response)
+        }
+    });
+    let _ = hyper::server::conn::Http::new()
+        .http1_only(true)
+        .serve_connection(stream, service)
+        .await;
+}
@@ -9,16 +9,22 @@ use core::result::Result::Ok;
 use hyper::service::{make_service_fn, service_fn};
 use hyper::{Request as HyperRequest, Response as HyperResponse, Body, Server as HyperServer};
 use std::net::SocketAddr;
+use tracing::{error, info, warn, Instrument};
 
 pub mod kube;
+pub mod metrics;
 pub mod tee;
+pub mod telemetry;
+use metrics::Metrics;
 use tee::*;
+use telemetry::Sensitive;
 
 // A http server for provide the current pod quote data
-#[derive(Copy, Clone)]
+#[derive(Clone)]
 pub struct PerPodQuoteServer {
     sock_address: SocketAddr,
     local_tee: tee::TeeType,
+    metrics: Metrics,
 }
 
 impl PerPodQuoteServer {
@@ -26,28 +32,35 @@ impl PerPodQuoteServer {
         PerPodQuoteServer {
             sock_address,
             local_tee,
+            metrics: Metrics::new(format!("{:?}", local_tee)),
         }
     }
 
+    pub fn metrics(&self) -> &Metrics {
+        &self.metrics
+    }
+
     pub async fn start(&self) -> Result<(), hyper::Error> {
         let local_tee = self.local_tee;
-        let make_svc = make_service_fn(|_conn| {
+        let metrics = self.metrics.clone();
+        let make_svc = make_service_fn(move |_conn| {
+            let metrics = metrics.clone();
             let service = service_fn(move |req| {
                 // Route request to the appropriate handler
-                Self::handle_request(local_tee, req)
+                Self::handle_request(local_tee, metrics.clone(), req)
             });
             async move { Ok::<_, hyper::Error>(service) }
         });
         let http_server = HyperServer::bind(&self.sock_address).serve(make_svc);
-        println!(
-            "The Pod Quote HTTP server is listening on: {:?}",
-            self.sock_address
-        );
+        info!(address = %self.sock_address, "pod quote HTTP server listening");
         http_server.await
     }
 
     // generate current pod quote based on its all containers' imageIDs
-    async fn get_current_pod_quote(local_tee: tee::TeeType) -> Result<String, anyhow::Error> {
+    async fn get_current_pod_quote(
+        local_tee: tee::TeeType,
+        metrics: &Metrics,
+    ) -> Result<String, anyhow::Error> {
         // Handle the "/quote" route
         // Create an instance of your custom kube client
         let pod_data = kube::get_cur_pod_images_info();
@@ -55,12 +68,13 @@ impl PerPodQuoteServer {
             Ok(report_data) => {
                 let report_data_clone = report_data.clone();
                 let hash_report_data = kube::sha256_hash(&report_data_clone);
-                let quote_data = get_quote(
-                    local_tee,
-                    hash_report_data.clone(),
-                    hash_report_data.clone(),
-                )
-                .unwrap();
+                let quote_data = metrics.time_quote(|| {
+                    get_quote(
+                        local_tee,
+                        hash_report_data.clone(),
+                        hash_report_data.clone(),
+                    )
+                })?;
                 Ok(quote_data)
             }
             Err(error) => Err(anyhow!(
@@ -72,19 +86,49 @@ impl PerPodQuoteServer {
 
     async fn handle_request(
         local_tee: tee::TeeType,
+        metrics: Metrics,
+        req: HyperRequest<Body>
+    ) -> Result<HyperResponse<Body>, hyper::Error> {
+        let span = telemetry::request_span(&req);
+        let path = req.uri().path().to_string();
+        let start = metrics.start_request();
+        let response = Self::route(local_tee, &metrics, req)
+            .instrument(span.clone())
+            .await;
+        let latency_ms = start.elapsed().as_millis() as u64;
+        if let Ok(response) = &response {
+            metrics.finish_request(&path, start, response.status());
+            let outcome = response.status().as_u16();
+            span.record("latency_ms", latency_ms);
+            span.record("outcome", outcome);
+            span.in_scope(|| {
+                if response.status().is_success() {
+                    info!(latency_ms, outcome, "request completed");
+                } else {
+                    warn!(latency_ms, outcome, "request failed");
+                }
+            });
+        }
+        response
+    }
+
+    async fn route(
+        local_tee: tee::TeeType,
+        metrics: &Metrics,
         req: HyperRequest<Body>
     ) -> Result<HyperResponse<Body>, hyper::Error> {
         match req.uri().path() {
             "/quote" => {
-                match Self::get_current_pod_quote(local_tee).await {
+                tracing::Span::current().record("tee", format!("{:?}", local_tee).as_str());
+                match Self::get_current_pod_quote(local_tee, metrics).await {
                     Ok(quote_data) => {
-                        println!("File content: {}", quote_data);
+                        info!(quote = %Sensitive(quote_data.as_bytes()), "generated pod quote");
                         // generate the response from quote file
                         let response = HyperResponse::new(Body::from(quote_data));
                         Ok(response)
                     }
                     Err(err) => {
-                        eprintln!("Error: {}", err);
+                        warn!(error = %err, "failed to generate pod quote");
                         let response = HyperResponse::builder()
                             .status(404)
                             .body(Body::from("Not Found Quote File"))
@@ -112,18 +156,21 @@ struct Cli {
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    telemetry::init();
     let http_addr = SocketAddr::from(([127, 0, 0, 1], 3000));
+    let http_server = PerPodQuoteServer::new(http_addr, {
+        match tee::get_tee_type() {
+            tee::TeeType::PLAIN => panic!("Not found any TEE device!"),
+            t => t,
+        }
+    });
+    http_server.metrics().serve_from_env().await?;
     // Create the http server tokio task for fetching quote with current pod image IDs
-    let _ = tokio::spawn(async move {
-        let http_server = PerPodQuoteServer::new(http_addr, {
-            match tee::get_tee_type() {
-                tee::TeeType::PLAIN => panic!("Not found any TEE device!"),
-                t => t,
-            }
-        });
+    let server = tokio::spawn(async move {
         if let Err(err) = http_server.start().await {
-            eprintln!("HTTP server error: {}", err);
+            error!(error = %err, "HTTP server error");
         }
     });
+    server.await?;
     Ok(())
 }
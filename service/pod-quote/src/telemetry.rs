@@ -0,0 +1,53 @@
+/*
+* Copyright (c) 2023, Intel Corporation. All rights reserved.<BR>
+* SPDX-License-Identifier: Apache-2.0
+*/
+
+pub use ccnp_telemetry::Sensitive;
+use ccnp_telemetry::{TraceParent, TRACEPARENT};
+use std::env;
+use tracing::field::Empty;
+use tracing::{info_span, Span};
+use tracing_subscriber::EnvFilter;
+
+// Environment variables configuring the logs, pod-quote takes no options
+const LOG_FORMAT: &str = "LOG_FORMAT";
+const LOG_SENSITIVE: &str = "LOG_SENSITIVE";
+
+// Install the global subscriber. The level is taken from RUST_LOG and
+// defaults to info, LOG_FORMAT=json selects JSON output and LOG_SENSITIVE=1
+// logs quotes in plaintext.
+pub fn init() {
+    let sensitive = env::var(LOG_SENSITIVE).is_ok_and(|v| v == "1" || v == "true");
+    ccnp_telemetry::set_log_sensitive(sensitive);
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
+    let builder = tracing_subscriber::fmt().with_env_filter(filter);
+    match env::var(LOG_FORMAT).as_deref() {
+        Ok("json") => builder.json().with_current_span(true).init(),
+        _ => builder.init(),
+    }
+}
+
+// Span of one HTTP request, carrying the caller's trace context. TEE type,
+// latency and outcome are recorded by the handler.
+pub fn request_span<B>(request: &hyper::Request<B>) -> Span {
+    let span = info_span!(
+        "request",
+        path = %request.uri().path(),
+        trace_id = Empty,
+        parent_id = Empty,
+        tee = Empty,
+        latency_ms = Empty,
+        outcome = Empty,
+    );
+    let trace_parent = request
+        .headers()
+        .get(TRACEPARENT)
+        .and_then(|v| v.to_str().ok())
+        .and_then(TraceParent::parse);
+    if let Some(tp) = trace_parent {
+        span.record("trace_id", tp.trace_id.as_str());
+        span.record("parent_id", tp.parent_id.as_str());
+    }
+    span
+}
@@ -7,7 +7,10 @@ use std::env;
 use std::path::PathBuf;
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
-    tonic_build::compile_protos("api/quote-server.proto")?;
+    let protos = ["api/quote-server.proto", "api/quote-server-v2.proto"];
+    for proto in protos {
+        tonic_build::compile_protos(proto)?;
+    }
 
     let original_out_dir = PathBuf::from(env::var("OUT_DIR")?);
     let out_dir = "./src";
@@ -15,7 +18,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     tonic_build::configure()
         .out_dir(out_dir)
         .file_descriptor_set_path(original_out_dir.join("quote_server_descriptor.bin"))
-        .compile(&["api/quote-server.proto"], &["api"])?;
+        .compile(&protos, &["api"])?;
 
     Ok(())
 }
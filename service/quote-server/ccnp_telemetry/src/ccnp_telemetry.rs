@@ -0,0 +1,123 @@
+/*
+* Copyright (c) 2023, Intel Corporation. All rights reserved.<BR>
+* SPDX-License-Identifier: Apache-2.0
+*/
+
+// Log redaction and W3C trace context parsing, shared by quote-server and
+// pod-quote so that both redact and correlate requests the same way.
+
+use sha2::{Digest, Sha256};
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+// W3C trace context header, see https://www.w3.org/TR/trace-context/
+pub const TRACEPARENT: &str = "traceparent";
+
+// Log user data, nonces and quotes in plaintext instead of their digest
+static LOG_SENSITIVE: AtomicBool = AtomicBool::new(false);
+
+// Set once at startup, before any request is logged
+pub fn set_log_sensitive(log_sensitive: bool) {
+    LOG_SENSITIVE.store(log_sensitive, Ordering::Relaxed);
+}
+
+// Request data that must not end up in logs, shown as the length and a
+// truncated SHA-256 digest unless sensitive logging is enabled
+pub struct Sensitive<'a>(pub &'a [u8]);
+
+impl fmt::Display for Sensitive<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if LOG_SENSITIVE.load(Ordering::Relaxed) {
+            return write!(f, "{}", String::from_utf8_lossy(self.0));
+        }
+        let digest = Sha256::digest(self.0);
+        write!(f, "<{} bytes sha256:", self.0.len())?;
+        for b in &digest[..8] {
+            write!(f, "{:02x}", b)?;
+        }
+        write!(f, "..>")
+    }
+}
+
+// Trace context of the caller from the traceparent header
+#[derive(Debug, PartialEq)]
+pub struct TraceParent {
+    pub trace_id: String,
+    pub parent_id: String,
+    pub sampled: bool,
+}
+
+impl TraceParent {
+    // Parse "<version>-<trace-id>-<parent-id>-<flags>", None if malformed
+    pub fn parse(value: &str) -> Option<Self> {
+        let fields: Vec<&str> = value.trim().split('-').collect();
+        // version 00 has exactly four fields, later versions may append more
+        if fields.len() < 4 || fields[0] == "ff" || (fields[0] == "00" && fields.len() != 4) {
+            return None;
+        }
+        let (version, trace_id, parent_id, flags) = (fields[0], fields[1], fields[2], fields[3]);
+        let is_hex = |s: &str, len| {
+            s.len() == len
+                && s.chars()
+                    .all(|c| c.is_ascii_digit() || ('a'..='f').contains(&c))
+        };
+        let is_zero = |s: &str| s.bytes().all(|b| b == b'0');
+        if !is_hex(version, 2) || !is_hex(trace_id, 32) || !is_hex(parent_id, 16) {
+            return None;
+        }
+        if !is_hex(flags, 2) || is_zero(trace_id) || is_zero(parent_id) {
+            return None;
+        }
+        let flags = u8::from_str_radix(flags, 16).ok()?;
+        Some(TraceParent {
+            trace_id: trace_id.to_string(),
+            parent_id: parent_id.to_string(),
+            sampled: flags & 1 == 1,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    //valid traceparent headers are parsed
+    fn parse_traceparent() {
+        let tp =
+            TraceParent::parse("00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01").unwrap();
+        assert_eq!(tp.trace_id, "4bf92f3577b34da6a3ce929d0e0e4736");
+        assert_eq!(tp.parent_id, "00f067aa0ba902b7");
+        assert!(tp.sampled);
+
+        // future versions may carry more fields
+        assert!(TraceParent::parse(
+            "01-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-00-extra"
+        )
+        .is_some());
+    }
+
+    #[test]
+    //malformed traceparent headers are ignored
+    fn parse_invalid_traceparent() {
+        for value in [
+            "",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7",
+            "00-4BF92F3577B34DA6A3CE929D0E0E4736-00f067aa0ba902b7-01",
+            "00-00000000000000000000000000000000-00f067aa0ba902b7-01",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-0000000000000000-01",
+            "ff-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01-extra",
+        ] {
+            assert!(TraceParent::parse(value).is_none(), "{}", value);
+        }
+    }
+
+    #[test]
+    //sensitive values are logged as length and digest only
+    fn sensitive_is_redacted() {
+        let shown = Sensitive(b"secret user data").to_string();
+        assert!(!shown.contains("secret"));
+        assert!(shown.starts_with("<16 bytes sha256:"));
+    }
+}
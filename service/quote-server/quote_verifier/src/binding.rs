@@ -0,0 +1,184 @@
+/*
+* Copyright (c) 2023, Intel Corporation. All rights reserved.<BR>
+* SPDX-License-Identifier: Apache-2.0
+*/
+
+// Schemes deriving the 64 bytes of TEE report data from the nonce and user
+// data of a quote request.
+//
+// The transcript schemes hash a sequence of length-prefixed fields, each
+// encoded as a 4 byte big-endian length followed by the bytes:
+//   "ccnp.report-data.transcript.v1" | hash name | "nonce" | nonce |
+//   "user_data" | user_data
+// where the hash name is "sha512" or "sha384". A SHA-384 digest is padded
+// with zeros to 64 bytes.
+
+use sha2::{Digest as _, Sha384, Sha512};
+use std::fmt;
+
+pub const REPORT_DATA_LEN: usize = 64;
+const TRANSCRIPT_TAG: &[u8] = b"ccnp.report-data.transcript.v1";
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Binding {
+    // user_data of at most 64 bytes, padded with zeros, e.g. a TLS key hash.
+    // The nonce must be empty.
+    Raw,
+    // SHA-512(nonce || user_data), the scheme of the v1 API. The boundary
+    // between nonce and user data is not bound.
+    Sha512Concat,
+    TranscriptSha512,
+    TranscriptSha384,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum BindingError {
+    UserDataTooLarge { size: usize, limit: usize },
+    NonceNotAllowed,
+}
+
+impl fmt::Display for BindingError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BindingError::UserDataTooLarge { size, limit } => write!(
+                f,
+                "user_data is {} bytes, raw report data take at most {}",
+                size, limit
+            ),
+            BindingError::NonceNotAllowed => write!(f, "raw report data take no nonce"),
+        }
+    }
+}
+
+impl std::error::Error for BindingError {}
+
+impl Binding {
+    pub fn report_data(
+        &self,
+        nonce: &[u8],
+        user_data: &[u8],
+    ) -> Result<[u8; REPORT_DATA_LEN], BindingError> {
+        let mut report_data = [0; REPORT_DATA_LEN];
+        match self {
+            Binding::Raw => {
+                if !nonce.is_empty() {
+                    return Err(BindingError::NonceNotAllowed);
+                }
+                if user_data.len() > REPORT_DATA_LEN {
+                    return Err(BindingError::UserDataTooLarge {
+                        size: user_data.len(),
+                        limit: REPORT_DATA_LEN,
+                    });
+                }
+                report_data[..user_data.len()].copy_from_slice(user_data);
+            }
+            Binding::Sha512Concat => {
+                let mut hasher = Sha512::new();
+                hasher.update(nonce);
+                hasher.update(user_data);
+                report_data.copy_from_slice(&hasher.finalize());
+            }
+            Binding::TranscriptSha512 => {
+                let digest = Sha512::digest(transcript("sha512", nonce, user_data));
+                report_data.copy_from_slice(&digest);
+            }
+            Binding::TranscriptSha384 => {
+                let digest = Sha384::digest(transcript("sha384", nonce, user_data));
+                report_data[..digest.len()].copy_from_slice(&digest);
+            }
+        }
+        Ok(report_data)
+    }
+}
+
+// Length-prefixed encoding of the request fields hashed by the transcript
+// schemes
+pub fn transcript(hash_name: &str, nonce: &[u8], user_data: &[u8]) -> Vec<u8> {
+    length_prefixed(&[
+        TRANSCRIPT_TAG,
+        hash_name.as_bytes(),
+        b"nonce",
+        nonce,
+        b"user_data",
+        user_data,
+    ])
+}
+
+// Fields each preceded by its length as 4 byte big-endian integer
+pub fn length_prefixed(fields: &[&[u8]]) -> Vec<u8> {
+    let mut encoded = Vec::new();
+    for field in fields {
+        encoded.extend_from_slice(&(field.len() as u32).to_be_bytes());
+        encoded.extend_from_slice(field);
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod binding_tests {
+    use super::*;
+
+    #[test]
+    //raw report data are zero padded and reject nonces and oversized data
+    fn raw_binding() {
+        let report_data = Binding::Raw.report_data(b"", &[0xab; 32]).unwrap();
+        assert_eq!(&report_data[..32], &[0xab; 32]);
+        assert_eq!(&report_data[32..], &[0; 32]);
+        assert_eq!(
+            Binding::Raw.report_data(b"n", b""),
+            Err(BindingError::NonceNotAllowed)
+        );
+        assert_eq!(
+            Binding::Raw.report_data(b"", &[0; 65]),
+            Err(BindingError::UserDataTooLarge {
+                size: 65,
+                limit: 64
+            })
+        );
+    }
+
+    #[test]
+    //the transcript binds the boundary between nonce and user data
+    fn transcript_is_unambiguous() {
+        for binding in [Binding::TranscriptSha512, Binding::TranscriptSha384] {
+            assert_ne!(
+                binding.report_data(b"ab", b"c").unwrap(),
+                binding.report_data(b"a", b"bc").unwrap()
+            );
+        }
+        assert_eq!(
+            Binding::Sha512Concat.report_data(b"ab", b"c").unwrap(),
+            Binding::Sha512Concat.report_data(b"a", b"bc").unwrap()
+        );
+    }
+
+    #[test]
+    //SHA-384 digests are zero padded and differ from the SHA-512 transcript
+    fn transcript_sha384() {
+        let sha384 = Binding::TranscriptSha384.report_data(b"n", b"u").unwrap();
+        assert_eq!(&sha384[48..], &[0; 16]);
+        let expected = Sha384::digest(transcript("sha384", b"n", b"u"));
+        assert_eq!(&sha384[..48], expected.as_slice());
+        let sha512 = Binding::TranscriptSha512.report_data(b"n", b"u").unwrap();
+        assert_ne!(&sha384[..48], &sha512[..48]);
+    }
+
+    #[test]
+    //the transcript encoding is stable
+    fn transcript_encoding() {
+        let encoded = transcript("sha512", b"n", b"");
+        let mut expected = Vec::new();
+        expected.extend_from_slice(&30u32.to_be_bytes());
+        expected.extend_from_slice(b"ccnp.report-data.transcript.v1");
+        expected.extend_from_slice(&6u32.to_be_bytes());
+        expected.extend_from_slice(b"sha512");
+        expected.extend_from_slice(&5u32.to_be_bytes());
+        expected.extend_from_slice(b"nonce");
+        expected.extend_from_slice(&1u32.to_be_bytes());
+        expected.extend_from_slice(b"n");
+        expected.extend_from_slice(&9u32.to_be_bytes());
+        expected.extend_from_slice(b"user_data");
+        expected.extend_from_slice(&0u32.to_be_bytes());
+        assert_eq!(encoded, expected);
+    }
+}
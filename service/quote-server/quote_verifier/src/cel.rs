@@ -0,0 +1,404 @@
+/*
+* Copyright (c) 2023, Intel Corporation. All rights reserved.<BR>
+* SPDX-License-Identifier: Apache-2.0
+*/
+
+// Conversion of TCG crypto agile event logs, boot and runtime, to the TCG
+// Canonical Event Log format (CEL) and back.
+//
+// Each event becomes a record with the PC Client standard content; the Spec
+// ID event is not a measurement and is left out. CEL-JSON is an array of
+//   {"recnum": 0, "pcr": 1, "digests": [{"hashAlg": "sha384",
+//    "digest": "<hex>"}], "content_type": "pcclient_std",
+//    "content": {"event_type": 6, "event_data": "<base64>"}}
+// and CEL-CBOR an array of maps keyed by the CEL TLV types:
+//   {0: recnum, 1: pcr, 3: [[algorithm ID, digest], ...],
+//    5: {0: event type, 1: event data}}
+// "pcr" is the register index of the log, i.e. the CC measurement register
+// index for the CCEL and the runtime event log.
+//
+// Logs read back from CEL declare the algorithms of their digests, and the
+// offset of each event is its record number.
+
+use crate::tcg::{
+    AlgorithmSize, Event, EventDigest, EventLog, SpecId, TPM_ALG_SHA1, TPM_ALG_SHA256,
+    TPM_ALG_SHA384, TPM_ALG_SHA512, TPM_ALG_SM3_256,
+};
+use ciborium::value::Value as Cbor;
+use serde_json::{json, Value as Json};
+use std::fmt;
+
+// CEL TLV types
+pub const CEL_T_RECNUM: u8 = 0;
+pub const CEL_T_PCR: u8 = 1;
+pub const CEL_T_DIGESTS: u8 = 3;
+pub const CEL_T_PCCLIENT_STD: u8 = 5;
+// Fields of the PC Client standard content
+const PCCLIENT_STD_EVENT_TYPE: u8 = 0;
+const PCCLIENT_STD_EVENT_DATA: u8 = 1;
+
+const CONTENT_PCCLIENT_STD: &str = "pcclient_std";
+
+const ALGORITHM_NAMES: [(u16, &str); 5] = [
+    (TPM_ALG_SHA1, "sha1"),
+    (TPM_ALG_SHA256, "sha256"),
+    (TPM_ALG_SHA384, "sha384"),
+    (TPM_ALG_SHA512, "sha512"),
+    (TPM_ALG_SM3_256, "sm3_256"),
+];
+
+#[derive(Debug, PartialEq)]
+pub enum CelError {
+    Decode(String),
+    InvalidRecord { record: usize, reason: &'static str },
+    UnknownAlgorithm(String),
+}
+
+impl fmt::Display for CelError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CelError::Decode(e) => write!(f, "failed to decode CEL: {}", e),
+            CelError::InvalidRecord { record, reason } => {
+                write!(f, "invalid CEL record {}: {}", record, reason)
+            }
+            CelError::UnknownAlgorithm(name) => write!(f, "unknown digest algorithm {}", name),
+        }
+    }
+}
+
+impl std::error::Error for CelError {}
+
+// Name of a digest algorithm in CEL-JSON, None if it has none
+pub fn algorithm_name(algorithm: u16) -> Option<&'static str> {
+    ALGORITHM_NAMES
+        .iter()
+        .find(|(id, _)| *id == algorithm)
+        .map(|(_, name)| *name)
+}
+
+// Algorithm of a name in CEL-JSON, algorithms without name are written as
+// hex encoded ID
+pub fn algorithm_id(name: &str) -> Option<u16> {
+    match name.strip_prefix("0x") {
+        Some(id) => u16::from_str_radix(id, 16).ok(),
+        None => ALGORITHM_NAMES
+            .iter()
+            .find(|(_, n)| *n == name)
+            .map(|(id, _)| *id),
+    }
+}
+
+pub fn to_json(log: &EventLog) -> Vec<u8> {
+    let records: Vec<Json> = log
+        .events
+        .iter()
+        .enumerate()
+        .map(|(recnum, event)| {
+            let digests: Vec<Json> = event
+                .digests
+                .iter()
+                .map(|d| {
+                    let name = algorithm_name(d.algorithm)
+                        .map(str::to_string)
+                        .unwrap_or_else(|| format!("{:#06x}", d.algorithm));
+                    json!({ "hashAlg": name, "digest": hex(&d.digest) })
+                })
+                .collect();
+            json!({
+                "recnum": recnum,
+                "pcr": event.index,
+                "digests": digests,
+                "content_type": CONTENT_PCCLIENT_STD,
+                "content": {
+                    "event_type": event.event_type,
+                    "event_data": base64::encode(&event.data),
+                },
+            })
+        })
+        .collect();
+    serde_json::to_vec(&records).unwrap()
+}
+
+pub fn to_cbor(log: &EventLog) -> Vec<u8> {
+    let key = |t: u8| Cbor::Integer(t.into());
+    let records = log
+        .events
+        .iter()
+        .enumerate()
+        .map(|(recnum, event)| {
+            let digests = event
+                .digests
+                .iter()
+                .map(|d| {
+                    Cbor::Array(vec![
+                        Cbor::Integer(d.algorithm.into()),
+                        Cbor::Bytes(d.digest.clone()),
+                    ])
+                })
+                .collect();
+            let content = vec![
+                (
+                    key(PCCLIENT_STD_EVENT_TYPE),
+                    Cbor::Integer(event.event_type.into()),
+                ),
+                (
+                    key(PCCLIENT_STD_EVENT_DATA),
+                    Cbor::Bytes(event.data.clone()),
+                ),
+            ];
+            Cbor::Map(vec![
+                (key(CEL_T_RECNUM), Cbor::Integer((recnum as u64).into())),
+                (key(CEL_T_PCR), Cbor::Integer(event.index.into())),
+                (key(CEL_T_DIGESTS), Cbor::Array(digests)),
+                (key(CEL_T_PCCLIENT_STD), Cbor::Map(content)),
+            ])
+        })
+        .collect();
+    let mut data = Vec::new();
+    ciborium::ser::into_writer(&Cbor::Array(records), &mut data).unwrap();
+    data
+}
+
+pub fn from_json(data: &[u8]) -> Result<EventLog, CelError> {
+    let records: Vec<Json> =
+        serde_json::from_slice(data).map_err(|e| CelError::Decode(e.to_string()))?;
+    let mut events = Vec::with_capacity(records.len());
+    for (record, value) in records.iter().enumerate() {
+        let invalid = |reason| CelError::InvalidRecord { record, reason };
+        let u32_field = |value: &Json, reason| {
+            value
+                .as_u64()
+                .and_then(|v| u32::try_from(v).ok())
+                .ok_or_else(|| invalid(reason))
+        };
+        if value["content_type"] != CONTENT_PCCLIENT_STD {
+            return Err(invalid("content type is not pcclient_std"));
+        }
+        let recnum = value["recnum"]
+            .as_u64()
+            .ok_or_else(|| invalid("missing recnum"))?;
+        let mut digests = Vec::new();
+        for digest in value["digests"]
+            .as_array()
+            .ok_or_else(|| invalid("missing digests"))?
+        {
+            let name = digest["hashAlg"]
+                .as_str()
+                .ok_or_else(|| invalid("missing hashAlg"))?;
+            let algorithm =
+                algorithm_id(name).ok_or_else(|| CelError::UnknownAlgorithm(name.to_string()))?;
+            let digest = digest["digest"]
+                .as_str()
+                .and_then(unhex)
+                .ok_or_else(|| invalid("digest is not hex encoded"))?;
+            digests.push(EventDigest { algorithm, digest });
+        }
+        let data = value["content"]["event_data"]
+            .as_str()
+            .and_then(|data| base64::decode(data).ok())
+            .ok_or_else(|| invalid("event_data is not base64 encoded"))?;
+        events.push(Event {
+            offset: recnum as usize,
+            index: u32_field(&value["pcr"], "missing pcr")?,
+            event_type: u32_field(&value["content"]["event_type"], "missing event_type")?,
+            digests,
+            data,
+        });
+    }
+    event_log(events, data.len())
+}
+
+pub fn from_cbor(data: &[u8]) -> Result<EventLog, CelError> {
+    let records = match ciborium::de::from_reader(data) {
+        Ok(Cbor::Array(records)) => records,
+        Ok(_) => return Err(CelError::Decode("not an array of records".to_string())),
+        Err(e) => return Err(CelError::Decode(e.to_string())),
+    };
+    let mut events = Vec::with_capacity(records.len());
+    for (record, value) in records.iter().enumerate() {
+        let invalid = |reason| CelError::InvalidRecord { record, reason };
+        let recnum = field(value, CEL_T_RECNUM)
+            .and_then(integer)
+            .ok_or_else(|| invalid("missing recnum"))?;
+        let index = field(value, CEL_T_PCR)
+            .and_then(integer)
+            .and_then(|i| u32::try_from(i).ok())
+            .ok_or_else(|| invalid("missing pcr"))?;
+        let mut digests = Vec::new();
+        for digest in field(value, CEL_T_DIGESTS)
+            .and_then(Cbor::as_array)
+            .ok_or_else(|| invalid("missing digests"))?
+        {
+            match digest.as_array().map(|d| &d[..]) {
+                Some([algorithm, Cbor::Bytes(digest)]) => digests.push(EventDigest {
+                    algorithm: integer(algorithm)
+                        .and_then(|a| u16::try_from(a).ok())
+                        .ok_or_else(|| invalid("invalid digest algorithm"))?,
+                    digest: digest.clone(),
+                }),
+                _ => return Err(invalid("digest is not an algorithm and digest pair")),
+            }
+        }
+        let content = field(value, CEL_T_PCCLIENT_STD)
+            .ok_or_else(|| invalid("content type is not pcclient_std"))?;
+        let event_type = field(content, PCCLIENT_STD_EVENT_TYPE)
+            .and_then(integer)
+            .and_then(|t| u32::try_from(t).ok())
+            .ok_or_else(|| invalid("missing event_type"))?;
+        let data = field(content, PCCLIENT_STD_EVENT_DATA)
+            .and_then(Cbor::as_bytes)
+            .ok_or_else(|| invalid("missing event_data"))?;
+        events.push(Event {
+            offset: recnum as usize,
+            index,
+            event_type,
+            digests,
+            data: data.clone(),
+        });
+    }
+    event_log(events, data.len())
+}
+
+// Event log of records read from CEL, declaring the algorithms of their
+// digests
+fn event_log(events: Vec<Event>, len: usize) -> Result<EventLog, CelError> {
+    let mut algorithms: Vec<AlgorithmSize> = Vec::new();
+    for (record, event) in events.iter().enumerate() {
+        for digest in &event.digests {
+            let digest_size = u16::try_from(digest.digest.len()).unwrap_or(u16::MAX);
+            match algorithms.iter().find(|a| a.algorithm == digest.algorithm) {
+                Some(a) if a.digest_size != digest_size => {
+                    return Err(CelError::InvalidRecord {
+                        record,
+                        reason: "digest size differs from earlier records",
+                    })
+                }
+                Some(_) => {}
+                None => algorithms.push(AlgorithmSize {
+                    algorithm: digest.algorithm,
+                    digest_size,
+                }),
+            }
+        }
+    }
+    Ok(EventLog {
+        spec_id: SpecId {
+            platform_class: 0,
+            spec_version: (0, 2, 0),
+            uintn_size: 2,
+            algorithms,
+            vendor_info: Vec::new(),
+        },
+        events,
+        len,
+    })
+}
+
+fn field(map: &Cbor, key: u8) -> Option<&Cbor> {
+    map.as_map()?
+        .iter()
+        .find(|(k, _)| integer(k) == Some(key as u64))
+        .map(|(_, v)| v)
+}
+
+fn integer(value: &Cbor) -> Option<u64> {
+    match value {
+        Cbor::Integer(i) => u64::try_from(*i).ok(),
+        _ => None,
+    }
+}
+
+pub(crate) fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+pub(crate) fn unhex(hex: &str) -> Option<Vec<u8>> {
+    hex.as_bytes()
+        .chunks(2)
+        .map(|pair| match pair {
+            [_, _] => u8::from_str_radix(std::str::from_utf8(pair).ok()?, 16).ok(),
+            _ => None,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod cel_tests {
+    use super::*;
+    use crate::runtime::{log_header, RuntimeEvent, RUNTIME_RTMR};
+    use crate::tcg::{self, EV_EFI_ACTION};
+
+    fn log() -> EventLog {
+        let mut log = log_header();
+        log.extend(
+            RuntimeEvent {
+                domain: "example.com".to_string(),
+                event_type: "model".to_string(),
+                content: b"digest".to_vec(),
+            }
+            .log_record(RUNTIME_RTMR),
+        );
+        let mut log = tcg::parse(&log).unwrap();
+        log.events.push(Event {
+            offset: 0,
+            index: 1,
+            event_type: EV_EFI_ACTION,
+            digests: vec![EventDigest {
+                algorithm: TPM_ALG_SHA384,
+                digest: vec![0xab; 48],
+            }],
+            data: b"Exit Boot Services Invocation".to_vec(),
+        });
+        log
+    }
+
+    // events as read back from CEL, where offsets are record numbers
+    fn numbered(log: &EventLog) -> Vec<Event> {
+        let mut events = log.events.clone();
+        for (recnum, event) in events.iter_mut().enumerate() {
+            event.offset = recnum;
+        }
+        events
+    }
+
+    #[test]
+    //events survive the round trip through CEL-JSON and CEL-CBOR
+    fn round_trip() {
+        let log = log();
+        let json = to_json(&log);
+        let parsed = from_json(&json).unwrap();
+        assert_eq!(parsed.events, numbered(&log));
+        assert_eq!(parsed.spec_id.digest_size(TPM_ALG_SHA384), Some(48));
+        let cbor = to_cbor(&log);
+        assert_eq!(from_cbor(&cbor).unwrap().events, numbered(&log));
+
+        let records: Json = serde_json::from_slice(&json).unwrap();
+        assert_eq!(records[1]["recnum"], 1);
+        assert_eq!(records[1]["pcr"], 1);
+        assert_eq!(records[1]["digests"][0]["hashAlg"], "sha384");
+        assert_eq!(records[1]["digests"][0]["digest"], "ab".repeat(48));
+        assert_eq!(records[0]["pcr"], RUNTIME_RTMR as u64 + 1);
+    }
+
+    #[test]
+    //malformed records are reported with their number
+    fn reject_malformed() {
+        assert!(matches!(from_json(b"{"), Err(CelError::Decode(_))));
+        assert!(matches!(from_cbor(&[0xff]), Err(CelError::Decode(_))));
+        let json = br#"[{"recnum": 0, "pcr": 1, "digests": [{"hashAlg": "md5", "digest": ""}],
+            "content_type": "pcclient_std", "content": {"event_type": 1, "event_data": ""}}]"#;
+        assert_eq!(
+            from_json(json),
+            Err(CelError::UnknownAlgorithm("md5".to_string()))
+        );
+        let json = br#"[{"recnum": 0, "pcr": 1, "digests": [],
+            "content_type": "pcclient_std", "content": {"event_type": 1, "event_data": "!"}}]"#;
+        assert_eq!(
+            from_json(json),
+            Err(CelError::InvalidRecord {
+                record: 0,
+                reason: "event_data is not base64 encoded"
+            })
+        );
+    }
+}
@@ -0,0 +1,54 @@
+/*
+* Copyright (c) 2023, Intel Corporation. All rights reserved.<BR>
+* SPDX-License-Identifier: Apache-2.0
+*/
+
+// Commitment of an evidence bundle to its event logs. For GetEvidence,
+// quote-server replaces the user data of the request with the evidence
+// digest, SHA-512 over the length-prefixed fields
+//   "ccnp.evidence.v1" | user_data | kind of log 1 | log 1 | ... |
+//   kind of log n | log n
+// in the order of the logs in the response, where the kind is "boot",
+// "runtime" or "ima". The selected binding scheme then derives the report
+// data from the nonce and the evidence digest, so a quote matching the report
+// data proves that the logs were read for this request.
+
+use crate::binding::length_prefixed;
+use sha2::{Digest as _, Sha512};
+
+const EVIDENCE_TAG: &[u8] = b"ccnp.evidence.v1";
+
+pub fn evidence_digest(user_data: &[u8], logs: &[(&str, &[u8])]) -> [u8; 64] {
+    let mut fields = vec![EVIDENCE_TAG, user_data];
+    for (kind, log) in logs {
+        fields.push(kind.as_bytes());
+        fields.push(log);
+    }
+    Sha512::digest(length_prefixed(&fields)).into()
+}
+
+#[cfg(test)]
+mod evidence_tests {
+    use super::*;
+
+    #[test]
+    //the digest covers user data, log kinds, contents and order
+    fn digest_covers_logs() {
+        let boot: &[u8] = b"boot log";
+        let runtime: &[u8] = b"runtime log";
+        let digest = evidence_digest(b"u", &[("boot", boot), ("runtime", runtime)]);
+        assert_ne!(digest, evidence_digest(b"u", &[("boot", boot)]));
+        assert_ne!(
+            digest,
+            evidence_digest(b"u", &[("runtime", runtime), ("boot", boot)])
+        );
+        assert_ne!(
+            digest,
+            evidence_digest(b"", &[("boot", boot), ("runtime", runtime)])
+        );
+        assert_eq!(
+            evidence_digest(b"", &[]),
+            <[u8; 64]>::from(Sha512::digest(length_prefixed(&[EVIDENCE_TAG, b""])))
+        );
+    }
+}
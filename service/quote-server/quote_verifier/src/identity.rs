@@ -0,0 +1,129 @@
+/*
+* Copyright (c) 2023, Intel Corporation. All rights reserved.<BR>
+* SPDX-License-Identifier: Apache-2.0
+*/
+
+// Binding of the caller identity into report data. When quote-server runs
+// with identity binding, the report data of a quote is
+//   SHA-512(report data of the request || identity digest)
+// where the identity digest is SHA-512 over the canonical encoding of the
+// requesting workload:
+//   "ccnp.caller-identity.v2\n"
+//   "pod_uid=<pod UID>\n"
+//   "container_id=<container ID>\n"
+//   "uid=<uid>\n"
+//   "gid=<gid>\n"
+//   "tls_client=<name of the TLS client certificate>\n"
+// Unknown values are encoded as empty strings, so an unknown uid differs from
+// uid 0. Known values are never empty.
+
+use sha2::{Digest as _, Sha512};
+use std::fmt::Display;
+
+const IDENTITY_TAG: &str = "ccnp.caller-identity.v2\n";
+
+// Identity of the workload which requested a quote, None where unknown
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Identity<'a> {
+    pub pod_uid: Option<&'a str>,
+    pub container_id: Option<&'a str>,
+    pub uid: Option<u32>,
+    pub gid: Option<u32>,
+    pub tls_client: Option<&'a str>,
+}
+
+pub fn identity_digest(identity: &Identity) -> [u8; 64] {
+    fn value<T: Display>(value: Option<T>) -> String {
+        value.map(|v| v.to_string()).unwrap_or_default()
+    }
+    let canonical = format!(
+        "{}pod_uid={}\ncontainer_id={}\nuid={}\ngid={}\ntls_client={}\n",
+        IDENTITY_TAG,
+        value(identity.pod_uid),
+        value(identity.container_id),
+        value(identity.uid),
+        value(identity.gid),
+        value(identity.tls_client),
+    );
+    Sha512::digest(canonical.as_bytes()).into()
+}
+
+pub fn bind_identity(report_data: &[u8], identity_digest: &[u8; 64]) -> [u8; 64] {
+    let mut hasher = Sha512::new();
+    hasher.update(report_data);
+    hasher.update(identity_digest);
+    hasher.finalize().into()
+}
+
+#[cfg(test)]
+mod identity_tests {
+    use super::*;
+
+    const BASE: Identity = Identity {
+        pod_uid: Some("pod"),
+        container_id: Some("ctr"),
+        uid: Some(0),
+        gid: Some(0),
+        tls_client: None,
+    };
+
+    #[test]
+    //identity digest depends on every identity field
+    fn identity_digest_fields() {
+        let base = identity_digest(&BASE);
+        for other in [
+            Identity {
+                pod_uid: Some("pod2"),
+                ..BASE
+            },
+            Identity {
+                container_id: Some("ctr2"),
+                ..BASE
+            },
+            Identity {
+                uid: Some(1),
+                ..BASE
+            },
+            Identity {
+                gid: Some(1),
+                ..BASE
+            },
+            Identity {
+                tls_client: Some("client"),
+                ..BASE
+            },
+        ] {
+            assert_ne!(base, identity_digest(&other));
+        }
+    }
+
+    #[test]
+    //unknown IDs are not encoded as root
+    fn identity_digest_unknown() {
+        let unknown = Identity {
+            uid: None,
+            gid: None,
+            ..BASE
+        };
+        assert_ne!(identity_digest(&BASE), identity_digest(&unknown));
+        let tcp = |name| Identity {
+            tls_client: Some(name),
+            ..Default::default()
+        };
+        assert_ne!(identity_digest(&tcp("a")), identity_digest(&tcp("b")));
+    }
+
+    #[test]
+    //bound report data differs between identities
+    fn bind_identity_differs() {
+        let report_data = [7u8; 64];
+        let pod = |pod_uid| Identity {
+            pod_uid: Some(pod_uid),
+            ..Default::default()
+        };
+        assert_ne!(
+            bind_identity(&report_data, &identity_digest(&pod("pod-a"))),
+            bind_identity(&report_data, &identity_digest(&pod("pod-b")))
+        );
+    }
+}
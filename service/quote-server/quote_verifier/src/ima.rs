@@ -0,0 +1,572 @@
+/*
+* Copyright (c) 2023, Intel Corporation. All rights reserved.<BR>
+* SPDX-License-Identifier: Apache-2.0
+*/
+
+// Parser and replay of the IMA measurement list of the Linux kernel, as read
+// from securityfs in its binary and ASCII forms. Entries of the ima-ng,
+// ima-sig and ima-buf templates are supported.
+//
+// An entry of binary_runtime_measurements is
+//   PCR (4) | template hash | template name length (4) | template name |
+//   template data length (4) | template data
+// with little-endian integers, as on x86 or with ima_canonical_fmt. The
+// template hash is of the algorithm of the list: SHA-1 for
+// binary_runtime_measurements, and the algorithm of the file name for
+// binary_runtime_measurements_<algorithm>. The template data are the fields
+// of the template, each preceded by its length (4):
+//   d-ng  hash algorithm name | ':' | NUL | file hash
+//   n-ng  file name | NUL
+//   sig   file signature, of ima-sig
+//   buf   measured buffer, of ima-buf, e.g. the kexec command line
+// A line of ascii_runtime_measurements shows the same entry as
+//   PCR template-hash template-name algorithm:file-hash file-name [hex]
+// so the template data of an ASCII entry are rebuilt from its fields.
+//
+// The template hash is the hash of the template data, and is extended into
+// the register as register = H(register | template hash). A violation, a file
+// measured while open for writing, has a zero template hash and is extended
+// with all ones instead. The first entry, boot_aggregate, records the hash
+// of the boot registers, e.g. of PCR0 to PCR7 for SHA-1 on a TPM.
+
+use crate::cel::{hex, unhex};
+use crate::tcg;
+use std::fmt;
+
+pub const TEMPLATE_IMA_NG: &str = "ima-ng";
+pub const TEMPLATE_IMA_SIG: &str = "ima-sig";
+pub const TEMPLATE_IMA_BUF: &str = "ima-buf";
+pub const BOOT_AGGREGATE: &str = "boot_aggregate";
+// Default PCR of IMA
+pub const IMA_PCR: u32 = 10;
+
+#[derive(Debug, PartialEq)]
+pub enum ImaError {
+    Truncated { entry: usize },
+    InvalidEntry { entry: usize, reason: &'static str },
+    UnknownTemplate { entry: usize, template: String },
+    TemplateHashMismatch { entry: usize },
+    UnsupportedAlgorithm { algorithm: u16 },
+    // The list does not start with a boot_aggregate entry
+    NoBootAggregate,
+    // The boot_aggregate entry does not match the boot registers
+    BootAggregateMismatch,
+    UnknownHashAlgorithm { name: String },
+}
+
+impl fmt::Display for ImaError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ImaError::Truncated { entry } => write!(f, "IMA entry {} is truncated", entry),
+            ImaError::InvalidEntry { entry, reason } => {
+                write!(f, "invalid IMA entry {}: {}", entry, reason)
+            }
+            ImaError::UnknownTemplate { entry, template } => {
+                write!(
+                    f,
+                    "IMA entry {} has unsupported template {}",
+                    entry, template
+                )
+            }
+            ImaError::TemplateHashMismatch { entry } => write!(
+                f,
+                "template hash of IMA entry {} does not match its template data",
+                entry
+            ),
+            ImaError::NoBootAggregate => write!(f, "IMA list has no boot_aggregate entry"),
+            ImaError::BootAggregateMismatch => {
+                write!(f, "boot_aggregate does not match the boot registers")
+            }
+            ImaError::UnknownHashAlgorithm { name } => {
+                write!(f, "unknown IMA hash algorithm {}", name)
+            }
+            ImaError::UnsupportedAlgorithm { algorithm } => {
+                write!(f, "unsupported hash algorithm {:#x}", algorithm)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ImaError {}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Entry {
+    pub pcr: u32,
+    pub template_hash: Vec<u8>,
+    pub template_name: String,
+    pub template_data: Vec<u8>,
+    // Name of the file hash algorithm, e.g. "sha256"
+    pub file_hash_algorithm: String,
+    pub file_hash: Vec<u8>,
+    pub file_name: String,
+    // Signature of ima-sig, empty for unsigned files
+    pub signature: Option<Vec<u8>>,
+    // Buffer of ima-buf
+    pub buffer: Option<Vec<u8>>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct MeasurementList {
+    // Algorithm of the template hashes, TPM_ALG_*
+    pub algorithm: u16,
+    pub entries: Vec<Entry>,
+}
+
+impl Entry {
+    pub fn is_violation(&self) -> bool {
+        self.template_hash.iter().all(|b| *b == 0)
+    }
+}
+
+impl MeasurementList {
+    // The boot_aggregate entry, the first of the list
+    pub fn boot_aggregate(&self) -> Option<&Entry> {
+        self.entries
+            .first()
+            .filter(|entry| entry.file_name == BOOT_AGGREGATE)
+    }
+
+    // Check the boot_aggregate entry against the boot registers it
+    // aggregates, e.g. PCR0 to PCR9 as quoted, with the file hash algorithm
+    // of the entry. A kernel without TPM records a zero boot_aggregate, which
+    // only matches if no registers are given.
+    pub fn check_boot_aggregate(&self, registers: &[&[u8]]) -> Result<(), ImaError> {
+        let entry = self.boot_aggregate().ok_or(ImaError::NoBootAggregate)?;
+        let expected = match registers.is_empty() {
+            true => vec![0; entry.file_hash.len()],
+            false => {
+                let algorithm = hash_algorithm(&entry.file_hash_algorithm).ok_or_else(|| {
+                    ImaError::UnknownHashAlgorithm {
+                        name: entry.file_hash_algorithm.clone(),
+                    }
+                })?;
+                boot_aggregate(algorithm, registers)?
+            }
+        };
+        match entry.file_hash == expected {
+            true => Ok(()),
+            false => Err(ImaError::BootAggregateMismatch),
+        }
+    }
+
+    // Value of register `pcr` after extending the entries of the list into
+    // `initial`: zero for a PCR used by IMA only, the value replayed from the
+    // boot event logs for an RTMR shared with the firmware
+    pub fn replay(&self, pcr: u32, initial: &[u8]) -> Result<Vec<u8>, ImaError> {
+        let violation = vec![0xff; initial.len()];
+        self.entries
+            .iter()
+            .filter(|entry| entry.pcr == pcr)
+            .try_fold(initial.to_vec(), |register, entry| {
+                let extended = match entry.is_violation() {
+                    true => &violation,
+                    false => &entry.template_hash,
+                };
+                hash(self.algorithm, &[&register, extended])
+            })
+    }
+}
+
+// Hash of the concatenation of `data` with a TPM_ALG_* algorithm
+pub fn hash(algorithm: u16, data: &[&[u8]]) -> Result<Vec<u8>, ImaError> {
+    tcg::hash(algorithm, data).ok_or(ImaError::UnsupportedAlgorithm { algorithm })
+}
+
+// TPM_ALG_* of a hash algorithm name of IMA, e.g. "sha256"
+pub fn hash_algorithm(name: &str) -> Option<u16> {
+    match name {
+        "sha1" => Some(tcg::TPM_ALG_SHA1),
+        "sha256" => Some(tcg::TPM_ALG_SHA256),
+        "sha384" => Some(tcg::TPM_ALG_SHA384),
+        "sha512" => Some(tcg::TPM_ALG_SHA512),
+        _ => None,
+    }
+}
+
+// Expected file hash of the boot_aggregate entry: the hash of the boot
+// registers in order, e.g. PCR0 to PCR7, and PCR8 and PCR9 for algorithms
+// other than SHA-1
+pub fn boot_aggregate(algorithm: u16, registers: &[&[u8]]) -> Result<Vec<u8>, ImaError> {
+    hash(algorithm, registers)
+}
+
+// Parse a binary measurement list whose template hashes are of `algorithm`
+pub fn parse_binary(data: &[u8], algorithm: u16) -> Result<MeasurementList, ImaError> {
+    let hash_len = hash(algorithm, &[])?.len();
+    let mut reader = Reader {
+        data,
+        offset: 0,
+        entry: 0,
+    };
+    let mut entries = Vec::new();
+    while reader.offset < data.len() {
+        reader.entry = entries.len();
+        let pcr = reader.u32()?;
+        let template_hash = reader.take(hash_len)?.to_vec();
+        let len = reader.u32()? as usize;
+        let template_name = std::str::from_utf8(reader.take(len)?)
+            .map_err(|_| reader.invalid("template name is not UTF-8"))?
+            .to_string();
+        let len = reader.u32()? as usize;
+        let template_data = reader.take(len)?.to_vec();
+        entries.push(entry(
+            entries.len(),
+            algorithm,
+            pcr,
+            template_hash,
+            template_name,
+            template_data,
+        )?);
+    }
+    Ok(MeasurementList { algorithm, entries })
+}
+
+// Parse an ASCII measurement list whose template hashes are of `algorithm`
+pub fn parse_ascii(text: &str, algorithm: u16) -> Result<MeasurementList, ImaError> {
+    let hash_len = hash(algorithm, &[])?.len();
+    let mut entries = Vec::new();
+    for line in text.lines().filter(|line| !line.is_empty()) {
+        let index = entries.len();
+        let invalid = |reason| ImaError::InvalidEntry {
+            entry: index,
+            reason,
+        };
+        let mut fields = line.splitn(5, ' ');
+        let mut field = || fields.next().ok_or(ImaError::Truncated { entry: index });
+        let pcr = field()?.parse().map_err(|_| invalid("invalid PCR"))?;
+        let template_hash = unhex(field()?)
+            .filter(|h| h.len() == hash_len)
+            .ok_or_else(|| invalid("invalid template hash"))?;
+        let template_name = field()?.to_string();
+        let (file_hash_algorithm, file_hash) = field()?
+            .split_once(':')
+            .ok_or_else(|| invalid("file hash has no algorithm"))?;
+        let file_hash = unhex(file_hash).ok_or_else(|| invalid("invalid file hash"))?;
+        let rest = field()?;
+
+        // the last field of ima-sig and ima-buf follows the file name, which
+        // may contain spaces, and is empty for unsigned files
+        let (file_name, last) = match template_name.as_str() {
+            TEMPLATE_IMA_NG => (rest, None),
+            TEMPLATE_IMA_SIG | TEMPLATE_IMA_BUF => {
+                let (name, last) = rest
+                    .rsplit_once(' ')
+                    .ok_or(ImaError::Truncated { entry: index })?;
+                let last = unhex(last).ok_or_else(|| invalid("invalid signature or buffer"))?;
+                (name, Some(last))
+            }
+            _ => {
+                return Err(ImaError::UnknownTemplate {
+                    entry: index,
+                    template: template_name,
+                })
+            }
+        };
+        let mut digest_field = format!("{}:\0", file_hash_algorithm).into_bytes();
+        digest_field.extend_from_slice(&file_hash);
+        let name_field = format!("{}\0", file_name).into_bytes();
+        let mut template_data = Vec::new();
+        for field in [Some(digest_field), Some(name_field), last]
+            .iter()
+            .flatten()
+        {
+            template_data.extend_from_slice(&(field.len() as u32).to_le_bytes());
+            template_data.extend_from_slice(field);
+        }
+        entries.push(entry(
+            index,
+            algorithm,
+            pcr,
+            template_hash,
+            template_name,
+            template_data,
+        )?);
+    }
+    Ok(MeasurementList { algorithm, entries })
+}
+
+// Decode the template data of an entry and check its template hash
+fn entry(
+    index: usize,
+    algorithm: u16,
+    pcr: u32,
+    template_hash: Vec<u8>,
+    template_name: String,
+    template_data: Vec<u8>,
+) -> Result<Entry, ImaError> {
+    let invalid = |reason| ImaError::InvalidEntry {
+        entry: index,
+        reason,
+    };
+    let field_count = match template_name.as_str() {
+        TEMPLATE_IMA_NG => 2,
+        TEMPLATE_IMA_SIG | TEMPLATE_IMA_BUF => 3,
+        _ => {
+            return Err(ImaError::UnknownTemplate {
+                entry: index,
+                template: template_name,
+            })
+        }
+    };
+    let mut reader = Reader {
+        data: &template_data,
+        offset: 0,
+        entry: index,
+    };
+    let mut fields = Vec::with_capacity(field_count);
+    for _ in 0..field_count {
+        let len = reader.u32()? as usize;
+        fields.push(reader.take(len)?);
+    }
+    if reader.offset != template_data.len() {
+        return Err(invalid("template data has trailing bytes"));
+    }
+
+    let (file_hash_algorithm, file_hash) = fields[0]
+        .iter()
+        .position(|b| *b == 0)
+        .filter(|nul| *nul > 0 && fields[0][nul - 1] == b':')
+        .map(|nul| (&fields[0][..nul - 1], &fields[0][nul + 1..]))
+        .ok_or_else(|| invalid("file hash has no algorithm"))?;
+    let file_hash_algorithm = std::str::from_utf8(file_hash_algorithm)
+        .map_err(|_| invalid("hash algorithm is not UTF-8"))?
+        .to_string();
+    let file_name = fields[1]
+        .strip_suffix(&[0])
+        .and_then(|name| std::str::from_utf8(name).ok())
+        .ok_or_else(|| invalid("file name is not a UTF-8 string"))?
+        .to_string();
+    let last = fields.get(2).map(|field| field.to_vec());
+
+    let entry = Entry {
+        pcr,
+        signature: last.clone().filter(|_| template_name == TEMPLATE_IMA_SIG),
+        buffer: last.filter(|_| template_name == TEMPLATE_IMA_BUF),
+        template_hash,
+        template_name,
+        file_hash_algorithm,
+        file_hash: file_hash.to_vec(),
+        file_name,
+        template_data,
+    };
+    if !entry.is_violation() && hash(algorithm, &[&entry.template_data])? != entry.template_hash {
+        return Err(ImaError::TemplateHashMismatch { entry: index });
+    }
+    Ok(entry)
+}
+
+// The ASCII form of an entry, as in ascii_runtime_measurements
+pub fn to_ascii(entry: &Entry) -> String {
+    let mut line = format!(
+        "{} {} {} {}:{} {}",
+        entry.pcr,
+        hex(&entry.template_hash),
+        entry.template_name,
+        entry.file_hash_algorithm,
+        hex(&entry.file_hash),
+        entry.file_name
+    );
+    if let Some(last) = entry.signature.as_ref().or(entry.buffer.as_ref()) {
+        line.push(' ');
+        line.push_str(&hex(last));
+    }
+    line
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    offset: usize,
+    // index of the entry read
+    entry: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], ImaError> {
+        let end = self
+            .offset
+            .checked_add(len)
+            .filter(|end| *end <= self.data.len())
+            .ok_or(ImaError::Truncated { entry: self.entry })?;
+        let bytes = &self.data[self.offset..end];
+        self.offset = end;
+        Ok(bytes)
+    }
+
+    fn u32(&mut self) -> Result<u32, ImaError> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn invalid(&self, reason: &'static str) -> ImaError {
+        ImaError::InvalidEntry {
+            entry: self.entry,
+            reason,
+        }
+    }
+}
+
+#[cfg(test)]
+mod ima_tests {
+    use super::*;
+    use crate::tcg::{TPM_ALG_SHA1, TPM_ALG_SHA256};
+
+    // entries as in ascii_runtime_measurements
+    fn ascii_list() -> String {
+        let entries = [
+            ("ima-ng", "sha256:", vec![1; 32], BOOT_AGGREGATE, None),
+            ("ima-ng", "sha256:", vec![2; 32], "/usr/bin/kmod", None),
+            (
+                "ima-sig",
+                "sha256:",
+                vec![3; 32],
+                "/usr/lib/my lib.so",
+                Some(vec![0x03, 0x02]),
+            ),
+            (
+                "ima-sig",
+                "sha256:",
+                vec![4; 32],
+                "/usr/bin/unsigned",
+                Some(vec![]),
+            ),
+            (
+                "ima-buf",
+                "sha256:",
+                vec![5; 32],
+                "kexec-cmdline",
+                Some(b"ro".to_vec()),
+            ),
+        ];
+        entries
+            .iter()
+            .map(|(template, algorithm, hash, name, last)| {
+                let mut data = Vec::new();
+                let mut digest = format!("{}\0", algorithm).into_bytes();
+                digest.extend_from_slice(hash);
+                let name_field = format!("{}\0", name).into_bytes();
+                for field in [Some(digest), Some(name_field), last.clone()]
+                    .iter()
+                    .flatten()
+                {
+                    data.extend_from_slice(&(field.len() as u32).to_le_bytes());
+                    data.extend_from_slice(field);
+                }
+                let template_hash = super::hash(TPM_ALG_SHA256, &[&data]).unwrap();
+                let mut line = format!(
+                    "10 {} {} {}{} {}",
+                    hex(&template_hash),
+                    template,
+                    algorithm,
+                    hex(hash),
+                    name
+                );
+                if let Some(last) = last {
+                    line.push(' ');
+                    line.push_str(&hex(last));
+                }
+                line + "\n"
+            })
+            .collect()
+    }
+
+    fn to_binary(list: &MeasurementList) -> Vec<u8> {
+        let mut data = Vec::new();
+        for entry in &list.entries {
+            data.extend_from_slice(&entry.pcr.to_le_bytes());
+            data.extend_from_slice(&entry.template_hash);
+            data.extend_from_slice(&(entry.template_name.len() as u32).to_le_bytes());
+            data.extend_from_slice(entry.template_name.as_bytes());
+            data.extend_from_slice(&(entry.template_data.len() as u32).to_le_bytes());
+            data.extend_from_slice(&entry.template_data);
+        }
+        data
+    }
+
+    #[test]
+    //ASCII and binary lists decode to the same entries with checked template hashes
+    fn parse_lists() {
+        let text = ascii_list();
+        let list = parse_ascii(&text, TPM_ALG_SHA256).unwrap();
+        assert_eq!(list.entries.len(), 5);
+        assert_eq!(list.boot_aggregate().unwrap().file_hash, vec![1; 32]);
+        assert_eq!(list.entries[2].file_name, "/usr/lib/my lib.so");
+        assert_eq!(list.entries[2].signature, Some(vec![0x03, 0x02]));
+        assert_eq!(list.entries[3].signature, Some(vec![]));
+        assert_eq!(list.entries[4].buffer, Some(b"ro".to_vec()));
+        assert_eq!(list.entries[4].file_hash_algorithm, "sha256");
+        assert_eq!(
+            text.lines().collect::<Vec<_>>(),
+            list.entries.iter().map(to_ascii).collect::<Vec<_>>()
+        );
+
+        let binary = to_binary(&list);
+        assert_eq!(parse_binary(&binary, TPM_ALG_SHA256), Ok(list.clone()));
+        assert_eq!(
+            parse_binary(&binary[..binary.len() - 1], TPM_ALG_SHA256),
+            Err(ImaError::Truncated { entry: 4 })
+        );
+
+        let mut tampered = list;
+        tampered.entries[1].template_data[20] ^= 1;
+        assert_eq!(
+            parse_binary(&to_binary(&tampered), TPM_ALG_SHA256),
+            Err(ImaError::TemplateHashMismatch { entry: 1 })
+        );
+        assert_eq!(
+            parse_ascii("10 00 ima sha1:00 /bin/sh\n", TPM_ALG_SHA1),
+            Err(ImaError::InvalidEntry {
+                entry: 0,
+                reason: "invalid template hash"
+            })
+        );
+    }
+
+    #[test]
+    //entries are replayed into their register, violations extend all ones
+    fn replay_list() {
+        let mut list = parse_ascii(&ascii_list(), TPM_ALG_SHA256).unwrap();
+        list.entries[3].template_hash = vec![0; 32];
+        list.entries[4].pcr = 11;
+        let zero = [0; 32];
+        let expected = [
+            &list.entries[0].template_hash[..],
+            &list.entries[1].template_hash,
+            &list.entries[2].template_hash,
+            &[0xff; 32],
+        ]
+        .iter()
+        .fold(zero.to_vec(), |register, extended| {
+            hash(TPM_ALG_SHA256, &[&register, extended]).unwrap()
+        });
+        assert_eq!(list.replay(IMA_PCR, &zero), Ok(expected));
+        assert_eq!(list.replay(12, &zero), Ok(zero.to_vec()));
+
+        let pcrs: Vec<[u8; 32]> = (0..10).map(|i| [i; 32]).collect();
+        let pcrs: Vec<&[u8]> = pcrs.iter().map(|pcr| &pcr[..]).collect();
+        assert_eq!(
+            boot_aggregate(TPM_ALG_SHA256, &pcrs).unwrap(),
+            hash(TPM_ALG_SHA256, &[&pcrs.concat()]).unwrap()
+        );
+        assert_eq!(
+            list.check_boot_aggregate(&pcrs),
+            Err(ImaError::BootAggregateMismatch)
+        );
+        list.entries[0].file_hash = boot_aggregate(TPM_ALG_SHA256, &pcrs).unwrap();
+        assert_eq!(list.check_boot_aggregate(&pcrs), Ok(()));
+        assert_eq!(
+            list.check_boot_aggregate(&pcrs[..8]),
+            Err(ImaError::BootAggregateMismatch)
+        );
+        // a kernel without TPM records a zero boot_aggregate
+        list.entries[0].file_hash = vec![0; 32];
+        assert_eq!(list.check_boot_aggregate(&[]), Ok(()));
+        list.entries.remove(0);
+        assert_eq!(
+            list.check_boot_aggregate(&pcrs),
+            Err(ImaError::NoBootAggregate)
+        );
+        assert_eq!(
+            hash(0x12, &[]),
+            Err(ImaError::UnsupportedAlgorithm { algorithm: 0x12 })
+        );
+    }
+}
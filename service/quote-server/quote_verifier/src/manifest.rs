@@ -0,0 +1,396 @@
+/*
+* Copyright (c) 2023, Intel Corporation. All rights reserved.<BR>
+* SPDX-License-Identifier: Apache-2.0
+*/
+
+// Boot manifest: the components of the boot chain measured in a boot event
+// log with their digests, and the kernel command line.
+//
+// Components are recognized from the events that measure them:
+//   EV_EFI_PLATFORM_FIRMWARE_BLOB(2)      firmware volumes, e.g. the TDVF CFV
+//   EV_EFI_HANDOFF_TABLES(2)              the TD HOB, "TdHob", and other
+//                                         configuration tables
+//   EV_PLATFORM_CONFIG_FLAGS "ACPI DATA"  the ACPI tables of the VMM
+//   EV_EFI_BOOT_SERVICES_APPLICATION      shim, GRUB, and the kernel when
+//                                         booted directly by the firmware
+//   EV_IPL                                files read by GRUB, e.g. the kernel
+//                                         and initrd, and the command line in
+//                                         "kernel_cmdline: ..."
+//   EV_EVENT_TAG                          the initrd and load options measured
+//                                         by the Linux EFI stub
+// Images and files are told apart by their file name: shim*, grub*, vmlinuz*,
+// vmlinux*, bzImage*, initrd*, initramfs*, and kernel and initrd for the
+// files of QEMU direct boot. Other images are reported as applications, e.g.
+// the removable media loader \EFI\BOOT\BOOTX64.EFI which may be shim or GRUB.
+//
+// The digests of these events are of the component, e.g. of the file read,
+// not of the event data, so names and kinds are not authenticated by the
+// registers the log replays to: policies match components by digest. The
+// command line is, as GRUB measures the command line itself; it is only
+// taken if it matches the digests of its event. The EFI stub logs the tag
+// "LOADED_IMAGE::LoadOptions" with the digest of the load options, the
+// UTF-16 command line, which is not logged.
+
+use crate::secureboot::{self, DecodeError};
+use crate::tcg::{
+    u32_at, u64_at, EventDigest, EventLog, EV_EFI_BOOT_SERVICES_APPLICATION, EV_EFI_HANDOFF_TABLES,
+    EV_EFI_HANDOFF_TABLES2, EV_EFI_PLATFORM_FIRMWARE_BLOB, EV_EFI_PLATFORM_FIRMWARE_BLOB2,
+    EV_EVENT_TAG, EV_IPL, EV_PLATFORM_CONFIG_FLAGS,
+};
+use std::fmt;
+
+// Tagged events of the Linux EFI stub, see drivers/firmware/efi/libstub/efistub.h
+const LINUX_EFI_INITRD_EVENT_TAG_ID: u32 = 0x8f3b22ec;
+const LINUX_EFI_LOAD_OPTIONS_EVENT_TAG_ID: u32 = 0x8f3b22ed;
+const LOAD_OPTIONS: &str = "LOADED_IMAGE::LoadOptions";
+const ACPI_DATA: &str = "ACPI DATA";
+const TD_HOB: &str = "TdHob";
+// Prefixes of the command line measured by GRUB, since and before 2.06
+const KERNEL_CMDLINE_PREFIXES: [&str; 2] = ["kernel_cmdline: ", "grub_kernel_cmdline "];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ComponentKind {
+    FirmwareVolume,
+    TdHob,
+    HandoffTables,
+    AcpiTables,
+    Shim,
+    Grub,
+    Application,
+    Kernel,
+    Initrd,
+    // Load options of the kernel, measured by the EFI stub
+    LoadOptions,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Component {
+    pub kind: ComponentKind,
+    // File path, description of the firmware blob or table, or the address
+    // and length of a firmware blob without description. Not covered by the
+    // digests.
+    pub name: String,
+    // Offset of the measuring event in the log
+    pub offset: usize,
+    pub index: u32,
+    pub digests: Vec<EventDigest>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct BootManifest {
+    // In the order they were measured
+    pub components: Vec<Component>,
+    // Last command line measured by GRUB, checked against its digests
+    pub kernel_cmdline: Option<String>,
+}
+
+impl fmt::Display for ComponentKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            ComponentKind::FirmwareVolume => "firmware_volume",
+            ComponentKind::TdHob => "td_hob",
+            ComponentKind::HandoffTables => "handoff_tables",
+            ComponentKind::AcpiTables => "acpi_tables",
+            ComponentKind::Shim => "shim",
+            ComponentKind::Grub => "grub",
+            ComponentKind::Application => "application",
+            ComponentKind::Kernel => "kernel",
+            ComponentKind::Initrd => "initrd",
+            ComponentKind::LoadOptions => "load_options",
+        };
+        f.write_str(name)
+    }
+}
+
+impl Component {
+    // Digest in the bank of `algorithm`
+    pub fn digest(&self, algorithm: u16) -> Option<&[u8]> {
+        self.digests
+            .iter()
+            .find(|d| d.algorithm == algorithm)
+            .map(|d| &d.digest[..])
+    }
+}
+
+impl BootManifest {
+    pub fn components_of(&self, kind: ComponentKind) -> impl Iterator<Item = &Component> {
+        self.components.iter().filter(move |c| c.kind == kind)
+    }
+
+    // Kernel booted, the last one loaded
+    pub fn kernel(&self) -> Option<&Component> {
+        self.components_of(ComponentKind::Kernel).last()
+    }
+
+    pub fn initrd(&self) -> Option<&Component> {
+        self.components_of(ComponentKind::Initrd).last()
+    }
+}
+
+// Boot manifest of a boot event log
+pub fn manifest(log: &EventLog) -> Result<BootManifest, DecodeError> {
+    let mut manifest = BootManifest::default();
+    for event in &log.events {
+        let invalid = |reason| DecodeError {
+            offset: event.offset,
+            event_type: event.event_type,
+            reason,
+        };
+        let component = match event.event_type {
+            EV_EFI_PLATFORM_FIRMWARE_BLOB => {
+                let base = u64_at(&event.data, 0).ok_or_else(|| invalid("truncated"))?;
+                let length = u64_at(&event.data, 8).ok_or_else(|| invalid("truncated"))?;
+                Some((
+                    ComponentKind::FirmwareVolume,
+                    format!("{:#x}+{:#x}", base, length),
+                ))
+            }
+            EV_EFI_PLATFORM_FIRMWARE_BLOB2 => {
+                let description = description(&event.data).ok_or_else(|| invalid("truncated"))?;
+                Some((ComponentKind::FirmwareVolume, description))
+            }
+            EV_EFI_HANDOFF_TABLES2 => {
+                let description = description(&event.data).ok_or_else(|| invalid("truncated"))?;
+                let kind = match description.as_str() {
+                    TD_HOB => ComponentKind::TdHob,
+                    ACPI_DATA => ComponentKind::AcpiTables,
+                    _ => ComponentKind::HandoffTables,
+                };
+                Some((kind, description))
+            }
+            EV_EFI_HANDOFF_TABLES => Some((ComponentKind::HandoffTables, String::new())),
+            EV_PLATFORM_CONFIG_FLAGS if text(&event.data) == ACPI_DATA => {
+                Some((ComponentKind::AcpiTables, ACPI_DATA.to_string()))
+            }
+            EV_EFI_BOOT_SERVICES_APPLICATION => {
+                let image = secureboot::decode_image_load(&event.data)
+                    .ok_or_else(|| invalid("truncated"))?;
+                let path = image.path.unwrap_or_default();
+                let kind = file_kind(&path).unwrap_or(ComponentKind::Application);
+                Some((kind, path))
+            }
+            EV_IPL => {
+                let line = text(&event.data);
+                match KERNEL_CMDLINE_PREFIXES
+                    .iter()
+                    .find_map(|prefix| line.strip_prefix(prefix))
+                {
+                    Some(cmdline) if event.measures(cmdline.as_bytes()) => {
+                        manifest.kernel_cmdline = Some(cmdline.to_string());
+                        None
+                    }
+                    Some(_) => return Err(invalid("digests do not match the command line")),
+                    // files are measured by their path, e.g. (hd0,gpt1)/vmlinuz,
+                    // commands such as "grub_cmd: linux /vmlinuz" are not
+                    None if !line.starts_with(['/', '(']) => None,
+                    None => file_kind(&line)
+                        .filter(|kind| {
+                            matches!(kind, ComponentKind::Kernel | ComponentKind::Initrd)
+                        })
+                        .map(|kind| (kind, line)),
+                }
+            }
+            EV_EVENT_TAG => match u32_at(&event.data, 0) {
+                Some(LINUX_EFI_INITRD_EVENT_TAG_ID) => {
+                    Some((ComponentKind::Initrd, "Linux initrd".to_string()))
+                }
+                Some(LINUX_EFI_LOAD_OPTIONS_EVENT_TAG_ID) => {
+                    Some((ComponentKind::LoadOptions, LOAD_OPTIONS.to_string()))
+                }
+                _ => None,
+            },
+            _ => None,
+        };
+        if let Some((kind, name)) = component {
+            manifest.components.push(Component {
+                kind,
+                name,
+                offset: event.offset,
+                index: event.index,
+                digests: event.digests.clone(),
+            });
+        }
+    }
+    Ok(manifest)
+}
+
+// Kind of an image or file by its file name
+fn file_kind(path: &str) -> Option<ComponentKind> {
+    let name = path
+        .rsplit(['/', '\\'])
+        .next()
+        .unwrap_or_default()
+        .to_ascii_lowercase();
+    let starts = |prefixes: &[&str]| prefixes.iter().any(|p| name.starts_with(p));
+    if starts(&["shim"]) {
+        Some(ComponentKind::Shim)
+    } else if starts(&["grub"]) {
+        Some(ComponentKind::Grub)
+    } else if name == "kernel" || starts(&["vmlinuz", "vmlinux", "bzimage"]) {
+        Some(ComponentKind::Kernel)
+    } else if starts(&["initrd", "initramfs"]) {
+        Some(ComponentKind::Initrd)
+    } else {
+        None
+    }
+}
+
+// Description of a UEFI_PLATFORM_FIRMWARE_BLOB2 or UEFI_HANDOFF_TABLE_POINTERS2,
+// preceded by its length as one byte
+fn description(data: &[u8]) -> Option<String> {
+    let len = *data.first()? as usize;
+    Some(text(data.get(1..1 + len)?))
+}
+
+// Text of an event, without its terminating NULs
+fn text(data: &[u8]) -> String {
+    String::from_utf8_lossy(data)
+        .trim_end_matches('\0')
+        .to_string()
+}
+
+#[cfg(test)]
+mod manifest_tests {
+    use super::*;
+    use crate::tcg::testing::{image_load, log, utf16le};
+    use crate::tcg::{hash, EV_SEPARATOR, TPM_ALG_SHA384};
+
+    fn with_description(description: &str) -> Vec<u8> {
+        let mut data = vec![description.len() as u8];
+        data.extend_from_slice(description.as_bytes());
+        data.extend_from_slice(&[0; 16]);
+        data
+    }
+
+    // TCG_PCClientTaggedEvent as logged by the EFI stub
+    fn tagged(id: u32, description: &str) -> Vec<u8> {
+        let description = format!("{}\0", description);
+        let mut event = id.to_le_bytes().to_vec();
+        event.extend_from_slice(&(description.len() as u32).to_le_bytes());
+        event.extend_from_slice(description.as_bytes());
+        event
+    }
+
+    // Set the digest of an event to that of what it measures, e.g. a file
+    fn measured(log: &mut EventLog, event: usize, measured: &[u8]) {
+        log.events[event].digests[0].digest = hash(TPM_ALG_SHA384, &[measured]).unwrap();
+    }
+
+    #[test]
+    //a shim and GRUB boot is summarized into its components and command line
+    fn grub_boot() {
+        let cmdline = "BOOT_IMAGE=/vmlinuz-6.2 ro ima_policy=tcb";
+        let mut grub = log(vec![
+            (EV_EFI_PLATFORM_FIRMWARE_BLOB2, with_description("CFV")),
+            (EV_EFI_HANDOFF_TABLES2, with_description("TdHob")),
+            (EV_PLATFORM_CONFIG_FLAGS, b"ACPI DATA\0".to_vec()),
+            (EV_SEPARATOR, vec![0; 4]),
+            (
+                EV_EFI_BOOT_SERVICES_APPLICATION,
+                image_load("\\EFI\\BOOT\\BOOTX64.EFI"),
+            ),
+            (
+                EV_EFI_BOOT_SERVICES_APPLICATION,
+                image_load("\\EFI\\ubuntu\\shimx64.efi"),
+            ),
+            (
+                EV_EFI_BOOT_SERVICES_APPLICATION,
+                image_load("\\EFI\\ubuntu\\grubx64.efi"),
+            ),
+            (EV_IPL, b"(hd0,gpt1)/boot/grub/grub.cfg\0".to_vec()),
+            (EV_IPL, b"grub_cmd: linux /vmlinuz-6.2 ro\0".to_vec()),
+            (
+                EV_IPL,
+                format!("kernel_cmdline: {}\0", cmdline).into_bytes(),
+            ),
+            (EV_IPL, b"/vmlinuz-6.2\0".to_vec()),
+            (EV_IPL, b"/initrd.img-6.2\0".to_vec()),
+        ]);
+        measured(&mut grub, 9, cmdline.as_bytes());
+        measured(&mut grub, 10, b"kernel image");
+        let manifest = manifest(&grub).unwrap();
+        let kinds: Vec<ComponentKind> = manifest.components.iter().map(|c| c.kind).collect();
+        assert_eq!(
+            kinds,
+            [
+                ComponentKind::FirmwareVolume,
+                ComponentKind::TdHob,
+                ComponentKind::AcpiTables,
+                ComponentKind::Application,
+                ComponentKind::Shim,
+                ComponentKind::Grub,
+                ComponentKind::Kernel,
+                ComponentKind::Initrd,
+            ]
+        );
+        assert_eq!(manifest.components[0].name, "CFV");
+        let kernel = manifest.kernel().unwrap();
+        assert_eq!(kernel.name, "/vmlinuz-6.2");
+        assert_eq!(
+            kernel.digest(TPM_ALG_SHA384),
+            hash(TPM_ALG_SHA384, &[b"kernel image"]).as_deref()
+        );
+        assert_eq!(manifest.initrd().unwrap().offset, 11);
+        assert_eq!(manifest.kernel_cmdline.as_deref(), Some(cmdline));
+        assert_eq!(ComponentKind::TdHob.to_string(), "td_hob");
+
+        // a command line that does not match its digests is rejected
+        grub.events[9].data = b"kernel_cmdline: BOOT_IMAGE=/vmlinuz-6.2 ro\0".to_vec();
+        assert_eq!(
+            super::manifest(&grub),
+            Err(DecodeError {
+                offset: 9,
+                event_type: EV_IPL,
+                reason: "digests do not match the command line"
+            })
+        );
+    }
+
+    #[test]
+    //a direct boot takes the kernel, and the initrd and load options measured
+    //by the EFI stub
+    fn direct_boot() {
+        let options = utf16le("console=hvc0 ima_policy=critical_data\0");
+        let mut direct = log(vec![
+            (
+                EV_EFI_PLATFORM_FIRMWARE_BLOB,
+                [0x1000u64, 0x2000u64].map(u64::to_le_bytes).concat(),
+            ),
+            (EV_EFI_BOOT_SERVICES_APPLICATION, image_load("kernel")),
+            (
+                EV_EVENT_TAG,
+                tagged(LINUX_EFI_INITRD_EVENT_TAG_ID, "Linux initrd"),
+            ),
+            (
+                EV_EVENT_TAG,
+                tagged(LINUX_EFI_LOAD_OPTIONS_EVENT_TAG_ID, LOAD_OPTIONS),
+            ),
+        ]);
+        measured(&mut direct, 2, b"initrd image");
+        measured(&mut direct, 3, &options);
+        let manifest = manifest(&direct).unwrap();
+        assert_eq!(manifest.components[0].name, "0x1000+0x2000");
+        assert_eq!(manifest.kernel().unwrap().name, "kernel");
+        assert_eq!(manifest.initrd().unwrap().name, "Linux initrd");
+        assert_eq!(manifest.initrd().unwrap().offset, 2);
+        let load_options = manifest
+            .components_of(ComponentKind::LoadOptions)
+            .next()
+            .unwrap();
+        assert_eq!(
+            load_options.digest(TPM_ALG_SHA384),
+            hash(TPM_ALG_SHA384, &[&options]).as_deref()
+        );
+        assert_eq!(manifest.kernel_cmdline, None);
+
+        let truncated = log(vec![(EV_EFI_PLATFORM_FIRMWARE_BLOB, vec![0; 8])]);
+        assert_eq!(
+            super::manifest(&truncated),
+            Err(DecodeError {
+                offset: 0,
+                event_type: EV_EFI_PLATFORM_FIRMWARE_BLOB,
+                reason: "truncated"
+            })
+        );
+    }
+}
@@ -0,0 +1,213 @@
+/*
+* Copyright (c) 2023, Intel Corporation. All rights reserved.<BR>
+* SPDX-License-Identifier: Apache-2.0
+*/
+
+// Merkle tree used by quote-server to generate one quote for a batch of
+// requests. The tree follows RFC 9162 section 2.1 with SHA-512 as hash
+// function, so the root fits the 64 bytes TDX report data:
+//   leaf hash = SHA-512(0x00 || leaf)
+//   node hash = SHA-512(0x01 || left || right)
+// A request is proven part of the batch by its leaf index, the batch size and
+// the audit path from its leaf up to the root.
+
+use sha2::{Digest as _, Sha512};
+
+pub const DIGEST_LEN: usize = 64;
+
+pub type Digest = [u8; DIGEST_LEN];
+
+const LEAF_PREFIX: u8 = 0x00;
+const NODE_PREFIX: u8 = 0x01;
+
+pub fn leaf_hash(leaf: &[u8]) -> Digest {
+    let mut hasher = Sha512::new();
+    hasher.update([LEAF_PREFIX]);
+    hasher.update(leaf);
+    hasher.finalize().into()
+}
+
+fn node_hash(left: &Digest, right: &Digest) -> Digest {
+    let mut hasher = Sha512::new();
+    hasher.update([NODE_PREFIX]);
+    hasher.update(left);
+    hasher.update(right);
+    hasher.finalize().into()
+}
+
+// Largest power of two smaller than n, for n > 1
+fn split_point(n: usize) -> usize {
+    let mut k = 1;
+    while k << 1 < n {
+        k <<= 1;
+    }
+    k
+}
+
+fn subtree_hash<L: AsRef<[u8]>>(leaves: &[L]) -> Digest {
+    if leaves.len() == 1 {
+        return leaf_hash(leaves[0].as_ref());
+    }
+    let k = split_point(leaves.len());
+    node_hash(&subtree_hash(&leaves[..k]), &subtree_hash(&leaves[k..]))
+}
+
+// Root of the tree over `leaves`, None for an empty tree
+pub fn root<L: AsRef<[u8]>>(leaves: &[L]) -> Option<Digest> {
+    if leaves.is_empty() {
+        return None;
+    }
+    Some(subtree_hash(leaves))
+}
+
+// Audit path of the leaf at `index`, ordered from the leaf up to the root.
+// None if `index` is out of range.
+pub fn inclusion_proof<L: AsRef<[u8]>>(leaves: &[L], index: usize) -> Option<Vec<Digest>> {
+    if index >= leaves.len() {
+        return None;
+    }
+    let mut path = Vec::new();
+    let mut leaves = leaves;
+    let mut index = index;
+    // collect siblings from the root down, then reverse
+    while leaves.len() > 1 {
+        let k = split_point(leaves.len());
+        if index < k {
+            path.push(subtree_hash(&leaves[k..]));
+            leaves = &leaves[..k];
+        } else {
+            path.push(subtree_hash(&leaves[..k]));
+            leaves = &leaves[k..];
+            index -= k;
+        }
+    }
+    path.reverse();
+    Some(path)
+}
+
+// Check that `leaf` is the leaf at `index` of a tree of `size` leaves with
+// the given `root`, following RFC 9162 section 2.1.3.2
+pub fn verify_inclusion(
+    leaf: &[u8],
+    index: u64,
+    size: u64,
+    proof: &[Digest],
+    root: &Digest,
+) -> bool {
+    if index >= size {
+        return false;
+    }
+    let mut fn_ = index;
+    let mut sn = size - 1;
+    let mut r = leaf_hash(leaf);
+    for p in proof {
+        if sn == 0 {
+            return false;
+        }
+        if fn_ & 1 == 1 || fn_ == sn {
+            r = node_hash(p, &r);
+            if fn_ & 1 == 0 {
+                while fn_ & 1 == 0 && fn_ != 0 {
+                    fn_ >>= 1;
+                    sn >>= 1;
+                }
+            }
+        } else {
+            r = node_hash(&r, p);
+        }
+        fn_ >>= 1;
+        sn >>= 1;
+    }
+    sn == 0 && &r == root
+}
+
+#[cfg(test)]
+mod merkle_tests {
+    use super::*;
+
+    fn leaves(n: usize) -> Vec<Vec<u8>> {
+        (0..n).map(|i| vec![i as u8; 64]).collect()
+    }
+
+    #[test]
+    //root of a single leaf tree is its leaf hash
+    fn root_single_leaf() {
+        let l = leaves(1);
+        assert_eq!(root(&l).unwrap(), leaf_hash(&l[0]));
+        assert!(inclusion_proof(&l, 0).unwrap().is_empty());
+    }
+
+    #[test]
+    //root of an empty tree is undefined
+    fn root_empty() {
+        let l: Vec<Vec<u8>> = Vec::new();
+        assert!(root(&l).is_none());
+    }
+
+    #[test]
+    //root of three leaves splits at the largest power of two
+    fn root_three_leaves() {
+        let l = leaves(3);
+        let left = node_hash(&leaf_hash(&l[0]), &leaf_hash(&l[1]));
+        let expected = node_hash(&left, &leaf_hash(&l[2]));
+        assert_eq!(root(&l).unwrap(), expected);
+    }
+
+    #[test]
+    //every leaf verifies against the root for various tree sizes
+    fn verify_all_leaves() {
+        for n in 1..=33 {
+            let l = leaves(n);
+            let r = root(&l).unwrap();
+            for i in 0..n {
+                let proof = inclusion_proof(&l, i).unwrap();
+                assert!(
+                    verify_inclusion(&l[i], i as u64, n as u64, &proof, &r),
+                    "leaf {} of {}",
+                    i,
+                    n
+                );
+            }
+        }
+    }
+
+    #[test]
+    //verification fails for a wrong leaf, index, size or root
+    fn verify_rejects_mismatch() {
+        let l = leaves(5);
+        let r = root(&l).unwrap();
+        let proof = inclusion_proof(&l, 2).unwrap();
+        assert!(!verify_inclusion(&l[3], 2, 5, &proof, &r));
+        assert!(!verify_inclusion(&l[2], 3, 5, &proof, &r));
+        assert!(!verify_inclusion(&l[2], 2, 9, &proof, &r));
+        assert!(!verify_inclusion(&l[2], 2, 5, &proof, &[0; DIGEST_LEN]));
+        assert!(!verify_inclusion(&l[2], 5, 5, &proof, &r));
+    }
+
+    #[test]
+    //verification fails for a truncated or extended proof
+    fn verify_rejects_wrong_proof_length() {
+        let l = leaves(8);
+        let r = root(&l).unwrap();
+        let mut proof = inclusion_proof(&l, 6).unwrap();
+        assert!(!verify_inclusion(&l[6], 6, 8, &proof[..2], &r));
+        proof.push([0; DIGEST_LEN]);
+        assert!(!verify_inclusion(&l[6], 6, 8, &proof, &r));
+    }
+
+    #[test]
+    //leaf and node hashes are domain separated
+    fn leaf_and_node_hash_differ() {
+        let a = [1u8; DIGEST_LEN];
+        let b = [2u8; DIGEST_LEN];
+        let mut concat = a.to_vec();
+        concat.extend_from_slice(&b);
+        assert_ne!(leaf_hash(&concat), node_hash(&a, &b));
+    }
+
+    #[test]
+    //out of range index has no proof
+    fn proof_out_of_range() {
+        assert!(inclusion_proof(&leaves(4), 4).is_none());
+    }
+}
@@ -0,0 +1,18 @@
+/*
+* Copyright (c) 2023, Intel Corporation. All rights reserved.<BR>
+* SPDX-License-Identifier: Apache-2.0
+*/
+
+pub mod binding;
+pub mod cel;
+pub mod evidence;
+pub mod identity;
+pub mod ima;
+pub mod manifest;
+pub mod merkle;
+pub mod replay;
+pub mod runtime;
+pub mod secureboot;
+pub mod tcg;
+pub mod tdx;
+pub mod watch;
@@ -0,0 +1,263 @@
+/*
+* Copyright (c) 2023, Intel Corporation. All rights reserved.<BR>
+* SPDX-License-Identifier: Apache-2.0
+*/
+
+// Replay of event logs against the RTMRs of a TD.
+//
+// An RTMR starts at zero and is extended with the SHA-384 digest of each
+// event as RTMR = SHA-384(RTMR | digest). Events are mapped to RTMRs by the
+// CC measurement register index they carry in the log: index 0 is MRTD,
+// measured by the TDX module when the TD is built, and indexes 1 to 4 are
+// RTMR0 to RTMR3. MRTD events and EV_NO_ACTION events are not replayed.
+//
+// For an RTMR that does not match, the replay locates the first event the
+// RTMR does not reflect: the event following the prefix of its events
+// whose replay gives the RTMR, e.g. an event logged but never
+// extended. If no prefix matches, the log diverges at the first event of the
+// RTMR.
+
+use crate::tcg::{EventLog, EV_NO_ACTION, TPM_ALG_SHA384};
+use crate::tdx::{MEASUREMENT_LEN, RTMR_COUNT};
+use sha2::{Digest as _, Sha384};
+use std::fmt;
+
+// Position of an event: the index of its log in the replayed logs and its
+// index in that log
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct EventPosition {
+    pub log: usize,
+    pub event: usize,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct RtmrReplay {
+    pub value: [u8; MEASUREMENT_LEN],
+    pub matches: bool,
+    // Number of events extended into the RTMR
+    pub events: usize,
+    // First event the RTMR does not reflect, None if it matches or has no
+    // events
+    pub divergence: Option<EventPosition>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Replay {
+    pub rtmrs: Vec<RtmrReplay>,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum ReplayError {
+    InvalidIndex { position: EventPosition, index: u32 },
+    MissingDigest { position: EventPosition },
+    InvalidRtmr { rtmr: usize },
+}
+
+impl fmt::Display for ReplayError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReplayError::InvalidIndex { position, index } => write!(
+                f,
+                "event {} of log {} has no RTMR for index {}",
+                position.event, position.log, index
+            ),
+            ReplayError::MissingDigest { position } => write!(
+                f,
+                "event {} of log {} has no SHA-384 digest",
+                position.event, position.log
+            ),
+            ReplayError::InvalidRtmr { rtmr } => {
+                write!(f, "RTMR{} is not {} bytes", rtmr, MEASUREMENT_LEN)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ReplayError {}
+
+impl Replay {
+    pub fn matches(&self) -> bool {
+        self.rtmrs.iter().all(|rtmr| rtmr.matches)
+    }
+}
+
+// RTMR of a CC measurement register index, None for MRTD and the index
+// itself as error if it names no register
+pub fn rtmr_index(cc_mr_index: u32) -> Result<Option<usize>, u32> {
+    match cc_mr_index {
+        0 => Ok(None),
+        i if (i as usize) <= RTMR_COUNT => Ok(Some(i as usize - 1)),
+        i => Err(i),
+    }
+}
+
+pub fn extend(rtmr: &[u8; MEASUREMENT_LEN], digest: &[u8]) -> [u8; MEASUREMENT_LEN] {
+    Sha384::new()
+        .chain_update(rtmr)
+        .chain_update(digest)
+        .finalize()
+        .into()
+}
+
+// Replay the events of `logs`, in order, and compare the results with the
+// RTMRs of a TDREPORT or quote
+pub fn replay(logs: &[&EventLog], rtmrs: [&[u8]; RTMR_COUNT]) -> Result<Replay, ReplayError> {
+    for (rtmr, value) in rtmrs.iter().enumerate() {
+        if value.len() != MEASUREMENT_LEN {
+            return Err(ReplayError::InvalidRtmr { rtmr });
+        }
+    }
+
+    // digests extended into each RTMR with their position
+    let mut extends: Vec<Vec<(EventPosition, &[u8])>> = vec![Vec::new(); RTMR_COUNT];
+    for (log_index, log) in logs.iter().enumerate() {
+        for (event_index, event) in log.events.iter().enumerate() {
+            let position = EventPosition {
+                log: log_index,
+                event: event_index,
+            };
+            if event.event_type == EV_NO_ACTION {
+                continue;
+            }
+            let rtmr = match rtmr_index(event.index) {
+                Ok(Some(rtmr)) => rtmr,
+                Ok(None) => continue,
+                Err(index) => return Err(ReplayError::InvalidIndex { position, index }),
+            };
+            let digest = event
+                .digest(TPM_ALG_SHA384)
+                .filter(|d| d.len() == MEASUREMENT_LEN)
+                .ok_or(ReplayError::MissingDigest { position })?;
+            extends[rtmr].push((position, digest));
+        }
+    }
+
+    let rtmrs = extends
+        .iter()
+        .zip(rtmrs)
+        .map(|(extends, expected)| {
+            let mut value = [0; MEASUREMENT_LEN];
+            // number of events whose replay first gave the expected value
+            let mut reflected = (value[..] == *expected).then_some(0);
+            for (count, (_, digest)) in extends.iter().enumerate() {
+                value = extend(&value, digest);
+                if reflected.is_none() && value[..] == *expected {
+                    reflected = Some(count + 1);
+                }
+            }
+            let matches = value[..] == *expected;
+            let divergence = match (matches, reflected) {
+                (true, _) => None,
+                (false, Some(count)) => extends.get(count).map(|(position, _)| *position),
+                (false, None) => extends.first().map(|(position, _)| *position),
+            };
+            RtmrReplay {
+                value,
+                matches,
+                events: extends.len(),
+                divergence,
+            }
+        })
+        .collect();
+    Ok(Replay { rtmrs })
+}
+
+#[cfg(test)]
+mod replay_tests {
+    use super::*;
+    use crate::tcg::{AlgorithmSize, Event, EventDigest, SpecId, EV_SEPARATOR};
+
+    fn log(events: &[(u32, u32, u8)]) -> EventLog {
+        EventLog {
+            spec_id: SpecId {
+                platform_class: 0,
+                spec_version: (0, 2, 0),
+                uintn_size: 2,
+                algorithms: vec![AlgorithmSize {
+                    algorithm: TPM_ALG_SHA384,
+                    digest_size: MEASUREMENT_LEN as u16,
+                }],
+                vendor_info: vec![],
+            },
+            events: events
+                .iter()
+                .map(|(index, event_type, digest)| Event {
+                    offset: 0,
+                    index: *index,
+                    event_type: *event_type,
+                    digests: vec![EventDigest {
+                        algorithm: TPM_ALG_SHA384,
+                        digest: vec![*digest; MEASUREMENT_LEN],
+                    }],
+                    data: vec![],
+                })
+                .collect(),
+            len: 0,
+        }
+    }
+
+    fn replayed(digests: &[u8]) -> [u8; MEASUREMENT_LEN] {
+        digests.iter().fold([0; MEASUREMENT_LEN], |rtmr, digest| {
+            extend(&rtmr, &[*digest; MEASUREMENT_LEN])
+        })
+    }
+
+    #[test]
+    //events are replayed per RTMR across logs, MRTD and EV_NO_ACTION are skipped
+    fn replay_logs() {
+        let boot = log(&[
+            (0, EV_SEPARATOR, 9),
+            (1, EV_SEPARATOR, 1),
+            (1, EV_NO_ACTION, 9),
+            (3, EV_SEPARATOR, 2),
+            (1, EV_SEPARATOR, 3),
+        ]);
+        let runtime = log(&[(4, EV_SEPARATOR, 4), (4, EV_SEPARATOR, 5)]);
+        let zero = [0; MEASUREMENT_LEN];
+        let rtmrs = [replayed(&[1, 3]), zero, replayed(&[2]), replayed(&[4, 5])];
+        let replay = replay(
+            &[&boot, &runtime],
+            [&rtmrs[0], &rtmrs[1], &rtmrs[2], &rtmrs[3]],
+        )
+        .unwrap();
+        assert!(replay.matches());
+        assert_eq!(replay.rtmrs[0].events, 2);
+        assert_eq!(replay.rtmrs[1].events, 0);
+        assert_eq!(replay.rtmrs[3].value, rtmrs[3]);
+    }
+
+    #[test]
+    //a mismatch is located at the first event the RTMR does not reflect
+    fn locate_divergence() {
+        let boot = log(&[(1, EV_SEPARATOR, 1)]);
+        let runtime = log(&[(4, EV_SEPARATOR, 4), (4, EV_SEPARATOR, 5)]);
+        let zero = [0; MEASUREMENT_LEN];
+        let logged_not_extended = replayed(&[4]);
+        let unknown = replayed(&[7]);
+        let rtmrs = [&unknown[..], &zero, &zero, &logged_not_extended];
+        let replay = replay(&[&boot, &runtime], rtmrs).unwrap();
+        assert!(!replay.matches());
+        assert_eq!(
+            replay.rtmrs[0].divergence,
+            Some(EventPosition { log: 0, event: 0 })
+        );
+        assert!(replay.rtmrs[1].matches);
+        assert_eq!(
+            replay.rtmrs[3].divergence,
+            Some(EventPosition { log: 1, event: 1 })
+        );
+
+        let invalid = log(&[(5, EV_SEPARATOR, 1)]);
+        assert_eq!(
+            super::replay(&[&invalid], [&zero, &zero, &zero, &zero]),
+            Err(ReplayError::InvalidIndex {
+                position: EventPosition { log: 0, event: 0 },
+                index: 5
+            })
+        );
+        assert_eq!(
+            super::replay(&[], [&zero, &zero, &zero, &zero[1..]]),
+            Err(ReplayError::InvalidRtmr { rtmr: 3 })
+        );
+    }
+}
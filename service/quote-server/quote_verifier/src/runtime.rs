@@ -0,0 +1,276 @@
+/*
+* Copyright (c) 2023, Intel Corporation. All rights reserved.<BR>
+* SPDX-License-Identifier: Apache-2.0
+*/
+
+// Runtime events quote-server extends into RTMR3 and records in its runtime
+// event log.
+//
+// The log uses the TCG PC Client crypto agile format with SHA-384 as the
+// only algorithm: a TCG_PCR_EVENT carrying the Spec ID Event03, followed by
+// one TCG_PCR_EVENT2 per event. As in the TDX CCEL, the register index of an
+// event is the RTMR index plus one, and all integers are little-endian. Each
+// event is of type EV_EVENT_TAG with a TCG_PCClientTaggedEvent of ID "CCNP"
+// as event data, whose tagged data are the length-prefixed fields
+//   "ccnp.runtime-event.v1" | domain | event type | content
+// The digest extended into the RTMR is SHA-384 of the event data.
+
+use crate::binding::length_prefixed;
+use crate::tcg::SPEC_ID_SIGNATURE;
+pub use crate::tcg::{EV_EVENT_TAG, EV_NO_ACTION, TPM_ALG_SHA384};
+use sha2::{Digest as _, Sha384};
+use std::fmt;
+
+// RTMR reserved for runtime events of workloads
+pub const RUNTIME_RTMR: u8 = 3;
+pub const SHA384_LEN: usize = 48;
+
+// taggedEventID of runtime events, "CCNP" read as big-endian integer
+pub const RUNTIME_EVENT_ID: u32 = 0x43434e50;
+
+const RUNTIME_EVENT_TAG: &[u8] = b"ccnp.runtime-event.v1";
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct RuntimeEvent {
+    pub domain: String,
+    pub event_type: String,
+    pub content: Vec<u8>,
+}
+
+// Event of a runtime event log together with where it was extended
+#[derive(Clone, Debug, PartialEq)]
+pub struct LoggedEvent {
+    pub rtmr: u8,
+    pub digest: [u8; SHA384_LEN],
+    pub event: RuntimeEvent,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum LogError {
+    Truncated { offset: usize },
+    InvalidHeader,
+    InvalidEvent { offset: usize, reason: &'static str },
+}
+
+impl fmt::Display for LogError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LogError::Truncated { offset } => write!(f, "event log truncated at {}", offset),
+            LogError::InvalidHeader => write!(f, "event log has no SHA-384 Spec ID event"),
+            LogError::InvalidEvent { offset, reason } => {
+                write!(f, "invalid event at {}: {}", offset, reason)
+            }
+        }
+    }
+}
+
+impl std::error::Error for LogError {}
+
+impl RuntimeEvent {
+    // TCG_PCClientTaggedEvent recorded as event data
+    pub fn event_data(&self) -> Vec<u8> {
+        let tagged = length_prefixed(&[
+            RUNTIME_EVENT_TAG,
+            self.domain.as_bytes(),
+            self.event_type.as_bytes(),
+            &self.content,
+        ]);
+        let mut data = Vec::with_capacity(8 + tagged.len());
+        data.extend_from_slice(&RUNTIME_EVENT_ID.to_le_bytes());
+        data.extend_from_slice(&(tagged.len() as u32).to_le_bytes());
+        data.extend_from_slice(&tagged);
+        data
+    }
+
+    // Digest extended into the RTMR
+    pub fn digest(&self) -> [u8; SHA384_LEN] {
+        Sha384::digest(self.event_data()).into()
+    }
+
+    // TCG_PCR_EVENT2 of the event extended into `rtmr`
+    pub fn log_record(&self, rtmr: u8) -> Vec<u8> {
+        let data = self.event_data();
+        let mut record = Vec::with_capacity(66 + data.len());
+        record.extend_from_slice(&(rtmr as u32 + 1).to_le_bytes());
+        record.extend_from_slice(&EV_EVENT_TAG.to_le_bytes());
+        record.extend_from_slice(&1u32.to_le_bytes());
+        record.extend_from_slice(&TPM_ALG_SHA384.to_le_bytes());
+        record.extend_from_slice(&Sha384::digest(&data));
+        record.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        record.extend_from_slice(&data);
+        record
+    }
+}
+
+// TCG_PCR_EVENT with the Spec ID Event03 declaring SHA-384, the first record
+// of every runtime event log
+pub fn log_header() -> Vec<u8> {
+    let mut spec_id = Vec::new();
+    spec_id.extend_from_slice(SPEC_ID_SIGNATURE);
+    spec_id.extend_from_slice(&0u32.to_le_bytes()); // platformClass
+    spec_id.extend_from_slice(&[0, 2, 0, 2]); // version 2.0, errata 0, UINTN of 8 bytes
+    spec_id.extend_from_slice(&1u32.to_le_bytes()); // numberOfAlgorithms
+    spec_id.extend_from_slice(&TPM_ALG_SHA384.to_le_bytes());
+    spec_id.extend_from_slice(&(SHA384_LEN as u16).to_le_bytes());
+    spec_id.push(0); // vendorInfoSize
+
+    let mut header = Vec::new();
+    header.extend_from_slice(&0u32.to_le_bytes());
+    header.extend_from_slice(&EV_NO_ACTION.to_le_bytes());
+    header.extend_from_slice(&[0; 20]);
+    header.extend_from_slice(&(spec_id.len() as u32).to_le_bytes());
+    header.extend_from_slice(&spec_id);
+    header
+}
+
+// Events of a runtime event log written by quote-server, checking that each
+// digest matches its event data
+pub fn parse_log(log: &[u8]) -> Result<Vec<LoggedEvent>, LogError> {
+    let header = log_header();
+    if log.len() < header.len() {
+        return Err(LogError::Truncated { offset: log.len() });
+    }
+    if log[..header.len()] != header[..] {
+        return Err(LogError::InvalidHeader);
+    }
+
+    let mut reader = Reader {
+        data: log,
+        offset: header.len(),
+    };
+    let mut events = Vec::new();
+    while reader.offset < log.len() {
+        let start = reader.offset;
+        let invalid = |reason| LogError::InvalidEvent {
+            offset: start,
+            reason,
+        };
+        let index = reader.u32()?;
+        if !(1..=4).contains(&index) {
+            return Err(invalid("not an RTMR"));
+        }
+        if reader.u32()? != EV_EVENT_TAG {
+            return Err(invalid("not an EV_EVENT_TAG event"));
+        }
+        if reader.u32()? != 1 || reader.u16()? != TPM_ALG_SHA384 {
+            return Err(invalid("digest is not a single SHA-384 digest"));
+        }
+        let digest: [u8; SHA384_LEN] = reader.take(SHA384_LEN)?.try_into().unwrap();
+        let size = reader.u32()? as usize;
+        let data = reader.take(size)?;
+        if <[u8; SHA384_LEN]>::from(Sha384::digest(data)) != digest {
+            return Err(invalid("digest does not match the event data"));
+        }
+        let event = decode_event(data).ok_or_else(|| invalid("not a runtime event"))?;
+        events.push(LoggedEvent {
+            rtmr: (index - 1) as u8,
+            digest,
+            event,
+        });
+    }
+    Ok(events)
+}
+
+fn decode_event(data: &[u8]) -> Option<RuntimeEvent> {
+    let mut reader = Reader { data, offset: 0 };
+    if reader.u32().ok()? != RUNTIME_EVENT_ID {
+        return None;
+    }
+    let size = reader.u32().ok()? as usize;
+    let mut tagged = Reader {
+        data: reader.take(size).ok()?,
+        offset: 0,
+    };
+    if reader.offset != data.len() {
+        return None;
+    }
+    let mut fields = Vec::new();
+    while tagged.offset < tagged.data.len() {
+        let len = u32::from_be_bytes(tagged.take(4).ok()?.try_into().ok()?) as usize;
+        fields.push(tagged.take(len).ok()?);
+    }
+    match fields[..] {
+        [tag, domain, event_type, content] if tag == RUNTIME_EVENT_TAG => Some(RuntimeEvent {
+            domain: String::from_utf8(domain.to_vec()).ok()?,
+            event_type: String::from_utf8(event_type.to_vec()).ok()?,
+            content: content.to_vec(),
+        }),
+        _ => None,
+    }
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], LogError> {
+        let end = self
+            .offset
+            .checked_add(len)
+            .filter(|end| *end <= self.data.len())
+            .ok_or(LogError::Truncated {
+                offset: self.offset,
+            })?;
+        let bytes = &self.data[self.offset..end];
+        self.offset = end;
+        Ok(bytes)
+    }
+
+    fn u32(&mut self) -> Result<u32, LogError> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn u16(&mut self) -> Result<u16, LogError> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into().unwrap()))
+    }
+}
+
+#[cfg(test)]
+mod runtime_tests {
+    use super::*;
+
+    fn event(content: &[u8]) -> RuntimeEvent {
+        RuntimeEvent {
+            domain: "example.com".to_string(),
+            event_type: "model".to_string(),
+            content: content.to_vec(),
+        }
+    }
+
+    #[test]
+    //logged events are parsed back with their RTMR and digest
+    fn log_round_trip() {
+        let mut log = log_header();
+        log.extend(event(b"a").log_record(RUNTIME_RTMR));
+        log.extend(event(b"b").log_record(2));
+        let events = parse_log(&log).unwrap();
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0].rtmr, RUNTIME_RTMR);
+        assert_eq!(events[0].digest, event(b"a").digest());
+        assert_eq!(events[0].event, event(b"a"));
+        assert_eq!(events[1].rtmr, 2);
+        assert_eq!(parse_log(&log_header()).unwrap(), vec![]);
+        // the register index is the RTMR index plus one, as in the CCEL
+        assert_eq!(&event(b"a").log_record(RUNTIME_RTMR)[..4], &[4, 0, 0, 0]);
+    }
+
+    #[test]
+    //truncated logs, foreign headers and altered events are rejected
+    fn invalid_logs() {
+        let mut log = log_header();
+        log.extend(event(b"a").log_record(RUNTIME_RTMR));
+        assert!(matches!(
+            parse_log(&log[..log.len() - 1]),
+            Err(LogError::Truncated { .. })
+        ));
+        assert_eq!(parse_log(&log[4..]), Err(LogError::InvalidHeader));
+        let last = log.len() - 1;
+        log[last] ^= 1;
+        assert!(matches!(
+            parse_log(&log),
+            Err(LogError::InvalidEvent { .. })
+        ));
+    }
+}
@@ -0,0 +1,609 @@
+/*
+* Copyright (c) 2023, Intel Corporation. All rights reserved.<BR>
+* SPDX-License-Identifier: Apache-2.0
+*/
+
+// Decoding of the UEFI Secure Boot events of a boot event log into a Secure
+// Boot posture. See the TCG PC Client Platform Firmware Profile, section 10.4
+// and the UEFI specification, section 32.
+//
+// EV_EFI_VARIABLE_DRIVER_CONFIG and EV_EFI_VARIABLE_AUTHORITY events carry a
+// UEFI_VARIABLE_DATA:
+//   variable GUID (16) | name length in characters (8) | data length (8) |
+//   UTF-16LE name | data
+// The data of SecureBoot is a single byte, 1 if Secure Boot is enforced. PK,
+// KEK, db and dbx hold EFI_SIGNATURE_LISTs:
+//   signature type GUID (16) | list size (4) | header size (4) | signature
+//   size (4) | header | signatures
+// where each EFI_SIGNATURE_DATA is an owner GUID (16) followed by the
+// signature data, e.g. a DER certificate or a SHA-256 digest. An authority
+// event is logged the first time an entry verifies an image: its data are
+// the EFI_SIGNATURE_DATA of the db or MokList entry. shim also logs
+// authorities of other forms, e.g. Shim with its built-in vendor certificate
+// in DER, MokListTrusted with one byte and SbatLevel with CSV text.
+//
+// The digests of variable and authority events are of their
+// UEFI_VARIABLE_DATA, and events whose data do not match their digests are
+// rejected, so that the posture is covered by the registers the log replays
+// to.
+//
+// EV_EFI_BOOT_SERVICES_APPLICATION events carry a UEFI_IMAGE_LOAD_EVENT:
+//   image address (8) | image length (8) | link time address (8) | device
+//   path length (8) | device path
+// All integers are little-endian.
+
+use crate::tcg::{
+    u32_at, u64_at, EventLog, EV_EFI_BOOT_SERVICES_APPLICATION, EV_EFI_VARIABLE_AUTHORITY,
+    EV_EFI_VARIABLE_DRIVER_CONFIG,
+};
+use sha2::{Digest as _, Sha256};
+use std::fmt;
+
+// GUID in its in-memory layout, printed in the registry format
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Guid(pub [u8; 16]);
+
+// Variables of the UEFI global variable GUID, e.g. SecureBoot, PK and KEK
+pub const EFI_GLOBAL_VARIABLE: Guid = Guid([
+    0x61, 0xdf, 0xe4, 0x8b, 0xca, 0x93, 0xd2, 0x11, 0xaa, 0x0d, 0x00, 0xe0, 0x98, 0x03, 0x2b, 0x8c,
+]);
+// Variables of the image security database, db and dbx
+pub const EFI_IMAGE_SECURITY_DATABASE: Guid = Guid([
+    0xcb, 0xb2, 0x19, 0xd7, 0x3a, 0x3d, 0x96, 0x45, 0xa3, 0xbc, 0xda, 0xd0, 0x0e, 0x67, 0x65, 0x6f,
+]);
+// Variables of shim, e.g. MokList and Shim
+pub const SHIM_LOCK: Guid = Guid([
+    0x50, 0xab, 0x5d, 0x60, 0x46, 0xe0, 0x00, 0x43, 0xab, 0xb6, 0x3d, 0xd8, 0x10, 0xdd, 0x8b, 0x23,
+]);
+// Signature types
+pub const EFI_CERT_X509: Guid = Guid([
+    0xa1, 0x59, 0xc0, 0xa5, 0xe4, 0x94, 0xa7, 0x4a, 0x87, 0xb5, 0xab, 0x15, 0x5c, 0x2b, 0xf0, 0x72,
+]);
+pub const EFI_CERT_SHA256: Guid = Guid([
+    0x26, 0x16, 0xc4, 0xc1, 0x4c, 0x50, 0x92, 0x40, 0xac, 0xa9, 0x41, 0xf9, 0x36, 0x93, 0x43, 0x28,
+]);
+
+const GUID_LEN: usize = 16;
+const SIGNATURE_LIST_HEADER_LEN: usize = GUID_LEN + 12;
+// Device path node of a file path, and the end of a device path
+const MEDIA_DEVICE_PATH: u8 = 4;
+const MEDIA_FILEPATH_DP: u8 = 4;
+const END_DEVICE_PATH: u8 = 0x7f;
+
+impl fmt::Display for Guid {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let g = &self.0;
+        write!(
+            f,
+            "{:08x}-{:04x}-{:04x}-{:02x}{:02x}-",
+            u32::from_le_bytes([g[0], g[1], g[2], g[3]]),
+            u16::from_le_bytes([g[4], g[5]]),
+            u16::from_le_bytes([g[6], g[7]]),
+            g[8],
+            g[9]
+        )?;
+        g[10..].iter().try_for_each(|b| write!(f, "{:02x}", b))
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct DecodeError {
+    // Offset of the event in the log
+    pub offset: usize,
+    pub event_type: u32,
+    pub reason: &'static str,
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "invalid event of type {:#x} at {}: {}",
+            self.event_type, self.offset, self.reason
+        )
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct VariableData {
+    pub guid: Guid,
+    pub name: String,
+    pub data: Vec<u8>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct SignatureData {
+    pub signature_type: Guid,
+    pub owner: Guid,
+    // DER certificate for EFI_CERT_X509, digest for EFI_CERT_SHA256
+    pub data: Vec<u8>,
+}
+
+// An entry that verified a loaded image
+#[derive(Clone, Debug, PartialEq)]
+pub struct Authority {
+    pub guid: Guid,
+    // Variable holding the entry, "db" for images verified by the firmware,
+    // e.g. "Shim" or "MokList" for images verified by shim
+    pub variable: String,
+    // Owner of the EFI_SIGNATURE_DATA of db and MokList entries
+    pub owner: Option<Guid>,
+    // Signature data of db and MokList entries, e.g. a DER certificate, the
+    // data of other variables as logged
+    pub data: Vec<u8>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct LoadedImage {
+    // File path of the image, None if loaded from elsewhere, e.g. a PCI ROM.
+    // The digest of the event is of the image, not of its path.
+    pub path: Option<String>,
+    pub length: u64,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SecureBootPosture {
+    // None if the log does not measure the SecureBoot variable
+    pub enabled: Option<bool>,
+    pub pk: Vec<SignatureData>,
+    pub kek: Vec<SignatureData>,
+    pub db: Vec<SignatureData>,
+    // Number of revoked certificates and digests in dbx
+    pub dbx_revocations: usize,
+    // In the order they were first used
+    pub authorities: Vec<Authority>,
+    // EFI applications loaded, in order
+    pub images: Vec<LoadedImage>,
+}
+
+impl SignatureData {
+    // "sha256:" followed by the hex encoded SHA-256 digest of the data, the
+    // fingerprint of a DER certificate
+    pub fn fingerprint(&self) -> String {
+        fingerprint(&self.data)
+    }
+}
+
+impl Authority {
+    // Whether the data are an EFI_SIGNATURE_DATA: db and MokList entries
+    fn is_signature_data(guid: Guid, variable: &str) -> bool {
+        matches!(
+            (guid, variable),
+            (EFI_IMAGE_SECURITY_DATABASE, "db") | (SHIM_LOCK, "MokList")
+        )
+    }
+
+    pub fn fingerprint(&self) -> String {
+        fingerprint(&self.data)
+    }
+}
+
+impl SecureBootPosture {
+    // Certificates of db and KEK, without digests
+    pub fn certificates(&self) -> impl Iterator<Item = &SignatureData> {
+        self.db
+            .iter()
+            .chain(&self.kek)
+            .filter(|s| s.signature_type == EFI_CERT_X509)
+    }
+
+    // Secure Boot is on and the firmware verified a loaded image, the shim
+    // when booting with shim, with the db certificate of `fingerprint`
+    pub fn secure_boot_signed_by(&self, fingerprint: &str) -> bool {
+        self.enabled == Some(true)
+            && self.authorities.iter().any(|a| {
+                a.guid == EFI_IMAGE_SECURITY_DATABASE
+                    && a.variable == "db"
+                    && a.fingerprint() == fingerprint
+            })
+    }
+}
+
+pub fn fingerprint(data: &[u8]) -> String {
+    let hex: String = Sha256::digest(data)
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect();
+    format!("sha256:{}", hex)
+}
+
+// Secure Boot posture recorded in a boot event log. A variable measured more
+// than once takes its last value.
+pub fn posture(log: &EventLog) -> Result<SecureBootPosture, DecodeError> {
+    let mut posture = SecureBootPosture::default();
+    for event in &log.events {
+        let invalid = |reason| DecodeError {
+            offset: event.offset,
+            event_type: event.event_type,
+            reason,
+        };
+        if matches!(
+            event.event_type,
+            EV_EFI_VARIABLE_DRIVER_CONFIG | EV_EFI_VARIABLE_AUTHORITY
+        ) && !event.measures(&event.data)
+        {
+            return Err(invalid("digests do not match the event data"));
+        }
+        match event.event_type {
+            EV_EFI_VARIABLE_DRIVER_CONFIG => {
+                let variable = decode_variable(&event.data).ok_or_else(|| invalid("truncated"))?;
+                let lists = || {
+                    decode_signature_lists(&variable.data)
+                        .ok_or_else(|| invalid("invalid signature list"))
+                };
+                match (variable.guid, variable.name.as_str()) {
+                    (EFI_GLOBAL_VARIABLE, "SecureBoot") => {
+                        posture.enabled = Some(variable.data.first() == Some(&1));
+                    }
+                    (EFI_GLOBAL_VARIABLE, "PK") => posture.pk = lists()?,
+                    (EFI_GLOBAL_VARIABLE, "KEK") => posture.kek = lists()?,
+                    (EFI_IMAGE_SECURITY_DATABASE, "db") => posture.db = lists()?,
+                    (EFI_IMAGE_SECURITY_DATABASE, "dbx") => {
+                        posture.dbx_revocations = lists()?.len()
+                    }
+                    _ => {}
+                }
+            }
+            EV_EFI_VARIABLE_AUTHORITY => {
+                let variable = decode_variable(&event.data).ok_or_else(|| invalid("truncated"))?;
+                let authority = match Authority::is_signature_data(variable.guid, &variable.name) {
+                    true if variable.data.len() < GUID_LEN => {
+                        return Err(invalid("authority has no signature owner"))
+                    }
+                    true => Authority {
+                        guid: variable.guid,
+                        owner: Some(guid(&variable.data)),
+                        data: variable.data[GUID_LEN..].to_vec(),
+                        variable: variable.name,
+                    },
+                    false => Authority {
+                        guid: variable.guid,
+                        owner: None,
+                        data: variable.data,
+                        variable: variable.name,
+                    },
+                };
+                posture.authorities.push(authority);
+            }
+            EV_EFI_BOOT_SERVICES_APPLICATION => {
+                posture
+                    .images
+                    .push(decode_image_load(&event.data).ok_or_else(|| invalid("truncated"))?);
+            }
+            _ => {}
+        }
+    }
+    Ok(posture)
+}
+
+// UEFI_VARIABLE_DATA of a variable event
+pub fn decode_variable(data: &[u8]) -> Option<VariableData> {
+    let guid_bytes = data.get(..GUID_LEN)?;
+    let name_len = usize::try_from(u64_at(data, GUID_LEN)?).ok()?;
+    let data_len = usize::try_from(u64_at(data, GUID_LEN + 8)?).ok()?;
+    let name_start = GUID_LEN + 16;
+    let name_end = name_start.checked_add(name_len.checked_mul(2)?)?;
+    let name = utf16(data.get(name_start..name_end)?)?;
+    let value = data.get(name_end..name_end.checked_add(data_len)?)?;
+    Some(VariableData {
+        guid: guid(guid_bytes),
+        name,
+        data: value.to_vec(),
+    })
+}
+
+// Signatures of a sequence of EFI_SIGNATURE_LISTs
+pub fn decode_signature_lists(data: &[u8]) -> Option<Vec<SignatureData>> {
+    let mut signatures = Vec::new();
+    let mut offset = 0;
+    while offset < data.len() {
+        let header = data.get(offset..offset + SIGNATURE_LIST_HEADER_LEN)?;
+        let signature_type = guid(header);
+        let list_size = u32_at(header, GUID_LEN)? as usize;
+        let header_size = u32_at(header, GUID_LEN + 4)? as usize;
+        let signature_size = u32_at(header, GUID_LEN + 8)? as usize;
+        let list = data.get(offset..offset.checked_add(list_size)?)?;
+        let start = SIGNATURE_LIST_HEADER_LEN.checked_add(header_size)?;
+        if signature_size <= GUID_LEN || start > list.len() {
+            return None;
+        }
+        let entries = &list[start..];
+        if entries.len() % signature_size != 0 {
+            return None;
+        }
+        for entry in entries.chunks(signature_size) {
+            signatures.push(SignatureData {
+                signature_type,
+                owner: guid(entry),
+                data: entry[GUID_LEN..].to_vec(),
+            });
+        }
+        offset += list_size;
+    }
+    Some(signatures)
+}
+
+// UEFI_IMAGE_LOAD_EVENT of an EV_EFI_BOOT_SERVICES_APPLICATION event
+pub fn decode_image_load(data: &[u8]) -> Option<LoadedImage> {
+    let length = u64_at(data, 8)?;
+    let path_len = usize::try_from(u64_at(data, 24)?).ok()?;
+    let mut path = data.get(32..32usize.checked_add(path_len)?)?;
+    // the file path is the concatenation of the file path nodes
+    let mut file: Option<String> = None;
+    while path.len() >= 4 {
+        let (node_type, sub_type) = (path[0], path[1]);
+        let len = u16::from_le_bytes([path[2], path[3]]) as usize;
+        if node_type == END_DEVICE_PATH || len < 4 || len > path.len() {
+            break;
+        }
+        if node_type == MEDIA_DEVICE_PATH && sub_type == MEDIA_FILEPATH_DP {
+            let name = utf16(&path[4..len])?;
+            file = Some(file.unwrap_or_default() + &name);
+        }
+        path = &path[len..];
+    }
+    Some(LoadedImage { path: file, length })
+}
+
+fn guid(data: &[u8]) -> Guid {
+    Guid(data[..GUID_LEN].try_into().unwrap())
+}
+
+// UTF-16LE string without its terminating NULs
+fn utf16(data: &[u8]) -> Option<String> {
+    let chunks = data.chunks_exact(2);
+    if !chunks.remainder().is_empty() {
+        return None;
+    }
+    let units: Vec<u16> = chunks.map(|c| u16::from_le_bytes([c[0], c[1]])).collect();
+    let name = String::from_utf16(&units).ok()?;
+    Some(name.trim_end_matches('\0').to_string())
+}
+
+#[cfg(test)]
+mod secureboot_tests {
+    use super::*;
+    use crate::tcg::testing::{image_load, log, utf16le};
+
+    fn variable(guid: Guid, name: &str, data: &[u8]) -> Vec<u8> {
+        let mut v = guid.0.to_vec();
+        v.extend_from_slice(&(name.encode_utf16().count() as u64).to_le_bytes());
+        v.extend_from_slice(&(data.len() as u64).to_le_bytes());
+        v.extend(utf16le(name));
+        v.extend_from_slice(data);
+        v
+    }
+
+    fn signature_list(signature_type: Guid, entries: &[&[u8]]) -> Vec<u8> {
+        let size = GUID_LEN + entries[0].len();
+        let mut list = signature_type.0.to_vec();
+        list.extend_from_slice(
+            &((SIGNATURE_LIST_HEADER_LEN + size * entries.len()) as u32).to_le_bytes(),
+        );
+        list.extend_from_slice(&0u32.to_le_bytes());
+        list.extend_from_slice(&(size as u32).to_le_bytes());
+        for entry in entries {
+            list.extend_from_slice(&[0x11; GUID_LEN]);
+            list.extend_from_slice(entry);
+        }
+        list
+    }
+
+    fn signature_data(data: &[u8]) -> Vec<u8> {
+        [&[0x22; GUID_LEN][..], data].concat()
+    }
+
+    #[test]
+    //variables, authorities and images are summarized into the posture
+    fn decode_posture() {
+        let ca: &[u8] = b"DER of our CA";
+        let log = log(vec![
+            (
+                EV_EFI_VARIABLE_DRIVER_CONFIG,
+                variable(EFI_GLOBAL_VARIABLE, "SecureBoot", &[1]),
+            ),
+            (
+                EV_EFI_VARIABLE_DRIVER_CONFIG,
+                variable(
+                    EFI_GLOBAL_VARIABLE,
+                    "KEK",
+                    &signature_list(EFI_CERT_X509, &[b"KEK cert"]),
+                ),
+            ),
+            (
+                EV_EFI_VARIABLE_DRIVER_CONFIG,
+                variable(
+                    EFI_IMAGE_SECURITY_DATABASE,
+                    "db",
+                    &signature_list(EFI_CERT_X509, &[ca]),
+                ),
+            ),
+            (
+                EV_EFI_VARIABLE_DRIVER_CONFIG,
+                variable(
+                    EFI_IMAGE_SECURITY_DATABASE,
+                    "dbx",
+                    &[
+                        signature_list(EFI_CERT_SHA256, &[&[1; 32], &[2; 32]]),
+                        signature_list(EFI_CERT_SHA256, &[&[3; 32]]),
+                    ]
+                    .concat(),
+                ),
+            ),
+            (
+                EV_EFI_VARIABLE_AUTHORITY,
+                variable(EFI_IMAGE_SECURITY_DATABASE, "db", &signature_data(ca)),
+            ),
+            (
+                EV_EFI_BOOT_SERVICES_APPLICATION,
+                image_load("\\EFI\\BOOT\\BOOTX64.EFI"),
+            ),
+        ]);
+        let posture = posture(&log).unwrap();
+        assert_eq!(posture.enabled, Some(true));
+        assert_eq!(posture.kek[0].data, b"KEK cert");
+        assert_eq!(posture.kek[0].owner, Guid([0x11; GUID_LEN]));
+        assert_eq!(posture.certificates().count(), 2);
+        assert_eq!(posture.dbx_revocations, 3);
+        assert_eq!(posture.authorities[0].variable, "db");
+        assert_eq!(posture.authorities[0].owner, Some(Guid([0x22; GUID_LEN])));
+        assert_eq!(posture.authorities[0].data, ca);
+        assert_eq!(
+            posture.images[0].path.as_deref(),
+            Some("\\EFI\\BOOT\\BOOTX64.EFI")
+        );
+        assert_eq!(posture.images[0].length, 0x1000);
+        assert!(posture.secure_boot_signed_by(&fingerprint(ca)));
+        assert!(!posture.secure_boot_signed_by(&fingerprint(b"KEK cert")));
+
+        let disabled = SecureBootPosture {
+            enabled: Some(false),
+            ..posture
+        };
+        assert!(!disabled.secure_boot_signed_by(&fingerprint(ca)));
+    }
+
+    #[test]
+    //authorities logged by shim are kept in their own forms
+    fn shim_authorities() {
+        let ca: &[u8] = b"DER of our CA";
+        let vendor: &[u8] = b"DER of the vendor certificate";
+        let mok: &[u8] = b"DER of a MOK";
+        let log = log(vec![
+            (
+                EV_EFI_VARIABLE_DRIVER_CONFIG,
+                variable(EFI_GLOBAL_VARIABLE, "SecureBoot", &[1]),
+            ),
+            (
+                EV_EFI_VARIABLE_AUTHORITY,
+                variable(EFI_IMAGE_SECURITY_DATABASE, "db", &signature_data(ca)),
+            ),
+            (
+                EV_EFI_BOOT_SERVICES_APPLICATION,
+                image_load("\\EFI\\ubuntu\\shimx64.efi"),
+            ),
+            (
+                EV_EFI_VARIABLE_AUTHORITY,
+                variable(SHIM_LOCK, "SbatLevel", b"sbat,1,2022052400\ngrub,2\n"),
+            ),
+            (
+                EV_EFI_VARIABLE_AUTHORITY,
+                variable(SHIM_LOCK, "MokListTrusted", &[1]),
+            ),
+            (
+                EV_EFI_VARIABLE_AUTHORITY,
+                variable(SHIM_LOCK, "Shim", vendor),
+            ),
+            (
+                EV_EFI_BOOT_SERVICES_APPLICATION,
+                image_load("\\EFI\\ubuntu\\grubx64.efi"),
+            ),
+            (
+                EV_EFI_VARIABLE_AUTHORITY,
+                variable(SHIM_LOCK, "MokList", &signature_data(mok)),
+            ),
+        ]);
+        let posture = posture(&log).unwrap();
+        let authorities: Vec<(&str, Option<Guid>, &[u8])> = posture
+            .authorities
+            .iter()
+            .map(|a| (a.variable.as_str(), a.owner, &a.data[..]))
+            .collect();
+        let owner = Some(Guid([0x22; GUID_LEN]));
+        assert_eq!(
+            authorities,
+            [
+                ("db", owner, ca),
+                ("SbatLevel", None, &b"sbat,1,2022052400\ngrub,2\n"[..]),
+                ("MokListTrusted", None, &[1][..]),
+                ("Shim", None, vendor),
+                ("MokList", owner, mok),
+            ]
+        );
+        assert_eq!(posture.authorities[3].fingerprint(), fingerprint(vendor));
+        assert!(posture.secure_boot_signed_by(&fingerprint(ca)));
+        assert!(!posture.secure_boot_signed_by(&fingerprint(vendor)));
+    }
+
+    #[test]
+    //variable data that do not match their digests are rejected
+    fn tampered_data() {
+        let mut log = log(vec![
+            (
+                EV_EFI_VARIABLE_DRIVER_CONFIG,
+                variable(EFI_GLOBAL_VARIABLE, "SecureBoot", &[0]),
+            ),
+            (
+                EV_EFI_VARIABLE_AUTHORITY,
+                variable(EFI_IMAGE_SECURITY_DATABASE, "db", &signature_data(b"CA")),
+            ),
+        ]);
+        assert_eq!(posture(&log).unwrap().enabled, Some(false));
+
+        let enabled = variable(EFI_GLOBAL_VARIABLE, "SecureBoot", &[1]);
+        log.events[0].data = enabled.clone();
+        let error = DecodeError {
+            offset: 0,
+            event_type: EV_EFI_VARIABLE_DRIVER_CONFIG,
+            reason: "digests do not match the event data",
+        };
+        assert_eq!(posture(&log), Err(error));
+
+        log.events[0].data = variable(EFI_GLOBAL_VARIABLE, "SecureBoot", &[0]);
+        log.events[1].data = variable(
+            EFI_IMAGE_SECURITY_DATABASE,
+            "db",
+            &signature_data(b"our CA"),
+        );
+        assert_eq!(posture(&log).unwrap_err().offset, 1);
+
+        // events without a digest of a supported algorithm are rejected too
+        log.events[1].digests.clear();
+        assert_eq!(posture(&log).unwrap_err().offset, 1);
+    }
+
+    #[test]
+    //GUIDs print in registry format, malformed events are reported
+    fn malformed_events() {
+        assert_eq!(
+            EFI_GLOBAL_VARIABLE.to_string(),
+            "8be4df61-93ca-11d2-aa0d-00e098032b8c"
+        );
+        assert_eq!(
+            EFI_CERT_X509.to_string(),
+            "a5c059a1-94e4-4aa7-87b5-ab155c2bf072"
+        );
+        assert_eq!(
+            SHIM_LOCK.to_string(),
+            "605dab50-e046-4300-abb6-3dd810dd8b23"
+        );
+        let mut truncated = variable(EFI_GLOBAL_VARIABLE, "KEK", &[]);
+        truncated.pop();
+        let bad_list = variable(EFI_GLOBAL_VARIABLE, "KEK", &[0; 8]);
+        let no_owner = variable(EFI_IMAGE_SECURITY_DATABASE, "db", &[0; 8]);
+        for (event_type, data, reason) in [
+            (EV_EFI_VARIABLE_DRIVER_CONFIG, truncated, "truncated"),
+            (
+                EV_EFI_VARIABLE_DRIVER_CONFIG,
+                bad_list,
+                "invalid signature list",
+            ),
+            (
+                EV_EFI_VARIABLE_AUTHORITY,
+                no_owner,
+                "authority has no signature owner",
+            ),
+        ] {
+            assert_eq!(
+                posture(&log(vec![(event_type, data)])),
+                Err(DecodeError {
+                    offset: 0,
+                    event_type,
+                    reason
+                })
+            );
+        }
+    }
+}
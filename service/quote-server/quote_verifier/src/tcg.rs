@@ -0,0 +1,637 @@
+/*
+* Copyright (c) 2023, Intel Corporation. All rights reserved.<BR>
+* SPDX-License-Identifier: Apache-2.0
+*/
+
+// Event logs in the TCG PC Client crypto agile format, as recorded by the
+// firmware in the TDX CCEL or in the TPM log the kernel exposes as
+// binary_bios_measurements. See the TCG PC Client Platform Firmware Profile,
+// section 10.
+//
+// The log starts with a TCG_PCR_EVENT in the SHA-1 format whose event data
+// are the Spec ID Event03, declaring the digest algorithms and their sizes:
+//   PCR index (4) | event type (4) | SHA-1 digest (20) | event size (4) |
+//   signature "Spec ID Event03\0" (16) | platform class (4) | spec version
+//   minor, major, errata (3) | UINTN size (1) | number of algorithms (4) |
+//   algorithm ID (2) and digest size (2) per algorithm | vendor info size
+//   (1) | vendor info
+// followed by one TCG_PCR_EVENT2 per event:
+//   PCR index (4) | event type (4) | digest count (4) | algorithm ID (2) and
+//   digest per digest | event size (4) | event data
+// All integers are little-endian. In the CCEL the register index is the
+// RTMR index plus one, index 0 being MRTD.
+//
+// The CCEL data are the whole log area reserved by the firmware, the unused
+// rest of which is filled with 0xff; TPM logs may be padded with zeros.
+//
+// The CCEL ACPI table locating the log area is:
+//   ACPI header (36) | CC type (1) | CC subtype (1) | reserved (2) |
+//   log area minimum length (8) | log area start address (8)
+
+use sha1::Sha1;
+use sha2::{Sha256, Sha384, Sha512};
+use std::fmt;
+
+// Event types, see the TCG PC Client Platform Firmware Profile, table 9
+pub const EV_PREBOOT_CERT: u32 = 0x0;
+pub const EV_POST_CODE: u32 = 0x1;
+pub const EV_NO_ACTION: u32 = 0x3;
+pub const EV_SEPARATOR: u32 = 0x4;
+pub const EV_ACTION: u32 = 0x5;
+pub const EV_EVENT_TAG: u32 = 0x6;
+pub const EV_S_CRTM_CONTENTS: u32 = 0x7;
+pub const EV_S_CRTM_VERSION: u32 = 0x8;
+pub const EV_CPU_MICROCODE: u32 = 0x9;
+pub const EV_PLATFORM_CONFIG_FLAGS: u32 = 0xa;
+pub const EV_TABLE_OF_DEVICES: u32 = 0xb;
+pub const EV_COMPACT_HASH: u32 = 0xc;
+pub const EV_IPL: u32 = 0xd;
+pub const EV_IPL_PARTITION_DATA: u32 = 0xe;
+pub const EV_NONHOST_CODE: u32 = 0xf;
+pub const EV_NONHOST_CONFIG: u32 = 0x10;
+pub const EV_NONHOST_INFO: u32 = 0x11;
+pub const EV_OMIT_BOOT_DEVICE_EVENTS: u32 = 0x12;
+pub const EV_POST_CODE2: u32 = 0x13;
+pub const EV_EFI_VARIABLE_DRIVER_CONFIG: u32 = 0x80000001;
+pub const EV_EFI_VARIABLE_BOOT: u32 = 0x80000002;
+pub const EV_EFI_BOOT_SERVICES_APPLICATION: u32 = 0x80000003;
+pub const EV_EFI_BOOT_SERVICES_DRIVER: u32 = 0x80000004;
+pub const EV_EFI_RUNTIME_SERVICES_DRIVER: u32 = 0x80000005;
+pub const EV_EFI_GPT_EVENT: u32 = 0x80000006;
+pub const EV_EFI_ACTION: u32 = 0x80000007;
+pub const EV_EFI_PLATFORM_FIRMWARE_BLOB: u32 = 0x80000008;
+pub const EV_EFI_HANDOFF_TABLES: u32 = 0x80000009;
+pub const EV_EFI_PLATFORM_FIRMWARE_BLOB2: u32 = 0x8000000a;
+pub const EV_EFI_HANDOFF_TABLES2: u32 = 0x8000000b;
+pub const EV_EFI_VARIABLE_BOOT2: u32 = 0x8000000c;
+pub const EV_EFI_GPT_EVENT2: u32 = 0x8000000d;
+pub const EV_EFI_HCRTM_EVENT: u32 = 0x80000010;
+pub const EV_EFI_VARIABLE_AUTHORITY: u32 = 0x800000e0;
+pub const EV_EFI_SPDM_FIRMWARE_BLOB: u32 = 0x800000e1;
+pub const EV_EFI_SPDM_FIRMWARE_CONFIG: u32 = 0x800000e2;
+
+// Digest algorithms, see the TCG Algorithm Registry
+pub const TPM_ALG_SHA1: u16 = 0x4;
+pub const TPM_ALG_SHA256: u16 = 0xb;
+pub const TPM_ALG_SHA384: u16 = 0xc;
+pub const TPM_ALG_SHA512: u16 = 0xd;
+pub const TPM_ALG_SM3_256: u16 = 0x12;
+
+pub const SPEC_ID_SIGNATURE: &[u8; 16] = b"Spec ID Event03\0";
+const SHA1_LEN: usize = 20;
+
+pub const CCEL_SIGNATURE: &[u8; 4] = b"CCEL";
+pub const CCEL_TABLE_LEN: usize = 56;
+// CC type of the CCEL table
+pub const CC_TYPE_TDX: u8 = 2;
+
+#[derive(Debug, PartialEq)]
+pub enum ParseError {
+    Truncated { offset: usize },
+    InvalidHeader { reason: &'static str },
+    UnknownAlgorithm { offset: usize, algorithm: u16 },
+    InvalidEvent { offset: usize, reason: &'static str },
+    InvalidTable { reason: &'static str },
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseError::Truncated { offset } => write!(f, "event log truncated at {}", offset),
+            ParseError::InvalidHeader { reason } => {
+                write!(f, "invalid Spec ID event: {}", reason)
+            }
+            ParseError::UnknownAlgorithm { offset, algorithm } => write!(
+                f,
+                "event at {} has a digest of undeclared algorithm {:#x}",
+                offset, algorithm
+            ),
+            ParseError::InvalidEvent { offset, reason } => {
+                write!(f, "invalid event at {}: {}", offset, reason)
+            }
+            ParseError::InvalidTable { reason } => write!(f, "invalid CCEL table: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AlgorithmSize {
+    pub algorithm: u16,
+    pub digest_size: u16,
+}
+
+// The Spec ID Event03 of a log
+#[derive(Clone, Debug, PartialEq)]
+pub struct SpecId {
+    pub platform_class: u32,
+    // minor, major, errata
+    pub spec_version: (u8, u8, u8),
+    pub uintn_size: u8,
+    pub algorithms: Vec<AlgorithmSize>,
+    pub vendor_info: Vec<u8>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct EventDigest {
+    pub algorithm: u16,
+    pub digest: Vec<u8>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Event {
+    // Offset of the event in the log, the record number for logs read from
+    // CEL
+    pub offset: usize,
+    // PCR index, or RTMR index plus one in the CCEL
+    pub index: u32,
+    pub event_type: u32,
+    pub digests: Vec<EventDigest>,
+    pub data: Vec<u8>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct EventLog {
+    pub spec_id: SpecId,
+    // Events following the Spec ID event, in the order they were recorded
+    pub events: Vec<Event>,
+    // Length of the log without its padding
+    pub len: usize,
+}
+
+// The CCEL ACPI table
+#[derive(Clone, Debug, PartialEq)]
+pub struct CcelTable {
+    pub cc_type: u8,
+    pub cc_subtype: u8,
+    // Size of the log area, including its padding
+    pub log_area_length: u64,
+    pub log_area_address: u64,
+}
+
+impl Event {
+    // Digest of the event in the bank of `algorithm`
+    pub fn digest(&self, algorithm: u16) -> Option<&[u8]> {
+        self.digests
+            .iter()
+            .find(|d| d.algorithm == algorithm)
+            .map(|d| &d.digest[..])
+    }
+
+    // Whether the digests of the event are of `data`, e.g. of its event data:
+    // each digest of an algorithm supported by hash matches, and there is
+    // one at least. The data of events whose digests are of something else,
+    // e.g. of a loaded image, are not covered by the digests.
+    pub fn measures(&self, data: &[u8]) -> bool {
+        let mut checked = false;
+        for digest in &self.digests {
+            match hash(digest.algorithm, &[data]) {
+                Some(expected) if expected != digest.digest => return false,
+                Some(_) => checked = true,
+                None => {}
+            }
+        }
+        checked
+    }
+}
+
+impl SpecId {
+    pub fn digest_size(&self, algorithm: u16) -> Option<usize> {
+        self.algorithms
+            .iter()
+            .find(|a| a.algorithm == algorithm)
+            .map(|a| a.digest_size as usize)
+    }
+}
+
+// Hash of the concatenation of `data` with a TPM_ALG_* algorithm, None if
+// the algorithm is not supported
+pub fn hash(algorithm: u16, data: &[&[u8]]) -> Option<Vec<u8>> {
+    fn digest<D: sha2::Digest>(data: &[&[u8]]) -> Vec<u8> {
+        data.iter()
+            .fold(D::new(), |hasher, part| hasher.chain_update(part))
+            .finalize()
+            .to_vec()
+    }
+    match algorithm {
+        TPM_ALG_SHA1 => Some(digest::<Sha1>(data)),
+        TPM_ALG_SHA256 => Some(digest::<Sha256>(data)),
+        TPM_ALG_SHA384 => Some(digest::<Sha384>(data)),
+        TPM_ALG_SHA512 => Some(digest::<Sha512>(data)),
+        _ => None,
+    }
+}
+
+// Name of an event type as in the TCG specification
+pub fn event_type_name(event_type: u32) -> Option<&'static str> {
+    Some(match event_type {
+        EV_PREBOOT_CERT => "EV_PREBOOT_CERT",
+        EV_POST_CODE => "EV_POST_CODE",
+        0x2 => "EV_UNUSED",
+        EV_NO_ACTION => "EV_NO_ACTION",
+        EV_SEPARATOR => "EV_SEPARATOR",
+        EV_ACTION => "EV_ACTION",
+        EV_EVENT_TAG => "EV_EVENT_TAG",
+        EV_S_CRTM_CONTENTS => "EV_S_CRTM_CONTENTS",
+        EV_S_CRTM_VERSION => "EV_S_CRTM_VERSION",
+        EV_CPU_MICROCODE => "EV_CPU_MICROCODE",
+        EV_PLATFORM_CONFIG_FLAGS => "EV_PLATFORM_CONFIG_FLAGS",
+        EV_TABLE_OF_DEVICES => "EV_TABLE_OF_DEVICES",
+        EV_COMPACT_HASH => "EV_COMPACT_HASH",
+        EV_IPL => "EV_IPL",
+        EV_IPL_PARTITION_DATA => "EV_IPL_PARTITION_DATA",
+        EV_NONHOST_CODE => "EV_NONHOST_CODE",
+        EV_NONHOST_CONFIG => "EV_NONHOST_CONFIG",
+        EV_NONHOST_INFO => "EV_NONHOST_INFO",
+        EV_OMIT_BOOT_DEVICE_EVENTS => "EV_OMIT_BOOT_DEVICE_EVENTS",
+        EV_POST_CODE2 => "EV_POST_CODE2",
+        EV_EFI_VARIABLE_DRIVER_CONFIG => "EV_EFI_VARIABLE_DRIVER_CONFIG",
+        EV_EFI_VARIABLE_BOOT => "EV_EFI_VARIABLE_BOOT",
+        EV_EFI_BOOT_SERVICES_APPLICATION => "EV_EFI_BOOT_SERVICES_APPLICATION",
+        EV_EFI_BOOT_SERVICES_DRIVER => "EV_EFI_BOOT_SERVICES_DRIVER",
+        EV_EFI_RUNTIME_SERVICES_DRIVER => "EV_EFI_RUNTIME_SERVICES_DRIVER",
+        EV_EFI_GPT_EVENT => "EV_EFI_GPT_EVENT",
+        EV_EFI_ACTION => "EV_EFI_ACTION",
+        EV_EFI_PLATFORM_FIRMWARE_BLOB => "EV_EFI_PLATFORM_FIRMWARE_BLOB",
+        EV_EFI_HANDOFF_TABLES => "EV_EFI_HANDOFF_TABLES",
+        EV_EFI_PLATFORM_FIRMWARE_BLOB2 => "EV_EFI_PLATFORM_FIRMWARE_BLOB2",
+        EV_EFI_HANDOFF_TABLES2 => "EV_EFI_HANDOFF_TABLES2",
+        EV_EFI_VARIABLE_BOOT2 => "EV_EFI_VARIABLE_BOOT2",
+        EV_EFI_GPT_EVENT2 => "EV_EFI_GPT_EVENT2",
+        EV_EFI_HCRTM_EVENT => "EV_EFI_HCRTM_EVENT",
+        EV_EFI_VARIABLE_AUTHORITY => "EV_EFI_VARIABLE_AUTHORITY",
+        EV_EFI_SPDM_FIRMWARE_BLOB => "EV_EFI_SPDM_FIRMWARE_BLOB",
+        EV_EFI_SPDM_FIRMWARE_CONFIG => "EV_EFI_SPDM_FIRMWARE_CONFIG",
+        _ => return None,
+    })
+}
+
+// Parse a crypto agile event log, ignoring trailing padding of 0xff or zero
+// bytes
+pub fn parse(log: &[u8]) -> Result<EventLog, ParseError> {
+    let mut reader = Reader {
+        data: log,
+        offset: 0,
+    };
+    let spec_id = parse_spec_id(&mut reader)?;
+
+    let mut events = Vec::new();
+    while !is_padding(&log[reader.offset..]) {
+        let offset = reader.offset;
+        let invalid = |reason| ParseError::InvalidEvent { offset, reason };
+        let index = reader.u32()?;
+        let event_type = reader.u32()?;
+        let count = reader.u32()? as usize;
+        if count == 0 || count > spec_id.algorithms.len() {
+            return Err(invalid("digest count does not match the algorithms"));
+        }
+        let mut digests = Vec::with_capacity(count);
+        for _ in 0..count {
+            let algorithm = reader.u16()?;
+            let size = spec_id
+                .digest_size(algorithm)
+                .ok_or(ParseError::UnknownAlgorithm { offset, algorithm })?;
+            if digests
+                .iter()
+                .any(|d: &EventDigest| d.algorithm == algorithm)
+            {
+                return Err(invalid("duplicate digest algorithm"));
+            }
+            digests.push(EventDigest {
+                algorithm,
+                digest: reader.take(size)?.to_vec(),
+            });
+        }
+        let size = reader.u32()? as usize;
+        let data = reader.take(size)?.to_vec();
+        events.push(Event {
+            offset,
+            index,
+            event_type,
+            digests,
+            data,
+        });
+    }
+    Ok(EventLog {
+        spec_id,
+        events,
+        len: reader.offset,
+    })
+}
+
+fn parse_spec_id(reader: &mut Reader) -> Result<SpecId, ParseError> {
+    let invalid = |reason| ParseError::InvalidHeader { reason };
+    if reader.u32()? != 0 || reader.u32()? != EV_NO_ACTION {
+        return Err(invalid("first event is not an EV_NO_ACTION event"));
+    }
+    if reader.take(SHA1_LEN)?.iter().any(|b| *b != 0) {
+        return Err(invalid("digest is not zero"));
+    }
+    let size = reader.u32()? as usize;
+    let mut event = Reader {
+        data: reader.take(size)?,
+        offset: 0,
+    };
+    let header = |e| match e {
+        ParseError::Truncated { .. } => invalid("event data truncated"),
+        e => e,
+    };
+    if event.take(SPEC_ID_SIGNATURE.len()).map_err(header)? != SPEC_ID_SIGNATURE {
+        return Err(invalid("not a Spec ID Event03"));
+    }
+    let platform_class = event.u32().map_err(header)?;
+    let version = event.take(4).map_err(header)?;
+    let count = event.u32().map_err(header)? as usize;
+    let mut algorithms = Vec::new();
+    for _ in 0..count {
+        algorithms.push(AlgorithmSize {
+            algorithm: event.u16().map_err(header)?,
+            digest_size: event.u16().map_err(header)?,
+        });
+    }
+    if algorithms.is_empty() {
+        return Err(invalid("no digest algorithm"));
+    }
+    let vendor_size = event.take(1).map_err(header)?[0] as usize;
+    let vendor_info = event.take(vendor_size).map_err(header)?.to_vec();
+    if event.offset != event.data.len() {
+        return Err(invalid("trailing event data"));
+    }
+    Ok(SpecId {
+        platform_class,
+        spec_version: (version[0], version[1], version[2]),
+        uintn_size: version[3],
+        algorithms,
+        vendor_info,
+    })
+}
+
+pub(crate) fn u64_at(data: &[u8], offset: usize) -> Option<u64> {
+    Some(u64::from_le_bytes(
+        data.get(offset..offset.checked_add(8)?)?.try_into().ok()?,
+    ))
+}
+
+pub(crate) fn u32_at(data: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(
+        data.get(offset..offset.checked_add(4)?)?.try_into().ok()?,
+    ))
+}
+
+fn is_padding(rest: &[u8]) -> bool {
+    rest.iter().all(|b| *b == 0xff) || rest.iter().all(|b| *b == 0)
+}
+
+// Parse the CCEL ACPI table
+pub fn parse_ccel_table(table: &[u8]) -> Result<CcelTable, ParseError> {
+    let invalid = |reason| ParseError::InvalidTable { reason };
+    if table.len() < CCEL_TABLE_LEN {
+        return Err(invalid("too short"));
+    }
+    if &table[..4] != CCEL_SIGNATURE {
+        return Err(invalid("signature is not CCEL"));
+    }
+    let length = u32::from_le_bytes(table[4..8].try_into().unwrap()) as usize;
+    if length != table.len() {
+        return Err(invalid("length does not match the table"));
+    }
+    if table.iter().fold(0u8, |sum, b| sum.wrapping_add(*b)) != 0 {
+        return Err(invalid("checksum mismatch"));
+    }
+    Ok(CcelTable {
+        cc_type: table[36],
+        cc_subtype: table[37],
+        log_area_length: u64::from_le_bytes(table[40..48].try_into().unwrap()),
+        log_area_address: u64::from_le_bytes(table[48..56].try_into().unwrap()),
+    })
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], ParseError> {
+        let end = self
+            .offset
+            .checked_add(len)
+            .filter(|end| *end <= self.data.len())
+            .ok_or(ParseError::Truncated {
+                offset: self.offset,
+            })?;
+        let bytes = &self.data[self.offset..end];
+        self.offset = end;
+        Ok(bytes)
+    }
+
+    fn u32(&mut self) -> Result<u32, ParseError> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn u16(&mut self) -> Result<u16, ParseError> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into().unwrap()))
+    }
+}
+
+// Builders of logs and event data for the tests of the modules decoding
+// events
+#[cfg(test)]
+pub(crate) mod testing {
+    use super::*;
+
+    // Log of events at RTMR0 whose SHA-384 digests are of their data
+    pub(crate) fn log(events: Vec<(u32, Vec<u8>)>) -> EventLog {
+        EventLog {
+            spec_id: SpecId {
+                platform_class: 0,
+                spec_version: (0, 2, 0),
+                uintn_size: 2,
+                algorithms: vec![AlgorithmSize {
+                    algorithm: TPM_ALG_SHA384,
+                    digest_size: 48,
+                }],
+                vendor_info: vec![],
+            },
+            events: events
+                .into_iter()
+                .enumerate()
+                .map(|(offset, (event_type, data))| Event {
+                    offset,
+                    index: 1,
+                    event_type,
+                    digests: vec![EventDigest {
+                        algorithm: TPM_ALG_SHA384,
+                        digest: hash(TPM_ALG_SHA384, &[&data]).unwrap(),
+                    }],
+                    data,
+                })
+                .collect(),
+            len: 0,
+        }
+    }
+
+    pub(crate) fn utf16le(s: &str) -> Vec<u8> {
+        s.encode_utf16().flat_map(|u| u.to_le_bytes()).collect()
+    }
+
+    // UEFI_IMAGE_LOAD_EVENT of an image of 0x1000 bytes loaded from `path`
+    pub(crate) fn image_load(path: &str) -> Vec<u8> {
+        let name = utf16le(&format!("{}\0", path));
+        let mut device_path = vec![4, 4];
+        device_path.extend_from_slice(&((4 + name.len()) as u16).to_le_bytes());
+        device_path.extend(name);
+        device_path.extend_from_slice(&[0x7f, 0xff, 4, 0]);
+        let mut data = vec![0; 8];
+        data.extend_from_slice(&0x1000u64.to_le_bytes());
+        data.extend_from_slice(&[0; 8]);
+        data.extend_from_slice(&(device_path.len() as u64).to_le_bytes());
+        data.extend(device_path);
+        data
+    }
+}
+
+#[cfg(test)]
+mod tcg_tests {
+    use super::*;
+
+    fn header(algorithms: &[(u16, u16)]) -> Vec<u8> {
+        let mut spec_id = SPEC_ID_SIGNATURE.to_vec();
+        spec_id.extend_from_slice(&0u32.to_le_bytes());
+        spec_id.extend_from_slice(&[0, 2, 0, 2]);
+        spec_id.extend_from_slice(&(algorithms.len() as u32).to_le_bytes());
+        for (algorithm, size) in algorithms {
+            spec_id.extend_from_slice(&algorithm.to_le_bytes());
+            spec_id.extend_from_slice(&size.to_le_bytes());
+        }
+        spec_id.push(0);
+        let mut log = 0u32.to_le_bytes().to_vec();
+        log.extend_from_slice(&EV_NO_ACTION.to_le_bytes());
+        log.extend_from_slice(&[0; SHA1_LEN]);
+        log.extend_from_slice(&(spec_id.len() as u32).to_le_bytes());
+        log.extend_from_slice(&spec_id);
+        log
+    }
+
+    fn event(index: u32, event_type: u32, digests: &[(u16, &[u8])], data: &[u8]) -> Vec<u8> {
+        let mut event = index.to_le_bytes().to_vec();
+        event.extend_from_slice(&event_type.to_le_bytes());
+        event.extend_from_slice(&(digests.len() as u32).to_le_bytes());
+        for (algorithm, digest) in digests {
+            event.extend_from_slice(&algorithm.to_le_bytes());
+            event.extend_from_slice(digest);
+        }
+        event.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        event.extend_from_slice(data);
+        event
+    }
+
+    #[test]
+    //events with several digest banks are parsed and padding is ignored
+    fn parse_events() {
+        let mut log = header(&[(TPM_ALG_SHA256, 32), (TPM_ALG_SHA384, 48)]);
+        let first = log.len();
+        log.extend(event(
+            1,
+            EV_EFI_ACTION,
+            &[(TPM_ALG_SHA256, &[1; 32]), (TPM_ALG_SHA384, &[2; 48])],
+            b"Calling EFI Application from Boot Option",
+        ));
+        log.extend(event(
+            4,
+            EV_SEPARATOR,
+            &[(TPM_ALG_SHA384, &[3; 48])],
+            &[0; 4],
+        ));
+        let len = log.len();
+        for padding in [0xff, 0] {
+            let mut padded = log.clone();
+            padded.extend([padding; 64]);
+            let parsed = parse(&padded).unwrap();
+            assert_eq!(parsed.len, len);
+            assert_eq!(parsed.spec_id.spec_version, (0, 2, 0));
+            assert_eq!(parsed.spec_id.digest_size(TPM_ALG_SHA384), Some(48));
+            assert_eq!(parsed.events.len(), 2);
+            assert_eq!(parsed.events[0].offset, first);
+            assert_eq!(parsed.events[0].digest(TPM_ALG_SHA384), Some(&[2; 48][..]));
+            assert_eq!(parsed.events[1].index, 4);
+            assert_eq!(parsed.events[1].digest(TPM_ALG_SHA256), None);
+        }
+        assert_eq!(event_type_name(EV_SEPARATOR), Some("EV_SEPARATOR"));
+        assert_eq!(event_type_name(0x7fff), None);
+    }
+
+    #[test]
+    //malformed headers and events are reported with their offset
+    fn reject_malformed() {
+        let log = header(&[(TPM_ALG_SHA384, 48)]);
+        let start = log.len();
+        assert!(matches!(
+            parse(&log[8..]),
+            Err(ParseError::InvalidHeader { .. })
+        ));
+        assert_eq!(
+            parse(&header(&[])),
+            Err(ParseError::InvalidHeader {
+                reason: "no digest algorithm"
+            })
+        );
+
+        let mut unknown = log.clone();
+        unknown.extend(event(1, EV_POST_CODE, &[(TPM_ALG_SHA256, &[1; 32])], b""));
+        assert_eq!(
+            parse(&unknown),
+            Err(ParseError::UnknownAlgorithm {
+                offset: start,
+                algorithm: TPM_ALG_SHA256
+            })
+        );
+
+        let mut truncated = log.clone();
+        truncated.extend(event(
+            1,
+            EV_POST_CODE,
+            &[(TPM_ALG_SHA384, &[1; 48])],
+            b"abc",
+        ));
+        truncated.pop();
+        assert!(matches!(
+            parse(&truncated),
+            Err(ParseError::Truncated { .. })
+        ));
+
+        let mut empty = log;
+        empty.extend(event(1, EV_POST_CODE, &[], b""));
+        assert!(matches!(
+            parse(&empty),
+            Err(ParseError::InvalidEvent { offset, .. }) if offset == start
+        ));
+    }
+
+    #[test]
+    //the CCEL table locates the log area and is checksummed
+    fn parse_table() {
+        let mut table = CCEL_SIGNATURE.to_vec();
+        table.extend_from_slice(&(CCEL_TABLE_LEN as u32).to_le_bytes());
+        table.resize(36, 0);
+        table.extend_from_slice(&[CC_TYPE_TDX, 0, 0, 0]);
+        table.extend_from_slice(&0x10000u64.to_le_bytes());
+        table.extend_from_slice(&0x7f000000u64.to_le_bytes());
+        let sum = table.iter().fold(0u8, |sum, b| sum.wrapping_add(*b));
+        table[9] = 0u8.wrapping_sub(sum);
+        let ccel = parse_ccel_table(&table).unwrap();
+        assert_eq!(ccel.cc_type, CC_TYPE_TDX);
+        assert_eq!(ccel.log_area_length, 0x10000);
+        assert_eq!(ccel.log_area_address, 0x7f000000);
+
+        table[40] ^= 1;
+        assert_eq!(
+            parse_ccel_table(&table),
+            Err(ParseError::InvalidTable {
+                reason: "checksum mismatch"
+            })
+        );
+        assert!(parse_ccel_table(&table[..40]).is_err());
+    }
+}
@@ -0,0 +1,380 @@
+/*
+* Copyright (c) 2023, Intel Corporation. All rights reserved.<BR>
+* SPDX-License-Identifier: Apache-2.0
+*/
+
+// Layout of TDX DCAP quotes, version 4 and 5, as far as needed to locate the
+// report data and the attestation key. See the Intel TDX DCAP Quoting Library
+// API, appendix A.
+//
+// Version 4:  header (48) | TD quote body (584) | signature data length (4) |
+//             signature data
+// Version 5:  header (48) | body type (2) | body size (4) | TD quote body
+//             (584 for TDX 1.0, 648 for TDX 1.5) | signature data length (4) |
+//             signature data
+//
+// The signature data starts with the ECDSA signature (64) over header and
+// body, followed by the ECDSA attestation public key (64) and the
+// certification data: type (2) | size (4) | data. Certification data of type
+// 6 hold the QE report (384), its signature (64), the QE authentication data
+// size (2) and data, followed by nested certification data, usually of type 5,
+// the PEM PCK certificate chain.
+//
+// A TDREPORT, the local report the quote is generated from, is 1024 bytes:
+// REPORTMACSTRUCT (256) | TEE_TCB_INFO (239) | reserved (17) | TDINFO (512).
+// See the Intel TDX module ABI specification, TDREPORT_STRUCT.
+
+use sha2::{Digest as _, Sha256};
+use std::fmt;
+
+pub const QUOTE_HEADER_LEN: usize = 48;
+pub const TD_QUOTE_BODY_V4_LEN: usize = 584;
+pub const TD_QUOTE_BODY_V5_LEN: usize = 648;
+pub const REPORT_DATA_LEN: usize = 64;
+// Report data are the last field of both TD quote body versions
+const REPORT_DATA_OFFSET: usize = 520;
+const SIGNATURE_LEN: usize = 64;
+const ATTESTATION_KEY_LEN: usize = 64;
+
+pub const TD_REPORT_LEN: usize = 1024;
+pub const MEASUREMENT_LEN: usize = 48;
+pub const RTMR_COUNT: usize = 4;
+const TEE_TCB_INFO_OFFSET: usize = 256;
+const TD_INFO_OFFSET: usize = 512;
+
+// Certification data types
+pub const CERT_DATA_PCK_CERT_CHAIN: u16 = 5;
+pub const CERT_DATA_QE_REPORT: u16 = 6;
+const QE_REPORT_LEN: usize = 384;
+const QE_REPORT_SIGNATURE_LEN: usize = 64;
+
+// Attestation key type in the quote header
+pub const ATT_KEY_TYPE_ECDSA_P256: u16 = 2;
+// TEE type in the quote header
+pub const TEE_TYPE_TDX: u32 = 0x81;
+
+#[derive(Debug, PartialEq)]
+pub enum FormatError {
+    TooShort { needed: usize, actual: usize },
+    UnsupportedVersion(u16),
+    UnsupportedBodyType(u16),
+}
+
+impl fmt::Display for FormatError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FormatError::TooShort { needed, actual } => {
+                write!(f, "quote has {} bytes, needs {}", actual, needed)
+            }
+            FormatError::UnsupportedVersion(v) => write!(f, "unsupported quote version {}", v),
+            FormatError::UnsupportedBodyType(t) => write!(f, "unsupported quote body type {}", t),
+        }
+    }
+}
+
+impl std::error::Error for FormatError {}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct QuoteHeader {
+    pub version: u16,
+    pub att_key_type: u16,
+    pub tee_type: u32,
+    pub qe_vendor_id: [u8; 16],
+}
+
+// A TDX quote split into its parts, borrowing from the quote bytes
+#[derive(Clone, Debug)]
+pub struct Quote<'a> {
+    pub header: QuoteHeader,
+    pub body: &'a [u8],
+    pub signature_data: &'a [u8],
+}
+
+impl<'a> Quote<'a> {
+    pub fn parse(quote: &'a [u8]) -> Result<Self, FormatError> {
+        let header = take(quote, 0, QUOTE_HEADER_LEN)?;
+        let header = QuoteHeader {
+            version: u16::from_le_bytes([header[0], header[1]]),
+            att_key_type: u16::from_le_bytes([header[2], header[3]]),
+            tee_type: u32::from_le_bytes([header[4], header[5], header[6], header[7]]),
+            qe_vendor_id: header[12..28].try_into().unwrap(),
+        };
+        let (body_offset, body_len) = match header.version {
+            4 => (QUOTE_HEADER_LEN, TD_QUOTE_BODY_V4_LEN),
+            5 => {
+                let body_header = take(quote, QUOTE_HEADER_LEN, 6)?;
+                let body_len = match u16::from_le_bytes([body_header[0], body_header[1]]) {
+                    2 => TD_QUOTE_BODY_V4_LEN,
+                    3 => TD_QUOTE_BODY_V5_LEN,
+                    t => return Err(FormatError::UnsupportedBodyType(t)),
+                };
+                (QUOTE_HEADER_LEN + 6, body_len)
+            }
+            v => return Err(FormatError::UnsupportedVersion(v)),
+        };
+        let body = take(quote, body_offset, body_len)?;
+        let length_offset = body_offset + body_len;
+        let length = take(quote, length_offset, 4)?;
+        let length = u32::from_le_bytes(length.try_into().unwrap()) as usize;
+        let signature_data = take(quote, length_offset + 4, length)?;
+        Ok(Quote {
+            header,
+            body,
+            signature_data,
+        })
+    }
+
+    // Report data of the TD the quote was generated for
+    pub fn report_data(&self) -> &'a [u8] {
+        &self.body[REPORT_DATA_OFFSET..REPORT_DATA_OFFSET + REPORT_DATA_LEN]
+    }
+
+    // RTMR0 to RTMR3, which precede the report data in both body versions
+    pub fn rtmrs(&self) -> [&'a [u8]; RTMR_COUNT] {
+        let rtmr = |i: usize| {
+            let offset = REPORT_DATA_OFFSET - (RTMR_COUNT - i) * MEASUREMENT_LEN;
+            &self.body[offset..offset + MEASUREMENT_LEN]
+        };
+        [rtmr(0), rtmr(1), rtmr(2), rtmr(3)]
+    }
+
+    // Public key of the ECDSA attestation key, None for other key types
+    pub fn attestation_key(&self) -> Option<&'a [u8]> {
+        if self.header.att_key_type != ATT_KEY_TYPE_ECDSA_P256 {
+            return None;
+        }
+        self.signature_data
+            .get(SIGNATURE_LEN..SIGNATURE_LEN + ATTESTATION_KEY_LEN)
+    }
+
+    // Type and data of the certification data in the signature data
+    pub fn certification_data(&self) -> Option<(u16, &'a [u8])> {
+        cert_data(self.signature_data, SIGNATURE_LEN + ATTESTATION_KEY_LEN)
+    }
+
+    // PEM PCK certificate chain, leaf first, if the quote carries it
+    pub fn pck_cert_chain(&self) -> Option<&'a [u8]> {
+        match self.certification_data()? {
+            (CERT_DATA_PCK_CERT_CHAIN, data) => Some(data),
+            (CERT_DATA_QE_REPORT, data) => {
+                let offset = QE_REPORT_LEN + QE_REPORT_SIGNATURE_LEN;
+                let size = data.get(offset..offset + 2)?;
+                let auth_len = u16::from_le_bytes([size[0], size[1]]) as usize;
+                match cert_data(data, offset + 2 + auth_len)? {
+                    (CERT_DATA_PCK_CERT_CHAIN, chain) => Some(chain),
+                    _ => None,
+                }
+            }
+            _ => None,
+        }
+    }
+}
+
+// A TDREPORT split into its fields, borrowing from the report bytes
+#[derive(Clone, Debug, PartialEq)]
+pub struct TdReport<'a> {
+    // REPORTTYPE: TEE type (0x81 for TDX), subtype and version
+    pub report_type: [u8; 4],
+    pub cpu_svn: &'a [u8],
+    pub tee_tcb_info_hash: &'a [u8],
+    pub tee_info_hash: &'a [u8],
+    pub report_data: &'a [u8],
+    // MAC over the REPORTMACSTRUCT, verifiable on the same platform only
+    pub mac: &'a [u8],
+    pub tee_tcb_svn: &'a [u8],
+    pub mr_seam: &'a [u8],
+    pub mr_signer_seam: &'a [u8],
+    pub td_attributes: &'a [u8],
+    pub xfam: &'a [u8],
+    pub mr_td: &'a [u8],
+    pub mr_config_id: &'a [u8],
+    pub mr_owner: &'a [u8],
+    pub mr_owner_config: &'a [u8],
+    pub rtmrs: [&'a [u8]; RTMR_COUNT],
+    // Zero on TDX 1.0
+    pub servtd_hash: &'a [u8],
+}
+
+impl<'a> TdReport<'a> {
+    pub fn parse(report: &'a [u8]) -> Result<Self, FormatError> {
+        if report.len() < TD_REPORT_LEN {
+            return Err(FormatError::TooShort {
+                needed: TD_REPORT_LEN,
+                actual: report.len(),
+            });
+        }
+        let field = |offset: usize, len: usize| &report[offset..offset + len];
+        let tcb = |offset: usize, len: usize| field(TEE_TCB_INFO_OFFSET + offset, len);
+        let td = |offset: usize, len: usize| field(TD_INFO_OFFSET + offset, len);
+        let rtmr = |i: usize| td(208 + i * MEASUREMENT_LEN, MEASUREMENT_LEN);
+        Ok(TdReport {
+            report_type: field(0, 4).try_into().unwrap(),
+            cpu_svn: field(16, 16),
+            tee_tcb_info_hash: field(32, MEASUREMENT_LEN),
+            tee_info_hash: field(80, MEASUREMENT_LEN),
+            report_data: field(128, REPORT_DATA_LEN),
+            mac: field(224, 32),
+            tee_tcb_svn: tcb(8, 16),
+            mr_seam: tcb(24, MEASUREMENT_LEN),
+            mr_signer_seam: tcb(72, MEASUREMENT_LEN),
+            td_attributes: td(0, 8),
+            xfam: td(8, 8),
+            mr_td: td(16, MEASUREMENT_LEN),
+            mr_config_id: td(64, MEASUREMENT_LEN),
+            mr_owner: td(112, MEASUREMENT_LEN),
+            mr_owner_config: td(160, MEASUREMENT_LEN),
+            rtmrs: [rtmr(0), rtmr(1), rtmr(2), rtmr(3)],
+            servtd_hash: td(400, MEASUREMENT_LEN),
+        })
+    }
+}
+
+// Identifier of an attestation key: "sha256:" followed by the hex encoded
+// SHA-256 digest of its public key
+pub fn attestation_key_id(public_key: &[u8]) -> String {
+    let digest = Sha256::digest(public_key);
+    let hex: String = digest.iter().map(|b| format!("{:02x}", b)).collect();
+    format!("sha256:{}", hex)
+}
+
+fn cert_data(data: &[u8], offset: usize) -> Option<(u16, &[u8])> {
+    let header = data.get(offset..offset + 6)?;
+    let cert_type = u16::from_le_bytes([header[0], header[1]]);
+    let size = u32::from_le_bytes(header[2..6].try_into().unwrap()) as usize;
+    Some((cert_type, data.get(offset + 6..offset + 6 + size)?))
+}
+
+fn take(quote: &[u8], offset: usize, len: usize) -> Result<&[u8], FormatError> {
+    quote
+        .get(offset..offset + len)
+        .ok_or(FormatError::TooShort {
+            needed: offset + len,
+            actual: quote.len(),
+        })
+}
+
+#[cfg(test)]
+mod tdx_tests {
+    use super::*;
+
+    // quote of the given version with recognizable report data and key
+    fn quote(version: u16, body_len: usize) -> Vec<u8> {
+        let mut quote = Vec::new();
+        quote.extend_from_slice(&version.to_le_bytes());
+        quote.extend_from_slice(&ATT_KEY_TYPE_ECDSA_P256.to_le_bytes());
+        quote.extend_from_slice(&TEE_TYPE_TDX.to_le_bytes());
+        quote.extend_from_slice(&[0; 4]);
+        quote.extend_from_slice(&[0x11; 16]);
+        quote.extend_from_slice(&[0; 20]);
+        if version == 5 {
+            let body_type: u16 = if body_len == TD_QUOTE_BODY_V5_LEN {
+                3
+            } else {
+                2
+            };
+            quote.extend_from_slice(&body_type.to_le_bytes());
+            quote.extend_from_slice(&(body_len as u32).to_le_bytes());
+        }
+        let mut body = vec![0; body_len];
+        body[REPORT_DATA_OFFSET..REPORT_DATA_OFFSET + REPORT_DATA_LEN].fill(0xaa);
+        body[REPORT_DATA_OFFSET - MEASUREMENT_LEN..REPORT_DATA_OFFSET].fill(0xdd);
+        quote.extend_from_slice(&body);
+        let mut signature_data = vec![0; SIGNATURE_LEN];
+        signature_data.extend_from_slice(&[0xbb; ATTESTATION_KEY_LEN]);
+        signature_data.extend_from_slice(&qe_cert_data(b"-----BEGIN CERTIFICATE-----"));
+        quote.extend_from_slice(&(signature_data.len() as u32).to_le_bytes());
+        quote.extend_from_slice(&signature_data);
+        quote
+    }
+
+    // certification data of type 6 wrapping a PCK certificate chain
+    fn qe_cert_data(chain: &[u8]) -> Vec<u8> {
+        let mut inner = vec![0; QE_REPORT_LEN + QE_REPORT_SIGNATURE_LEN];
+        inner.extend_from_slice(&2u16.to_le_bytes());
+        inner.extend_from_slice(&[0xcc; 2]);
+        inner.extend_from_slice(&CERT_DATA_PCK_CERT_CHAIN.to_le_bytes());
+        inner.extend_from_slice(&(chain.len() as u32).to_le_bytes());
+        inner.extend_from_slice(chain);
+        let mut data = CERT_DATA_QE_REPORT.to_le_bytes().to_vec();
+        data.extend_from_slice(&(inner.len() as u32).to_le_bytes());
+        data.extend_from_slice(&inner);
+        data
+    }
+
+    #[test]
+    //report data and attestation key are found in version 4 and 5 quotes
+    fn parse_quote_versions() {
+        for (version, body_len) in [
+            (4, TD_QUOTE_BODY_V4_LEN),
+            (5, TD_QUOTE_BODY_V4_LEN),
+            (5, TD_QUOTE_BODY_V5_LEN),
+        ] {
+            let bytes = quote(version, body_len);
+            let quote = Quote::parse(&bytes).unwrap();
+            assert_eq!(quote.header.version, version);
+            assert_eq!(quote.header.tee_type, TEE_TYPE_TDX);
+            assert_eq!(quote.header.qe_vendor_id, [0x11; 16]);
+            assert_eq!(quote.body.len(), body_len);
+            assert_eq!(quote.report_data(), &[0xaa; REPORT_DATA_LEN][..]);
+            assert_eq!(quote.rtmrs()[3], &[0xdd; MEASUREMENT_LEN][..]);
+            assert_eq!(quote.rtmrs()[2], &[0; MEASUREMENT_LEN][..]);
+            assert_eq!(
+                quote.attestation_key().unwrap(),
+                &[0xbb; ATTESTATION_KEY_LEN][..]
+            );
+            assert_eq!(
+                quote.pck_cert_chain().unwrap(),
+                b"-----BEGIN CERTIFICATE-----"
+            );
+        }
+    }
+
+    #[test]
+    //truncated quotes and unknown versions are rejected
+    fn parse_invalid_quote() {
+        let bytes = quote(4, TD_QUOTE_BODY_V4_LEN);
+        assert!(matches!(
+            Quote::parse(&bytes[..bytes.len() - 1]),
+            Err(FormatError::TooShort { .. })
+        ));
+        let mut bytes = bytes;
+        bytes[0] = 3;
+        assert_eq!(
+            Quote::parse(&bytes).unwrap_err(),
+            FormatError::UnsupportedVersion(3)
+        );
+    }
+
+    #[test]
+    //TDREPORT fields are found at their ABI offsets
+    fn parse_td_report() {
+        let mut bytes = vec![0; TD_REPORT_LEN];
+        bytes[0] = 0x81;
+        bytes[128..192].fill(0xaa);
+        bytes[TD_INFO_OFFSET + 16..TD_INFO_OFFSET + 64].fill(0x01);
+        for i in 0..RTMR_COUNT {
+            let offset = TD_INFO_OFFSET + 208 + i * MEASUREMENT_LEN;
+            bytes[offset..offset + MEASUREMENT_LEN].fill(0x10 + i as u8);
+        }
+        let report = TdReport::parse(&bytes).unwrap();
+        assert_eq!(report.report_type, [0x81, 0, 0, 0]);
+        assert_eq!(report.report_data, &[0xaa; REPORT_DATA_LEN][..]);
+        assert_eq!(report.mr_td, &[0x01; MEASUREMENT_LEN][..]);
+        assert_eq!(report.rtmrs[3], &[0x13; MEASUREMENT_LEN][..]);
+        assert_eq!(report.servtd_hash, &[0; MEASUREMENT_LEN][..]);
+        assert!(matches!(
+            TdReport::parse(&bytes[..1000]),
+            Err(FormatError::TooShort { .. })
+        ));
+    }
+
+    #[test]
+    //key IDs are the SHA-256 digest of the public key
+    fn key_id() {
+        let id = attestation_key_id(b"");
+        assert_eq!(
+            id,
+            "sha256:e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+    }
+}
@@ -0,0 +1,36 @@
+/*
+* Copyright (c) 2023, Intel Corporation. All rights reserved.<BR>
+* SPDX-License-Identifier: Apache-2.0
+*/
+
+// Nonces of the quotes of a WatchAttestation stream. The quote with sequence
+// number n is requested with the nonce SHA-512 over the length-prefixed
+// fields
+//   "ccnp.watch.v1" | nonce seed | n as 8 byte big-endian integer
+// so a verifier holding the seed knows the nonce of every quote of the
+// stream, and a quote cannot be passed off under another sequence number.
+
+use crate::binding::length_prefixed;
+use sha2::{Digest as _, Sha512};
+
+const WATCH_TAG: &[u8] = b"ccnp.watch.v1";
+
+pub fn watch_nonce(seed: &[u8], sequence: u64) -> [u8; 64] {
+    Sha512::digest(length_prefixed(&[WATCH_TAG, seed, &sequence.to_be_bytes()])).into()
+}
+
+#[cfg(test)]
+mod watch_tests {
+    use super::*;
+
+    #[test]
+    //every sequence number and seed yields its own nonce
+    fn nonce_per_sequence() {
+        assert_ne!(watch_nonce(b"seed", 0), watch_nonce(b"seed", 1));
+        assert_ne!(watch_nonce(b"seed", 0), watch_nonce(b"seed2", 0));
+        assert_eq!(
+            watch_nonce(b"", 0),
+            <[u8; 64]>::from(Sha512::digest(length_prefixed(&[WATCH_TAG, b"", &[0; 8]])))
+        );
+    }
+}
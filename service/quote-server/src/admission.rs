@@ -0,0 +1,247 @@
+/*
+* Copyright (c) 2023, Intel Corporation. All rights reserved.<BR>
+* SPDX-License-Identifier: Apache-2.0
+*/
+
+use crate::error::QuoteError;
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use tokio::sync::oneshot;
+
+// The TEE device and QGS serve quote requests one at a time
+pub const DEFAULT_MAX_CONCURRENT: usize = 1;
+// Requests waiting for a slot, over all callers, before new ones are rejected
+pub const DEFAULT_MAX_QUEUED: usize = 64;
+
+struct State {
+    running: usize,
+    queued: usize,
+    // pending requests of each caller, in arrival order
+    queues: HashMap<String, VecDeque<oneshot::Sender<Permit>>>,
+    // callers with pending requests, served round-robin
+    order: VecDeque<String>,
+}
+
+struct Inner {
+    max_concurrent: usize,
+    max_queued: usize,
+    state: Mutex<State>,
+}
+
+// Bounds the number of quote requests in flight and queues the rest per
+// caller, so a caller flooding the server only delays its own requests.
+// Callers are keyed by their workload identity, see CallerIdentity::key.
+#[derive(Clone)]
+pub struct AdmissionControl {
+    inner: Arc<Inner>,
+}
+
+// A slot to run one quote request, returned to the admission control on drop
+pub struct Permit {
+    inner: Arc<Inner>,
+}
+
+impl AdmissionControl {
+    pub fn new(max_concurrent: usize, max_queued: usize) -> Self {
+        AdmissionControl {
+            inner: Arc::new(Inner {
+                max_concurrent: max_concurrent.max(1),
+                max_queued,
+                state: Mutex::new(State {
+                    running: 0,
+                    queued: 0,
+                    queues: HashMap::new(),
+                    order: VecDeque::new(),
+                }),
+            }),
+        }
+    }
+
+    // Wait for a slot on behalf of `caller`. Fails immediately with
+    // ResourceExhausted when the queue is full.
+    pub async fn acquire(&self, caller: &str) -> Result<Permit, QuoteError> {
+        let receiver = {
+            let mut state = self.inner.state.lock().unwrap();
+            if state.running < self.inner.max_concurrent && state.queued == 0 {
+                state.running += 1;
+                return Ok(Permit {
+                    inner: self.inner.clone(),
+                });
+            }
+            if state.queued >= self.inner.max_queued {
+                purge_cancelled(&mut state);
+            }
+            if state.queued >= self.inner.max_queued {
+                return Err(QuoteError::ResourceExhausted {
+                    queued: state.queued,
+                    limit: self.inner.max_queued,
+                });
+            }
+
+            let (sender, receiver) = oneshot::channel();
+            let queue = state.queues.entry(caller.to_string()).or_default();
+            queue.push_back(sender);
+            if queue.len() == 1 {
+                state.order.push_back(caller.to_string());
+            }
+            state.queued += 1;
+            receiver
+        };
+
+        // the sender is only dropped without a permit if the admission
+        // control itself is gone
+        receiver.await.map_err(|_| {
+            QuoteError::QuoteServiceUnavailable("admission control shut down".to_string())
+        })
+    }
+
+    // Number of requests currently waiting for a slot
+    pub fn queued(&self) -> usize {
+        self.inner.state.lock().unwrap().queued
+    }
+}
+
+// Drop waiters whose request has gone away, e.g. on client cancellation
+fn purge_cancelled(state: &mut State) {
+    let mut purged = 0;
+    for queue in state.queues.values_mut() {
+        let before = queue.len();
+        queue.retain(|s| !s.is_closed());
+        purged += before - queue.len();
+    }
+    state.queued -= purged;
+    state.queues.retain(|_, q| !q.is_empty());
+    let queues = &state.queues;
+    state.order.retain(|c| queues.contains_key(c));
+}
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        let mut state = self.inner.state.lock().unwrap();
+        // hand the slot over to the next caller in round-robin order
+        while let Some(caller) = state.order.pop_front() {
+            let queue = state.queues.get_mut(&caller).unwrap();
+            let sender = queue.pop_front().unwrap();
+            if queue.is_empty() {
+                state.queues.remove(&caller);
+            } else {
+                state.order.push_back(caller);
+            }
+            state.queued -= 1;
+
+            let permit = Permit {
+                inner: self.inner.clone(),
+            };
+            match sender.send(permit) {
+                Ok(()) => return,
+                // the waiter is gone, the slot must not be released twice
+                Err(permit) => std::mem::forget(permit),
+            }
+        }
+        state.running -= 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use tokio::sync::mpsc;
+
+    #[tokio::test]
+    //acquire grants slots up to the concurrency limit without queuing
+    async fn acquire_within_limit() {
+        let admission = AdmissionControl::new(2, 4);
+        let _a = admission.acquire("a").await.unwrap();
+        let _b = admission.acquire("b").await.unwrap();
+        assert_eq!(admission.queued(), 0);
+    }
+
+    #[tokio::test]
+    //acquire fails with ResourceExhausted when the queue is full
+    async fn acquire_queue_full() {
+        let admission = AdmissionControl::new(1, 1);
+        let _running = admission.acquire("a").await.unwrap();
+
+        let waiting = admission.clone();
+        let _pending = tokio::spawn(async move { waiting.acquire("a").await.map(|_| ()) });
+        tokio::time::sleep(Duration::from_millis(20)).await;
+
+        match admission.acquire("b").await {
+            Err(QuoteError::ResourceExhausted { queued, limit }) => {
+                assert_eq!(queued, 1);
+                assert_eq!(limit, 1);
+            }
+            _ => panic!("expected ResourceExhausted"),
+        }
+    }
+
+    #[tokio::test]
+    //a released slot goes to the next waiter
+    async fn release_wakes_waiter() {
+        let admission = AdmissionControl::new(1, 4);
+        let running = admission.acquire("a").await.unwrap();
+
+        let waiting = admission.clone();
+        let pending = tokio::spawn(async move { waiting.acquire("b").await.is_ok() });
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert_eq!(admission.queued(), 1);
+
+        drop(running);
+        assert!(pending.await.unwrap());
+        assert_eq!(admission.queued(), 0);
+    }
+
+    #[tokio::test]
+    //a noisy caller does not starve other callers
+    async fn round_robin_between_callers() {
+        let admission = AdmissionControl::new(1, 16);
+        let running = admission.acquire("noisy").await.unwrap();
+
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let mut tasks = Vec::new();
+        for caller in ["noisy", "noisy", "noisy", "quiet"] {
+            let admission = admission.clone();
+            let tx = tx.clone();
+            tasks.push(tokio::spawn(async move {
+                let _permit = admission.acquire(caller).await.unwrap();
+                tx.send(caller).unwrap();
+                tokio::time::sleep(Duration::from_millis(5)).await;
+            }));
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+        drop(running);
+        for task in tasks {
+            task.await.unwrap();
+        }
+
+        let mut served = Vec::new();
+        while let Ok(caller) = rx.try_recv() {
+            served.push(caller);
+        }
+        assert_eq!(served, vec!["noisy", "quiet", "noisy", "noisy"]);
+    }
+
+    #[tokio::test]
+    //cancelled waiters are skipped and do not leak slots
+    async fn cancelled_waiter_skipped() {
+        let admission = AdmissionControl::new(1, 1);
+        let running = admission.acquire("a").await.unwrap();
+
+        let waiting = admission.clone();
+        let cancelled = tokio::time::timeout(Duration::from_millis(20), async move {
+            waiting.acquire("b").await.is_ok()
+        })
+        .await;
+        assert!(cancelled.is_err());
+
+        // the cancelled waiter frees its queue entry for a new request
+        let waiting = admission.clone();
+        let pending = tokio::spawn(async move { waiting.acquire("c").await.is_ok() });
+        tokio::time::sleep(Duration::from_millis(20)).await;
+
+        drop(running);
+        assert!(pending.await.unwrap());
+        assert!(admission.acquire("d").await.is_ok());
+    }
+}
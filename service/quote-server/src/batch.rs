@@ -0,0 +1,303 @@
+/*
+* Copyright (c) 2023, Intel Corporation. All rights reserved.<BR>
+* SPDX-License-Identifier: Apache-2.0
+*/
+
+use crate::admission::AdmissionControl;
+use crate::error::QuoteError;
+use crate::executor::QuoteExecutor;
+use quote_verifier::merkle::{self, Digest};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{mpsc, oneshot};
+use tokio::time::Instant;
+use tonic::Status;
+
+#[derive(Clone, Copy, Debug)]
+pub struct BatchConfig {
+    // How long the first request of a batch waits for others to join
+    pub window: Duration,
+    // Number of requests after which a batch is closed early
+    pub max_size: usize,
+    // Requests waiting to be assigned to a batch before new ones are rejected
+    pub max_queued: usize,
+}
+
+// Quote generated for a whole batch together with the proof that a request's
+// report data is a leaf of the Merkle tree whose root is in the quote
+#[derive(Clone, Debug)]
+pub struct BatchedQuote {
+    pub quote: Vec<u8>,
+    pub leaf: Vec<u8>,
+    pub leaf_index: u64,
+    pub batch_size: u64,
+    pub audit_path: Vec<Digest>,
+}
+
+type QuoteFn = Arc<dyn Fn(String) -> anyhow::Result<Vec<u8>> + Send + Sync>;
+
+struct Pending {
+    caller: String,
+    deadline: Instant,
+    report_data: Vec<u8>,
+    reply: oneshot::Sender<Result<BatchedQuote, Status>>,
+}
+
+// Coalesces quote requests arriving within a short window into one quote over
+// the Merkle root of their report data.
+#[derive(Clone)]
+pub struct Batcher {
+    sender: mpsc::Sender<Pending>,
+    admission: AdmissionControl,
+    max_queued: usize,
+}
+
+impl Batcher {
+    // Start the batching task. `quote_fn` generates a raw quote over the
+    // base64 encoded report data it is given.
+    pub fn start(
+        config: BatchConfig,
+        executor: QuoteExecutor,
+        admission: AdmissionControl,
+        quote_fn: QuoteFn,
+    ) -> Self {
+        let (sender, receiver) = mpsc::channel(config.max_queued.max(1));
+        tokio::spawn(run(config, executor, admission.clone(), quote_fn, receiver));
+        Batcher {
+            sender,
+            admission,
+            max_queued: config.max_queued,
+        }
+    }
+
+    // Submit the report data of one request of `caller` and wait for the
+    // batch quote. The request waits for its turn in the admission control
+    // before it joins a batch, so a caller flooding the server only delays its
+    // own requests. The batch quote is generated until the latest deadline of
+    // its requests.
+    pub async fn submit(
+        &self,
+        caller: &str,
+        deadline: Instant,
+        report_data: Vec<u8>,
+    ) -> Result<BatchedQuote, Status> {
+        let permit = self
+            .admission
+            .acquire(caller)
+            .await
+            .map_err(|e| Status::from(&e))?;
+        let (reply, receiver) = oneshot::channel();
+        let pending = Pending {
+            caller: caller.to_string(),
+            deadline,
+            report_data,
+            reply,
+        };
+        let queued = self.sender.try_send(pending);
+        drop(permit);
+        if queued.is_err() {
+            return Err((&QuoteError::ResourceExhausted {
+                queued: self.max_queued,
+                limit: self.max_queued,
+            })
+                .into());
+        }
+        match receiver.await {
+            Ok(result) => result,
+            Err(_) => Err(Status::internal("[Batcher] batch dropped the request")),
+        }
+    }
+
+    // Number of requests waiting to be assigned to a batch
+    pub fn queued(&self) -> usize {
+        self.sender.max_capacity() - self.sender.capacity()
+    }
+}
+
+async fn run(
+    config: BatchConfig,
+    executor: QuoteExecutor,
+    admission: AdmissionControl,
+    quote_fn: QuoteFn,
+    mut receiver: mpsc::Receiver<Pending>,
+) {
+    while let Some(first) = receiver.recv().await {
+        let mut batch = vec![first];
+        let window_end = Instant::now() + config.window;
+        while batch.len() < config.max_size {
+            match tokio::time::timeout_at(window_end, receiver.recv()).await {
+                Ok(Some(pending)) => batch.push(pending),
+                _ => break,
+            }
+        }
+        // requests whose callers gave up are not worth a leaf
+        batch.retain(|p| !p.reply.is_closed());
+        if batch.is_empty() {
+            continue;
+        }
+
+        let leaves: Vec<Vec<u8>> = batch.iter().map(|p| p.report_data.clone()).collect();
+        let root = merkle::root(&leaves).unwrap();
+        let deadline = batch.iter().map(|p| p.deadline).max().unwrap();
+        let budget = deadline.saturating_duration_since(Instant::now());
+        // the device slot is taken in the turn of the caller opening the batch
+        let result =
+            match tokio::time::timeout_at(deadline, admission.acquire(&batch[0].caller)).await {
+                Ok(Ok(permit)) => {
+                    let quote_fn = quote_fn.clone();
+                    let remaining = deadline.saturating_duration_since(Instant::now());
+                    executor
+                        .run(remaining, move || {
+                            let _permit = permit;
+                            quote_fn(base64::encode(root))
+                        })
+                        .await
+                }
+                Ok(Err(e)) => Err((&e).into()),
+                Err(_) => Err((&QuoteError::DeadlineExceeded(budget)).into()),
+            };
+
+        let size = batch.len() as u64;
+        for (index, pending) in batch.into_iter().enumerate() {
+            let reply = match &result {
+                Ok(quote) => Ok(BatchedQuote {
+                    quote: quote.clone(),
+                    audit_path: merkle::inclusion_proof(&leaves, index).unwrap(),
+                    leaf: pending.report_data,
+                    leaf_index: index as u64,
+                    batch_size: size,
+                }),
+                Err(status) => Err(status.clone()),
+            };
+            let _ = pending.reply.send(reply);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::admission::{DEFAULT_MAX_CONCURRENT, DEFAULT_MAX_QUEUED};
+    use crate::executor::DEFAULT_QUOTE_TIMEOUT;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    fn config(window_ms: u64, max_size: usize) -> BatchConfig {
+        BatchConfig {
+            window: Duration::from_millis(window_ms),
+            max_size,
+            max_queued: 16,
+        }
+    }
+
+    // fake quote generation echoing the report data, counting its calls
+    fn echo_quote(calls: Arc<AtomicUsize>) -> QuoteFn {
+        Arc::new(move |report_data| {
+            calls.fetch_add(1, Ordering::SeqCst);
+            Ok(base64::decode(report_data).unwrap())
+        })
+    }
+
+    fn batcher(config: BatchConfig, quote_fn: QuoteFn) -> Batcher {
+        batcher_with_timeout(config, DEFAULT_QUOTE_TIMEOUT, quote_fn)
+    }
+
+    fn batcher_with_timeout(config: BatchConfig, timeout: Duration, quote_fn: QuoteFn) -> Batcher {
+        Batcher::start(
+            config,
+            QuoteExecutor::new(timeout),
+            AdmissionControl::new(DEFAULT_MAX_CONCURRENT, DEFAULT_MAX_QUEUED),
+            quote_fn,
+        )
+    }
+
+    async fn submit(
+        batcher: &Batcher,
+        caller: &str,
+        report_data: Vec<u8>,
+    ) -> Result<BatchedQuote, Status> {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        batcher.submit(caller, deadline, report_data).await
+    }
+
+    #[tokio::test]
+    //requests within the window share one quote over the Merkle root
+    async fn batch_within_window() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let batcher = batcher(config(50, 64), echo_quote(calls.clone()));
+
+        let mut tasks = Vec::new();
+        for i in 0..5u8 {
+            let batcher = batcher.clone();
+            tasks.push(tokio::spawn(async move {
+                submit(&batcher, "a", vec![i; 64]).await.unwrap()
+            }));
+        }
+        let mut results = Vec::new();
+        for task in tasks {
+            results.push(task.await.unwrap());
+        }
+
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+        for r in results {
+            assert_eq!(r.batch_size, 5);
+            let root: Digest = r.quote.clone().try_into().unwrap();
+            assert!(merkle::verify_inclusion(
+                &r.leaf,
+                r.leaf_index,
+                r.batch_size,
+                &r.audit_path,
+                &root
+            ));
+        }
+    }
+
+    #[tokio::test]
+    //a full batch is closed before the window expires
+    async fn batch_closed_at_max_size() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let batcher = batcher(config(5000, 2), echo_quote(calls.clone()));
+
+        let start = Instant::now();
+        let (a, b) = tokio::join!(
+            submit(&batcher, "a", vec![1; 64]),
+            submit(&batcher, "b", vec![2; 64])
+        );
+        assert!(start.elapsed() < Duration::from_secs(5));
+        assert_eq!(a.unwrap().batch_size, 2);
+        assert_eq!(b.unwrap().batch_size, 2);
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    //a quote generation failure is reported to every request of the batch
+    async fn batch_error_to_all() {
+        let quote_fn: QuoteFn =
+            Arc::new(|_| Err(QuoteError::QuoteServiceUnavailable("QGS down".to_string()).into()));
+        let batcher = batcher(config(20, 64), quote_fn);
+
+        let (a, b) = tokio::join!(
+            submit(&batcher, "a", vec![1; 64]),
+            submit(&batcher, "b", vec![2; 64])
+        );
+        assert_eq!(a.unwrap_err().code(), tonic::Code::Unavailable);
+        assert_eq!(b.unwrap_err().code(), tonic::Code::Unavailable);
+    }
+
+    #[tokio::test]
+    //the batch quote is bounded by the deadlines of its requests
+    async fn batch_within_caller_deadline() {
+        let quote_fn: QuoteFn = Arc::new(|report_data| {
+            std::thread::sleep(Duration::from_millis(100));
+            Ok(base64::decode(report_data).unwrap())
+        });
+        let batcher = batcher_with_timeout(config(10, 64), Duration::from_millis(20), quote_fn);
+        assert!(submit(&batcher, "a", vec![1; 64]).await.is_ok());
+
+        let deadline = Instant::now() + Duration::from_millis(20);
+        let status = batcher
+            .submit("a", deadline, vec![1; 64])
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), tonic::Code::DeadlineExceeded);
+    }
+}
@@ -0,0 +1,386 @@
+/*
+* Copyright (c) 2023, Intel Corporation. All rights reserved.<BR>
+* SPDX-License-Identifier: Apache-2.0
+*/
+
+// Server-issued challenges, used as quote nonces to prove freshness.
+//
+// A challenge is 64 bytes:
+//   random (16) | issued at (8) | expires at (8) | HMAC-SHA256 (32)
+// with both times in seconds since the Unix epoch, big-endian, and the HMAC
+// over the first 32 bytes under a server key. Challenges are not stored when
+// issued; the HMAC proves that the server issued them. Once used in a quote,
+// a challenge is recorded as consumed until it expires, so it is accepted only
+// once.
+//
+// The consumed challenges can be kept in a state file, together with the key,
+// so that restarts neither invalidate issued challenges nor allow replays.
+// The file starts with a "key <base64>" line, followed by one
+// "<base64 HMAC> <expires at>" line per consumed challenge.
+
+use crate::error::QuoteError;
+use anyhow::{Context, Result};
+use hmac::{Hmac, Mac};
+use rand::rngs::OsRng;
+use rand::RngCore;
+use sha2::Sha256;
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+pub const CHALLENGE_LEN: usize = 64;
+pub const DEFAULT_CHALLENGE_TTL: Duration = Duration::from_secs(300);
+// Consumed challenges kept until they expire, before quotes are rejected
+pub const DEFAULT_MAX_CONSUMED: usize = 65536;
+
+const RANDOM_LEN: usize = 16;
+const SIGNED_LEN: usize = 32;
+const KEY_LEN: usize = 32;
+
+type HmacSha256 = Hmac<Sha256>;
+// HMAC of each consumed challenge and its expiry
+type Consumed = HashMap<[u8; 32], u64>;
+
+#[derive(Clone, Debug)]
+pub struct ChallengeConfig {
+    pub ttl: Duration,
+    pub max_consumed: usize,
+    // Only accept quote requests whose nonce is an unused challenge
+    pub strict: bool,
+    pub state_file: Option<PathBuf>,
+}
+
+impl Default for ChallengeConfig {
+    fn default() -> Self {
+        ChallengeConfig {
+            ttl: DEFAULT_CHALLENGE_TTL,
+            max_consumed: DEFAULT_MAX_CONSUMED,
+            strict: false,
+            state_file: None,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Challenge {
+    pub nonce: Vec<u8>,
+    pub expires_at: SystemTime,
+}
+
+struct State {
+    consumed: Consumed,
+    // state file opened for appending, if persistent
+    file: Option<File>,
+}
+
+struct Inner {
+    config: ChallengeConfig,
+    key: [u8; KEY_LEN],
+    state: Mutex<State>,
+}
+
+#[derive(Clone)]
+pub struct Challenges {
+    inner: Arc<Inner>,
+}
+
+impl Challenges {
+    // Load key and consumed challenges from the state file, if configured,
+    // or start with a new key
+    pub fn new(config: ChallengeConfig) -> Result<Self> {
+        let (key, mut consumed) = match &config.state_file {
+            Some(path) if path.exists() => load_state(path)?,
+            _ => {
+                let mut key = [0; KEY_LEN];
+                OsRng.fill_bytes(&mut key);
+                (key, HashMap::new())
+            }
+        };
+        let now = unix_time(SystemTime::now());
+        consumed.retain(|_, expires_at| *expires_at > now);
+        let file = match &config.state_file {
+            Some(path) => Some(write_state(path, &key, &consumed)?),
+            None => None,
+        };
+        Ok(Challenges {
+            inner: Arc::new(Inner {
+                config,
+                key,
+                state: Mutex::new(State { consumed, file }),
+            }),
+        })
+    }
+
+    pub fn strict(&self) -> bool {
+        self.inner.config.strict
+    }
+
+    pub fn ttl(&self) -> Duration {
+        self.inner.config.ttl
+    }
+
+    pub fn issue(&self) -> Challenge {
+        self.issue_at(SystemTime::now())
+    }
+
+    fn issue_at(&self, now: SystemTime) -> Challenge {
+        let expires_at = now + self.inner.config.ttl;
+        let mut nonce = vec![0; RANDOM_LEN];
+        OsRng.fill_bytes(&mut nonce);
+        nonce.extend_from_slice(&unix_time(now).to_be_bytes());
+        nonce.extend_from_slice(&unix_time(expires_at).to_be_bytes());
+        let mac = self.mac(&nonce).finalize().into_bytes();
+        nonce.extend_from_slice(&mac);
+        Challenge { nonce, expires_at }
+    }
+
+    // Check the nonce of a quote request. A valid challenge is consumed; in
+    // strict mode any other nonce is rejected. With a state file, the check
+    // runs on the blocking pool, as consuming a challenge syncs the file.
+    pub async fn check(&self, nonce: &[u8]) -> Result<()> {
+        if self.inner.config.state_file.is_none() {
+            return self.check_at(nonce, SystemTime::now());
+        }
+        let challenges = self.clone();
+        let nonce = nonce.to_vec();
+        tokio::task::spawn_blocking(move || challenges.check_at(&nonce, SystemTime::now()))
+            .await
+            .context("[Challenges] challenge check failed")?
+    }
+
+    fn check_at(&self, nonce: &[u8], now: SystemTime) -> Result<()> {
+        let signed = match self.verify(nonce) {
+            Some(signed) => signed,
+            None if self.strict() => {
+                return Err(QuoteError::InvalidChallenge(
+                    "nonce is not a challenge issued by this server".to_string(),
+                )
+                .into())
+            }
+            None => return Ok(()),
+        };
+        let expires_at = u64::from_be_bytes(signed[24..32].try_into().unwrap());
+        let now = unix_time(now);
+        if expires_at <= now {
+            return Err(QuoteError::ChallengeExpired.into());
+        }
+
+        let mac: [u8; 32] = nonce[SIGNED_LEN..].try_into().unwrap();
+        let mut state = self.inner.state.lock().unwrap();
+        if state.consumed.contains_key(&mac) {
+            return Err(QuoteError::ChallengeReused.into());
+        }
+        let limit = self.inner.config.max_consumed;
+        if state.consumed.len() >= limit {
+            state.consumed.retain(|_, expires_at| *expires_at > now);
+            if state.consumed.len() >= limit {
+                return Err(QuoteError::ChallengeStoreFull { limit }.into());
+            }
+            if let Some(path) = &self.inner.config.state_file {
+                state.file = Some(write_state(path, &self.inner.key, &state.consumed)?);
+            }
+        }
+        if let Some(file) = &mut state.file {
+            writeln!(file, "{} {}", base64::encode(mac), expires_at)
+                .and_then(|_| file.sync_data())
+                .context("[Challenges] failed to persist consumed challenge")?;
+        }
+        state.consumed.insert(mac, expires_at);
+        Ok(())
+    }
+
+    // The signed part of a challenge with a valid HMAC
+    fn verify<'a>(&self, nonce: &'a [u8]) -> Option<&'a [u8]> {
+        if nonce.len() != CHALLENGE_LEN {
+            return None;
+        }
+        let (signed, mac) = nonce.split_at(SIGNED_LEN);
+        self.mac(signed).verify_slice(mac).ok()?;
+        Some(signed)
+    }
+
+    fn mac(&self, signed: &[u8]) -> HmacSha256 {
+        let mut mac = HmacSha256::new_from_slice(&self.inner.key).unwrap();
+        mac.update(signed);
+        mac
+    }
+
+    // Number of consumed challenges kept, including expired ones not yet
+    // pruned
+    pub fn consumed(&self) -> usize {
+        self.inner.state.lock().unwrap().consumed.len()
+    }
+}
+
+fn unix_time(t: SystemTime) -> u64 {
+    t.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs()
+}
+
+fn load_state(path: &Path) -> Result<([u8; KEY_LEN], Consumed)> {
+    let invalid = || anyhow::anyhow!("[Challenges] invalid state file {}", path.display());
+    let content = fs::read_to_string(path)
+        .with_context(|| format!("[Challenges] failed to read {}", path.display()))?;
+    let mut lines = content.lines();
+    let key = lines
+        .next()
+        .and_then(|l| l.strip_prefix("key "))
+        .and_then(|k| base64::decode(k).ok())
+        .and_then(|k| <[u8; KEY_LEN]>::try_from(k).ok())
+        .ok_or_else(invalid)?;
+    let mut consumed = HashMap::new();
+    for line in lines {
+        // a line cut short by a crash while appending is skipped
+        let entry = line.split_once(' ').and_then(|(mac, expires_at)| {
+            let mac = base64::decode(mac).ok()?.try_into().ok()?;
+            Some((mac, expires_at.parse().ok()?))
+        });
+        if let Some((mac, expires_at)) = entry {
+            consumed.insert(mac, expires_at);
+        }
+    }
+    Ok((key, consumed))
+}
+
+// Replace the state file with the given state and open it for appending
+fn write_state(path: &Path, key: &[u8], consumed: &Consumed) -> Result<File> {
+    let mut content = format!("key {}\n", base64::encode(key));
+    for (mac, expires_at) in consumed {
+        content.push_str(&format!("{} {}\n", base64::encode(mac), expires_at));
+    }
+    let tmp = path.with_extension("tmp");
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(&tmp)
+        .with_context(|| format!("[Challenges] failed to write {}", tmp.display()))?;
+    file.write_all(content.as_bytes())
+        .and_then(|_| file.sync_all())
+        .with_context(|| format!("[Challenges] failed to write {}", tmp.display()))?;
+    fs::rename(&tmp, path)
+        .with_context(|| format!("[Challenges] failed to replace {}", path.display()))?;
+    OpenOptions::new()
+        .append(true)
+        .open(path)
+        .with_context(|| format!("[Challenges] failed to open {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn challenges(strict: bool, max_consumed: usize) -> Challenges {
+        Challenges::new(ChallengeConfig {
+            strict,
+            max_consumed,
+            ..Default::default()
+        })
+        .unwrap()
+    }
+
+    fn reason(r: Result<()>) -> &'static str {
+        r.unwrap_err()
+            .downcast_ref::<QuoteError>()
+            .unwrap()
+            .reason()
+    }
+
+    #[test]
+    //a challenge is accepted once, until it expires
+    fn consume_once() {
+        let challenges = challenges(true, 16);
+        let now = SystemTime::now();
+        let challenge = challenges.issue_at(now);
+        assert_eq!(challenge.nonce.len(), CHALLENGE_LEN);
+        assert_eq!(
+            unix_time(challenge.expires_at),
+            unix_time(now) + DEFAULT_CHALLENGE_TTL.as_secs()
+        );
+        challenges.check_at(&challenge.nonce, now).unwrap();
+        assert_eq!(
+            reason(challenges.check_at(&challenge.nonce, now)),
+            "CHALLENGE_REUSED"
+        );
+
+        let challenge = challenges.issue_at(now);
+        let later = now + DEFAULT_CHALLENGE_TTL;
+        assert_eq!(
+            reason(challenges.check_at(&challenge.nonce, later)),
+            "CHALLENGE_EXPIRED"
+        );
+    }
+
+    #[tokio::test]
+    //strict mode rejects client nonces and forged challenges
+    async fn strict_mode() {
+        let strict = challenges(true, 16);
+        assert_eq!(reason(strict.check(b"12345678").await), "INVALID_CHALLENGE");
+        let mut nonce = strict.issue().nonce;
+        // extend the expiry
+        nonce[31] ^= 1;
+        assert_eq!(reason(strict.check(&nonce).await), "INVALID_CHALLENGE");
+        let other = challenges(true, 16).issue().nonce;
+        assert_eq!(reason(strict.check(&other).await), "INVALID_CHALLENGE");
+
+        let lax = challenges(false, 16);
+        lax.check(b"12345678").await.unwrap();
+        lax.check(b"12345678").await.unwrap();
+        let nonce = lax.issue().nonce;
+        lax.check(&nonce).await.unwrap();
+        assert_eq!(reason(lax.check(&nonce).await), "CHALLENGE_REUSED");
+    }
+
+    #[test]
+    //expired challenges make room in a full store
+    fn bounded_store() {
+        let challenges = challenges(true, 2);
+        let now = SystemTime::now();
+        for _ in 0..2 {
+            let nonce = challenges.issue_at(now).nonce;
+            challenges.check_at(&nonce, now).unwrap();
+        }
+        let nonce = challenges.issue_at(now).nonce;
+        assert_eq!(
+            reason(challenges.check_at(&nonce, now)),
+            "CHALLENGE_STORE_FULL"
+        );
+
+        let later = now + DEFAULT_CHALLENGE_TTL;
+        let nonce = challenges.issue_at(later).nonce;
+        challenges.check_at(&nonce, later).unwrap();
+        assert_eq!(challenges.consumed(), 1);
+    }
+
+    #[tokio::test]
+    //the state file keeps key and consumed challenges across restarts
+    async fn persistent_state() {
+        let path =
+            std::env::temp_dir().join(format!("quote-server-challenges-{}", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let config = ChallengeConfig {
+            strict: true,
+            state_file: Some(path.clone()),
+            ..Default::default()
+        };
+        let challenges = Challenges::new(config.clone()).unwrap();
+        let used = challenges.issue().nonce;
+        let unused = challenges.issue().nonce;
+        challenges.check(&used).await.unwrap();
+        drop(challenges);
+
+        let restarted = Challenges::new(config).unwrap();
+        assert_eq!(reason(restarted.check(&used).await), "CHALLENGE_REUSED");
+        restarted.check(&unused).await.unwrap();
+        let mode = fs::metadata(&path).unwrap().permissions();
+        assert_eq!(
+            std::os::unix::fs::PermissionsExt::mode(&mode) & 0o777,
+            0o600
+        );
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,418 @@
+/*
+* Copyright (c) 2023, Intel Corporation. All rights reserved.<BR>
+* SPDX-License-Identifier: Apache-2.0
+*/
+
+// Collateral to verify TDX quotes offline, fetched from a provisioning
+// certification caching service (PCCS) or the Intel PCS with the v4 API.
+// The PCK certificate chain comes from the quote itself; the FMSPC and CA
+// type of its leaf certificate select the TCB info and the PCK CRL. Fetched
+// collateral is cached per FMSPC and CA type. Once the FMSPC and CA type of
+// the node are known from a quote, later requests fetch the collateral
+// before their quote is generated.
+
+use crate::config::CollateralConfig;
+use crate::error::QuoteError;
+use anyhow::{Context, Result};
+use hyper::client::HttpConnector;
+use hyper::{Body, Client, StatusCode};
+use hyper_rustls::{HttpsConnector, HttpsConnectorBuilder};
+use quote_verifier::tdx::Quote;
+use rustls::{ClientConfig, RootCertStore};
+use std::collections::HashMap;
+use std::fs;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tracing::warn;
+use x509_parser::der_parser::{self, ber::BerObject};
+use x509_parser::pem::Pem;
+use x509_parser::prelude::{FromDer, X509Certificate};
+
+pub const DEFAULT_CACHE_TIME: Duration = Duration::from_secs(3600);
+
+// Intel SGX extensions of PCK certificates and the FMSPC within them
+const SGX_EXTENSIONS_OID: &str = "1.2.840.113741.1.13.1";
+const FMSPC_OID: &str = "1.2.840.113741.1.13.1.4";
+
+// Response headers carrying the URL-encoded PEM issuer chain
+const PCK_CRL_ISSUER_CHAIN: &str = "SGX-PCK-CRL-Issuer-Chain";
+const TCB_INFO_ISSUER_CHAIN: &str = "TCB-Info-Issuer-Chain";
+const QE_IDENTITY_ISSUER_CHAIN: &str = "SGX-Enclave-Identity-Issuer-Chain";
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Collateral {
+    pub pck_cert_chain: Vec<u8>,
+    pub pck_crl: Vec<u8>,
+    pub pck_crl_issuer_chain: Vec<u8>,
+    // Only served by a PCCS, empty otherwise
+    pub root_ca_crl: Vec<u8>,
+    pub tcb_info: Vec<u8>,
+    pub tcb_info_issuer_chain: Vec<u8>,
+    pub qe_identity: Vec<u8>,
+    pub qe_identity_issuer_chain: Vec<u8>,
+}
+
+// Collateral by FMSPC and CA type, with the time it was fetched
+type Cache = HashMap<(String, &'static str), (Instant, Collateral)>;
+
+struct Inner {
+    url: String,
+    client: Client<HttpsConnector<HttpConnector>, Body>,
+    cache_time: Duration,
+    cache: Mutex<Cache>,
+    // FMSPC and CA type of the node, from the last quote collateral was
+    // fetched for
+    platform: Mutex<Option<(String, &'static str)>>,
+}
+
+#[derive(Clone)]
+pub struct CollateralClient {
+    inner: Arc<Inner>,
+}
+
+impl CollateralClient {
+    // Client for the collateral service of the config, None if unset
+    pub fn new(config: &CollateralConfig) -> Result<Option<Self>> {
+        let url = match &config.url {
+            Some(url) => url.trim_end_matches('/').to_string(),
+            None => return Ok(None),
+        };
+        let mut roots = RootCertStore::empty();
+        match &config.ca {
+            Some(path) => {
+                let pem = fs::read(path)
+                    .with_context(|| format!("[Collateral] failed to read {}", path.display()))?;
+                let certs = Pem::iter_from_buffer(&pem)
+                    .map(|p| p.map(|p| p.contents))
+                    .collect::<Result<Vec<_>, _>>()
+                    .with_context(|| format!("[Collateral] invalid PEM {}", path.display()))?;
+                roots.add_parsable_certificates(&certs);
+            }
+            None => {
+                let certs = rustls_native_certs::load_native_certs()
+                    .context("[Collateral] failed to load the system CA certificates")?;
+                roots
+                    .add_parsable_certificates(&certs.into_iter().map(|c| c.0).collect::<Vec<_>>());
+            }
+        }
+        let tls = ClientConfig::builder()
+            .with_safe_defaults()
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+        let connector = HttpsConnectorBuilder::new()
+            .with_tls_config(tls)
+            .https_or_http()
+            .enable_http1()
+            .build();
+        Ok(Some(CollateralClient {
+            inner: Arc::new(Inner {
+                url,
+                client: Client::builder().build(connector),
+                cache_time: Duration::from_secs(config.cache_secs),
+                cache: Mutex::new(HashMap::new()),
+                platform: Mutex::new(None),
+            }),
+        }))
+    }
+
+    // Collateral to verify `quote`
+    pub async fn fetch(&self, quote: &[u8]) -> Result<Collateral> {
+        let chain = Quote::parse(quote)
+            .ok()
+            .and_then(|q| q.pck_cert_chain())
+            .ok_or_else(|| unavailable("quote carries no PCK certificate chain"))?;
+        let (fmspc, ca) = pck_info(chain)?;
+        *self.inner.platform.lock().unwrap() = Some((fmspc.clone(), ca));
+        let mut collateral = self.fetch_cached(fmspc, ca).await?;
+        collateral.pck_cert_chain = chain.to_vec();
+        Ok(collateral)
+    }
+
+    // Make sure the collateral of the node is cached, so that a failing
+    // collateral service is reported before a quote is generated for nothing.
+    // Does nothing until the node is known from a first quote.
+    pub async fn prefetch(&self) -> Result<()> {
+        let platform = self.inner.platform.lock().unwrap().clone();
+        match platform {
+            Some((fmspc, ca)) => self.fetch_cached(fmspc, ca).await.map(|_| ()),
+            None => Ok(()),
+        }
+    }
+
+    async fn fetch_cached(&self, fmspc: String, ca: &'static str) -> Result<Collateral> {
+        let key = (fmspc, ca);
+        let cached = self.inner.cache.lock().unwrap().get(&key).cloned();
+        match cached {
+            Some((fetched, collateral)) if fetched.elapsed() < self.inner.cache_time => {
+                Ok(collateral)
+            }
+            _ => {
+                let collateral = self.fetch_uncached(&key.0, ca).await?;
+                self.inner
+                    .cache
+                    .lock()
+                    .unwrap()
+                    .insert(key, (Instant::now(), collateral.clone()));
+                Ok(collateral)
+            }
+        }
+    }
+
+    async fn fetch_uncached(&self, fmspc: &str, ca: &str) -> Result<Collateral> {
+        let pck_crl_path = format!("/sgx/certification/v4/pckcrl?ca={}", ca);
+        let tcb_info_path = format!("/tdx/certification/v4/tcb?fmspc={}", fmspc);
+        let (pck_crl, tcb_info, qe_identity, root_ca_crl) = tokio::join!(
+            self.get(&pck_crl_path, PCK_CRL_ISSUER_CHAIN),
+            self.get(&tcb_info_path, TCB_INFO_ISSUER_CHAIN),
+            self.get(
+                "/tdx/certification/v4/qe/identity",
+                QE_IDENTITY_ISSUER_CHAIN
+            ),
+            self.get("/sgx/certification/v4/rootcacrl", ""),
+        );
+        let (pck_crl, pck_crl_issuer_chain) = pck_crl?;
+        let (tcb_info, tcb_info_issuer_chain) = tcb_info?;
+        let (qe_identity, qe_identity_issuer_chain) = qe_identity?;
+        // the Intel PCS serves the root CA CRL from its distribution point only
+        let root_ca_crl = match root_ca_crl {
+            Ok((crl, _)) => crl,
+            Err(e) => {
+                warn!(error = %e, "root CA CRL not available");
+                Vec::new()
+            }
+        };
+        Ok(Collateral {
+            pck_cert_chain: Vec::new(),
+            pck_crl,
+            pck_crl_issuer_chain,
+            root_ca_crl,
+            tcb_info,
+            tcb_info_issuer_chain,
+            qe_identity,
+            qe_identity_issuer_chain,
+        })
+    }
+
+    // Body of a collateral resource and its decoded issuer chain header
+    async fn get(&self, path: &str, issuer_chain: &str) -> Result<(Vec<u8>, Vec<u8>)> {
+        let uri = format!("{}{}", self.inner.url, path);
+        let response = self
+            .inner
+            .client
+            .get(uri.parse().context("[Collateral] invalid collateral URL")?)
+            .await
+            .map_err(|e| unavailable(&format!("{}: {}", uri, e)))?;
+        if response.status() != StatusCode::OK {
+            return Err(unavailable(&format!("{}: {}", uri, response.status())));
+        }
+        let chain = response
+            .headers()
+            .get(issuer_chain)
+            .map(|v| percent_decode(v.as_bytes()))
+            .unwrap_or_default();
+        let body = hyper::body::to_bytes(response.into_body())
+            .await
+            .map_err(|e| unavailable(&format!("{}: {}", uri, e)))?;
+        Ok((body.to_vec(), chain))
+    }
+}
+
+fn unavailable(m: &str) -> anyhow::Error {
+    QuoteError::CollateralUnavailable(m.to_string()).into()
+}
+
+// FMSPC (hex) and CA type ("platform" or "processor") of the leaf of a PEM
+// PCK certificate chain
+pub fn pck_info(chain: &[u8]) -> Result<(String, &'static str)> {
+    let pem = Pem::iter_from_buffer(chain)
+        .next()
+        .and_then(|p| p.ok())
+        .ok_or_else(|| unavailable("invalid PCK certificate chain"))?;
+    let (_, cert) = X509Certificate::from_der(&pem.contents)
+        .map_err(|e| unavailable(&format!("invalid PCK certificate: {}", e)))?;
+    let issuer = cert.issuer().to_string();
+    let ca = if issuer.contains("Processor") {
+        "processor"
+    } else {
+        "platform"
+    };
+    let fmspc = cert
+        .extensions()
+        .iter()
+        .find(|e| e.oid.to_id_string() == SGX_EXTENSIONS_OID)
+        .and_then(|e| fmspc(e.value))
+        .ok_or_else(|| unavailable("PCK certificate has no FMSPC"))?;
+    Ok((fmspc, ca))
+}
+
+// The SGX extensions are a sequence of (OID, value) sequences
+fn fmspc(extensions: &[u8]) -> Option<String> {
+    let (_, extensions) = der_parser::parse_der(extensions).ok()?;
+    extensions.as_sequence().ok()?.iter().find_map(|entry| {
+        let entry: &Vec<BerObject> = entry.as_sequence().ok()?;
+        if entry.first()?.as_oid().ok()?.to_id_string() != FMSPC_OID {
+            return None;
+        }
+        let value = entry.get(1)?.as_slice().ok()?;
+        Some(value.iter().map(|b| format!("{:02X}", b)).collect())
+    })
+}
+
+// Decode the URL encoding of issuer chain headers, e.g. "%20" for a space
+fn percent_decode(value: &[u8]) -> Vec<u8> {
+    let mut decoded = Vec::with_capacity(value.len());
+    let mut i = 0;
+    while i < value.len() {
+        let hex = value
+            .get(i + 1..i + 3)
+            .and_then(|h| std::str::from_utf8(h).ok())
+            .and_then(|h| u8::from_str_radix(h, 16).ok());
+        match (value[i], hex) {
+            (b'%', Some(b)) => {
+                decoded.push(b);
+                i += 3;
+            }
+            (c, _) => {
+                decoded.push(c);
+                i += 1;
+            }
+        }
+    }
+    decoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hyper::service::{make_service_fn, service_fn};
+    use hyper::{Request, Response, Server};
+    use std::convert::Infallible;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    // DER of the SGX extensions holding only the FMSPC
+    fn sgx_extensions(fmspc: [u8; 6]) -> Vec<u8> {
+        let mut entry = vec![
+            0x06, 0x0a, 0x2a, 0x86, 0x48, 0x86, 0xf8, 0x4d, 0x01, 0x0d, 0x01, 0x04,
+        ];
+        entry.extend_from_slice(&[0x04, 0x06]);
+        entry.extend_from_slice(&fmspc);
+        let mut der = vec![0x30, entry.len() as u8 + 2, 0x30, entry.len() as u8];
+        der.extend_from_slice(&entry);
+        der
+    }
+
+    fn pck_cert_chain() -> String {
+        let mut params = rcgen::CertificateParams::new(vec![]);
+        params
+            .distinguished_name
+            .push(rcgen::DnType::CommonName, "Intel SGX PCK Processor CA");
+        params.custom_extensions = vec![rcgen::CustomExtension::from_oid_content(
+            &[1, 2, 840, 113741, 1, 13, 1],
+            sgx_extensions([0x00, 0x80, 0x6f, 0x05, 0x00, 0x00]),
+        )];
+        rcgen::Certificate::from_params(params)
+            .unwrap()
+            .serialize_pem()
+            .unwrap()
+    }
+
+    // version 4 quote carrying the PCK certificate chain
+    fn quote(chain: &[u8]) -> Vec<u8> {
+        let mut quote = vec![0; 48 + 584];
+        quote[0] = 4;
+        quote[2] = 2;
+        let mut signature_data = vec![0; 128];
+        signature_data.extend_from_slice(&5u16.to_le_bytes());
+        signature_data.extend_from_slice(&(chain.len() as u32).to_le_bytes());
+        signature_data.extend_from_slice(chain);
+        quote.extend_from_slice(&(signature_data.len() as u32).to_le_bytes());
+        quote.extend_from_slice(&signature_data);
+        quote
+    }
+
+    #[test]
+    //the FMSPC and CA type are read from the leaf PCK certificate
+    fn pck_info_of_chain() {
+        let (fmspc, ca) = pck_info(pck_cert_chain().as_bytes()).unwrap();
+        assert_eq!(fmspc, "00806F050000");
+        assert_eq!(ca, "processor");
+        assert!(pck_info(b"not a certificate").is_err());
+    }
+
+    #[test]
+    //issuer chain headers are URL-decoded
+    fn decode_issuer_chain() {
+        assert_eq!(
+            percent_decode(b"-----BEGIN%20CERTIFICATE-----%0A%zz"),
+            b"-----BEGIN CERTIFICATE-----\n%zz"
+        );
+    }
+
+    #[tokio::test]
+    //collateral is fetched once per FMSPC and CA type, then served from cache
+    async fn fetch_and_cache() {
+        let requests = Arc::new(AtomicUsize::new(0));
+        let counter = requests.clone();
+        let make_service = make_service_fn(move |_| {
+            let counter = counter.clone();
+            async move {
+                Ok::<_, Infallible>(service_fn(move |req: Request<Body>| {
+                    counter.fetch_add(1, Ordering::SeqCst);
+                    let uri = req.uri().to_string();
+                    let response = match uri.as_str() {
+                        "/sgx/certification/v4/pckcrl?ca=processor" => Response::builder()
+                            .header(PCK_CRL_ISSUER_CHAIN, "crl%20chain")
+                            .body(Body::from("crl")),
+                        "/tdx/certification/v4/tcb?fmspc=00806F050000" => Response::builder()
+                            .header(TCB_INFO_ISSUER_CHAIN, "tcb%20chain")
+                            .body(Body::from("{\"tcbInfo\":{}}")),
+                        "/tdx/certification/v4/qe/identity" => {
+                            Response::builder().body(Body::from("{\"enclaveIdentity\":{}}"))
+                        }
+                        _ => Response::builder().status(404).body(Body::empty()),
+                    };
+                    async move { Ok::<_, Infallible>(response.unwrap()) }
+                }))
+            }
+        });
+        let server = Server::bind(&([127, 0, 0, 1], 0).into()).serve(make_service);
+        let url = format!("http://{}/", server.local_addr());
+        tokio::spawn(server);
+
+        let client = CollateralClient::new(&CollateralConfig {
+            url: Some(url.clone()),
+            ..Default::default()
+        })
+        .unwrap()
+        .unwrap();
+        // nothing to prefetch before the node is known from a quote
+        client.prefetch().await.unwrap();
+        assert_eq!(requests.load(Ordering::SeqCst), 0);
+        let chain = pck_cert_chain();
+        let collateral = client.fetch(&quote(chain.as_bytes())).await.unwrap();
+        assert_eq!(collateral.pck_cert_chain, chain.as_bytes());
+        assert_eq!(collateral.pck_crl, b"crl");
+        assert_eq!(collateral.pck_crl_issuer_chain, b"crl chain");
+        assert_eq!(collateral.tcb_info_issuer_chain, b"tcb chain");
+        assert_eq!(collateral.qe_identity, b"{\"enclaveIdentity\":{}}");
+        assert!(collateral.root_ca_crl.is_empty());
+        assert_eq!(requests.load(Ordering::SeqCst), 4);
+
+        client.fetch(&quote(chain.as_bytes())).await.unwrap();
+        client.prefetch().await.unwrap();
+        assert_eq!(requests.load(Ordering::SeqCst), 4);
+        assert!(client.fetch(b"not a quote").await.is_err());
+
+        // without caching, every prefetch asks the service again
+        let uncached = CollateralClient::new(&CollateralConfig {
+            url: Some(url),
+            cache_secs: 0,
+            ..Default::default()
+        })
+        .unwrap()
+        .unwrap();
+        uncached.fetch(&quote(chain.as_bytes())).await.unwrap();
+        uncached.prefetch().await.unwrap();
+        assert_eq!(requests.load(Ordering::SeqCst), 12);
+    }
+}
@@ -0,0 +1,257 @@
+/*
+* Copyright (c) 2023, Intel Corporation. All rights reserved.<BR>
+* SPDX-License-Identifier: Apache-2.0
+*/
+
+use crate::challenge::{DEFAULT_CHALLENGE_TTL, DEFAULT_MAX_CONSUMED};
+use crate::collateral::DEFAULT_CACHE_TIME;
+use crate::prober::DEFAULT_PROBE_INTERVAL;
+use crate::shutdown::{DEFAULT_DRAIN_TIMEOUT, DEFAULT_PRE_STOP_DELAY};
+use crate::telemetry::LogFormat;
+use crate::watch::DEFAULT_MAX_WATCHES;
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use std::fs;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+
+pub const DEFAULT_SOCKET_PATH: &str = "/run/ccnp/uds/quote-server.sock";
+pub const DEFAULT_BATCH_MAX_SIZE: usize = 64;
+
+// Settings of quote-server, read from a TOML file. Command line options
+// override the values of the file.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields, default)]
+pub struct Config {
+    pub unix: UnixConfig,
+    pub tcp: Option<TcpConfig>,
+    // Authorization policy file, all callers are allowed if unset
+    pub policy: Option<PathBuf>,
+    pub bind_caller_identity: bool,
+    // Batch window in milliseconds, 0 disables batching
+    pub batch_window_ms: u64,
+    pub batch_max_size: usize,
+    // Seconds NOT_SERVING is reported on shutdown before new requests are
+    // refused
+    pub pre_stop_delay_secs: u64,
+    // Seconds in-flight requests may take to finish on shutdown
+    pub shutdown_timeout_secs: u64,
+    // Seconds between health probes, 0 disables probing
+    pub probe_interval_secs: u64,
+    // Probe with a full quote instead of a TDREPORT
+    pub probe_quote: bool,
+    pub log_format: LogFormat,
+    // Log user data and nonces in plaintext instead of their digest
+    pub log_sensitive: bool,
+    pub metrics: MetricsConfig,
+    // Only accept quote requests whose nonce is a challenge from GetChallenge
+    pub strict_challenges: bool,
+    // Seconds a challenge stays valid
+    pub challenge_ttl_secs: u64,
+    // Consumed challenges kept until they expire
+    pub challenge_max_consumed: usize,
+    // File keeping the challenge key and consumed challenges across
+    // restarts, kept in memory only if unset
+    pub challenge_state_file: Option<PathBuf>,
+    pub collateral: CollateralConfig,
+    // File keeping the runtime event log across restarts of the server. Runtime
+    // events are not extended if unset. It must not outlive the TD, e.g. be on
+    // a tmpfs.
+    pub runtime_event_log_file: Option<PathBuf>,
+    // WatchAttestation streams open at the same time
+    pub max_watches: usize,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            unix: UnixConfig::default(),
+            tcp: None,
+            policy: None,
+            bind_caller_identity: false,
+            batch_window_ms: 0,
+            batch_max_size: DEFAULT_BATCH_MAX_SIZE,
+            pre_stop_delay_secs: DEFAULT_PRE_STOP_DELAY.as_secs(),
+            shutdown_timeout_secs: DEFAULT_DRAIN_TIMEOUT.as_secs(),
+            probe_interval_secs: DEFAULT_PROBE_INTERVAL.as_secs(),
+            probe_quote: false,
+            log_format: LogFormat::Text,
+            log_sensitive: false,
+            metrics: MetricsConfig::default(),
+            strict_challenges: false,
+            challenge_ttl_secs: DEFAULT_CHALLENGE_TTL.as_secs(),
+            challenge_max_consumed: DEFAULT_MAX_CONSUMED,
+            challenge_state_file: None,
+            collateral: CollateralConfig::default(),
+            runtime_event_log_file: None,
+            max_watches: DEFAULT_MAX_WATCHES,
+        }
+    }
+}
+
+// Unix domain socket listener, ignored under systemd socket activation
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields, default)]
+pub struct UnixConfig {
+    pub path: PathBuf,
+    // Permission bits of the socket file, e.g. 0o660
+    pub mode: Option<u32>,
+    // User and group owning the socket file, by name or numeric ID
+    pub owner: Option<String>,
+    pub group: Option<String>,
+}
+
+impl Default for UnixConfig {
+    fn default() -> Self {
+        UnixConfig {
+            path: PathBuf::from(DEFAULT_SOCKET_PATH),
+            mode: None,
+            owner: None,
+            group: None,
+        }
+    }
+}
+
+// TCP listener. Clients must present a certificate issued by `client_ca`
+// and, if `allowed_clients` is not empty, match one of its entries by
+// subject common name, DNS or URI SAN, or "sha256:<hex>" fingerprint.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct TcpConfig {
+    pub address: SocketAddr,
+    pub cert: PathBuf,
+    pub key: PathBuf,
+    pub client_ca: PathBuf,
+    #[serde(default)]
+    pub allowed_clients: Vec<String>,
+}
+
+// Prometheus metrics endpoint, served over plain HTTP on a TCP address, a
+// Unix domain socket, or both. Disabled if neither is set.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields, default)]
+pub struct MetricsConfig {
+    pub address: Option<SocketAddr>,
+    pub unix: Option<UnixConfig>,
+}
+
+// Collateral service (PCCS or Intel PCS) for GetEvidence, e.g.
+// "https://localhost:8081". Collateral is not available if unset.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields, default)]
+pub struct CollateralConfig {
+    pub url: Option<String>,
+    // CA certificates in PEM trusted for the service instead of the system
+    // CAs, e.g. of a PCCS with a self-signed certificate
+    pub ca: Option<PathBuf>,
+    // Seconds fetched collateral is reused
+    pub cache_secs: u64,
+}
+
+impl Default for CollateralConfig {
+    fn default() -> Self {
+        CollateralConfig {
+            url: None,
+            ca: None,
+            cache_secs: DEFAULT_CACHE_TIME.as_secs(),
+        }
+    }
+}
+
+impl Config {
+    pub fn load(path: &Path) -> Result<Self> {
+        let content = fs::read_to_string(path)
+            .with_context(|| format!("[Config] failed to read {}", path.display()))?;
+        toml::from_str(&content)
+            .with_context(|| format!("[Config] invalid config {}", path.display()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    //missing settings take their defaults
+    fn parse_defaults() {
+        let config: Config = toml::from_str("").unwrap();
+        assert_eq!(config, Config::default());
+        assert_eq!(config.unix.path, PathBuf::from(DEFAULT_SOCKET_PATH));
+    }
+
+    #[test]
+    //a complete config file
+    fn parse_full() {
+        let config: Config = toml::from_str(
+            r#"
+policy = "/etc/ccnp/policy.toml"
+batch_window_ms = 10
+strict_challenges = true
+challenge_state_file = "/var/lib/ccnp/challenges"
+runtime_event_log_file = "/run/ccnp/runtime-events"
+max_watches = 8
+pre_stop_delay_secs = 0
+
+[unix]
+path = "/run/quote-server.sock"
+mode = 0o660
+owner = "root"
+group = "ccnp"
+
+[tcp]
+address = "0.0.0.0:40081"
+cert = "/etc/ccnp/tls/server.crt"
+key = "/etc/ccnp/tls/server.key"
+client_ca = "/etc/ccnp/tls/ca.crt"
+allowed_clients = ["verifier.example.com"]
+
+[metrics]
+address = "127.0.0.1:9090"
+
+[metrics.unix]
+path = "/run/ccnp/metrics.sock"
+
+[collateral]
+url = "https://localhost:8081"
+ca = "/etc/ccnp/pccs-ca.pem"
+"#,
+        )
+        .unwrap();
+        assert_eq!(config.unix.mode, Some(0o660));
+        assert_eq!(config.unix.group.as_deref(), Some("ccnp"));
+        assert_eq!(config.batch_window_ms, 10);
+        assert_eq!(config.batch_max_size, DEFAULT_BATCH_MAX_SIZE);
+        assert_eq!(config.pre_stop_delay_secs, 0);
+        assert_eq!(
+            config.shutdown_timeout_secs,
+            DEFAULT_DRAIN_TIMEOUT.as_secs()
+        );
+        assert!(config.strict_challenges);
+        assert_eq!(config.challenge_ttl_secs, DEFAULT_CHALLENGE_TTL.as_secs());
+        let tcp = config.tcp.unwrap();
+        assert_eq!(tcp.address.port(), 40081);
+        assert_eq!(tcp.allowed_clients, vec!["verifier.example.com"]);
+        assert_eq!(config.metrics.address.unwrap().port(), 9090);
+        assert_eq!(
+            config.metrics.unix.unwrap().path,
+            PathBuf::from("/run/ccnp/metrics.sock")
+        );
+        assert_eq!(
+            config.collateral.url.as_deref(),
+            Some("https://localhost:8081")
+        );
+        assert_eq!(config.collateral.cache_secs, DEFAULT_CACHE_TIME.as_secs());
+        assert_eq!(
+            config.runtime_event_log_file,
+            Some(PathBuf::from("/run/ccnp/runtime-events"))
+        );
+        assert_eq!(config.max_watches, 8);
+    }
+
+    #[test]
+    //a TCP listener without TLS material is rejected
+    fn reject_tcp_without_tls() {
+        assert!(toml::from_str::<Config>("[tcp]\naddress = \"0.0.0.0:40081\"\n").is_err());
+        assert!(toml::from_str::<Config>("socket = \"/tmp/x.sock\"\n").is_err());
+    }
+}
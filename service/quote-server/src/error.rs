@@ -0,0 +1,512 @@
+/*
+* Copyright (c) 2023, Intel Corporation. All rights reserved.<BR>
+* SPDX-License-Identifier: Apache-2.0
+*/
+
+use std::collections::HashMap;
+use std::fmt;
+use std::time::Duration;
+use tonic::{Code, Status};
+use tonic_types::{ErrorDetails, StatusExt};
+
+// Domain reported in the google.rpc.ErrorInfo detail of every error status
+pub const ERROR_DOMAIN: &str = "quoteserver.ccnp";
+
+// Delay suggested to clients in google.rpc.RetryInfo when the quote service is unavailable
+const RETRY_DELAY: Duration = Duration::from_secs(1);
+
+#[derive(Debug)]
+pub enum QuoteError {
+    // A request field is not base64 encoded
+    InvalidEncoding {
+        field: &'static str,
+        detail: String,
+    },
+    // A request field exceeds its documented size limit, sizes in bytes after decoding
+    TooLarge {
+        field: &'static str,
+        size: usize,
+        limit: usize,
+    },
+    // The requested report data binding is unknown or does not fit the request
+    InvalidBinding(String),
+    // The requested event log format is unknown
+    InvalidLogFormat(String),
+    // Strict mode: the nonce is not a challenge issued by this server
+    InvalidChallenge(String),
+    // The challenge used as nonce has expired
+    ChallengeExpired,
+    // The challenge used as nonce was already used for a quote
+    ChallengeReused,
+    // Too many consumed challenges are kept until they expire
+    ChallengeStoreFull {
+        limit: usize,
+    },
+    // Collateral was requested but no collateral service is configured
+    CollateralNotConfigured,
+    // The collateral service failed or does not know the platform
+    CollateralUnavailable(String),
+    // A field of a runtime event is not valid
+    InvalidEvent {
+        field: &'static str,
+        detail: String,
+    },
+    // The runtime event log holds the maximum number of events
+    RuntimeLogFull {
+        limit: usize,
+    },
+    // The IMA measurement list exceeds the size included in evidence
+    ImaLogTooLarge {
+        limit: usize,
+    },
+    // Runtime events are only extended into a log kept in a file
+    RuntimeLogNotPersistent,
+    // Runtime events were extended during every attempt to quote the logs
+    RuntimeLogBusy,
+    // Extending a runtime measurement register failed
+    ExtendFailed(String),
+    // The interval of a WatchAttestation stream is too short
+    InvalidInterval {
+        min: Duration,
+    },
+    // Too many WatchAttestation streams are open
+    TooManyWatches {
+        limit: usize,
+    },
+    // The TEE device node is missing or cannot be opened
+    DeviceNotFound(String),
+    // An ioctl of the TEE device failed, e.g. getting the TDREPORT
+    DeviceError(String),
+    // The quote generation service (QGS) is busy or not reachable
+    QuoteServiceUnavailable(String),
+    // The QGS answered with an error or a malformed response
+    QuoteServiceError(String),
+    // The TEE is detected but quote generation is not implemented for it
+    Unsupported(String),
+    // Quote generation did not finish within the request's time budget
+    DeadlineExceeded(Duration),
+    // Too many quote requests are already waiting to be served
+    ResourceExhausted {
+        queued: usize,
+        limit: usize,
+    },
+    // Caller identity binding is on but the process of the Unix socket peer
+    // is not visible, so its container and pod are unknown
+    IdentityUnresolved(String),
+    // The authorization policy does not allow the caller to use the method
+    PermissionDenied {
+        method: &'static str,
+        caller: String,
+    },
+}
+
+impl QuoteError {
+    pub fn code(&self) -> Code {
+        match self {
+            QuoteError::InvalidEncoding { .. }
+            | QuoteError::TooLarge { .. }
+            | QuoteError::InvalidBinding(_)
+            | QuoteError::InvalidLogFormat(_)
+            | QuoteError::InvalidChallenge(_)
+            | QuoteError::InvalidEvent { .. }
+            | QuoteError::InvalidInterval { .. } => Code::InvalidArgument,
+            QuoteError::ChallengeExpired
+            | QuoteError::ChallengeReused
+            | QuoteError::CollateralNotConfigured
+            | QuoteError::RuntimeLogNotPersistent
+            | QuoteError::ImaLogTooLarge { .. }
+            | QuoteError::IdentityUnresolved(_) => Code::FailedPrecondition,
+            QuoteError::CollateralUnavailable(_) => Code::Unavailable,
+            QuoteError::DeviceNotFound(_) => Code::FailedPrecondition,
+            QuoteError::QuoteServiceUnavailable(_) | QuoteError::RuntimeLogBusy => {
+                Code::Unavailable
+            }
+            QuoteError::DeviceError(_)
+            | QuoteError::QuoteServiceError(_)
+            | QuoteError::ExtendFailed(_) => Code::Internal,
+            QuoteError::Unsupported(_) => Code::Unimplemented,
+            QuoteError::DeadlineExceeded(_) => Code::DeadlineExceeded,
+            QuoteError::ResourceExhausted { .. }
+            | QuoteError::ChallengeStoreFull { .. }
+            | QuoteError::RuntimeLogFull { .. }
+            | QuoteError::TooManyWatches { .. } => Code::ResourceExhausted,
+            QuoteError::PermissionDenied { .. } => Code::PermissionDenied,
+        }
+    }
+
+    // Stable, machine-readable reason carried in google.rpc.ErrorInfo
+    pub fn reason(&self) -> &'static str {
+        match self {
+            QuoteError::InvalidEncoding { .. } => "INVALID_BASE64",
+            QuoteError::TooLarge { .. } => "FIELD_TOO_LARGE",
+            QuoteError::InvalidBinding(_) => "INVALID_REPORT_DATA_BINDING",
+            QuoteError::InvalidLogFormat(_) => "INVALID_EVENT_LOG_FORMAT",
+            QuoteError::InvalidChallenge(_) => "INVALID_CHALLENGE",
+            QuoteError::ChallengeExpired => "CHALLENGE_EXPIRED",
+            QuoteError::ChallengeReused => "CHALLENGE_REUSED",
+            QuoteError::ChallengeStoreFull { .. } => "CHALLENGE_STORE_FULL",
+            QuoteError::CollateralNotConfigured => "COLLATERAL_NOT_CONFIGURED",
+            QuoteError::CollateralUnavailable(_) => "COLLATERAL_UNAVAILABLE",
+            QuoteError::InvalidEvent { .. } => "INVALID_EVENT",
+            QuoteError::RuntimeLogFull { .. } => "RUNTIME_EVENT_LOG_FULL",
+            QuoteError::RuntimeLogNotPersistent => "RUNTIME_EVENT_LOG_NOT_PERSISTENT",
+            QuoteError::ImaLogTooLarge { .. } => "IMA_LOG_TOO_LARGE",
+            QuoteError::RuntimeLogBusy => "RUNTIME_EVENT_LOG_BUSY",
+            QuoteError::ExtendFailed(_) => "RTMR_EXTEND_FAILED",
+            QuoteError::InvalidInterval { .. } => "INVALID_WATCH_INTERVAL",
+            QuoteError::TooManyWatches { .. } => "TOO_MANY_WATCHES",
+            QuoteError::DeviceNotFound(_) => "TEE_DEVICE_NOT_FOUND",
+            QuoteError::DeviceError(_) => "TEE_DEVICE_ERROR",
+            QuoteError::QuoteServiceUnavailable(_) => "QGS_UNAVAILABLE",
+            QuoteError::QuoteServiceError(_) => "QGS_ERROR",
+            QuoteError::Unsupported(_) => "TEE_NOT_SUPPORTED",
+            QuoteError::DeadlineExceeded(_) => "DEADLINE_EXCEEDED",
+            QuoteError::ResourceExhausted { .. } => "QUEUE_FULL",
+            QuoteError::IdentityUnresolved(_) => "CALLER_IDENTITY_UNRESOLVED",
+            QuoteError::PermissionDenied { .. } => "CALLER_NOT_AUTHORIZED",
+        }
+    }
+
+    fn details(&self) -> ErrorDetails {
+        let mut metadata = HashMap::new();
+        let mut details = ErrorDetails::new();
+        match self {
+            QuoteError::InvalidEncoding { field, .. } => {
+                metadata.insert("field".to_string(), field.to_string());
+                details.add_bad_request_violation(*field, "must be base64 encoded");
+            }
+            QuoteError::TooLarge { field, size, limit } => {
+                metadata.insert("field".to_string(), field.to_string());
+                metadata.insert("size".to_string(), size.to_string());
+                metadata.insert("limit".to_string(), limit.to_string());
+                details.add_bad_request_violation(
+                    *field,
+                    format!("decoded size must not exceed {} bytes", limit),
+                );
+            }
+            QuoteError::InvalidBinding(m) => {
+                details.add_bad_request_violation("report_data_binding", m);
+            }
+            QuoteError::InvalidLogFormat(m) => {
+                details.add_bad_request_violation("event_log_format", m);
+            }
+            QuoteError::InvalidChallenge(m) => {
+                details.add_bad_request_violation("nonce", m);
+            }
+            QuoteError::ChallengeExpired | QuoteError::ChallengeReused => {
+                details.add_precondition_failure_violation(
+                    "CHALLENGE",
+                    "nonce",
+                    "nonce must be an unused, unexpired challenge from GetChallenge",
+                );
+            }
+            QuoteError::ChallengeStoreFull { limit } => {
+                metadata.insert("limit".to_string(), limit.to_string());
+                details.add_quota_failure_violation(
+                    "consumed-challenges",
+                    format!("at most {} unexpired challenges may be used", limit),
+                );
+                details.set_retry_info(Some(RETRY_DELAY));
+            }
+            QuoteError::CollateralNotConfigured => {
+                details.add_precondition_failure_violation(
+                    "COLLATERAL_SERVICE",
+                    "collateral.url",
+                    "a collateral service must be configured",
+                );
+            }
+            QuoteError::CollateralUnavailable(_) => {
+                details.set_retry_info(Some(RETRY_DELAY));
+            }
+            QuoteError::InvalidEvent { field, detail } => {
+                metadata.insert("field".to_string(), field.to_string());
+                details.add_bad_request_violation(*field, detail);
+            }
+            QuoteError::RuntimeLogFull { limit } => {
+                // events are never removed, so retrying does not help
+                metadata.insert("limit".to_string(), limit.to_string());
+                details.add_quota_failure_violation(
+                    "runtime-events",
+                    format!("at most {} runtime events may be recorded", limit),
+                );
+            }
+            QuoteError::InvalidInterval { min } => {
+                metadata.insert("min_secs".to_string(), min.as_secs().to_string());
+                details.add_bad_request_violation(
+                    "interval_secs",
+                    format!("must be 0 or at least {}", min.as_secs()),
+                );
+            }
+            QuoteError::TooManyWatches { limit } => {
+                metadata.insert("limit".to_string(), limit.to_string());
+                details.add_quota_failure_violation(
+                    "watch-streams",
+                    format!("at most {} WatchAttestation streams may be open", limit),
+                );
+                details.set_retry_info(Some(RETRY_DELAY));
+            }
+            QuoteError::DeviceNotFound(_) => {
+                details.add_precondition_failure_violation(
+                    "TEE_DEVICE",
+                    "/dev/tdx_guest",
+                    "TEE device node must be present and accessible",
+                );
+            }
+            QuoteError::QuoteServiceUnavailable(_) | QuoteError::RuntimeLogBusy => {
+                details.set_retry_info(Some(RETRY_DELAY));
+            }
+            QuoteError::ImaLogTooLarge { limit } => {
+                metadata.insert("limit".to_string(), limit.to_string());
+                details.add_precondition_failure_violation(
+                    "IMA_LOG",
+                    "include_ima_log",
+                    format!("IMA measurement list must not exceed {} bytes", limit),
+                );
+            }
+            QuoteError::RuntimeLogNotPersistent => {
+                details.add_precondition_failure_violation(
+                    "RUNTIME_EVENT_LOG",
+                    "runtime_event_log_file",
+                    "runtime event log must be kept in a file",
+                );
+            }
+            QuoteError::IdentityUnresolved(_) => {
+                details.add_precondition_failure_violation(
+                    "CALLER_IDENTITY",
+                    "hostPID",
+                    "the process of the caller must be visible to the server",
+                );
+            }
+            QuoteError::DeadlineExceeded(budget) => {
+                metadata.insert("budget_ms".to_string(), budget.as_millis().to_string());
+            }
+            QuoteError::ResourceExhausted { limit, .. } => {
+                metadata.insert("limit".to_string(), limit.to_string());
+                details.add_quota_failure_violation(
+                    "quote-queue",
+                    format!("at most {} quote requests may wait", limit),
+                );
+                details.set_retry_info(Some(RETRY_DELAY));
+            }
+            QuoteError::PermissionDenied { method, .. } => {
+                metadata.insert("method".to_string(), method.to_string());
+            }
+            QuoteError::DeviceError(_)
+            | QuoteError::QuoteServiceError(_)
+            | QuoteError::ExtendFailed(_)
+            | QuoteError::Unsupported(_) => {}
+        }
+        details.set_error_info(self.reason(), ERROR_DOMAIN, metadata);
+        details
+    }
+}
+
+impl fmt::Display for QuoteError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            QuoteError::InvalidEncoding { field, detail } => {
+                write!(f, "{} is not base64 encoded: {}", field, detail)
+            }
+            QuoteError::TooLarge { field, size, limit } => write!(
+                f,
+                "{} is {} bytes, exceeding the limit of {} bytes",
+                field, size, limit
+            ),
+            QuoteError::InvalidBinding(m) => write!(f, "invalid report data binding: {}", m),
+            QuoteError::InvalidLogFormat(m) => write!(f, "invalid event log format: {}", m),
+            QuoteError::InvalidChallenge(m) => write!(f, "invalid challenge: {}", m),
+            QuoteError::ChallengeExpired => write!(f, "challenge has expired"),
+            QuoteError::ChallengeReused => write!(f, "challenge was already used"),
+            QuoteError::ChallengeStoreFull { limit } => write!(
+                f,
+                "too many challenges in use: {} unexpired challenges consumed",
+                limit
+            ),
+            QuoteError::CollateralNotConfigured => {
+                write!(f, "no collateral service configured")
+            }
+            QuoteError::CollateralUnavailable(m) => write!(f, "collateral not available: {}", m),
+            QuoteError::InvalidEvent { field, detail } => {
+                write!(f, "invalid event {}: {}", field, detail)
+            }
+            QuoteError::RuntimeLogFull { limit } => {
+                write!(f, "runtime event log is full: {} events recorded", limit)
+            }
+            QuoteError::RuntimeLogNotPersistent => {
+                write!(f, "runtime event log is not kept in a file")
+            }
+            QuoteError::ImaLogTooLarge { limit } => write!(
+                f,
+                "IMA measurement list exceeds the limit of {} bytes",
+                limit
+            ),
+            QuoteError::RuntimeLogBusy => {
+                write!(
+                    f,
+                    "runtime events kept being extended while quoting the logs"
+                )
+            }
+            QuoteError::ExtendFailed(m) => write!(f, "RTMR extend failed: {}", m),
+            QuoteError::InvalidInterval { min } => write!(
+                f,
+                "watch interval must be 0 or at least {} seconds",
+                min.as_secs()
+            ),
+            QuoteError::TooManyWatches { limit } => {
+                write!(f, "too many watch streams: {} open", limit)
+            }
+            QuoteError::DeviceNotFound(m) => write!(f, "TEE device not available: {}", m),
+            QuoteError::DeviceError(m) => write!(f, "TEE device error: {}", m),
+            QuoteError::QuoteServiceUnavailable(m) => {
+                write!(f, "quote generation service unavailable: {}", m)
+            }
+            QuoteError::QuoteServiceError(m) => {
+                write!(f, "quote generation service error: {}", m)
+            }
+            QuoteError::Unsupported(m) => write!(f, "{}", m),
+            QuoteError::DeadlineExceeded(budget) => write!(
+                f,
+                "quote generation did not finish within {} ms",
+                budget.as_millis()
+            ),
+            QuoteError::ResourceExhausted { queued, limit } => write!(
+                f,
+                "quote request queue is full: {} of {} waiting",
+                queued, limit
+            ),
+            QuoteError::IdentityUnresolved(caller) => write!(
+                f,
+                "workload of {} cannot be resolved to bind its identity",
+                caller
+            ),
+            QuoteError::PermissionDenied { method, caller } => {
+                write!(f, "{} is not allowed to call {}", caller, method)
+            }
+        }
+    }
+}
+
+impl std::error::Error for QuoteError {}
+
+impl From<&QuoteError> for Status {
+    fn from(e: &QuoteError) -> Self {
+        Status::with_error_details(e.code(), e.to_string(), e.details())
+    }
+}
+
+// Convert any error raised on the quote path into a gRPC status. Errors that
+// are not a QuoteError are unexpected and reported as INTERNAL.
+pub fn to_status(e: &anyhow::Error) -> Status {
+    match e.downcast_ref::<QuoteError>() {
+        Some(qe) => qe.into(),
+        None => {
+            let mut details = ErrorDetails::new();
+            details.set_error_info("INTERNAL", ERROR_DOMAIN, HashMap::new());
+            Status::with_error_details(Code::Internal, e.to_string(), details)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    //invalid base64 maps to INVALID_ARGUMENT with a bad request violation
+    fn invalid_encoding_to_status() {
+        let e = anyhow::Error::new(QuoteError::InvalidEncoding {
+            field: "nonce",
+            detail: "Invalid byte 94, offset 2.".to_string(),
+        });
+        let status = to_status(&e);
+        assert_eq!(status.code(), Code::InvalidArgument);
+
+        let details = status.get_error_details();
+        let violations = &details.bad_request().unwrap().field_violations;
+        assert_eq!(violations[0].field, "nonce");
+        assert_eq!(details.error_info().unwrap().reason, "INVALID_BASE64");
+    }
+
+    #[test]
+    //oversized field reports its size and limit in the error info metadata
+    fn too_large_to_status() {
+        let e = anyhow::Error::new(QuoteError::TooLarge {
+            field: "user_data",
+            size: 5000,
+            limit: 4096,
+        });
+        let status = to_status(&e);
+        assert_eq!(status.code(), Code::InvalidArgument);
+
+        let details = status.get_error_details();
+        let info = details.error_info().unwrap();
+        assert_eq!(info.reason, "FIELD_TOO_LARGE");
+        assert_eq!(info.domain, ERROR_DOMAIN);
+        assert_eq!(info.metadata.get("limit").unwrap(), "4096");
+    }
+
+    #[test]
+    //missing device maps to FAILED_PRECONDITION
+    fn device_not_found_to_status() {
+        let e = anyhow::Error::new(QuoteError::DeviceNotFound("no device".to_string()));
+        let status = to_status(&e);
+        assert_eq!(status.code(), Code::FailedPrecondition);
+        assert!(status.get_error_details().precondition_failure().is_some());
+    }
+
+    #[test]
+    //QGS failure maps to UNAVAILABLE with a retry hint
+    fn quote_service_unavailable_to_status() {
+        let e = anyhow::Error::new(QuoteError::QuoteServiceUnavailable(
+            "QGS response error".to_string(),
+        ));
+        let status = to_status(&e);
+        assert_eq!(status.code(), Code::Unavailable);
+
+        let details = status.get_error_details();
+        assert_eq!(details.retry_info().unwrap().retry_delay, Some(RETRY_DELAY));
+        assert_eq!(details.error_info().unwrap().reason, "QGS_UNAVAILABLE");
+    }
+
+    #[test]
+    //full queue maps to RESOURCE_EXHAUSTED with quota and retry details
+    fn resource_exhausted_to_status() {
+        let e = anyhow::Error::new(QuoteError::ResourceExhausted {
+            queued: 64,
+            limit: 64,
+        });
+        let status = to_status(&e);
+        assert_eq!(status.code(), Code::ResourceExhausted);
+
+        let details = status.get_error_details();
+        assert!(details.quota_failure().is_some());
+        assert!(details.retry_info().is_some());
+        assert_eq!(details.error_info().unwrap().reason, "QUEUE_FULL");
+    }
+
+    #[test]
+    //reused challenges map to FAILED_PRECONDITION, so clients fetch a new one
+    fn challenge_reused_to_status() {
+        let status = to_status(&anyhow::Error::new(QuoteError::ChallengeReused));
+        assert_eq!(status.code(), Code::FailedPrecondition);
+
+        let details = status.get_error_details();
+        assert!(details.precondition_failure().is_some());
+        assert_eq!(details.error_info().unwrap().reason, "CHALLENGE_REUSED");
+    }
+
+    #[test]
+    //QuoteError wrapped with context is still mapped by its variant
+    fn context_keeps_code() {
+        let e = anyhow::Error::new(QuoteError::Unsupported("TPM to be supported!".to_string()))
+            .context("[get_quote]");
+        assert_eq!(to_status(&e).code(), Code::Unimplemented);
+    }
+
+    #[test]
+    //errors other than QuoteError map to INTERNAL
+    fn other_error_to_status() {
+        let e = anyhow::anyhow!("unexpected");
+        assert_eq!(to_status(&e).code(), Code::Internal);
+    }
+}
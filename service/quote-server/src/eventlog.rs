@@ -0,0 +1,647 @@
+/*
+* Copyright (c) 2023, Intel Corporation. All rights reserved.<BR>
+* SPDX-License-Identifier: Apache-2.0
+*/
+
+// Event logs included in evidence bundles
+
+use crate::error::QuoteError;
+use anyhow::{Context, Result};
+use quote_verifier::cel;
+use quote_verifier::ima::{self, MeasurementList};
+use quote_verifier::replay::{self, Replay};
+use quote_verifier::runtime::{self, RuntimeEvent};
+use quote_verifier::tcg::{self, CcelTable, TPM_ALG_SHA1, TPM_ALG_SHA256, TPM_ALG_SHA384};
+use quote_verifier::tdx::TdReport;
+use std::fs::{self, File};
+use std::io::{ErrorKind, Read, Write};
+use std::path::Path;
+use tracing::{error, warn};
+
+// Data of the ACPI table of the TDX confidential computing event log (CCEL),
+// the boot event log recorded by the firmware. Containers see the host path
+// mounted under /run.
+pub const CCEL_DATA_PATHS: [&str; 2] = [
+    "/run/firmware/acpi/tables/data/CCEL",
+    "/sys/firmware/acpi/tables/data/CCEL",
+];
+// The CCEL ACPI table locating the log area
+pub const CCEL_TABLE_PATHS: [&str; 2] = [
+    "/run/firmware/acpi/tables/CCEL",
+    "/sys/firmware/acpi/tables/CCEL",
+];
+// Boot event log of a (v)TPM, indexed by PCR. The TDX quote does not cover
+// it, so it is never part of evidence bundles.
+pub const TPM_LOG_PATH: &str = "/sys/kernel/security/tpm0/binary_bios_measurements";
+
+// Encoding of the event logs: TCG PC Client crypto agile log format, as
+// recorded, or TCG Canonical Event Log, see quote_verifier::cel
+pub const FORMAT_TCG_PC_CLIENT: &str = "tcg-pc-client";
+pub const FORMAT_CEL_JSON: &str = "cel-json";
+pub const FORMAT_CEL_CBOR: &str = "cel-cbor";
+// Encoding of the IMA measurement list: the binary list of the kernel with
+// template hashes of the algorithm named, see quote_verifier::ima
+pub const FORMAT_IMA_SHA1: &str = "ima-binary-sha1";
+pub const FORMAT_IMA_SHA256: &str = "ima-binary-sha256";
+pub const FORMAT_IMA_SHA384: &str = "ima-binary-sha384";
+
+// IMA measurement lists of the kernel, in order of preference: the lists of
+// the SHA-384 and SHA-256 banks, and the SHA-1 list of all kernels
+pub const IMA_LOG_PATHS: [(&str, &str); 3] = [
+    (
+        "/sys/kernel/security/ima/binary_runtime_measurements_sha384",
+        FORMAT_IMA_SHA384,
+    ),
+    (
+        "/sys/kernel/security/ima/binary_runtime_measurements_sha256",
+        FORMAT_IMA_SHA256,
+    ),
+    (
+        "/sys/kernel/security/ima/binary_runtime_measurements",
+        FORMAT_IMA_SHA1,
+    ),
+];
+
+// Limits of runtime events, sizes in bytes
+pub const MAX_EVENT_NAME_SIZE: usize = 255;
+pub const MAX_EVENT_CONTENT_SIZE: usize = 4096;
+pub const MAX_RUNTIME_EVENTS: usize = 16384;
+// Limit of the IMA measurement list in evidence, in bytes, so that responses
+// stay below the 4 MiB message limit of gRPC clients
+pub const MAX_IMA_LOG_SIZE: usize = 2 * 1024 * 1024;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum EventLogKind {
+    Boot,
+    Runtime,
+    Ima,
+}
+
+impl EventLogKind {
+    // Name of the kind in the evidence digest, see quote_verifier::evidence
+    pub fn name(&self) -> &'static str {
+        match self {
+            EventLogKind::Boot => "boot",
+            EventLogKind::Runtime => "runtime",
+            EventLogKind::Ima => "ima",
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct EventLog {
+    pub kind: EventLogKind,
+    pub format: &'static str,
+    pub data: Vec<u8>,
+}
+
+// The CCEL, the boot event log the TDX quote covers through MRTD and the
+// RTMRs. The TPM log of a vTPM is not used: it is indexed by PCR and not
+// covered by the quote.
+pub fn boot_log_path() -> Option<&'static str> {
+    CCEL_DATA_PATHS
+        .into_iter()
+        .find(|path| Path::new(path).exists())
+}
+
+// The CCEL ACPI table, None if the firmware does not expose one
+pub fn read_ccel_table() -> Result<Option<CcelTable>> {
+    let path = match CCEL_TABLE_PATHS
+        .into_iter()
+        .find(|path| Path::new(path).exists())
+    {
+        Some(path) => path,
+        None => return Ok(None),
+    };
+    let table =
+        fs::read(path).with_context(|| format!("[read_ccel_table] failed to read {}", path))?;
+    let table = tcg::parse_ccel_table(&table)
+        .with_context(|| format!("[read_ccel_table] failed to parse {}", path))?;
+    Ok(Some(table))
+}
+
+// The boot event log as recorded by the firmware, None if the firmware does
+// not expose one
+pub fn read_boot_log() -> Result<Option<EventLog>> {
+    let path = match boot_log_path() {
+        Some(path) => path,
+        None => return Ok(None),
+    };
+    let mut data =
+        fs::read(path).with_context(|| format!("[read_boot_log] failed to read {}", path))?;
+    if let Some(table) = read_ccel_table()? {
+        data.truncate(table.log_area_length.try_into().unwrap_or(usize::MAX));
+    }
+    Ok(Some(EventLog {
+        kind: EventLogKind::Boot,
+        format: FORMAT_TCG_PC_CLIENT,
+        data: strip_padding(data),
+    }))
+}
+
+// The boot event log of the TPM in the TCG crypto agile format, None if the
+// node has no TPM or securityfs is not mounted
+pub fn read_tpm_log() -> Result<Option<EventLog>> {
+    read_tpm_file(Path::new(TPM_LOG_PATH))
+}
+
+fn read_tpm_file(path: &Path) -> Result<Option<EventLog>> {
+    let data = match fs::read(path) {
+        Ok(data) => data,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+        Err(e) => {
+            return Err(e)
+                .with_context(|| format!("[read_tpm_log] failed to read {}", path.display()))
+        }
+    };
+    Ok(Some(EventLog {
+        kind: EventLogKind::Boot,
+        format: FORMAT_TCG_PC_CLIENT,
+        data: strip_padding(data),
+    }))
+}
+
+pub fn ima_log_path() -> Option<(&'static str, &'static str)> {
+    IMA_LOG_PATHS
+        .into_iter()
+        .find(|(path, _)| Path::new(path).exists())
+}
+
+// The IMA measurement list of the kernel, None if IMA is not enabled or
+// securityfs is not mounted
+pub fn read_ima_log() -> Result<Option<EventLog>> {
+    match ima_log_path() {
+        Some((path, format)) => read_ima_file(Path::new(path), format, MAX_IMA_LOG_SIZE).map(Some),
+        None => Ok(None),
+    }
+}
+
+fn read_ima_file(path: &Path, format: &'static str, limit: usize) -> Result<EventLog> {
+    // securityfs reports no file size, so read at most one byte over the limit
+    let mut data = Vec::new();
+    File::open(path)
+        .and_then(|file| file.take(limit as u64 + 1).read_to_end(&mut data))
+        .with_context(|| format!("[read_ima_log] failed to read {}", path.display()))?;
+    if data.len() > limit {
+        return Err(QuoteError::ImaLogTooLarge { limit }.into());
+    }
+    Ok(EventLog {
+        kind: EventLogKind::Ima,
+        format,
+        data,
+    })
+}
+
+// Drop the unused rest of the log area, keeping logs that cannot be parsed
+// as they are for the verifier to reject
+fn strip_padding(mut data: Vec<u8>) -> Vec<u8> {
+    match tcg::parse(&data) {
+        Ok(log) => data.truncate(log.len),
+        Err(e) => warn!(error = %e, "boot event log cannot be parsed"),
+    }
+    data
+}
+
+// The log in `format`, or as it is if it cannot be parsed. IMA measurement
+// lists are kept as they are.
+pub fn encode(log: EventLog, format: &'static str) -> EventLog {
+    if format == log.format || log.kind == EventLogKind::Ima {
+        return log;
+    }
+    let parsed = match parse(&log) {
+        Ok(parsed) => parsed,
+        Err(e) => {
+            warn!(error = ?e, format, "event log cannot be converted");
+            return log;
+        }
+    };
+    let data = match format {
+        FORMAT_CEL_JSON => cel::to_json(&parsed),
+        FORMAT_CEL_CBOR => cel::to_cbor(&parsed),
+        _ => return log,
+    };
+    EventLog {
+        kind: log.kind,
+        format,
+        data,
+    }
+}
+
+pub fn parse(log: &EventLog) -> Result<tcg::EventLog> {
+    let parsed = match log.format {
+        FORMAT_CEL_JSON => cel::from_json(&log.data)?,
+        FORMAT_CEL_CBOR => cel::from_cbor(&log.data)?,
+        _ => tcg::parse(&log.data)?,
+    };
+    Ok(parsed)
+}
+
+pub fn parse_ima(log: &EventLog) -> Result<MeasurementList> {
+    let algorithm = match log.format {
+        FORMAT_IMA_SHA1 => TPM_ALG_SHA1,
+        FORMAT_IMA_SHA256 => TPM_ALG_SHA256,
+        FORMAT_IMA_SHA384 => TPM_ALG_SHA384,
+        format => {
+            return Err(anyhow::anyhow!(
+                "[parse_ima] {} is not an IMA format",
+                format
+            ))
+        }
+    };
+    Ok(ima::parse_binary(&log.data, algorithm)?)
+}
+
+// Replay the event logs of an evidence bundle against the RTMRs of a TDREPORT
+// taken before its quote. The IMA measurement list is not replayed: the
+// register IMA extends depends on the kernel, see quote_verifier::ima.
+pub fn replay_evidence(logs: &[EventLog], report: &[u8]) -> Result<Replay> {
+    let parsed = logs
+        .iter()
+        .filter(|log| log.kind != EventLogKind::Ima)
+        .map(|log| {
+            parse(log).with_context(|| {
+                format!("[replay_evidence] failed to parse {} log", log.kind.name())
+            })
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let report = TdReport::parse(report).context("[replay_evidence] failed to parse TDREPORT")?;
+    let parsed: Vec<_> = parsed.iter().collect();
+    Ok(replay::replay(&parsed, report.rtmrs)?)
+}
+
+// Check the fields of a runtime event against their limits
+pub fn check_event(event: &RuntimeEvent) -> Result<()> {
+    for (field, value) in [("domain", &event.domain), ("event_type", &event.event_type)] {
+        if value.is_empty() {
+            return Err(QuoteError::InvalidEvent {
+                field,
+                detail: "must not be empty".to_string(),
+            }
+            .into());
+        }
+        crate::tee::check_size(field, value.as_bytes(), MAX_EVENT_NAME_SIZE)?;
+    }
+    crate::tee::check_size("content", &event.content, MAX_EVENT_CONTENT_SIZE)
+}
+
+// Runtime event log of the events quote-server extended into RTMR3, see
+// quote_verifier::runtime. With a file, each event is written and synced
+// before it is extended, so that a restarted server continues the log. The
+// file must not outlive the TD, e.g. by keeping it on a tmpfs under /run.
+pub struct RuntimeEventLog {
+    data: Vec<u8>,
+    // offset of each event in data
+    offsets: Vec<usize>,
+    file: Option<File>,
+}
+
+impl RuntimeEventLog {
+    pub fn new(path: Option<&Path>) -> Result<Self> {
+        let mut log = RuntimeEventLog {
+            data: runtime::log_header(),
+            offsets: Vec::new(),
+            file: None,
+        };
+        let path = match path {
+            Some(path) => path,
+            None => return Ok(log),
+        };
+
+        let context = || format!("[RuntimeEventLog] failed to load {}", path.display());
+        match fs::read(path) {
+            Ok(data) if !data.is_empty() => {
+                let events = runtime::parse_log(&data).with_context(context)?;
+                // records are self-delimiting, so offsets follow from the sizes
+                let mut offset = log.data.len();
+                for event in events {
+                    log.offsets.push(offset);
+                    offset += event.event.log_record(event.rtmr).len();
+                }
+                log.data = data;
+            }
+            Ok(_) => fs::write(path, &log.data).with_context(context)?,
+            Err(e) if e.kind() == ErrorKind::NotFound => {
+                fs::write(path, &log.data).with_context(context)?
+            }
+            Err(e) => return Err(e).with_context(context),
+        }
+        log.file = Some(
+            File::options()
+                .append(true)
+                .open(path)
+                .with_context(context)?,
+        );
+        Ok(log)
+    }
+
+    pub fn events(&self) -> usize {
+        self.offsets.len()
+    }
+
+    // Whether the log is kept in a file, so that it survives a restart of
+    // the server like the extends of RTMR3 do
+    pub fn persistent(&self) -> bool {
+        self.file.is_some()
+    }
+
+    // The log as included in evidence, None before the first event
+    pub fn log(&self) -> Option<EventLog> {
+        if self.offsets.is_empty() {
+            return None;
+        }
+        Some(EventLog {
+            kind: EventLogKind::Runtime,
+            format: FORMAT_TCG_PC_CLIENT,
+            data: self.data.clone(),
+        })
+    }
+
+    // Record the event and extend its digest into `rtmr` with `extend`,
+    // returning its index in the log. The event is recorded first and
+    // dropped again if the extend fails, so that the log never misses an
+    // extended event.
+    pub fn record<F>(&mut self, event: &RuntimeEvent, rtmr: u8, extend: F) -> Result<u64>
+    where
+        F: FnOnce(&[u8]) -> Result<()>,
+    {
+        let index = self.append(event, rtmr)?;
+        if let Err(e) = extend(&event.digest()) {
+            if let Err(revert) = self.revert() {
+                error!(error = ?revert, "runtime event log keeps an event that was not extended");
+            }
+            return Err(e);
+        }
+        Ok(index)
+    }
+
+    fn append(&mut self, event: &RuntimeEvent, rtmr: u8) -> Result<u64> {
+        if self.offsets.len() >= MAX_RUNTIME_EVENTS {
+            return Err(QuoteError::RuntimeLogFull {
+                limit: MAX_RUNTIME_EVENTS,
+            }
+            .into());
+        }
+        let record = event.log_record(rtmr);
+        let offset = self.data.len();
+        if let Some(file) = &mut self.file {
+            let written = file.write_all(&record).and_then(|_| file.sync_data());
+            if let Err(e) = written {
+                // drop a partial record, the log is unusable if that fails
+                file.set_len(offset as u64)
+                    .context("[RuntimeEventLog] failed to truncate")?;
+                return Err(e).context("[RuntimeEventLog] failed to write");
+            }
+        }
+        self.data.extend_from_slice(&record);
+        self.offsets.push(offset);
+        Ok(self.offsets.len() as u64 - 1)
+    }
+
+    // Drop the last recorded event
+    fn revert(&mut self) -> Result<()> {
+        let offset = match self.offsets.pop() {
+            Some(offset) => offset,
+            None => return Ok(()),
+        };
+        self.data.truncate(offset);
+        if let Some(file) = &self.file {
+            file.set_len(offset as u64)
+                .and_then(|_| file.sync_data())
+                .context("[RuntimeEventLog] failed to revert")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use quote_verifier::runtime::RUNTIME_RTMR;
+
+    #[test]
+    //IMA lists over the limit are rejected instead of read whole
+    fn ima_log_limit() {
+        let path =
+            std::env::temp_dir().join(format!("quote-server-ima-{}.log", std::process::id()));
+        fs::write(&path, [7; 16]).unwrap();
+        let log = read_ima_file(&path, FORMAT_IMA_SHA256, 16).unwrap();
+        assert_eq!((log.kind, log.data.len()), (EventLogKind::Ima, 16));
+        let e = read_ima_file(&path, FORMAT_IMA_SHA256, 15).unwrap_err();
+        assert!(matches!(
+            e.downcast_ref::<QuoteError>(),
+            Some(QuoteError::ImaLogTooLarge { limit: 15 })
+        ));
+        let _ = fs::remove_file(path);
+    }
+
+    #[test]
+    //the crypto agile TPM log is read as recorded, a missing one is None
+    fn tpm_log() {
+        let path = log_file("tpm");
+        assert_eq!(read_tpm_file(&path).unwrap(), None);
+
+        // Spec ID Event03 declaring SHA-256 and SHA-384, and one separator
+        let mut spec_id = tcg::SPEC_ID_SIGNATURE.to_vec();
+        spec_id.extend_from_slice(&0u32.to_le_bytes());
+        spec_id.extend_from_slice(&[0, 2, 0, 2]);
+        spec_id.extend_from_slice(&2u32.to_le_bytes());
+        for (algorithm, size) in [(TPM_ALG_SHA256, 32u16), (TPM_ALG_SHA384, 48)] {
+            spec_id.extend_from_slice(&algorithm.to_le_bytes());
+            spec_id.extend_from_slice(&size.to_le_bytes());
+        }
+        spec_id.push(0);
+        let mut data = 0u32.to_le_bytes().to_vec();
+        data.extend_from_slice(&tcg::EV_NO_ACTION.to_le_bytes());
+        data.extend_from_slice(&[0; 20]);
+        data.extend_from_slice(&(spec_id.len() as u32).to_le_bytes());
+        data.extend_from_slice(&spec_id);
+        data.extend_from_slice(&7u32.to_le_bytes());
+        data.extend_from_slice(&tcg::EV_SEPARATOR.to_le_bytes());
+        data.extend_from_slice(&2u32.to_le_bytes());
+        data.extend_from_slice(&TPM_ALG_SHA256.to_le_bytes());
+        data.extend_from_slice(&[1; 32]);
+        data.extend_from_slice(&TPM_ALG_SHA384.to_le_bytes());
+        data.extend_from_slice(&[2; 48]);
+        data.extend_from_slice(&4u32.to_le_bytes());
+        data.extend_from_slice(&[0; 4]);
+        fs::write(&path, &data).unwrap();
+
+        let log = read_tpm_file(&path).unwrap().unwrap();
+        assert_eq!(
+            (log.kind, log.format),
+            (EventLogKind::Boot, FORMAT_TCG_PC_CLIENT)
+        );
+        assert_eq!(log.data, data);
+        let parsed = tcg::parse(&log.data).unwrap();
+        assert_eq!(parsed.events.len(), 1);
+        assert_eq!(parsed.events[0].index, 7);
+        assert_eq!(parsed.events[0].digest(TPM_ALG_SHA384), Some(&[2; 48][..]));
+        let _ = fs::remove_file(path);
+    }
+
+    fn event(content: &[u8]) -> RuntimeEvent {
+        RuntimeEvent {
+            domain: "example.com".to_string(),
+            event_type: "model".to_string(),
+            content: content.to_vec(),
+        }
+    }
+
+    fn log_file(name: &str) -> std::path::PathBuf {
+        let path = std::env::temp_dir().join(format!("ccnp-{}-{}", name, std::process::id()));
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    fn extended(_: &[u8]) -> Result<()> {
+        Ok(())
+    }
+
+    fn not_extended(_: &[u8]) -> Result<()> {
+        Err(QuoteError::DeviceNotFound("no TEE device found".to_string()).into())
+    }
+
+    #[test]
+    //events are recorded in order, events that fail to extend leave no trace
+    fn record_and_revert() {
+        let mut log = RuntimeEventLog::new(None).unwrap();
+        assert!(log.log().is_none());
+        let digest = event(b"a").digest();
+        let index = log.record(&event(b"a"), RUNTIME_RTMR, |d| {
+            assert_eq!(d, digest);
+            Ok(())
+        });
+        assert_eq!(index.unwrap(), 0);
+        let one = log.log().unwrap().data;
+        assert!(log
+            .record(&event(b"b"), RUNTIME_RTMR, not_extended)
+            .is_err());
+        assert_eq!(log.events(), 1);
+        assert_eq!(log.log().unwrap().data, one);
+        let events = runtime::parse_log(&one).unwrap();
+        assert_eq!(events[0].event, event(b"a"));
+    }
+
+    #[test]
+    //a restarted server continues the log of its file
+    fn continue_from_file() {
+        let path = log_file("runtime-log");
+        let mut log = RuntimeEventLog::new(Some(&path)).unwrap();
+        log.record(&event(b"a"), RUNTIME_RTMR, extended).unwrap();
+        assert!(log
+            .record(&event(b"b"), RUNTIME_RTMR, not_extended)
+            .is_err());
+        drop(log);
+
+        let mut log = RuntimeEventLog::new(Some(&path)).unwrap();
+        assert_eq!(log.events(), 1);
+        let index = log.record(&event(b"c"), RUNTIME_RTMR, extended);
+        assert_eq!(index.unwrap(), 1);
+        let data = log.log().unwrap().data;
+        assert_eq!(fs::read(&path).unwrap(), data);
+        assert_eq!(runtime::parse_log(&data).unwrap().len(), 2);
+
+        // a file that is not a runtime event log is not overwritten
+        fs::write(&path, b"not a log").unwrap();
+        assert!(RuntimeEventLog::new(Some(&path)).is_err());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    //the padding of the log area is dropped, unparsable logs are kept
+    fn boot_log_padding() {
+        let mut log = runtime::log_header();
+        log.extend(event(b"a").log_record(RUNTIME_RTMR));
+        let mut padded = log.clone();
+        padded.resize(log.len() + 4096, 0xff);
+        assert_eq!(strip_padding(padded), log);
+        assert_eq!(strip_padding(b"not a log".to_vec()), b"not a log");
+    }
+
+    #[test]
+    //the runtime log replays to RTMR3 of the TDREPORT, also as CEL, and a
+    //missing extend is located
+    fn replay_runtime_log() {
+        let mut log = RuntimeEventLog::new(None).unwrap();
+        log.record(&event(b"a"), RUNTIME_RTMR, extended).unwrap();
+        log.record(&event(b"b"), RUNTIME_RTMR, extended).unwrap();
+        let logs = [log.log().unwrap()];
+
+        // TDREPORT whose RTMR3 reflects the first event only
+        let mut report = vec![0; 1024];
+        let rtmr3 = replay::extend(&[0; 48], &event(b"a").digest());
+        report[864..912].copy_from_slice(&rtmr3);
+
+        let replay = replay_evidence(&logs, &report).unwrap();
+        assert!(replay.rtmrs[..3].iter().all(|rtmr| rtmr.matches));
+        assert!(!replay.rtmrs[3].matches);
+        assert_eq!(replay.rtmrs[3].divergence.unwrap().event, 1);
+        assert!(replay_evidence(&logs, b"not a report").is_err());
+
+        // logs converted to CEL replay the same
+        for format in [FORMAT_CEL_JSON, FORMAT_CEL_CBOR] {
+            let cel = [encode(logs[0].clone(), format)];
+            assert_eq!(cel[0].format, format);
+            assert_eq!(replay_evidence(&cel, &report).unwrap(), replay);
+        }
+        let raw = EventLog {
+            kind: EventLogKind::Boot,
+            format: FORMAT_TCG_PC_CLIENT,
+            data: b"not a log".to_vec(),
+        };
+        assert_eq!(encode(raw.clone(), FORMAT_CEL_JSON), raw);
+    }
+
+    #[test]
+    //IMA lists are kept as recorded, checked by their template hashes and not
+    //replayed against the RTMRs
+    fn ima_log() {
+        let mut template_data = Vec::new();
+        let mut digest = b"sha384:\0".to_vec();
+        digest.extend_from_slice(&[7; 48]);
+        for field in [digest, b"/usr/bin/kmod\0".to_vec()] {
+            template_data.extend_from_slice(&(field.len() as u32).to_le_bytes());
+            template_data.extend(field);
+        }
+        let mut data = ima::IMA_PCR.to_le_bytes().to_vec();
+        data.extend(ima::hash(TPM_ALG_SHA384, &[&template_data]).unwrap());
+        data.extend_from_slice(&6u32.to_le_bytes());
+        data.extend_from_slice(b"ima-ng");
+        data.extend_from_slice(&(template_data.len() as u32).to_le_bytes());
+        data.extend(template_data);
+        let log = EventLog {
+            kind: EventLogKind::Ima,
+            format: FORMAT_IMA_SHA384,
+            data,
+        };
+        assert_eq!(encode(log.clone(), FORMAT_CEL_JSON), log);
+        let list = parse_ima(&log).unwrap();
+        assert_eq!(list.entries[0].file_name, "/usr/bin/kmod");
+        let sha1 = EventLog {
+            format: FORMAT_IMA_SHA1,
+            ..log.clone()
+        };
+        assert!(parse_ima(&sha1).is_err());
+
+        assert!(replay_evidence(&[log], &[0; 1024]).unwrap().matches());
+    }
+
+    #[test]
+    //event names must be set, all fields are limited in size
+    fn event_limits() {
+        assert!(check_event(&event(b"a")).is_ok());
+        let mut e = event(b"");
+        e.domain.clear();
+        match check_event(&e).unwrap_err().downcast_ref::<QuoteError>() {
+            Some(QuoteError::InvalidEvent { field, .. }) => assert_eq!(*field, "domain"),
+            e => panic!("unexpected error: {:?}", e),
+        }
+        match check_event(&event(&[0; MAX_EVENT_CONTENT_SIZE + 1]))
+            .unwrap_err()
+            .downcast_ref::<QuoteError>()
+        {
+            Some(QuoteError::TooLarge { field, .. }) => assert_eq!(*field, "content"),
+            e => panic!("unexpected error: {:?}", e),
+        }
+    }
+}
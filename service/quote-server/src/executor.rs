@@ -0,0 +1,199 @@
+/*
+* Copyright (c) 2023, Intel Corporation. All rights reserved.<BR>
+* SPDX-License-Identifier: Apache-2.0
+*/
+
+use crate::error::{self, QuoteError};
+use std::time::{Duration, Instant};
+use tonic::metadata::MetadataMap;
+use tonic::Status;
+
+// Upper bound for a single quote generation, applied when the client sets no
+// deadline or a longer one
+pub const DEFAULT_QUOTE_TIMEOUT: Duration = Duration::from_secs(30);
+
+const GRPC_TIMEOUT_HEADER: &str = "grpc-timeout";
+
+// Runs the synchronous quote generation ioctls on tokio's blocking pool so a
+// slow QGS round-trip never occupies an async worker thread.
+#[derive(Clone)]
+pub struct QuoteExecutor {
+    timeout: Duration,
+}
+
+impl QuoteExecutor {
+    pub fn new(timeout: Duration) -> Self {
+        QuoteExecutor { timeout }
+    }
+
+    // Server-side upper bound of a quote generation
+    pub fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    // Effective time budget of a request: the client's grpc-timeout capped by
+    // the server-side timeout
+    pub fn budget(&self, metadata: &MetadataMap) -> Duration {
+        match parse_grpc_timeout(metadata) {
+            Some(d) if d < self.timeout => d,
+            _ => self.timeout,
+        }
+    }
+
+    // Run `f` on the blocking pool within `budget`. Work still queued when the
+    // budget expires is skipped; work already running is detached and its
+    // result dropped, since an ioctl cannot be interrupted.
+    pub async fn run<F, T>(&self, budget: Duration, f: F) -> Result<T, Status>
+    where
+        F: FnOnce() -> anyhow::Result<T> + Send + 'static,
+        T: Send + 'static,
+    {
+        let deadline = Instant::now() + budget;
+        let task = tokio::task::spawn_blocking(move || {
+            if Instant::now() >= deadline {
+                return Err(QuoteError::DeadlineExceeded(budget).into());
+            }
+            f()
+        });
+
+        match tokio::time::timeout(budget, task).await {
+            Ok(Ok(result)) => result.map_err(|e| error::to_status(&e)),
+            Ok(Err(e)) => Err(error::to_status(&anyhow::anyhow!(
+                "[QuoteExecutor] quote task failed: {:?}",
+                e
+            ))),
+            Err(_) => Err((&QuoteError::DeadlineExceeded(budget)).into()),
+        }
+    }
+}
+
+// Parse the gRPC "grpc-timeout" request header, e.g. "100m" or "5S"
+fn parse_grpc_timeout(metadata: &MetadataMap) -> Option<Duration> {
+    let value = metadata.get(GRPC_TIMEOUT_HEADER)?.to_str().ok()?;
+    if value.len() < 2 || value.len() > 9 {
+        return None;
+    }
+    let (amount, unit) = value.split_at(value.len() - 1);
+    let amount: u64 = amount.parse().ok()?;
+    match unit {
+        "H" => Some(Duration::from_secs(amount * 60 * 60)),
+        "M" => Some(Duration::from_secs(amount * 60)),
+        "S" => Some(Duration::from_secs(amount)),
+        "m" => Some(Duration::from_millis(amount)),
+        "u" => Some(Duration::from_micros(amount)),
+        "n" => Some(Duration::from_nanos(amount)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tonic::Code;
+
+    fn metadata_with_timeout(value: &str) -> MetadataMap {
+        let mut metadata = MetadataMap::new();
+        metadata.insert(GRPC_TIMEOUT_HEADER, value.parse().unwrap());
+        metadata
+    }
+
+    #[test]
+    //grpc-timeout units are parsed as defined by the gRPC over HTTP2 spec
+    fn parse_grpc_timeout_units() {
+        let cases = [
+            ("1H", Duration::from_secs(3600)),
+            ("2M", Duration::from_secs(120)),
+            ("3S", Duration::from_secs(3)),
+            ("100m", Duration::from_millis(100)),
+            ("250u", Duration::from_micros(250)),
+            ("999n", Duration::from_nanos(999)),
+        ];
+        for (value, expected) in cases {
+            assert_eq!(
+                parse_grpc_timeout(&metadata_with_timeout(value)),
+                Some(expected)
+            );
+        }
+    }
+
+    #[test]
+    //malformed grpc-timeout values are ignored
+    fn parse_grpc_timeout_invalid() {
+        for value in ["", "m", "10", "10x", "-1S", "1234567890S"] {
+            let mut metadata = MetadataMap::new();
+            if !value.is_empty() {
+                metadata.insert(GRPC_TIMEOUT_HEADER, value.parse().unwrap());
+            }
+            assert_eq!(parse_grpc_timeout(&metadata), None);
+        }
+    }
+
+    #[test]
+    //budget is the client deadline capped by the server timeout
+    fn budget_capped_by_server_timeout() {
+        let executor = QuoteExecutor::new(Duration::from_secs(10));
+        assert_eq!(
+            executor.budget(&metadata_with_timeout("500m")),
+            Duration::from_millis(500)
+        );
+        assert_eq!(
+            executor.budget(&metadata_with_timeout("1M")),
+            Duration::from_secs(10)
+        );
+        assert_eq!(
+            executor.budget(&MetadataMap::new()),
+            Duration::from_secs(10)
+        );
+    }
+
+    #[tokio::test]
+    //run returns the closure result
+    async fn run_ok() {
+        let executor = QuoteExecutor::new(DEFAULT_QUOTE_TIMEOUT);
+        let result = executor.run(DEFAULT_QUOTE_TIMEOUT, || Ok(42)).await;
+        assert_eq!(result.unwrap(), 42);
+    }
+
+    #[tokio::test]
+    //run maps closure errors to gRPC status
+    async fn run_error_mapped() {
+        let executor = QuoteExecutor::new(DEFAULT_QUOTE_TIMEOUT);
+        let result: Result<(), Status> = executor
+            .run(DEFAULT_QUOTE_TIMEOUT, || {
+                Err(QuoteError::QuoteServiceUnavailable("busy".to_string()).into())
+            })
+            .await;
+        assert_eq!(result.unwrap_err().code(), Code::Unavailable);
+    }
+
+    #[tokio::test]
+    //run gives up with DEADLINE_EXCEEDED when the budget expires
+    async fn run_deadline_exceeded() {
+        let executor = QuoteExecutor::new(DEFAULT_QUOTE_TIMEOUT);
+        let result = executor
+            .run(Duration::from_millis(50), || {
+                std::thread::sleep(Duration::from_millis(500));
+                Ok(())
+            })
+            .await;
+        assert_eq!(result.unwrap_err().code(), Code::DeadlineExceeded);
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    //slow quote generation does not block other tasks on the runtime
+    async fn run_does_not_block_runtime() {
+        let executor = QuoteExecutor::new(DEFAULT_QUOTE_TIMEOUT);
+        let slow = executor.run(DEFAULT_QUOTE_TIMEOUT, || {
+            std::thread::sleep(Duration::from_millis(300));
+            Ok(())
+        });
+        let fast = async {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            Instant::now()
+        };
+        let start = Instant::now();
+        let (slow_result, fast_done) = tokio::join!(slow, fast);
+        assert!(slow_result.is_ok());
+        assert!(fast_done.duration_since(start) < Duration::from_millis(300));
+    }
+}
@@ -0,0 +1,334 @@
+/*
+* Copyright (c) 2023, Intel Corporation. All rights reserved.<BR>
+* SPDX-License-Identifier: Apache-2.0
+*/
+
+use crate::listener;
+use quote_verifier::identity::Identity;
+use std::path::Path;
+use tonic::transport::server::UdsConnectInfo;
+use tonic::{Request, Status};
+
+const PROC_ROOT: &str = "/proc";
+const CONTAINER_ID_LEN: usize = 64;
+
+// Who is calling, from the SO_PEERCRED credentials of the Unix socket peer and
+// the cgroup of the peer process. Fields are None when they cannot be
+// resolved, e.g. for callers outside Kubernetes or connections over TCP.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct CallerIdentity {
+    pub pid: Option<i32>,
+    pub uid: Option<u32>,
+    pub gid: Option<u32>,
+    pub cgroup: Option<String>,
+    pub container_id: Option<String>,
+    pub pod_uid: Option<String>,
+    // Kubernetes namespace and service account of the pod, only resolved
+    // when the authorization policy needs them
+    pub namespace: Option<String>,
+    pub service_account: Option<String>,
+    // Name of the TLS client certificate, for connections over TCP
+    pub tls_client: Option<String>,
+}
+
+impl CallerIdentity {
+    pub fn from_connect_info(info: Option<&UdsConnectInfo>) -> Self {
+        let cred = match info.and_then(|i| i.peer_cred) {
+            Some(c) => c,
+            None => return CallerIdentity::default(),
+        };
+        CallerIdentity {
+            // the PID is 0 if the peer is outside the PID namespace of the
+            // server, e.g. in another pod of a DaemonSet without hostPID
+            pid: cred.pid().filter(|pid| *pid > 0),
+            uid: Some(cred.uid()),
+            gid: Some(cred.gid()),
+            ..Default::default()
+        }
+    }
+
+    // Resolve the cgroup of the peer process, and so its container and pod,
+    // from /proc/<pid>/cgroup. Done by the handlers, so that the interceptor
+    // does no file I/O.
+    pub async fn resolve_cgroup(&mut self) {
+        let pid = match (self.pid, &self.cgroup) {
+            (Some(pid), None) => pid,
+            _ => return,
+        };
+        let path = Path::new(PROC_ROOT).join(pid.to_string()).join("cgroup");
+        if let Ok(content) = tokio::fs::read_to_string(path).await {
+            self.set_cgroup(&content);
+        }
+    }
+
+    // Whether the workload of a Unix socket peer is known, so that its
+    // identity can be bound into quotes. Peers over TCP are identified by
+    // their TLS client certificate instead.
+    pub fn workload_resolved(&self) -> bool {
+        self.uid.is_none() || self.cgroup.is_some()
+    }
+
+    fn set_cgroup(&mut self, content: &str) {
+        let cgroup = match parse_cgroup(content) {
+            Some(c) => c,
+            None => return,
+        };
+        self.container_id = container_id(&cgroup);
+        self.pod_uid = pod_uid(&cgroup);
+        self.cgroup = Some(cgroup);
+    }
+
+    // Key the caller is queued under: the most specific workload identity known
+    pub fn key(&self) -> String {
+        if let Some(pod_uid) = &self.pod_uid {
+            return format!("pod:{}", pod_uid);
+        }
+        if let Some(container_id) = &self.container_id {
+            return format!("container:{}", container_id);
+        }
+        if let Some(pid) = self.pid {
+            return format!("pid:{}", pid);
+        }
+        if let Some(tls_client) = &self.tls_client {
+            return format!("tls:{}", tls_client);
+        }
+        match self.uid {
+            Some(uid) => format!("uid:{}", uid),
+            None => "anonymous".to_string(),
+        }
+    }
+
+    // Digest of the workload identity bound into report data, see
+    // quote_verifier::identity for the encoding
+    pub fn digest(&self) -> [u8; 64] {
+        quote_verifier::identity::identity_digest(&Identity {
+            pod_uid: self.pod_uid.as_deref(),
+            container_id: self.container_id.as_deref(),
+            uid: self.uid,
+            gid: self.gid,
+            tls_client: self.tls_client.as_deref(),
+        })
+    }
+}
+
+// Interceptor resolving the caller identity of each request and handing it to
+// the handlers as a request extension
+pub fn resolve_identity(mut request: Request<()>) -> Result<Request<()>, Status> {
+    let mut identity =
+        CallerIdentity::from_connect_info(request.extensions().get::<UdsConnectInfo>());
+    if let Some(certs) = request.peer_certs() {
+        identity.tls_client = certs
+            .first()
+            .and_then(|c| listener::client_names(c.get_ref()).into_iter().next());
+    }
+    request.extensions_mut().insert(identity);
+    Ok(request)
+}
+
+// Identity resolved by the interceptor, empty if there was none
+pub fn caller_identity<T>(request: &Request<T>) -> CallerIdentity {
+    request
+        .extensions()
+        .get::<CallerIdentity>()
+        .cloned()
+        .unwrap_or_default()
+}
+
+// Pick the cgroup path of a process from /proc/<pid>/cgroup. The unified
+// (v2) hierarchy is preferred, v1 falls back to the first kubepods path.
+fn parse_cgroup(content: &str) -> Option<String> {
+    let mut paths = Vec::new();
+    for line in content.lines() {
+        let mut fields = line.splitn(3, ':');
+        let (id, controllers, path) = match (fields.next(), fields.next(), fields.next()) {
+            (Some(i), Some(c), Some(p)) => (i, c, p),
+            _ => continue,
+        };
+        if id == "0" && controllers.is_empty() {
+            return Some(path.to_string());
+        }
+        paths.push(path);
+    }
+    paths
+        .iter()
+        .find(|p| p.contains("kubepods"))
+        .or(paths.first())
+        .map(|p| p.to_string())
+}
+
+// Container ID from the last cgroup path element, e.g.
+// "cri-containerd-<id>.scope", "crio-<id>.scope", "docker-<id>.scope" or "<id>"
+fn container_id(cgroup: &str) -> Option<String> {
+    let last = cgroup.rsplit('/').next()?;
+    let last = last.strip_suffix(".scope").unwrap_or(last);
+    let id = last.rsplit('-').next()?;
+    if id.len() == CONTAINER_ID_LEN && id.chars().all(|c| c.is_ascii_hexdigit()) {
+        Some(id.to_string())
+    } else {
+        None
+    }
+}
+
+// Pod UID from a kubepods cgroup path element, e.g. "pod<uid>" (cgroupfs) or
+// "kubepods-besteffort-pod<uid>.slice" with dashes escaped as "_" (systemd)
+fn pod_uid(cgroup: &str) -> Option<String> {
+    if !cgroup.contains("kubepods") {
+        return None;
+    }
+    for element in cgroup.split('/') {
+        let element = element.strip_suffix(".slice").unwrap_or(element);
+        let uid = match element.rfind("pod") {
+            Some(i) if i == 0 || element[..i].ends_with('-') => &element[i + 3..],
+            _ => continue,
+        };
+        if !uid.is_empty()
+            && uid
+                .chars()
+                .all(|c| c.is_ascii_hexdigit() || c == '-' || c == '_')
+        {
+            return Some(uid.replace('_', "-"));
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONTAINER: &str = "0123456789abcdef0123456789abcdef0123456789abcdef0123456789abcdef";
+
+    #[test]
+    //cgroup v2 path of a containerd pod with the systemd driver
+    fn parse_cgroup_v2_systemd() {
+        let content = format!(
+            "0::/kubepods.slice/kubepods-besteffort.slice/\
+            kubepods-besteffort-pod2a6b4c1d_3e5f_4a7b_8c9d_0e1f2a3b4c5d.slice/\
+            cri-containerd-{}.scope\n",
+            CONTAINER
+        );
+        let mut identity = CallerIdentity::default();
+        identity.set_cgroup(&content);
+        assert_eq!(identity.container_id.as_deref(), Some(CONTAINER));
+        assert_eq!(
+            identity.pod_uid.as_deref(),
+            Some("2a6b4c1d-3e5f-4a7b-8c9d-0e1f2a3b4c5d")
+        );
+    }
+
+    #[test]
+    //cgroup v1 path of a pod with the cgroupfs driver
+    fn parse_cgroup_v1_cgroupfs() {
+        let content = format!(
+            "12:pids:/user.slice\n\
+            11:memory:/kubepods/burstable/pod2a6b4c1d-3e5f-4a7b-8c9d-0e1f2a3b4c5d/{}\n\
+            1:name=systemd:/kubepods/burstable/pod2a6b4c1d-3e5f-4a7b-8c9d-0e1f2a3b4c5d/{}\n",
+            CONTAINER, CONTAINER
+        );
+        let mut identity = CallerIdentity::default();
+        identity.set_cgroup(&content);
+        assert_eq!(identity.container_id.as_deref(), Some(CONTAINER));
+        assert_eq!(
+            identity.pod_uid.as_deref(),
+            Some("2a6b4c1d-3e5f-4a7b-8c9d-0e1f2a3b4c5d")
+        );
+    }
+
+    #[test]
+    //a process outside of containers has neither container ID nor pod UID
+    fn parse_cgroup_host_process() {
+        let mut identity = CallerIdentity::default();
+        identity.set_cgroup("0::/system.slice/sshd.service\n");
+        assert_eq!(
+            identity.cgroup.as_deref(),
+            Some("/system.slice/sshd.service")
+        );
+        assert_eq!(identity.container_id, None);
+        assert_eq!(identity.pod_uid, None);
+    }
+
+    #[test]
+    //a docker container outside Kubernetes has a container ID only
+    fn parse_cgroup_docker() {
+        let mut identity = CallerIdentity::default();
+        identity.set_cgroup(&format!("0::/system.slice/docker-{}.scope\n", CONTAINER));
+        assert_eq!(identity.container_id.as_deref(), Some(CONTAINER));
+        assert_eq!(identity.pod_uid, None);
+    }
+
+    #[test]
+    //queue key prefers the pod over the container over the process
+    fn key_precedence() {
+        let mut identity = CallerIdentity {
+            pid: Some(42),
+            uid: Some(1000),
+            ..Default::default()
+        };
+        assert_eq!(identity.key(), "pid:42");
+        identity.container_id = Some(CONTAINER.to_string());
+        assert_eq!(identity.key(), format!("container:{}", CONTAINER));
+        identity.pod_uid = Some("uid-1".to_string());
+        assert_eq!(identity.key(), "pod:uid-1");
+        assert_eq!(CallerIdentity::default().key(), "anonymous");
+    }
+
+    #[test]
+    //identity digest ignores the process ID
+    fn digest_ignores_pid() {
+        let a = CallerIdentity {
+            pid: Some(1),
+            pod_uid: Some("uid-1".to_string()),
+            ..Default::default()
+        };
+        let b = CallerIdentity {
+            pid: Some(2),
+            ..a.clone()
+        };
+        assert_eq!(a.digest(), b.digest());
+    }
+
+    #[test]
+    //callers over TCP are told apart by their certificate
+    fn digest_of_tls_clients() {
+        let client = |name: &str| CallerIdentity {
+            tls_client: Some(name.to_string()),
+            ..Default::default()
+        };
+        assert_ne!(client("a").digest(), client("b").digest());
+        assert_ne!(CallerIdentity::default().digest(), client("a").digest());
+    }
+
+    #[tokio::test]
+    //identity is resolved for the current process over a real Unix socket pair
+    async fn from_connect_info_self() {
+        let (a, _b) = std::os::unix::net::UnixStream::pair().unwrap();
+        a.set_nonblocking(true).unwrap();
+        let stream = tokio::net::UnixStream::from_std(a).unwrap();
+        let info = UdsConnectInfo {
+            peer_addr: None,
+            peer_cred: stream.peer_cred().ok(),
+        };
+        let mut identity = CallerIdentity::from_connect_info(Some(&info));
+        assert_eq!(identity.pid, Some(std::process::id() as i32));
+        assert!(!identity.workload_resolved());
+        identity.resolve_cgroup().await;
+        assert!(identity.cgroup.is_some());
+        assert!(identity.workload_resolved());
+    }
+
+    #[tokio::test]
+    //peers whose process is not visible keep an unresolved workload
+    async fn unresolved_workload() {
+        let mut identity = CallerIdentity {
+            uid: Some(1000),
+            ..Default::default()
+        };
+        identity.resolve_cgroup().await;
+        assert!(!identity.workload_resolved());
+        let tls = CallerIdentity {
+            tls_client: Some("client".to_string()),
+            ..Default::default()
+        };
+        assert!(tls.workload_resolved());
+    }
+}
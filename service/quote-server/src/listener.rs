@@ -0,0 +1,253 @@
+/*
+* Copyright (c) 2023, Intel Corporation. All rights reserved.<BR>
+* SPDX-License-Identifier: Apache-2.0
+*/
+
+use crate::config::{TcpConfig, UnixConfig};
+use anyhow::{anyhow, Context, Result};
+use nix::sys::socket::{getsockname, AddressFamily, SockaddrLike, SockaddrStorage};
+use nix::unistd::{chown, Gid, Group, Uid, User};
+use sha2::{Digest, Sha256};
+use std::env;
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::io::{FromRawFd, RawFd};
+use std::sync::Arc;
+use tokio::net::{TcpListener, UnixListener};
+use tonic::transport::{Certificate, Identity, ServerTlsConfig};
+use tonic::{Request, Status};
+use tracing::warn;
+use x509_parser::prelude::{FromDer, GeneralName, X509Certificate};
+
+// First file descriptor passed by systemd socket activation, see sd_listen_fds(3)
+const SD_LISTEN_FDS_START: RawFd = 3;
+
+pub enum Listener {
+    Unix(UnixListener),
+    Tcp(TcpListener),
+}
+
+// Bind the Unix domain socket, replacing a stale socket file, and apply the
+// configured permissions and ownership
+pub fn bind_unix(config: &UnixConfig) -> Result<UnixListener> {
+    let path = &config.path;
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)
+            .with_context(|| format!("[bind_unix] failed to create {}", dir.display()))?;
+    }
+    let _ = fs::remove_file(path);
+    let listener = UnixListener::bind(path)
+        .with_context(|| format!("[bind_unix] failed to bind {}", path.display()))?;
+
+    if let Some(mode) = config.mode {
+        fs::set_permissions(path, fs::Permissions::from_mode(mode))
+            .with_context(|| format!("[bind_unix] failed to set mode of {}", path.display()))?;
+    }
+    let owner = config.owner.as_deref().map(lookup_user).transpose()?;
+    let group = config.group.as_deref().map(lookup_group).transpose()?;
+    if owner.is_some() || group.is_some() {
+        chown(path, owner, group)
+            .with_context(|| format!("[bind_unix] failed to change owner of {}", path.display()))?;
+    }
+    Ok(listener)
+}
+
+fn lookup_user(name: &str) -> Result<Uid> {
+    if let Ok(uid) = name.parse() {
+        return Ok(Uid::from_raw(uid));
+    }
+    match User::from_name(name)? {
+        Some(user) => Ok(user.uid),
+        None => Err(anyhow!("[bind_unix] unknown user {}", name)),
+    }
+}
+
+fn lookup_group(name: &str) -> Result<Gid> {
+    if let Ok(gid) = name.parse() {
+        return Ok(Gid::from_raw(gid));
+    }
+    match Group::from_name(name)? {
+        Some(group) => Ok(group.gid),
+        None => Err(anyhow!("[bind_unix] unknown group {}", name)),
+    }
+}
+
+pub async fn bind_tcp(config: &TcpConfig) -> Result<TcpListener> {
+    TcpListener::bind(config.address)
+        .await
+        .with_context(|| format!("[bind_tcp] failed to bind {}", config.address))
+}
+
+// Listeners passed by systemd socket activation, empty if the process was
+// not socket activated
+pub fn systemd_listeners() -> Result<Vec<Listener>> {
+    let pid = env::var("LISTEN_PID")
+        .ok()
+        .and_then(|p| p.parse::<u32>().ok());
+    if pid != Some(std::process::id()) {
+        return Ok(Vec::new());
+    }
+    let count: RawFd = env::var("LISTEN_FDS")
+        .context("[systemd_listeners] LISTEN_FDS is not set")?
+        .parse()
+        .context("[systemd_listeners] invalid LISTEN_FDS")?;
+    env::remove_var("LISTEN_PID");
+    env::remove_var("LISTEN_FDS");
+    env::remove_var("LISTEN_FDNAMES");
+
+    let mut listeners = Vec::new();
+    for fd in SD_LISTEN_FDS_START..SD_LISTEN_FDS_START + count {
+        let family = getsockname::<SockaddrStorage>(fd)
+            .with_context(|| format!("[systemd_listeners] fd {} is not a socket", fd))?
+            .family();
+        // Safety: systemd hands the descriptors over to this process, they are
+        // not owned by anything else
+        let listener = match family {
+            Some(AddressFamily::Unix) => {
+                let l = unsafe { std::os::unix::net::UnixListener::from_raw_fd(fd) };
+                l.set_nonblocking(true)?;
+                Listener::Unix(UnixListener::from_std(l)?)
+            }
+            Some(AddressFamily::Inet) | Some(AddressFamily::Inet6) => {
+                let l = unsafe { std::net::TcpListener::from_raw_fd(fd) };
+                l.set_nonblocking(true)?;
+                Listener::Tcp(TcpListener::from_std(l)?)
+            }
+            f => return Err(anyhow!("[systemd_listeners] fd {} has family {:?}", fd, f)),
+        };
+        listeners.push(listener);
+    }
+    Ok(listeners)
+}
+
+// TLS settings requiring a client certificate issued by the configured CA
+pub fn tls_config(config: &TcpConfig) -> Result<ServerTlsConfig> {
+    let read = |path: &std::path::Path| {
+        fs::read(path).with_context(|| format!("[tls_config] failed to read {}", path.display()))
+    };
+    Ok(ServerTlsConfig::new()
+        .identity(Identity::from_pem(read(&config.cert)?, read(&config.key)?))
+        .client_ca_root(Certificate::from_pem(read(&config.client_ca)?)))
+}
+
+// Names a client certificate can be allowed by: its SHA-256 fingerprint as
+// "sha256:<hex>", subject common names, and DNS and URI SANs. The most
+// readable name comes first.
+pub fn client_names(der: &[u8]) -> Vec<String> {
+    let mut names = Vec::new();
+    if let Ok((_, cert)) = X509Certificate::from_der(der) {
+        for cn in cert.subject().iter_common_name() {
+            if let Ok(cn) = cn.as_str() {
+                names.push(cn.to_string());
+            }
+        }
+        if let Ok(Some(san)) = cert.subject_alternative_name() {
+            for name in &san.value.general_names {
+                match name {
+                    GeneralName::DNSName(n) | GeneralName::URI(n) => names.push(n.to_string()),
+                    _ => {}
+                }
+            }
+        }
+    }
+    let fingerprint: String = Sha256::digest(der)
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect();
+    names.push(format!("sha256:{}", fingerprint));
+    names
+}
+
+// Allowlist of TLS client certificates, empty allows every client with a
+// certificate issued by the client CA
+#[derive(Clone)]
+pub struct ClientAllowlist {
+    allowed: Arc<Vec<String>>,
+}
+
+impl ClientAllowlist {
+    pub fn new(allowed: Vec<String>) -> Self {
+        ClientAllowlist {
+            allowed: Arc::new(allowed),
+        }
+    }
+
+    // Interceptor rejecting clients that are not on the allowlist
+    pub fn check(&self, request: Request<()>) -> Result<Request<()>, Status> {
+        let certs = request
+            .peer_certs()
+            .ok_or_else(|| Status::unauthenticated("client certificate required"))?;
+        let cert = certs
+            .first()
+            .ok_or_else(|| Status::unauthenticated("client certificate required"))?;
+        let names = client_names(cert.get_ref());
+        if self.allowed.is_empty() || names.iter().any(|n| self.allowed.contains(n)) {
+            return Ok(request);
+        }
+        warn!(
+            client = names[0].as_str(),
+            fingerprint = names.last().unwrap().as_str(),
+            "rejected TLS client"
+        );
+        Err(Status::permission_denied(format!(
+            "client certificate {} is not allowed",
+            names[0]
+        )))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rcgen::{CertificateParams, SanType};
+    use std::os::unix::fs::MetadataExt;
+
+    #[tokio::test]
+    //socket file is created with the configured mode, replacing a stale one
+    async fn bind_unix_mode() {
+        let path = env::temp_dir().join(format!("quote-server-{}/test.sock", std::process::id()));
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, "stale").unwrap();
+
+        let config = UnixConfig {
+            path: path.clone(),
+            mode: Some(0o600),
+            owner: Some(Uid::current().to_string()),
+            group: None,
+        };
+        let _listener = bind_unix(&config).unwrap();
+        let metadata = fs::metadata(&path).unwrap();
+        assert_eq!(metadata.mode() & 0o777, 0o600);
+        assert_eq!(metadata.uid(), Uid::current().as_raw());
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    //client names include common name, SANs and fingerprint
+    fn client_names_of_cert() {
+        let mut params = CertificateParams::new(vec!["verifier.example.com".to_string()]);
+        params
+            .distinguished_name
+            .push(rcgen::DnType::CommonName, "verifier");
+        params
+            .subject_alt_names
+            .push(SanType::URI("spiffe://example.com/verifier".to_string()));
+        let der = rcgen::Certificate::from_params(params)
+            .unwrap()
+            .serialize_der()
+            .unwrap();
+
+        let names = client_names(&der);
+        assert_eq!(names[0], "verifier");
+        assert!(names.contains(&"verifier.example.com".to_string()));
+        assert!(names.contains(&"spiffe://example.com/verifier".to_string()));
+        assert!(names.last().unwrap().starts_with("sha256:"));
+        assert_eq!(names.last().unwrap().len(), 7 + 64);
+    }
+
+    #[test]
+    //no systemd listeners without socket activation
+    fn systemd_listeners_not_activated() {
+        assert!(systemd_listeners().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,349 @@
+/*
+* Copyright (c) 2023, Intel Corporation. All rights reserved.<BR>
+* SPDX-License-Identifier: Apache-2.0
+*/
+
+use crate::listener::Listener;
+use crate::prober::ProbeStatus;
+use crate::shutdown::Shutdown;
+use hyper::header::CONTENT_TYPE;
+use hyper::service::service_fn;
+use hyper::{Body, Method, Request, Response, StatusCode};
+use prometheus::{
+    Encoder, Gauge, GaugeVec, HistogramOpts, HistogramVec, IntCounterVec, IntGauge, IntGaugeVec,
+    Opts, Registry, TextEncoder,
+};
+use std::convert::Infallible;
+use std::sync::Arc;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tonic::Status;
+use tracing::warn;
+
+const METRICS_PATH: &str = "/metrics";
+
+// Quote generation takes milliseconds for a TDREPORT and up to seconds for a
+// QGS round-trip
+const LATENCY_BUCKETS: &[f64] = &[
+    0.001, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0,
+];
+
+// Renders the current metrics in the Prometheus text format
+pub type RenderFn = Arc<dyn Fn() -> String + Send + Sync>;
+
+// Prometheus metrics of the quote server. Each instance has its own registry.
+#[derive(Clone)]
+pub struct Metrics {
+    registry: Registry,
+    tee: String,
+    requests: IntCounterVec,
+    request_duration: HistogramVec,
+    report_duration: HistogramVec,
+    quote_duration: HistogramVec,
+    last_success: GaugeVec,
+    queue_depth: IntGaugeVec,
+    probe_healthy: IntGauge,
+    probe_latency: Gauge,
+    probe_failures: IntGauge,
+}
+
+impl Metrics {
+    pub fn new(tee: String) -> Self {
+        let registry = Registry::new();
+        let histogram = |name: &str, help: &str, labels: &[&str]| {
+            let opts = HistogramOpts::new(name, help).buckets(LATENCY_BUCKETS.to_vec());
+            register(&registry, HistogramVec::new(opts, labels).unwrap())
+        };
+        let metrics = Metrics {
+            requests: register(
+                &registry,
+                IntCounterVec::new(
+                    Opts::new(
+                        "quote_server_requests_total",
+                        "Requests by RPC and status code",
+                    ),
+                    &["method", "tee", "code"],
+                )
+                .unwrap(),
+            ),
+            request_duration: histogram(
+                "quote_server_request_duration_seconds",
+                "Total latency of requests, including queueing",
+                &["method", "tee"],
+            ),
+            report_duration: histogram(
+                "quote_server_report_duration_seconds",
+                "Latency of TDREPORT generation on the TEE device",
+                &["tee"],
+            ),
+            quote_duration: histogram(
+                "quote_server_quote_generation_duration_seconds",
+                "Latency of quote generation, the TDREPORT and the QGS round-trip",
+                &["tee"],
+            ),
+            last_success: register(
+                &registry,
+                GaugeVec::new(
+                    Opts::new(
+                        "quote_server_last_success_timestamp_seconds",
+                        "Unix time of the last successful request",
+                    ),
+                    &["method", "tee"],
+                )
+                .unwrap(),
+            ),
+            queue_depth: register(
+                &registry,
+                IntGaugeVec::new(
+                    Opts::new(
+                        "quote_server_queue_depth",
+                        "Requests waiting in the admission control or for a batch",
+                    ),
+                    &["queue"],
+                )
+                .unwrap(),
+            ),
+            probe_healthy: register(
+                &registry,
+                IntGauge::new(
+                    "quote_server_probe_healthy",
+                    "1 if the last health probe of the TEE succeeded",
+                )
+                .unwrap(),
+            ),
+            probe_latency: register(
+                &registry,
+                Gauge::new(
+                    "quote_server_probe_latency_seconds",
+                    "Latency of the last health probe",
+                )
+                .unwrap(),
+            ),
+            probe_failures: register(
+                &registry,
+                IntGauge::new(
+                    "quote_server_probe_consecutive_failures",
+                    "Health probes failed in a row",
+                )
+                .unwrap(),
+            ),
+            registry,
+            tee,
+        };
+
+        let build_info = register(
+            &metrics.registry,
+            IntGaugeVec::new(
+                Opts::new("quote_server_build_info", "Version of the quote server"),
+                &["version"],
+            )
+            .unwrap(),
+        );
+        build_info
+            .with_label_values(&[env!("CARGO_PKG_VERSION")])
+            .set(1);
+        let tee_info = register(
+            &metrics.registry,
+            IntGaugeVec::new(
+                Opts::new("quote_server_tee_info", "TEE type of the node"),
+                &["tee"],
+            )
+            .unwrap(),
+        );
+        tee_info.with_label_values(&[&metrics.tee]).set(1);
+        metrics
+    }
+
+    // Count a finished request and observe its latency
+    pub fn record_request<T>(&self, method: &str, start: Instant, result: &Result<T, Status>) {
+        let code = match result {
+            Ok(_) => "OK".to_string(),
+            Err(status) => format!("{:?}", status.code()),
+        };
+        self.requests
+            .with_label_values(&[method, &self.tee, &code])
+            .inc();
+        self.request_duration
+            .with_label_values(&[method, &self.tee])
+            .observe(start.elapsed().as_secs_f64());
+        if result.is_ok() {
+            let now = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default();
+            self.last_success
+                .with_label_values(&[method, &self.tee])
+                .set(now.as_secs_f64());
+        }
+    }
+
+    // Run a TDREPORT generation and observe its latency
+    pub fn time_report<T>(&self, f: impl FnOnce() -> T) -> T {
+        time(&self.report_duration, &self.tee, f)
+    }
+
+    // Run a quote generation and observe its latency
+    pub fn time_quote<T>(&self, f: impl FnOnce() -> T) -> T {
+        time(&self.quote_duration, &self.tee, f)
+    }
+
+    pub fn set_queue_depth(&self, queue: &str, depth: usize) {
+        self.queue_depth
+            .with_label_values(&[queue])
+            .set(depth as i64);
+    }
+
+    pub fn set_probe_status(&self, status: &ProbeStatus) {
+        self.probe_healthy.set(status.healthy as i64);
+        self.probe_failures.set(status.consecutive_failures as i64);
+        if let Some(latency) = status.latency {
+            self.probe_latency.set(latency.as_secs_f64());
+        }
+    }
+
+    pub fn render(&self) -> String {
+        let mut buffer = Vec::new();
+        // encoding into a Vec only fails on invalid metric families
+        if let Err(e) = TextEncoder::new().encode(&self.registry.gather(), &mut buffer) {
+            warn!(error = %e, "failed to encode metrics");
+        }
+        String::from_utf8(buffer).unwrap_or_default()
+    }
+}
+
+fn register<C: prometheus::core::Collector + Clone + 'static>(registry: &Registry, c: C) -> C {
+    registry.register(Box::new(c.clone())).unwrap();
+    c
+}
+
+fn time<T>(histogram: &HistogramVec, tee: &str, f: impl FnOnce() -> T) -> T {
+    let start = Instant::now();
+    let result = f();
+    histogram
+        .with_label_values(&[tee])
+        .observe(start.elapsed().as_secs_f64());
+    result
+}
+
+// Serve the metrics over plain HTTP on `listener` until shutdown
+pub async fn serve(listener: Listener, render: RenderFn, shutdown: Shutdown) {
+    let shutdown = shutdown.wait();
+    tokio::pin!(shutdown);
+    loop {
+        let accepted = tokio::select! {
+            _ = &mut shutdown => return,
+            accepted = accept(&listener, render.clone()) => accepted,
+        };
+        if let Err(e) = accepted {
+            warn!(error = %e, "failed to accept metrics connection");
+            tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+        }
+    }
+}
+
+async fn accept(listener: &Listener, render: RenderFn) -> std::io::Result<()> {
+    match listener {
+        Listener::Unix(l) => tokio::spawn(connection(l.accept().await?.0, render)),
+        Listener::Tcp(l) => tokio::spawn(connection(l.accept().await?.0, render)),
+    };
+    Ok(())
+}
+
+async fn connection<S>(stream: S, render: RenderFn)
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let service = service_fn(move |request| {
+        let render = render.clone();
+        async move { Ok::<_, Infallible>(respond(request, &render)) }
+    });
+    let _ = hyper::server::conn::Http::new()
+        .http1_only(true)
+        .serve_connection(stream, service)
+        .await;
+}
+
+fn respond(request: Request<Body>, render: &RenderFn) -> Response<Body> {
+    if request.method() != Method::GET || request.uri().path() != METRICS_PATH {
+        let mut response = Response::new(Body::from("Not Found"));
+        *response.status_mut() = StatusCode::NOT_FOUND;
+        return response;
+    }
+    let mut response = Response::new(Body::from(render()));
+    response.headers_mut().insert(
+        CONTENT_TYPE,
+        TextEncoder::new().format_type().parse().unwrap(),
+    );
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::UnixConfig;
+    use crate::listener;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::UnixStream;
+
+    #[test]
+    //requests are counted by method, TEE and status code
+    fn record_requests() {
+        let metrics = Metrics::new("TDX".to_string());
+        let start = Instant::now();
+        metrics.record_request("GetQuote", start, &Ok::<(), Status>(()));
+        metrics.record_request::<()>("GetQuote", start, &Err(Status::unavailable("QGS down")));
+        metrics.time_quote(|| ());
+        metrics.set_queue_depth("admission", 3);
+
+        let text = metrics.render();
+        for line in [
+            "quote_server_requests_total{code=\"OK\",method=\"GetQuote\",tee=\"TDX\"} 1",
+            "quote_server_requests_total{code=\"Unavailable\",method=\"GetQuote\",tee=\"TDX\"} 1",
+            "quote_server_request_duration_seconds_count{method=\"GetQuote\",tee=\"TDX\"} 2",
+            "quote_server_quote_generation_duration_seconds_count{tee=\"TDX\"} 1",
+            "quote_server_queue_depth{queue=\"admission\"} 3",
+            "quote_server_tee_info{tee=\"TDX\"} 1",
+        ] {
+            assert!(text.contains(line), "{} missing in\n{}", line, text);
+        }
+        assert!(text.contains("quote_server_last_success_timestamp_seconds{method=\"GetQuote\""));
+    }
+
+    #[tokio::test]
+    //metrics are served over HTTP on a Unix domain socket
+    async fn serve_over_unix_socket() {
+        let path =
+            std::env::temp_dir().join(format!("quote-server-metrics-{}.sock", std::process::id()));
+        let listener = listener::bind_unix(&UnixConfig {
+            path: path.clone(),
+            ..Default::default()
+        })
+        .unwrap();
+        let metrics = Metrics::new("TDX".to_string());
+        let render: RenderFn = Arc::new(move || metrics.render());
+        let shutdown = Shutdown::new();
+        let server = tokio::spawn(serve(Listener::Unix(listener), render, shutdown.clone()));
+
+        let get = |target: &'static str| {
+            let path = path.clone();
+            async move {
+                let mut stream = UnixStream::connect(path).await.unwrap();
+                let request = format!("GET {} HTTP/1.0\r\n\r\n", target);
+                stream.write_all(request.as_bytes()).await.unwrap();
+                let mut response = String::new();
+                stream.read_to_string(&mut response).await.unwrap();
+                response
+            }
+        };
+        let response = get("/metrics").await;
+        assert!(response.starts_with("HTTP/1.0 200"));
+        assert!(response.contains("quote_server_build_info"));
+        assert!(get("/").await.starts_with("HTTP/1.0 404"));
+
+        shutdown.trigger();
+        tokio::time::timeout(std::time::Duration::from_secs(1), server)
+            .await
+            .unwrap()
+            .unwrap();
+        let _ = std::fs::remove_file(path);
+    }
+}
@@ -0,0 +1,180 @@
+/*
+* Copyright (c) 2023, Intel Corporation. All rights reserved.<BR>
+* SPDX-License-Identifier: Apache-2.0
+*/
+
+use anyhow::Result;
+use k8s_openapi::api::core::v1::Pod;
+use kube::api::{Api, ListParams};
+use kube::runtime::watcher::{watcher, Event};
+use kube::Client;
+use std::collections::HashMap;
+use std::env;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use tokio::sync::watch;
+use tokio_stream::StreamExt;
+use tracing::{info, warn};
+
+// Node the quote server runs on, set through the downward API
+const NODE_NAME: &str = "NODE_NAME";
+// Pause before the watch is restarted after an API error
+const WATCH_RETRY: Duration = Duration::from_secs(5);
+// How long a lookup waits for the initial pod list after startup
+const SYNC_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct PodInfo {
+    pub namespace: String,
+    pub service_account: String,
+}
+
+// Resolves pod UIDs of callers to their namespace and service account.
+// Pods of the node are watched in the background and kept in a local cache,
+// lookups never call the Kubernetes API, so an unknown pod UID is answered
+// from the cache as well.
+#[derive(Clone)]
+pub struct PodResolver {
+    pods: Arc<RwLock<HashMap<String, PodInfo>>>,
+    synced: watch::Receiver<bool>,
+}
+
+impl PodResolver {
+    pub async fn new() -> Result<Self> {
+        let client = Client::try_default().await?;
+        let mut params = ListParams::default();
+        match env::var(NODE_NAME) {
+            Ok(node_name) => params = params.fields(&format!("spec.nodeName={}", node_name)),
+            Err(_) => warn!("{} is not set, watching the pods of all nodes", NODE_NAME),
+        }
+        let (resolver, synced) = PodResolver::empty();
+        tokio::spawn(resolver.clone().watch(Api::all(client), params, synced));
+        Ok(resolver)
+    }
+
+    fn empty() -> (Self, watch::Sender<bool>) {
+        let (synced, receiver) = watch::channel(false);
+        let resolver = PodResolver {
+            pods: Arc::new(RwLock::new(HashMap::new())),
+            synced: receiver,
+        };
+        (resolver, synced)
+    }
+
+    pub async fn lookup(&self, pod_uid: &str) -> Option<PodInfo> {
+        let mut synced = self.synced.clone();
+        if !*synced.borrow() {
+            let wait = async {
+                while !*synced.borrow() {
+                    if synced.changed().await.is_err() {
+                        break;
+                    }
+                }
+            };
+            if tokio::time::timeout(SYNC_TIMEOUT, wait).await.is_err() {
+                warn!("pod list is not synced yet");
+            }
+        }
+        self.pods.read().unwrap().get(pod_uid).cloned()
+    }
+
+    async fn watch(self, pods: Api<Pod>, params: ListParams, synced: watch::Sender<bool>) {
+        let mut events = Box::pin(watcher(pods, params));
+        while let Some(event) = events.next().await {
+            match event {
+                Ok(event) => {
+                    if self.apply(event) && !*synced.borrow() {
+                        info!("pod list synced");
+                        let _ = synced.send(true);
+                    }
+                }
+                Err(e) => {
+                    warn!(error = ?e, "failed to watch pods");
+                    tokio::time::sleep(WATCH_RETRY).await;
+                }
+            }
+        }
+    }
+
+    // Applies a watch event to the cache, returns whether the cache was
+    // replaced by a full pod list
+    fn apply(&self, event: Event<Pod>) -> bool {
+        let mut cache = self.pods.write().unwrap();
+        match event {
+            Event::Applied(pod) => {
+                if let Some((uid, info)) = pod_info(pod) {
+                    cache.insert(uid, info);
+                }
+                false
+            }
+            Event::Deleted(pod) => {
+                if let Some(uid) = pod.metadata.uid {
+                    cache.remove(&uid);
+                }
+                false
+            }
+            Event::Restarted(pods) => {
+                *cache = pods.into_iter().filter_map(pod_info).collect();
+                true
+            }
+        }
+    }
+}
+
+fn pod_info(pod: Pod) -> Option<(String, PodInfo)> {
+    let metadata = pod.metadata;
+    let (uid, namespace) = match (metadata.uid, metadata.namespace) {
+        (Some(u), Some(n)) => (u, n),
+        _ => return None,
+    };
+    let service_account = pod
+        .spec
+        .and_then(|s| s.service_account_name)
+        .unwrap_or_else(|| "default".to_string());
+    Some((
+        uid,
+        PodInfo {
+            namespace,
+            service_account,
+        },
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use k8s_openapi::api::core::v1::PodSpec;
+    use kube::api::ObjectMeta;
+
+    fn pod(uid: &str, namespace: &str) -> Pod {
+        Pod {
+            metadata: ObjectMeta {
+                uid: Some(uid.to_string()),
+                namespace: Some(namespace.to_string()),
+                ..Default::default()
+            },
+            spec: Some(PodSpec::default()),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    //lookups are answered from the watched pods without an API call
+    async fn lookup_from_watch_events() {
+        let (resolver, synced) = PodResolver::empty();
+        assert!(resolver.apply(Event::Restarted(vec![pod("a", "ns-a")])));
+        synced.send(true).unwrap();
+        assert!(!resolver.apply(Event::Applied(pod("b", "ns-b"))));
+
+        let info = resolver.lookup("b").await.unwrap();
+        assert_eq!(info.namespace, "ns-b");
+        assert_eq!(info.service_account, "default");
+        assert!(resolver.lookup("a").await.is_some());
+        assert!(resolver.lookup("unknown").await.is_none());
+
+        resolver.apply(Event::Deleted(pod("a", "ns-a")));
+        assert!(resolver.lookup("a").await.is_none());
+        resolver.apply(Event::Restarted(vec![]));
+        assert!(resolver.lookup("b").await.is_none());
+    }
+}
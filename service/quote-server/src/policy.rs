@@ -0,0 +1,335 @@
+/*
+* Copyright (c) 2023, Intel Corporation. All rights reserved.<BR>
+* SPDX-License-Identifier: Apache-2.0
+*/
+
+use crate::error::QuoteError;
+use crate::identity::CallerIdentity;
+use crate::pods::PodResolver;
+use anyhow::{anyhow, Context, Result};
+use serde::Deserialize;
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+use tracing::warn;
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Action {
+    Allow,
+    Deny,
+}
+
+// One policy rule. Every condition that is set must match the caller, a
+// condition matches if any of its values does. A rule without conditions
+// matches every caller.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Rule {
+    pub action: Option<Action>,
+    // RPC method names, e.g. "GetQuote", "*" or empty for all methods
+    #[serde(default)]
+    pub methods: Vec<String>,
+    #[serde(default)]
+    pub uids: Vec<u32>,
+    #[serde(default)]
+    pub gids: Vec<u32>,
+    // cgroup path patterns, "*" matches any sequence of characters
+    #[serde(default)]
+    pub cgroups: Vec<String>,
+    #[serde(default)]
+    pub namespaces: Vec<String>,
+    #[serde(default)]
+    pub service_accounts: Vec<String>,
+}
+
+// Authorization policy loaded from a TOML or YAML file. Rules are evaluated
+// in order and the first matching rule decides, `default` applies if none
+// matches.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Policy {
+    #[serde(default = "default_action")]
+    pub default: Action,
+    #[serde(default)]
+    pub rules: Vec<Rule>,
+}
+
+fn default_action() -> Action {
+    Action::Deny
+}
+
+impl Policy {
+    // Load a policy, the format is chosen by the file extension
+    pub fn load(path: &Path) -> Result<Self> {
+        let content = fs::read_to_string(path)
+            .with_context(|| format!("[Policy] failed to read {}", path.display()))?;
+        let policy = match path.extension().and_then(|e| e.to_str()) {
+            Some("toml") => Policy::from_toml(&content),
+            Some("yaml") | Some("yml") => Policy::from_yaml(&content),
+            _ => Err(anyhow!("[Policy] policy file must be .toml, .yaml or .yml")),
+        };
+        policy.with_context(|| format!("[Policy] invalid policy {}", path.display()))
+    }
+
+    pub fn from_toml(content: &str) -> Result<Self> {
+        Policy::validate(toml::from_str(content)?)
+    }
+
+    pub fn from_yaml(content: &str) -> Result<Self> {
+        Policy::validate(serde_yaml::from_str(content)?)
+    }
+
+    fn validate(policy: Policy) -> Result<Self> {
+        for (i, rule) in policy.rules.iter().enumerate() {
+            if rule.action.is_none() {
+                return Err(anyhow!("rule {} has no action", i));
+            }
+        }
+        Ok(policy)
+    }
+
+    // Whether any rule needs the Kubernetes namespace or service account
+    pub fn needs_pod_info(&self) -> bool {
+        self.rules
+            .iter()
+            .any(|r| !r.namespaces.is_empty() || !r.service_accounts.is_empty())
+    }
+
+    pub fn evaluate(&self, method: &str, caller: &CallerIdentity) -> Action {
+        self.rules
+            .iter()
+            .find(|r| r.matches(method, caller))
+            .and_then(|r| r.action)
+            .unwrap_or(self.default)
+    }
+}
+
+impl Rule {
+    fn matches(&self, method: &str, caller: &CallerIdentity) -> bool {
+        any_or_unset(&self.methods, |m| m == "*" || m == method)
+            && any_or_unset(&self.uids, |u| caller.uid == Some(*u))
+            && any_or_unset(&self.gids, |g| caller.gid == Some(*g))
+            && any_or_unset(&self.cgroups, |p| {
+                caller.cgroup.as_deref().is_some_and(|c| glob_match(p, c))
+            })
+            && any_or_unset(&self.namespaces, |n| {
+                caller.namespace.as_deref() == Some(n.as_str())
+            })
+            && any_or_unset(&self.service_accounts, |s| {
+                caller.service_account.as_deref() == Some(s.as_str())
+            })
+    }
+}
+
+fn any_or_unset<T, F: Fn(&T) -> bool>(values: &[T], f: F) -> bool {
+    values.is_empty() || values.iter().any(f)
+}
+
+// Match `text` against `pattern` where "*" matches any sequence of characters
+fn glob_match(pattern: &str, text: &str) -> bool {
+    let parts: Vec<&str> = pattern.split('*').collect();
+    if parts.len() == 1 {
+        return pattern == text;
+    }
+    let (first, last) = (parts[0], parts[parts.len() - 1]);
+    if !text.starts_with(first) || !text[first.len()..].ends_with(last) {
+        return false;
+    }
+    let mut rest = &text[first.len()..text.len() - last.len()];
+    for part in &parts[1..parts.len() - 1] {
+        match rest.find(part) {
+            Some(i) => rest = &rest[i + part.len()..],
+            None => return false,
+        }
+    }
+    true
+}
+
+// Enforces the authorization policy on every RPC
+#[derive(Clone)]
+pub struct Authorizer {
+    policy: Arc<Policy>,
+    pods: Option<PodResolver>,
+}
+
+impl Authorizer {
+    pub fn new(policy: Policy, pods: Option<PodResolver>) -> Self {
+        Authorizer {
+            policy: Arc::new(policy),
+            pods,
+        }
+    }
+
+    // Check that `caller` may call `method`, resolving its Kubernetes
+    // namespace and service account first if the policy needs them
+    // Whether the policy allows `method` for a caller already resolved by
+    // authorize, without logging a denial
+    pub fn allows(&self, method: &str, caller: &CallerIdentity) -> bool {
+        self.policy.evaluate(method, caller) == Action::Allow
+    }
+
+    pub async fn authorize(
+        &self,
+        method: &'static str,
+        caller: &mut CallerIdentity,
+    ) -> Result<(), QuoteError> {
+        if let (Some(pods), Some(pod_uid)) = (&self.pods, &caller.pod_uid) {
+            if let Some(pod) = pods.lookup(pod_uid).await {
+                caller.namespace = Some(pod.namespace);
+                caller.service_account = Some(pod.service_account);
+            }
+        }
+        match self.policy.evaluate(method, caller) {
+            Action::Allow => Ok(()),
+            Action::Deny => {
+                warn!(method, caller = ?caller, "caller denied by policy");
+                Err(QuoteError::PermissionDenied {
+                    method,
+                    caller: caller.key(),
+                })
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const POLICY_TOML: &str = r#"
+default = "deny"
+
+[[rules]]
+action = "deny"
+cgroups = ["/kubepods.slice/*besteffort*"]
+
+[[rules]]
+action = "allow"
+methods = ["GetQuote"]
+namespaces = ["confidential"]
+service_accounts = ["attester", "verifier"]
+
+[[rules]]
+action = "allow"
+uids = [0]
+"#;
+
+    fn pod_caller(namespace: &str, service_account: &str) -> CallerIdentity {
+        CallerIdentity {
+            uid: Some(1000),
+            gid: Some(1000),
+            cgroup: Some("/kubepods.slice/kubepods-burstable.slice/pod1.slice".to_string()),
+            pod_uid: Some("uid-1".to_string()),
+            namespace: Some(namespace.to_string()),
+            service_account: Some(service_account.to_string()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    //first matching rule decides, the default applies otherwise
+    fn evaluate_rules_in_order() {
+        let policy = Policy::from_toml(POLICY_TOML).unwrap();
+        let caller = pod_caller("confidential", "attester");
+        assert_eq!(policy.evaluate("GetQuote", &caller), Action::Allow);
+        assert_eq!(policy.evaluate("GetReport", &caller), Action::Deny);
+        assert_eq!(
+            policy.evaluate("GetQuote", &pod_caller("default", "attester")),
+            Action::Deny
+        );
+
+        let besteffort = CallerIdentity {
+            cgroup: Some("/kubepods.slice/kubepods-besteffort.slice/pod2.slice".to_string()),
+            ..pod_caller("confidential", "attester")
+        };
+        assert_eq!(policy.evaluate("GetQuote", &besteffort), Action::Deny);
+
+        let root = CallerIdentity {
+            uid: Some(0),
+            ..Default::default()
+        };
+        assert_eq!(policy.evaluate("GetReport", &root), Action::Allow);
+    }
+
+    #[test]
+    //callers with unknown attributes do not match rules on them
+    fn unknown_attributes_do_not_match() {
+        let policy = Policy::from_toml(POLICY_TOML).unwrap();
+        assert_eq!(
+            policy.evaluate("GetQuote", &CallerIdentity::default()),
+            Action::Deny
+        );
+    }
+
+    #[test]
+    //YAML and TOML policies are equivalent
+    fn load_yaml_policy() {
+        let policy = Policy::from_yaml(
+            r#"
+default: allow
+rules:
+  - action: deny
+    gids: [65534]
+"#,
+        )
+        .unwrap();
+        let nobody = CallerIdentity {
+            gid: Some(65534),
+            ..Default::default()
+        };
+        assert_eq!(policy.evaluate("GetQuote", &nobody), Action::Deny);
+        assert_eq!(
+            policy.evaluate("GetQuote", &CallerIdentity::default()),
+            Action::Allow
+        );
+        assert!(!policy.needs_pod_info());
+    }
+
+    #[test]
+    //misspelled conditions and rules without action are rejected
+    fn reject_invalid_policy() {
+        assert!(Policy::from_toml("[[rules]]\naction = \"allow\"\nuid = [0]\n").is_err());
+        assert!(Policy::from_toml("[[rules]]\nuids = [0]\n").is_err());
+        assert!(Policy::from_yaml("default: permit\n").is_err());
+    }
+
+    #[test]
+    //cgroup patterns with wildcards
+    fn glob_patterns() {
+        assert!(glob_match("/kubepods/*", "/kubepods/burstable/pod1"));
+        assert!(glob_match("*pod1*", "/kubepods/burstable/pod1/abc"));
+        assert!(glob_match("/a/*/c", "/a/b/c"));
+        assert!(glob_match("/exact", "/exact"));
+        assert!(!glob_match("/exact", "/exact/more"));
+        assert!(!glob_match("/a/*/c", "/a/b/d"));
+        assert!(!glob_match("/a*b*a", "/ab"));
+        assert!(!glob_match("a*a", "a"));
+    }
+
+    #[tokio::test]
+    //denied callers get PermissionDenied naming the method
+    async fn authorize_denied() {
+        let authorizer = Authorizer::new(Policy::from_toml(POLICY_TOML).unwrap(), None);
+        let mut caller = CallerIdentity {
+            uid: Some(1000),
+            ..Default::default()
+        };
+        match authorizer.authorize("GetQuote", &mut caller).await {
+            Err(QuoteError::PermissionDenied { method, caller }) => {
+                assert_eq!(method, "GetQuote");
+                assert_eq!(caller, "uid:1000");
+            }
+            _ => panic!("expected PermissionDenied"),
+        }
+    }
+
+    #[test]
+    //allows tells the policy decision for a method without authorizing
+    fn allows_method() {
+        let authorizer = Authorizer::new(Policy::from_toml(POLICY_TOML).unwrap(), None);
+        let caller = pod_caller("confidential", "attester");
+        assert!(authorizer.allows("GetQuote", &caller));
+        assert!(!authorizer.allows("GetChallenge", &caller));
+    }
+}
@@ -0,0 +1,247 @@
+/*
+* Copyright (c) 2023, Intel Corporation. All rights reserved.<BR>
+* SPDX-License-Identifier: Apache-2.0
+*/
+
+use crate::admission::AdmissionControl;
+use crate::executor::QuoteExecutor;
+use crate::shutdown::Shutdown;
+use sha2::{Digest, Sha512};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tonic_health::server::HealthReporter;
+use tonic_health::ServingStatus;
+use tracing::{info, warn};
+
+pub const DEFAULT_PROBE_INTERVAL: Duration = Duration::from_secs(10);
+
+// Caller key the prober uses in the admission control, so probes queue
+// behind client requests like any other caller
+const PROBE_CALLER: &str = "probe";
+const PROBE_NONCE_TAG: &[u8] = b"ccnp.health-probe.v1";
+
+#[derive(Clone, Copy, Debug)]
+pub struct ProbeConfig {
+    pub interval: Duration,
+    // Generate a full quote instead of a TDREPORT only, exercising the QGS
+    pub quote: bool,
+}
+
+// Outcome of the last probe
+#[derive(Clone, Debug, Default)]
+pub struct ProbeStatus {
+    pub healthy: bool,
+    pub checked_at: Option<SystemTime>,
+    pub latency: Option<Duration>,
+    pub error: Option<String>,
+    pub consecutive_failures: u64,
+}
+
+// Probe generating a TDREPORT or quote over the given base64 report data
+pub type ProbeFn = Arc<dyn Fn(String, bool) -> anyhow::Result<()> + Send + Sync>;
+
+// Periodically checks that the TEE device, and optionally the QGS, serve
+// requests and reports the result as serving status of the given services
+#[derive(Clone)]
+pub struct Prober {
+    status: Arc<RwLock<ProbeStatus>>,
+}
+
+impl Prober {
+    pub fn start(
+        config: ProbeConfig,
+        executor: QuoteExecutor,
+        admission: AdmissionControl,
+        probe_fn: ProbeFn,
+        health: (HealthReporter, &'static [&'static str]),
+        shutdown: Shutdown,
+    ) -> Self {
+        let prober = Prober {
+            status: Arc::new(RwLock::new(ProbeStatus {
+                healthy: true,
+                ..Default::default()
+            })),
+        };
+        let task = prober
+            .clone()
+            .run(config, executor, admission, probe_fn, health);
+        tokio::spawn(async move {
+            tokio::select! {
+                _ = task => {}
+                _ = shutdown.wait() => {}
+            }
+        });
+        prober
+    }
+
+    pub fn status(&self) -> ProbeStatus {
+        self.status.read().unwrap().clone()
+    }
+
+    async fn run(
+        self,
+        config: ProbeConfig,
+        executor: QuoteExecutor,
+        admission: AdmissionControl,
+        probe_fn: ProbeFn,
+        (mut reporter, service_names): (HealthReporter, &'static [&'static str]),
+    ) {
+        let mut interval = tokio::time::interval(config.interval);
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        loop {
+            interval.tick().await;
+            let result = probe(&executor, &admission, &probe_fn, config.quote).await;
+            let (was_healthy, healthy) = self.record(result);
+            if was_healthy != healthy {
+                let status = if healthy {
+                    ServingStatus::Serving
+                } else {
+                    ServingStatus::NotServing
+                };
+                for service_name in service_names {
+                    reporter.set_service_status(service_name, status).await;
+                }
+            }
+        }
+    }
+
+    // Record a probe result, returning the health before and after it
+    fn record(&self, result: Result<Duration, (Duration, String)>) -> (bool, bool) {
+        let mut status = self.status.write().unwrap();
+        let was_healthy = status.healthy;
+        status.checked_at = Some(SystemTime::now());
+        match result {
+            Ok(latency) => {
+                if !was_healthy {
+                    info!(
+                        failures = status.consecutive_failures,
+                        "health probe recovered"
+                    );
+                }
+                status.healthy = true;
+                status.latency = Some(latency);
+                status.error = None;
+                status.consecutive_failures = 0;
+            }
+            Err((latency, error)) => {
+                warn!(
+                    latency_ms = latency.as_millis() as u64,
+                    error = error.as_str(),
+                    "health probe failed"
+                );
+                status.healthy = false;
+                status.latency = Some(latency);
+                status.error = Some(error);
+                status.consecutive_failures += 1;
+            }
+        }
+        (was_healthy, status.healthy)
+    }
+}
+
+// Report data of a probe, distinct from anything a client could request
+fn probe_report_data() -> String {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos();
+    let mut hasher = Sha512::new();
+    hasher.update(PROBE_NONCE_TAG);
+    hasher.update(now.to_le_bytes());
+    base64::encode(hasher.finalize())
+}
+
+async fn probe(
+    executor: &QuoteExecutor,
+    admission: &AdmissionControl,
+    probe_fn: &ProbeFn,
+    quote: bool,
+) -> Result<Duration, (Duration, String)> {
+    let start = Instant::now();
+    let permit = admission
+        .acquire(PROBE_CALLER)
+        .await
+        .map_err(|e| (start.elapsed(), e.to_string()))?;
+    // latency covers the device work only, not the wait for a slot
+    let start = Instant::now();
+    let probe_fn = probe_fn.clone();
+    let result = executor
+        .run(executor.timeout(), move || {
+            let _permit = permit;
+            probe_fn(probe_report_data(), quote)
+        })
+        .await;
+    match result {
+        Ok(()) => Ok(start.elapsed()),
+        Err(status) => Err((start.elapsed(), status.message().to_string())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::admission::{DEFAULT_MAX_CONCURRENT, DEFAULT_MAX_QUEUED};
+    use crate::executor::DEFAULT_QUOTE_TIMEOUT;
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    fn start(failing: Arc<AtomicBool>, quote: bool) -> (Prober, Shutdown) {
+        let shutdown = Shutdown::new();
+        let (reporter, _) = tonic_health::server::health_reporter();
+        let probe_fn: ProbeFn = Arc::new(move |report_data, probe_quote| {
+            assert_eq!(base64::decode(report_data).unwrap().len(), 64);
+            assert_eq!(probe_quote, quote);
+            if failing.load(Ordering::SeqCst) {
+                Err(anyhow::anyhow!("QGS down"))
+            } else {
+                Ok(())
+            }
+        });
+        let prober = Prober::start(
+            ProbeConfig {
+                interval: Duration::from_millis(20),
+                quote,
+            },
+            QuoteExecutor::new(DEFAULT_QUOTE_TIMEOUT),
+            AdmissionControl::new(DEFAULT_MAX_CONCURRENT, DEFAULT_MAX_QUEUED),
+            probe_fn,
+            (reporter, &["quoteserver.GetQuote"]),
+            shutdown.clone(),
+        );
+        (prober, shutdown)
+    }
+
+    #[tokio::test]
+    //a failing probe marks the service unhealthy with its error, and recovers
+    async fn probe_failure_and_recovery() {
+        let failing = Arc::new(AtomicBool::new(true));
+        let (prober, shutdown) = start(failing.clone(), true);
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        let status = prober.status();
+        assert!(!status.healthy);
+        assert!(status.error.unwrap().contains("QGS down"));
+        assert!(status.consecutive_failures >= 1);
+        assert!(status.latency.is_some());
+
+        failing.store(false, Ordering::SeqCst);
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        let status = prober.status();
+        assert!(status.healthy);
+        assert!(status.error.is_none());
+        assert_eq!(status.consecutive_failures, 0);
+        shutdown.trigger();
+    }
+
+    #[tokio::test]
+    //the prober stops probing on shutdown
+    async fn probe_stops_on_shutdown() {
+        let (prober, shutdown) = start(Arc::new(AtomicBool::new(false)), false);
+        tokio::time::sleep(Duration::from_millis(30)).await;
+        shutdown.trigger();
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        let checked_at = prober.status().checked_at;
+        assert!(checked_at.is_some());
+        tokio::time::sleep(Duration::from_millis(60)).await;
+        assert_eq!(prober.status().checked_at, checked_at);
+    }
+}
@@ -3,14 +3,65 @@
 * SPDX-License-Identifier: Apache-2.0
 */
 
+// tonic::Status is the error type of every gRPC code path
+#![allow(clippy::result_large_err)]
+
+use admission::{AdmissionControl, DEFAULT_MAX_CONCURRENT, DEFAULT_MAX_QUEUED};
+use batch::{BatchConfig, BatchedQuote, Batcher};
+use challenge::{ChallengeConfig, Challenges};
 use clap::Parser;
+use collateral::CollateralClient;
+use config::{Config, TcpConfig, UnixConfig};
+use error::QuoteError;
+use eventlog::RuntimeEventLog;
+use executor::{QuoteExecutor, DEFAULT_QUOTE_TIMEOUT};
+use identity::CallerIdentity;
+use listener::{ClientAllowlist, Listener};
+use metrics::{Metrics, RenderFn};
+use pods::PodResolver;
+use policy::{Authorizer, Policy};
+use prober::{ProbeConfig, Prober};
 use quote_server::get_quote_server::{GetQuote, GetQuoteServer};
-use quote_server::{GetQuoteRequest, GetQuoteResponse};
-use tokio::net::UnixListener;
-use tokio_stream::wrappers::UnixListenerStream;
-use tonic::{transport::Server, Request, Response, Status};
+use quote_server::{BatchInclusionProof, GetQuoteRequest, GetQuoteResponse};
+use quote_verifier::identity::bind_identity;
+use shutdown::Shutdown;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use telemetry::{LogFormat, Sensitive};
+use tokio::net::{TcpListener, UnixListener};
+use tokio::sync::RwLock;
+use tokio_stream::wrappers::{TcpListenerStream, UnixListenerStream};
+use tonic::codegen::InterceptedService;
+use tonic::server::NamedService;
+use tonic::transport::{Server, ServerTlsConfig};
+use tonic::{Request, Response, Status};
+use tonic_health::pb::health_server::{Health, HealthServer};
+use tonic_health::server::HealthReporter;
+use tonic_health::ServingStatus;
+use tracing::{debug, info, warn};
+use watch::{Watches, DEFAULT_MAX_WATCHES};
 
+pub mod admission;
+pub mod batch;
+pub mod challenge;
+pub mod collateral;
+pub mod config;
+pub mod error;
+pub mod eventlog;
+pub mod executor;
+pub mod identity;
+pub mod listener;
+pub mod metrics;
+pub mod pods;
+pub mod policy;
+pub mod prober;
+pub mod shutdown;
 pub mod tee;
+pub mod telemetry;
+pub mod v2;
+pub mod watch;
 use tee::*;
 
 pub mod quote_server {
@@ -20,88 +71,855 @@ pub mod quote_server {
         tonic::include_file_descriptor_set!("quote_server_descriptor");
 }
 
+pub mod quote_server_v2 {
+    tonic::include_proto!("quoteserver.v2");
+}
+
+// gRPC services of both API versions, which share health status
+type GetQuoteServerV2 = quote_server_v2::get_quote_server::GetQuoteServer<CCNPGetQuote>;
+const QUOTE_SERVICES: [&str; 2] = [
+    <GetQuoteServer<CCNPGetQuote> as NamedService>::NAME,
+    <GetQuoteServerV2 as NamedService>::NAME,
+];
+
+// Methods that change the measurements of the TD, denied unless a policy
+// allows them
+const POLICY_METHODS: [&str; 1] = ["ExtendRuntimeMeasurement"];
+
+// Cheap to clone, the state is shared by all clones
+#[derive(Clone)]
 pub struct CCNPGetQuote {
     local_tee: tee::TeeType,
+    // detected once, so that responses do not take a TDREPORT
+    tdx_version: Option<TdxVersion>,
+    executor: QuoteExecutor,
+    admission: AdmissionControl,
+    batcher: Option<Batcher>,
+    bind_identity: bool,
+    authorizer: Option<Authorizer>,
+    prober: Option<Prober>,
+    challenges: Challenges,
+    collateral: Option<CollateralClient>,
+    // held for writing while an event is extended, and for reading while
+    // evidence covering the log is generated
+    runtime_log: Arc<RwLock<RuntimeEventLog>>,
+    watches: Watches,
+    // ends WatchAttestation streams, which would hold up the shutdown
+    shutdown: Shutdown,
+    metrics: Metrics,
 }
 
 impl CCNPGetQuote {
     fn new(_local_tee: TeeType) -> Self {
         CCNPGetQuote {
+            metrics: Metrics::new(format!("{:?}", _local_tee)),
+            tdx_version: match _local_tee {
+                TeeType::TDX => tee::detect_tdx_version(),
+                _ => None,
+            },
             local_tee: _local_tee,
+            executor: QuoteExecutor::new(DEFAULT_QUOTE_TIMEOUT),
+            admission: AdmissionControl::new(DEFAULT_MAX_CONCURRENT, DEFAULT_MAX_QUEUED),
+            batcher: None,
+            bind_identity: false,
+            authorizer: None,
+            prober: None,
+            challenges: Challenges::new(ChallengeConfig::default()).unwrap(),
+            collateral: None,
+            runtime_log: Arc::new(RwLock::new(RuntimeEventLog::new(None).unwrap())),
+            watches: Watches::new(DEFAULT_MAX_WATCHES),
+            shutdown: Shutdown::new(),
+        }
+    }
+
+    fn with_watches(mut self, max_watches: usize, shutdown: Shutdown) -> Self {
+        self.watches = Watches::new(max_watches);
+        self.shutdown = shutdown;
+        self
+    }
+
+    // Continue the runtime event log of a previous run
+    fn with_runtime_log(mut self, runtime_log: RuntimeEventLog) -> Self {
+        self.runtime_log = Arc::new(RwLock::new(runtime_log));
+        self
+    }
+
+    // Fetch collateral for GetEvidence from this service
+    fn with_collateral(mut self, collateral: CollateralClient) -> Self {
+        self.collateral = Some(collateral);
+        self
+    }
+
+    fn with_challenges(mut self, challenges: Challenges) -> Self {
+        self.challenges = challenges;
+        self
+    }
+
+    // Consume the nonce of a quote request if it is a challenge, rejecting
+    // other nonces in strict mode
+    async fn check_nonce(&self, nonce: &[u8]) -> Result<(), Status> {
+        self.challenges
+            .check(nonce)
+            .await
+            .map_err(|e| error::to_status(&e))
+    }
+
+    // Only serve callers the authorization policy allows
+    fn with_authorizer(mut self, authorizer: Authorizer) -> Self {
+        self.authorizer = Some(authorizer);
+        self
+    }
+
+    async fn authorize(
+        &self,
+        method: &'static str,
+        caller: &mut CallerIdentity,
+    ) -> Result<(), Status> {
+        // resolved here rather than in the interceptor, which cannot wait for
+        // file I/O
+        caller.resolve_cgroup().await;
+        tracing::Span::current().record("caller", caller.key().as_str());
+        match &self.authorizer {
+            Some(authorizer) => authorizer
+                .authorize(method, caller)
+                .await
+                .map_err(|e| Status::from(&e)),
+            None if POLICY_METHODS.contains(&method) => {
+                warn!(method, caller = ?caller, "caller denied without policy");
+                Err(Status::from(&QuoteError::PermissionDenied {
+                    method,
+                    caller: caller.key(),
+                }))
+            }
+            None => Ok(()),
+        }
+    }
+
+    // Whether the policy lets `caller` call `method`, for telling clients what
+    // they can use. The caller must have been authorized for another method
+    // before, so that its pod is resolved.
+    fn allows(&self, method: &str, caller: &CallerIdentity) -> bool {
+        match &self.authorizer {
+            Some(authorizer) => authorizer.allows(method, caller),
+            None => !POLICY_METHODS.contains(&method),
+        }
+    }
+
+    // Hash the caller identity into the report data of every quote
+    fn with_identity_binding(mut self) -> Self {
+        self.bind_identity = true;
+        self
+    }
+
+    fn response_identity(&self, caller: &CallerIdentity) -> Option<quote_server::CallerIdentity> {
+        if !self.bind_identity {
+            return None;
+        }
+        Some(quote_server::CallerIdentity {
+            pod_uid: caller.pod_uid.clone().unwrap_or_default(),
+            container_id: caller.container_id.clone().unwrap_or_default(),
+            uid: caller.uid,
+            gid: caller.gid,
+            tls_client: caller.tls_client.clone().unwrap_or_default(),
+        })
+    }
+
+    // Probe the TEE periodically and report the result as serving status
+    fn with_prober(
+        mut self,
+        config: ProbeConfig,
+        reporter: HealthReporter,
+        shutdown: Shutdown,
+    ) -> Self {
+        let local_tee = self.local_tee.clone();
+        let metrics = self.metrics.clone();
+        self.prober = Some(Prober::start(
+            config,
+            self.executor.clone(),
+            self.admission.clone(),
+            Arc::new(move |report_data, quote| {
+                if quote {
+                    metrics
+                        .time_quote(|| get_quote_with_report_data(local_tee.clone(), report_data))
+                        .map(|_| ())
+                } else {
+                    metrics
+                        .time_report(|| get_report(local_tee.clone(), report_data))
+                        .map(|_| ())
+                }
+            }),
+            (reporter, &QUOTE_SERVICES),
+            shutdown,
+        ));
+        self
+    }
+
+    // Serve quote requests in batches, one quote per batch
+    fn with_batching(mut self, config: BatchConfig) -> Self {
+        let local_tee = self.local_tee.clone();
+        let metrics = self.metrics.clone();
+        self.batcher = Some(Batcher::start(
+            config,
+            self.executor.clone(),
+            self.admission.clone(),
+            Arc::new(move |report_data| {
+                metrics.time_quote(|| get_quote_with_report_data(local_tee.clone(), report_data))
+            }),
+        ));
+        self
+    }
+
+    // Current metrics, with the queue depths and probe status as of now
+    fn render_metrics(&self) -> String {
+        self.metrics
+            .set_queue_depth("admission", self.admission.queued());
+        if let Some(batcher) = &self.batcher {
+            self.metrics.set_queue_depth("batch", batcher.queued());
+        }
+        if let Some(prober) = &self.prober {
+            self.metrics.set_probe_status(&prober.status());
+        }
+        self.metrics.render()
+    }
+
+    // A quote bound to a partial identity would not tell which workload
+    // requested it, so callers whose workload is unknown are refused
+    fn bind_caller(
+        &self,
+        caller: &CallerIdentity,
+        report_data: Vec<u8>,
+    ) -> Result<Vec<u8>, Status> {
+        if !self.bind_identity {
+            return Ok(report_data);
+        }
+        if !caller.workload_resolved() {
+            warn!(caller = ?caller, "caller workload cannot be resolved");
+            return Err(Status::from(&QuoteError::IdentityUnresolved(caller.key())));
+        }
+        Ok(bind_identity(&report_data, &caller.digest()).to_vec())
+    }
+
+    // Generate the quote of one request over its report data, bound to the
+    // caller identity if enabled
+    async fn generate(
+        &self,
+        caller: &CallerIdentity,
+        budget: Duration,
+        report_data: Vec<u8>,
+    ) -> Result<GeneratedQuote, Status> {
+        let report_data = self.bind_caller(caller, report_data)?;
+        match &self.batcher {
+            Some(batcher) => {
+                let batched = self
+                    .batched_quote(caller, batcher, budget, report_data)
+                    .await?;
+                Ok(GeneratedQuote {
+                    quote: batched.quote.clone(),
+                    batch: Some(batched),
+                })
+            }
+            None => Ok(GeneratedQuote {
+                quote: self.direct_quote(caller, budget, report_data).await?,
+                batch: None,
+            }),
+        }
+    }
+
+    // Generate the local report of one request, bound to the caller identity
+    // if enabled. Reports take no QGS round-trip and are never batched.
+    async fn report(
+        &self,
+        caller: &CallerIdentity,
+        budget: Duration,
+        report_data: Vec<u8>,
+    ) -> Result<Vec<u8>, Status> {
+        let report_data = self.bind_caller(caller, report_data)?;
+        let local_tee = self.local_tee.clone();
+        let metrics = self.metrics.clone();
+        self.run_on_device(caller, budget, move || {
+            metrics.time_report(|| get_report(local_tee, base64::encode(report_data)))
+        })
+        .await
+    }
+
+    async fn direct_quote(
+        &self,
+        caller: &CallerIdentity,
+        budget: Duration,
+        report_data: Vec<u8>,
+    ) -> Result<Vec<u8>, Status> {
+        let local_tee = self.local_tee.clone();
+        let metrics = self.metrics.clone();
+        self.run_on_device(caller, budget, move || {
+            metrics
+                .time_quote(|| get_quote_with_report_data(local_tee, base64::encode(report_data)))
+        })
+        .await
+    }
+
+    // Run `f` on the TEE device once the admission control lets `caller` in
+    async fn run_on_device<F, T>(
+        &self,
+        caller: &CallerIdentity,
+        budget: Duration,
+        f: F,
+    ) -> Result<T, Status>
+    where
+        F: FnOnce() -> anyhow::Result<T> + Send + 'static,
+        T: Send + 'static,
+    {
+        let deadline = Instant::now() + budget;
+
+        // time spent waiting in the queue counts against the request deadline
+        let permit = match tokio::time::timeout(budget, self.admission.acquire(&caller.key())).await
+        {
+            Ok(p) => p.map_err(|e| Status::from(&e))?,
+            Err(_) => return Err((&QuoteError::DeadlineExceeded(budget)).into()),
+        };
+        let remaining = deadline.saturating_duration_since(Instant::now());
+
+        // the permit is held until the device work is done, even if the
+        // request gives up waiting for it
+        self.executor
+            .run(remaining, move || {
+                let _permit = permit;
+                f()
+            })
+            .await
+    }
+
+    async fn batched_quote(
+        &self,
+        caller: &CallerIdentity,
+        batcher: &Batcher,
+        budget: Duration,
+        leaf: Vec<u8>,
+    ) -> Result<BatchedQuote, Status> {
+        let deadline = tokio::time::Instant::now() + budget;
+        match tokio::time::timeout(budget, batcher.submit(&caller.key(), deadline, leaf)).await {
+            Ok(r) => r,
+            Err(_) => Err((&QuoteError::DeadlineExceeded(budget)).into()),
         }
     }
 }
 
+// Quote of one request, before it is encoded for an API version
+struct GeneratedQuote {
+    quote: Vec<u8>,
+    batch: Option<BatchedQuote>,
+}
+
 #[tonic::async_trait]
 impl GetQuote for CCNPGetQuote {
     async fn get_quote(
         &self,
         request: Request<GetQuoteRequest>,
     ) -> Result<Response<GetQuoteResponse>, Status> {
-        let msg;
-        let req = request.into_inner();
+        let start = Instant::now();
+        let budget = self.executor.budget(request.metadata());
+        let mut caller = identity::caller_identity(&request);
+        let span = tracing::Span::current();
+        span.record("caller", caller.key().as_str());
+        span.record("tee", format!("{:?}", self.local_tee).as_str());
 
-        println!(
-            "Got a request with: user_data = {:?}, nonce = {:?}",
-            req.user_data, req.nonce
-        );
-        let result = get_quote(self.local_tee.clone(), req.user_data, req.nonce);
-        match result {
-            Ok(q) => {
-                msg = Response::new(quote_server::GetQuoteResponse {
-                    quote: q,
-                    quote_type: format!("{:?}", self.local_tee).to_string(),
-                })
-            }
-            Err(e) => return Err(Status::internal(e.to_string())),
+        let result = async {
+            self.authorize("GetQuote", &mut caller).await?;
+            let req = request.into_inner();
+            debug!(
+                user_data = %Sensitive(req.user_data.as_bytes()),
+                nonce = %Sensitive(req.nonce.as_bytes()),
+                "GetQuote request"
+            );
+            let report_data =
+                get_report_data(self.local_tee.clone(), req.user_data, req.nonce.clone())
+                    .map_err(|e| error::to_status(&e))?;
+            // the nonce was validated as base64 above
+            self.check_nonce(&base64::decode(req.nonce).unwrap_or_default())
+                .await?;
+            let report_data =
+                base64::decode(report_data).map_err(|e| Status::internal(e.to_string()))?;
+            let generated = self.generate(&caller, budget, report_data).await?;
+            Ok(GetQuoteResponse {
+                quote: encode_legacy_quote(&generated.quote).map_err(|e| error::to_status(&e))?,
+                quote_type: format!("{:?}", self.local_tee),
+                batch_proof: generated.batch.map(|batched| BatchInclusionProof {
+                    leaf: base64::encode(batched.leaf),
+                    leaf_index: batched.leaf_index,
+                    batch_size: batched.batch_size,
+                    audit_path: batched.audit_path.iter().map(base64::encode).collect(),
+                }),
+                caller_identity: self.response_identity(&caller),
+            })
         }
-        Ok(msg)
+        .await;
+        telemetry::finish(start, &result);
+        self.metrics.record_request("GetQuote", start, &result);
+        result.map(Response::new)
     }
 }
 
 #[derive(Parser)]
 struct Cli {
-    port: String,
+    /// TOML config file, the options below override its values
+    #[arg(long)]
+    config: Option<PathBuf>,
+
+    /// Path of the Unix domain socket
+    #[arg(long)]
+    socket_path: Option<PathBuf>,
+
+    /// Permission bits of the socket file in octal, e.g. 660
+    #[arg(long, value_parser = parse_mode)]
+    socket_mode: Option<u32>,
+
+    /// User owning the socket file, by name or numeric ID
+    #[arg(long)]
+    socket_owner: Option<String>,
+
+    /// Group owning the socket file, by name or numeric ID
+    #[arg(long)]
+    socket_group: Option<String>,
+
+    /// Also listen on this TCP address with mutual TLS
+    #[arg(long)]
+    tcp_address: Option<SocketAddr>,
+
+    /// Server certificate chain of the TCP listener in PEM
+    #[arg(long)]
+    tls_cert: Option<PathBuf>,
+
+    /// Private key of the server certificate in PEM
+    #[arg(long)]
+    tls_key: Option<PathBuf>,
+
+    /// CA certificates in PEM that client certificates must be issued by
+    #[arg(long)]
+    tls_client_ca: Option<PathBuf>,
+
+    /// Allowed client certificate, by subject common name, DNS or URI SAN, or
+    /// "sha256:<hex>" fingerprint. May be repeated, all clients are allowed if
+    /// none is given.
+    #[arg(long)]
+    tls_allowed_client: Vec<String>,
+
+    /// Batch quote requests arriving within this many milliseconds into one
+    /// quote, 0 disables batching
+    #[arg(long)]
+    batch_window_ms: Option<u64>,
+
+    /// Maximum number of requests in one batch
+    #[arg(long)]
+    batch_max_size: Option<usize>,
+
+    /// Hash the identity of the requesting workload into the report data
+    #[arg(long)]
+    bind_caller_identity: bool,
+
+    /// Authorization policy file (.toml, .yaml or .yml), all callers are
+    /// allowed if unset
+    #[arg(long)]
+    policy: Option<PathBuf>,
+
+    /// Seconds NOT_SERVING is reported after SIGTERM or SIGINT before new
+    /// requests are refused
+    #[arg(long)]
+    pre_stop_delay_secs: Option<u64>,
+
+    /// Seconds in-flight requests may take to finish after SIGTERM or SIGINT
+    #[arg(long)]
+    shutdown_timeout_secs: Option<u64>,
+
+    /// Seconds between health probes of the TEE, 0 disables probing
+    #[arg(long)]
+    probe_interval_secs: Option<u64>,
+
+    /// Probe with a full quote instead of a TDREPORT, also checking the QGS
+    #[arg(long)]
+    probe_quote: bool,
+
+    /// Log output format
+    #[arg(long, value_enum)]
+    log_format: Option<LogFormat>,
+
+    /// Log user data and nonces in plaintext instead of their digest
+    #[arg(long)]
+    log_sensitive: bool,
+
+    /// Serve Prometheus metrics over HTTP on this TCP address
+    #[arg(long)]
+    metrics_address: Option<SocketAddr>,
+
+    /// Serve Prometheus metrics over HTTP on this Unix domain socket
+    #[arg(long)]
+    metrics_socket: Option<PathBuf>,
+
+    /// Only accept quote requests whose nonce is an unused challenge from
+    /// GetChallenge
+    #[arg(long)]
+    strict_challenges: bool,
+
+    /// Seconds a challenge stays valid
+    #[arg(long)]
+    challenge_ttl_secs: Option<u64>,
+
+    /// Maximum number of consumed challenges kept until they expire
+    #[arg(long)]
+    challenge_max_consumed: Option<usize>,
+
+    /// File keeping the challenge key and consumed challenges across restarts
+    #[arg(long)]
+    challenge_state_file: Option<PathBuf>,
+
+    /// Base URL of the PCCS or Intel PCS to fetch quote collateral from
+    #[arg(long)]
+    collateral_url: Option<String>,
+
+    /// File keeping the runtime event log across restarts, on a tmpfs. Runtime
+    /// events are not extended without it
+    #[arg(long)]
+    runtime_event_log_file: Option<PathBuf>,
+
+    /// Maximum number of WatchAttestation streams open at the same time
+    #[arg(long)]
+    max_watches: Option<usize>,
 }
 
-#[tokio::main]
-async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let path = "/run/ccnp/uds/quote-server.sock";
-    let _ = std::fs::remove_file(path);
-    let uds = match UnixListener::bind(path) {
-        Ok(r) => r,
-        Err(e) => panic!("[quote-server]: bind UDS socket error: {:?}", e),
-    };
-    let uds_stream = UnixListenerStream::new(uds);
+fn parse_mode(mode: &str) -> Result<u32, String> {
+    u32::from_str_radix(mode.trim_start_matches("0o"), 8).map_err(|e| e.to_string())
+}
 
-    let getquote = CCNPGetQuote::new({
-        match tee::get_tee_type() {
-            tee::TeeType::PLAIN => panic!("[quote-server]: Not found any TEE device!"),
-            t => t,
+impl Cli {
+    // Load the config file and apply the command line options on top
+    fn config(self) -> anyhow::Result<Config> {
+        let mut config = match &self.config {
+            Some(path) => Config::load(path)?,
+            None => Config::default(),
+        };
+
+        let unix = &mut config.unix;
+        unix.path = self.socket_path.unwrap_or(unix.path.clone());
+        unix.mode = self.socket_mode.or(unix.mode);
+        unix.owner = self.socket_owner.or(unix.owner.take());
+        unix.group = self.socket_group.or(unix.group.take());
+
+        if let Some(address) = self.tcp_address {
+            let missing = |name| anyhow::anyhow!("--tcp-address requires --{}", name);
+            config.tcp = Some(match config.tcp.take() {
+                Some(tcp) => TcpConfig { address, ..tcp },
+                None => TcpConfig {
+                    address,
+                    cert: self.tls_cert.clone().ok_or_else(|| missing("tls-cert"))?,
+                    key: self.tls_key.clone().ok_or_else(|| missing("tls-key"))?,
+                    client_ca: self
+                        .tls_client_ca
+                        .clone()
+                        .ok_or_else(|| missing("tls-client-ca"))?,
+                    allowed_clients: Vec::new(),
+                },
+            });
+        }
+        if let Some(tcp) = &mut config.tcp {
+            tcp.cert = self.tls_cert.unwrap_or(tcp.cert.clone());
+            tcp.key = self.tls_key.unwrap_or(tcp.key.clone());
+            tcp.client_ca = self.tls_client_ca.unwrap_or(tcp.client_ca.clone());
+            if !self.tls_allowed_client.is_empty() {
+                tcp.allowed_clients = self.tls_allowed_client;
+            }
         }
-    });
 
-    let (mut health_reporter, health_service) = tonic_health::server::health_reporter();
-    health_reporter
-        .set_serving::<GetQuoteServer<CCNPGetQuote>>()
-        .await;
+        config.batch_window_ms = self.batch_window_ms.unwrap_or(config.batch_window_ms);
+        config.batch_max_size = self.batch_max_size.unwrap_or(config.batch_max_size);
+        config.bind_caller_identity |= self.bind_caller_identity;
+        config.policy = self.policy.or(config.policy);
+        config.pre_stop_delay_secs = self
+            .pre_stop_delay_secs
+            .unwrap_or(config.pre_stop_delay_secs);
+        config.shutdown_timeout_secs = self
+            .shutdown_timeout_secs
+            .unwrap_or(config.shutdown_timeout_secs);
+        config.probe_interval_secs = self
+            .probe_interval_secs
+            .unwrap_or(config.probe_interval_secs);
+        config.probe_quote |= self.probe_quote;
+        config.log_format = self.log_format.unwrap_or(config.log_format);
+        config.log_sensitive |= self.log_sensitive;
+        config.metrics.address = self.metrics_address.or(config.metrics.address);
+        if let Some(path) = self.metrics_socket {
+            config.metrics.unix = Some(UnixConfig {
+                path,
+                ..config.metrics.unix.unwrap_or_default()
+            });
+        }
+        config.strict_challenges |= self.strict_challenges;
+        config.challenge_ttl_secs = self.challenge_ttl_secs.unwrap_or(config.challenge_ttl_secs);
+        config.challenge_max_consumed = self
+            .challenge_max_consumed
+            .unwrap_or(config.challenge_max_consumed);
+        config.challenge_state_file = self.challenge_state_file.or(config.challenge_state_file);
+        config.collateral.url = self.collateral_url.or(config.collateral.url);
+        config.runtime_event_log_file = self
+            .runtime_event_log_file
+            .or(config.runtime_event_log_file);
+        config.max_watches = self.max_watches.unwrap_or(config.max_watches);
+        Ok(config)
+    }
+}
 
+async fn serve_unix<H: Health>(
+    listener: UnixListener,
+    getquote: Arc<CCNPGetQuote>,
+    health_service: HealthServer<H>,
+    shutdown: Shutdown,
+) -> Result<(), tonic::transport::Error> {
     let reflection_service = tonic_reflection::server::Builder::configure()
         .register_encoded_file_descriptor_set(quote_server::FILE_DESCRIPTOR_SET)
         .build()
         .unwrap();
 
-    println!(
-        "Starting quote server in {} enviroment...",
-        format!("{:?}", tee::get_tee_type()).to_string()
-    );
+    Server::builder()
+        .trace_fn(telemetry::request_span)
+        .add_service(reflection_service)
+        .add_service(health_service)
+        .add_service(InterceptedService::new(
+            GetQuoteServer::from_arc(getquote.clone()),
+            identity::resolve_identity,
+        ))
+        .add_service(InterceptedService::new(
+            GetQuoteServerV2::from_arc(getquote),
+            identity::resolve_identity,
+        ))
+        .serve_with_incoming_shutdown(UnixListenerStream::new(listener), shutdown.wait())
+        .await
+}
+
+async fn serve_tcp<H: Health>(
+    listener: TcpListener,
+    tls: ServerTlsConfig,
+    allowlist: ClientAllowlist,
+    getquote: Arc<CCNPGetQuote>,
+    health_service: HealthServer<H>,
+    shutdown: Shutdown,
+) -> Result<(), tonic::transport::Error> {
+    let reflection_service = tonic_reflection::server::Builder::configure()
+        .register_encoded_file_descriptor_set(quote_server::FILE_DESCRIPTOR_SET)
+        .build()
+        .unwrap();
 
+    // the allowlist guards every service of the TCP listener, health checks
+    // and reflection included
     Server::builder()
+        .trace_fn(telemetry::request_span)
+        .tls_config(tls)?
+        .layer(tonic::service::interceptor(move |request| {
+            allowlist.check(request)
+        }))
         .add_service(reflection_service)
         .add_service(health_service)
-        .add_service(GetQuoteServer::new(getquote))
-        .serve_with_incoming(uds_stream)
-        .await?;
+        .add_service(InterceptedService::new(
+            GetQuoteServer::from_arc(getquote.clone()),
+            identity::resolve_identity,
+        ))
+        .add_service(InterceptedService::new(
+            GetQuoteServerV2::from_arc(getquote),
+            identity::resolve_identity,
+        ))
+        .serve_with_incoming_shutdown(TcpListenerStream::new(listener), shutdown.wait())
+        .await
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let config = Cli::parse().config()?;
+    telemetry::init(config.log_format, config.log_sensitive);
+
+    let mut listeners = listener::systemd_listeners()?;
+    // the socket file is only removed on exit if this process created it
+    let mut socket_path = None;
+    if listeners.is_empty() {
+        listeners.push(Listener::Unix(listener::bind_unix(&config.unix)?));
+        socket_path = Some(config.unix.path.clone());
+        if let Some(tcp) = &config.tcp {
+            listeners.push(Listener::Tcp(listener::bind_tcp(tcp).await?));
+        }
+    }
+
+    let mut getquote = CCNPGetQuote::new({
+        match tee::get_tee_type() {
+            tee::TeeType::PLAIN => panic!("[quote-server]: Not found any TEE device!"),
+            t => t,
+        }
+    });
+    if config.batch_window_ms > 0 {
+        getquote = getquote.with_batching(BatchConfig {
+            window: Duration::from_millis(config.batch_window_ms),
+            max_size: config.batch_max_size,
+            max_queued: DEFAULT_MAX_QUEUED,
+        });
+    }
+
+    if config.bind_caller_identity {
+        getquote = getquote.with_identity_binding();
+    }
+
+    getquote = getquote.with_challenges(Challenges::new(ChallengeConfig {
+        ttl: Duration::from_secs(config.challenge_ttl_secs),
+        max_consumed: config.challenge_max_consumed,
+        strict: config.strict_challenges,
+        state_file: config.challenge_state_file.clone(),
+    })?);
+
+    if let Some(collateral) = CollateralClient::new(&config.collateral)? {
+        getquote = getquote.with_collateral(collateral);
+    }
+
+    if let Some(path) = &config.runtime_event_log_file {
+        getquote = getquote.with_runtime_log(RuntimeEventLog::new(Some(path))?);
+    }
+
+    if getquote.local_tee == tee::TeeType::TDX {
+        match getquote.tdx_version {
+            Some(version) => info!(?version, "TDX module detected"),
+            None => warn!("TDX module version could not be determined"),
+        }
+    }
+
+    // the TPM log is not covered by any quote the server generates, so it is
+    // only reported here and never served as evidence
+    if getquote.local_tee == tee::TeeType::TPM {
+        match eventlog::read_tpm_log() {
+            Ok(Some(log)) => info!(size = log.data.len(), "TPM boot event log found"),
+            Ok(None) => {}
+            Err(e) => warn!(error = ?e, "TPM boot event log cannot be read"),
+        }
+    }
+
+    let shutdown = Shutdown::new();
+    getquote = getquote.with_watches(config.max_watches, shutdown.clone());
+
+    if let Some(path) = &config.policy {
+        let policy = Policy::load(path)?;
+        let pods = if policy.needs_pod_info() {
+            Some(PodResolver::new().await?)
+        } else {
+            None
+        };
+        getquote = getquote.with_authorizer(Authorizer::new(policy, pods));
+    }
+
+    let (mut health_reporter, health_service) = tonic_health::server::health_reporter();
+    for service in QUOTE_SERVICES {
+        health_reporter
+            .set_service_status(service, ServingStatus::Serving)
+            .await;
+    }
+
+    // stopped before shutdown reports NOT_SERVING, so that it cannot flip
+    // the status back
+    let stop_probing = Shutdown::new();
+    if config.probe_interval_secs > 0 {
+        getquote = getquote.with_prober(
+            ProbeConfig {
+                interval: Duration::from_secs(config.probe_interval_secs),
+                quote: config.probe_quote,
+            },
+            health_reporter.clone(),
+            stop_probing.clone(),
+        );
+    }
+    let getquote = Arc::new(getquote);
+
+    info!(tee = ?tee::get_tee_type(), "starting quote server");
+
+    // metrics stay available until the requests have drained
+    let stop_metrics = Shutdown::new();
+    let mut metrics_listeners = Vec::new();
+    if let Some(address) = config.metrics.address {
+        let l = TcpListener::bind(address).await?;
+        metrics_listeners.push(Listener::Tcp(l));
+    }
+    if let Some(unix) = &config.metrics.unix {
+        metrics_listeners.push(Listener::Unix(listener::bind_unix(unix)?));
+    }
+    for l in metrics_listeners {
+        let getquote = getquote.clone();
+        let render: RenderFn = Arc::new(move || getquote.render_metrics());
+        tokio::spawn(metrics::serve(l, render, stop_metrics.clone()));
+    }
+
+    let mut servers = Vec::new();
+    for l in listeners {
+        let getquote = getquote.clone();
+        let health_service = health_service.clone();
+        let shutdown = shutdown.clone();
+        match l {
+            Listener::Unix(l) => {
+                servers.push(tokio::spawn(serve_unix(
+                    l,
+                    getquote,
+                    health_service,
+                    shutdown,
+                )));
+            }
+            Listener::Tcp(l) => {
+                let tcp = config
+                    .tcp
+                    .as_ref()
+                    .ok_or("[quote-server]: TCP listener requires the tcp config")?;
+                let tls = listener::tls_config(tcp)?;
+                let allowlist = ClientAllowlist::new(tcp.allowed_clients.clone());
+                servers.push(tokio::spawn(serve_tcp(
+                    l,
+                    tls,
+                    allowlist,
+                    getquote,
+                    health_service,
+                    shutdown,
+                )));
+            }
+        }
+    }
+    let servers = async {
+        for server in servers {
+            server.await??;
+        }
+        Ok::<(), Box<dyn std::error::Error>>(())
+    };
+    tokio::pin!(servers);
+
+    tokio::select! {
+        signal = shutdown::wait_for_signal() => {
+            info!(signal = signal?, "shutting down");
+        }
+        result = &mut servers => return result,
+    }
+
+    // report NOT_SERVING first and keep serving for the pre-stop delay, so
+    // that probes see it and stop routing new clients here, then stop
+    // accepting requests and let in-flight ones finish
+    stop_probing.trigger();
+    for service in QUOTE_SERVICES.into_iter().chain([""]) {
+        health_reporter
+            .set_service_status(service, ServingStatus::NotServing)
+            .await;
+    }
+    tokio::time::sleep(Duration::from_secs(config.pre_stop_delay_secs)).await;
+    shutdown.trigger();
+    let drain_timeout = Duration::from_secs(config.shutdown_timeout_secs);
+    match tokio::time::timeout(drain_timeout, &mut servers).await {
+        Ok(result) => result?,
+        Err(_) => warn!(
+            timeout_secs = config.shutdown_timeout_secs,
+            "requests still in flight, exiting"
+        ),
+    }
+
+    stop_metrics.trigger();
+    if let Some(path) = socket_path {
+        let _ = std::fs::remove_file(path);
+    }
+    if let Some(unix) = &config.metrics.unix {
+        let _ = std::fs::remove_file(&unix.path);
+    }
     Ok(())
 }
 
@@ -114,14 +932,35 @@ mod quote_server_tests {
     use tonic::transport::{Endpoint, Uri};
     use tower::service_fn;
 
-    async fn creat_server() {
-        let path = "/tmp/quote-server.sock";
-        let _ = std::fs::remove_file(path);
-        let uds = match UnixListener::bind(path) {
-            Ok(r) => r,
-            Err(e) => panic!("[quote-server]: bind UDS socket error: {:?}", e),
+    #[test]
+    //identity binding refuses Unix socket callers whose workload is unknown
+    fn bind_unresolved_caller() {
+        let getquote = CCNPGetQuote::new(TeeType::TDX).with_identity_binding();
+        let unresolved = CallerIdentity {
+            uid: Some(1000),
+            ..Default::default()
+        };
+        let status = getquote.bind_caller(&unresolved, vec![0; 64]).unwrap_err();
+        assert_eq!(status.code(), tonic::Code::FailedPrecondition);
+
+        let resolved = CallerIdentity {
+            cgroup: Some("/user.slice".to_string()),
+            ..unresolved.clone()
         };
-        let uds_stream = UnixListenerStream::new(uds);
+        assert!(getquote.bind_caller(&resolved, vec![0; 64]).is_ok());
+        let unbound = CCNPGetQuote::new(TeeType::TDX);
+        assert_eq!(
+            unbound.bind_caller(&unresolved, vec![0; 64]).unwrap(),
+            vec![0; 64]
+        );
+    }
+
+    async fn creat_server() {
+        let listener = listener::bind_unix(&config::UnixConfig {
+            path: PathBuf::from("/tmp/quote-server.sock"),
+            ..Default::default()
+        })
+        .unwrap();
 
         let getquote = CCNPGetQuote::new({
             match tee::get_tee_type() {
@@ -129,14 +968,193 @@ mod quote_server_tests {
                 t => t,
             }
         });
+        let (_, health_service) = tonic_health::server::health_reporter();
 
         tokio::spawn(async {
-            Server::builder()
-                .add_service(GetQuoteServer::new(getquote))
-                .serve_with_incoming(uds_stream)
+            serve_unix(
+                listener,
+                Arc::new(getquote),
+                health_service,
+                Shutdown::new(),
+            )
+            .await
+            .unwrap();
+        });
+    }
+
+    #[tokio::test]
+    //requests in flight when shutdown starts are served before the server exits
+    async fn shutdown_drains_in_flight() {
+        let path =
+            std::env::temp_dir().join(format!("quote-server-drain-{}.sock", std::process::id()));
+        let listener = listener::bind_unix(&config::UnixConfig {
+            path: path.clone(),
+            ..Default::default()
+        })
+        .unwrap();
+        // the batch window keeps the request in flight while shutting down
+        let getquote = CCNPGetQuote::new(TeeType::TDX).with_batching(BatchConfig {
+            window: Duration::from_millis(300),
+            max_size: 64,
+            max_queued: DEFAULT_MAX_QUEUED,
+        });
+        let (_, health_service) = tonic_health::server::health_reporter();
+        let shutdown = Shutdown::new();
+        let server = tokio::spawn(serve_unix(
+            listener,
+            Arc::new(getquote),
+            health_service,
+            shutdown.clone(),
+        ));
+
+        let socket = path.clone();
+        let channel = Endpoint::try_from("http://[::]:40081")
+            .unwrap()
+            .connect_with_connector(service_fn(move |_: Uri| {
+                UnixStream::connect(socket.clone())
+            }))
+            .await
+            .unwrap();
+        let mut client = GetQuoteClient::new(channel);
+        let request = tokio::spawn(async move {
+            client
+                .get_quote(GetQuoteRequest {
+                    user_data: "".to_string(),
+                    nonce: "".to_string(),
+                })
                 .await
-                .unwrap();
         });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        shutdown.trigger();
+
+        // the response comes from the handler, not from a cut connection
+        if let Err(status) = request.await.unwrap() {
+            assert_eq!(status.code(), tonic::Code::FailedPrecondition);
+        }
+        tokio::time::timeout(Duration::from_secs(5), server)
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap();
+        let _ = std::fs::remove_file(path);
+    }
+
+    // CA issuing the server certificate and the client certificates
+    struct TestPki {
+        ca: rcgen::Certificate,
+    }
+
+    impl TestPki {
+        fn new() -> Self {
+            let mut params = rcgen::CertificateParams::new(Vec::new());
+            params.is_ca = rcgen::IsCa::Ca(rcgen::BasicConstraints::Unconstrained);
+            TestPki {
+                ca: rcgen::Certificate::from_params(params).unwrap(),
+            }
+        }
+
+        // PEM certificate and key issued by the CA
+        fn issue(&self, name: &str) -> (String, String) {
+            let cert = rcgen::Certificate::from_params(rcgen::CertificateParams::new(vec![
+                name.to_string()
+            ]))
+            .unwrap();
+            (
+                cert.serialize_pem_with_signer(&self.ca).unwrap(),
+                cert.serialize_private_key_pem(),
+            )
+        }
+
+        fn write(&self, dir: &std::path::Path, allowed_clients: Vec<String>) -> TcpConfig {
+            std::fs::create_dir_all(dir).unwrap();
+            let (cert, key) = self.issue("localhost");
+            std::fs::write(dir.join("server.crt"), cert).unwrap();
+            std::fs::write(dir.join("server.key"), key).unwrap();
+            std::fs::write(dir.join("ca.crt"), self.ca.serialize_pem().unwrap()).unwrap();
+            TcpConfig {
+                address: "127.0.0.1:0".parse().unwrap(),
+                cert: dir.join("server.crt"),
+                key: dir.join("server.key"),
+                client_ca: dir.join("ca.crt"),
+                allowed_clients,
+            }
+        }
+    }
+
+    #[tokio::test]
+    //TCP listener only serves clients with an allowed certificate
+    async fn request_over_mtls_allowlist() {
+        use tonic::transport::{Certificate, ClientTlsConfig, Identity};
+
+        let pki = TestPki::new();
+        let dir = std::env::temp_dir().join(format!("quote-server-tls-{}", std::process::id()));
+        let config = pki.write(&dir, vec!["allowed.example.com".to_string()]);
+        let listener = listener::bind_tcp(&config).await.unwrap();
+        let address = listener.local_addr().unwrap();
+
+        let getquote = Arc::new(CCNPGetQuote::new(TeeType::TDX));
+        let (_, health_service) = tonic_health::server::health_reporter();
+        tokio::spawn(serve_tcp(
+            listener,
+            listener::tls_config(&config).unwrap(),
+            ClientAllowlist::new(config.allowed_clients.clone()),
+            getquote,
+            health_service,
+            Shutdown::new(),
+        ));
+
+        let ca = std::fs::read(&config.client_ca).unwrap();
+        let request = |client_name: &str| {
+            let (cert, key) = pki.issue(client_name);
+            let tls = ClientTlsConfig::new()
+                .domain_name("localhost")
+                .ca_certificate(Certificate::from_pem(&ca))
+                .identity(Identity::from_pem(cert, key));
+            async move {
+                let channel = Endpoint::try_from(format!("https://{}", address))
+                    .unwrap()
+                    .tls_config(tls)
+                    .unwrap()
+                    .connect()
+                    .await
+                    .unwrap();
+                GetQuoteClient::new(channel)
+                    .get_quote(GetQuoteRequest {
+                        user_data: "".to_string(),
+                        nonce: "".to_string(),
+                    })
+                    .await
+            }
+        };
+
+        let denied = request("denied.example.com").await.unwrap_err();
+        assert_eq!(denied.code(), tonic::Code::PermissionDenied);
+        // health checks are behind the allowlist as well
+        let (cert, key) = pki.issue("denied.example.com");
+        let tls = ClientTlsConfig::new()
+            .domain_name("localhost")
+            .ca_certificate(Certificate::from_pem(&ca))
+            .identity(Identity::from_pem(cert, key));
+        let channel = Endpoint::try_from(format!("https://{}", address))
+            .unwrap()
+            .tls_config(tls)
+            .unwrap()
+            .connect()
+            .await
+            .unwrap();
+        let denied = tonic_health::pb::health_client::HealthClient::new(channel)
+            .check(tonic_health::pb::HealthCheckRequest {
+                service: "".to_string(),
+            })
+            .await
+            .unwrap_err();
+        assert_eq!(denied.code(), tonic::Code::PermissionDenied);
+        // the allowed client gets past the allowlist to quote generation
+        if let Err(status) = request("allowed.example.com").await {
+            assert_ne!(status.code(), tonic::Code::PermissionDenied);
+            assert_ne!(status.code(), tonic::Code::Unauthenticated);
+        }
+        std::fs::remove_dir_all(dir).unwrap();
     }
 
     #[tokio::test]
@@ -301,8 +1319,476 @@ mod quote_server_tests {
 
         assert_eq!(response.quote_type, "TDX");
         let quote = base64::decode(response.quote.replace("\"", "")).unwrap();
-        let mut report_data_in_quote: [u8; 64 as usize] = [0; 64 as usize];
+        let mut report_data_in_quote: [u8; 64] = [0; 64];
         report_data_in_quote.copy_from_slice(&quote[568..632]);
         assert_eq!(report_data_in_quote, expected_report_data);
     }
+
+    #[tokio::test]
+    //the v2 API is served alongside v1 and validates raw request fields
+    async fn request_v2_invalid_nonce() {
+        use crate::quote_server_v2::get_quote_client::GetQuoteClient as GetQuoteClientV2;
+
+        let path =
+            std::env::temp_dir().join(format!("quote-server-v2-{}.sock", std::process::id()));
+        let listener = listener::bind_unix(&config::UnixConfig {
+            path: path.clone(),
+            ..Default::default()
+        })
+        .unwrap();
+        let (_, health_service) = tonic_health::server::health_reporter();
+        let shutdown = Shutdown::new();
+        tokio::spawn(serve_unix(
+            listener,
+            Arc::new(CCNPGetQuote::new(TeeType::TDX)),
+            health_service,
+            shutdown.clone(),
+        ));
+
+        let socket = path.clone();
+        let channel = Endpoint::try_from("http://[::]:40081")
+            .unwrap()
+            .connect_with_connector(service_fn(move |_: Uri| {
+                UnixStream::connect(socket.clone())
+            }))
+            .await
+            .unwrap();
+        let mut client = GetQuoteClientV2::new(channel);
+        let status = client
+            .get_quote(quote_server_v2::GetQuoteRequest {
+                user_data: Vec::new(),
+                nonce: vec![0; MAX_NONCE_SIZE + 1],
+                report_data_binding: 0,
+            })
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), tonic::Code::InvalidArgument);
+
+        // raw report data leave no room for a nonce
+        let status = client
+            .get_quote(quote_server_v2::GetQuoteRequest {
+                user_data: vec![0; 32],
+                nonce: b"12345678".to_vec(),
+                report_data_binding: quote_server_v2::ReportDataBinding::Raw.into(),
+            })
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), tonic::Code::InvalidArgument);
+        assert_eq!(
+            tonic_types::StatusExt::get_error_details(&status)
+                .error_info()
+                .unwrap()
+                .reason,
+            "INVALID_REPORT_DATA_BINDING"
+        );
+
+        // collateral needs a collateral service
+        let status = client
+            .get_evidence(quote_server_v2::GetEvidenceRequest {
+                user_data: Vec::new(),
+                nonce: b"12345678".to_vec(),
+                report_data_binding: 0,
+                include_collateral: true,
+                event_log_format: 0,
+                include_ima_log: false,
+            })
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), tonic::Code::FailedPrecondition);
+
+        // event logs are only returned in known formats
+        let status = client
+            .get_evidence(quote_server_v2::GetEvidenceRequest {
+                user_data: Vec::new(),
+                nonce: b"12345678".to_vec(),
+                report_data_binding: 0,
+                include_collateral: false,
+                event_log_format: 42,
+                include_ima_log: true,
+            })
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), tonic::Code::InvalidArgument);
+        assert_eq!(
+            tonic_types::StatusExt::get_error_details(&status)
+                .error_info()
+                .unwrap()
+                .reason,
+            "INVALID_EVENT_LOG_FORMAT"
+        );
+
+        shutdown.trigger();
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[tokio::test]
+    //in strict mode both API versions only take nonces from GetChallenge, as
+    //announced in the capabilities
+    async fn request_strict_challenges() {
+        use crate::quote_server_v2::get_quote_client::GetQuoteClient as GetQuoteClientV2;
+
+        let path = std::env::temp_dir().join(format!(
+            "quote-server-challenge-{}.sock",
+            std::process::id()
+        ));
+        let listener = listener::bind_unix(&config::UnixConfig {
+            path: path.clone(),
+            ..Default::default()
+        })
+        .unwrap();
+        let challenges = Challenges::new(ChallengeConfig {
+            strict: true,
+            ..Default::default()
+        })
+        .unwrap();
+        let (_, health_service) = tonic_health::server::health_reporter();
+        let shutdown = Shutdown::new();
+        tokio::spawn(serve_unix(
+            listener,
+            Arc::new(CCNPGetQuote::new(TeeType::TDX).with_challenges(challenges)),
+            health_service,
+            shutdown.clone(),
+        ));
+
+        let socket = path.clone();
+        let channel = Endpoint::try_from("http://[::]:40081")
+            .unwrap()
+            .connect_with_connector(service_fn(move |_: Uri| {
+                UnixStream::connect(socket.clone())
+            }))
+            .await
+            .unwrap();
+        let mut client = GetQuoteClientV2::new(channel.clone());
+        let capabilities = client
+            .get_capabilities(quote_server_v2::GetCapabilitiesRequest {})
+            .await
+            .unwrap()
+            .into_inner();
+        assert!(capabilities.challenges && capabilities.strict_challenges);
+        assert_eq!(capabilities.max_nonce_size as usize, MAX_NONCE_SIZE);
+
+        let challenge = client
+            .get_challenge(quote_server_v2::GetChallengeRequest {})
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(challenge.nonce.len(), challenge::CHALLENGE_LEN);
+        assert!(challenge.expires_at.is_some());
+
+        let status = client
+            .get_quote(quote_server_v2::GetQuoteRequest {
+                user_data: Vec::new(),
+                nonce: b"12345678".to_vec(),
+                report_data_binding: 0,
+            })
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), tonic::Code::InvalidArgument);
+
+        let status = GetQuoteClient::new(channel)
+            .get_quote(GetQuoteRequest {
+                user_data: base64::encode("abcdefg"),
+                nonce: base64::encode("12345678"),
+            })
+            .await
+            .unwrap_err();
+        assert_eq!(
+            tonic_types::StatusExt::get_error_details(&status)
+                .error_info()
+                .unwrap()
+                .reason,
+            "INVALID_CHALLENGE"
+        );
+
+        shutdown.trigger();
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[tokio::test]
+    //extends need a policy and a log file, callers without either are denied
+    async fn extend_runtime_measurement_preconditions() {
+        use crate::quote_server_v2::get_quote_server::GetQuote as GetQuoteV2;
+
+        let event = || {
+            tonic::Request::new(quote_server_v2::ExtendRuntimeMeasurementRequest {
+                domain: "example.com".to_string(),
+                event_type: "model".to_string(),
+                content: b"digest".to_vec(),
+            })
+        };
+        let getquote = CCNPGetQuote::new(TeeType::PLAIN);
+        let status = getquote
+            .extend_runtime_measurement(event())
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), tonic::Code::PermissionDenied);
+
+        let allow = Policy::from_toml("default = \"allow\"").unwrap();
+        let getquote = getquote.with_authorizer(Authorizer::new(allow, None));
+        let status = getquote
+            .extend_runtime_measurement(event())
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), tonic::Code::FailedPrecondition);
+        assert_eq!(
+            tonic_types::StatusExt::get_error_details(&status)
+                .error_info()
+                .unwrap()
+                .reason,
+            "RUNTIME_EVENT_LOG_NOT_PERSISTENT"
+        );
+    }
+
+    #[tokio::test]
+    //invalid events are rejected, events that cannot be extended are not logged
+    async fn request_extend_runtime_measurement() {
+        use crate::quote_server_v2::get_quote_client::GetQuoteClient as GetQuoteClientV2;
+
+        let path =
+            std::env::temp_dir().join(format!("quote-server-runtime-{}.sock", std::process::id()));
+        let listener = listener::bind_unix(&config::UnixConfig {
+            path: path.clone(),
+            ..Default::default()
+        })
+        .unwrap();
+        let log_path =
+            std::env::temp_dir().join(format!("quote-server-runtime-{}.log", std::process::id()));
+        let _ = std::fs::remove_file(&log_path);
+        let allow = Policy::from_toml("default = \"allow\"").unwrap();
+        let getquote = Arc::new(
+            CCNPGetQuote::new(TeeType::PLAIN)
+                .with_authorizer(Authorizer::new(allow, None))
+                .with_runtime_log(RuntimeEventLog::new(Some(&log_path)).unwrap()),
+        );
+        let (_, health_service) = tonic_health::server::health_reporter();
+        let shutdown = Shutdown::new();
+        tokio::spawn(serve_unix(
+            listener,
+            getquote.clone(),
+            health_service,
+            shutdown.clone(),
+        ));
+
+        let socket = path.clone();
+        let channel = Endpoint::try_from("http://[::]:40081")
+            .unwrap()
+            .connect_with_connector(service_fn(move |_: Uri| {
+                UnixStream::connect(socket.clone())
+            }))
+            .await
+            .unwrap();
+        let mut client = GetQuoteClientV2::new(channel);
+        let status = client
+            .extend_runtime_measurement(quote_server_v2::ExtendRuntimeMeasurementRequest {
+                domain: String::new(),
+                event_type: "model".to_string(),
+                content: b"digest".to_vec(),
+            })
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), tonic::Code::InvalidArgument);
+        assert_eq!(
+            tonic_types::StatusExt::get_error_details(&status)
+                .error_info()
+                .unwrap()
+                .reason,
+            "INVALID_EVENT"
+        );
+
+        let status = client
+            .extend_runtime_measurement(quote_server_v2::ExtendRuntimeMeasurementRequest {
+                domain: "example.com".to_string(),
+                event_type: "model".to_string(),
+                content: b"digest".to_vec(),
+            })
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), tonic::Code::FailedPrecondition);
+        assert_eq!(getquote.runtime_log.read().await.events(), 0);
+
+        shutdown.trigger();
+        let _ = std::fs::remove_file(path);
+        let _ = std::fs::remove_file(log_path);
+    }
+
+    #[tokio::test]
+    //watch streams check their interval, and end on the first failed quote
+    async fn request_watch_attestation() {
+        use crate::quote_server_v2::get_quote_client::GetQuoteClient as GetQuoteClientV2;
+
+        let path =
+            std::env::temp_dir().join(format!("quote-server-watch-{}.sock", std::process::id()));
+        let listener = listener::bind_unix(&config::UnixConfig {
+            path: path.clone(),
+            ..Default::default()
+        })
+        .unwrap();
+        let shutdown = Shutdown::new();
+        let getquote = Arc::new(CCNPGetQuote::new(TeeType::TDX).with_watches(1, shutdown.clone()));
+        let (_, health_service) = tonic_health::server::health_reporter();
+        tokio::spawn(serve_unix(
+            listener,
+            getquote.clone(),
+            health_service,
+            shutdown.clone(),
+        ));
+
+        let socket = path.clone();
+        let channel = Endpoint::try_from("http://[::]:40081")
+            .unwrap()
+            .connect_with_connector(service_fn(move |_: Uri| {
+                UnixStream::connect(socket.clone())
+            }))
+            .await
+            .unwrap();
+        let mut client = GetQuoteClientV2::new(channel);
+        let request = quote_server_v2::WatchAttestationRequest {
+            nonce_seed: b"seed".to_vec(),
+            interval_secs: 1,
+            ..Default::default()
+        };
+        let status = client.watch_attestation(request.clone()).await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::InvalidArgument);
+        assert_eq!(
+            tonic_types::StatusExt::get_error_details(&status)
+                .error_info()
+                .unwrap()
+                .reason,
+            "INVALID_WATCH_INTERVAL"
+        );
+
+        let mut stream = client
+            .watch_attestation(quote_server_v2::WatchAttestationRequest {
+                interval_secs: 0,
+                ..request
+            })
+            .await
+            .unwrap()
+            .into_inner();
+        let status = stream.message().await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::FailedPrecondition);
+        assert!(stream.message().await.unwrap().is_none());
+        assert_eq!(getquote.watches.active(), 0);
+
+        shutdown.trigger();
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[tokio::test]
+    #[serial]
+    //TDX ENV required: watch streams start with a quote over the first nonce
+    async fn request_watch_to_server() {
+        use crate::quote_server_v2::get_quote_client::GetQuoteClient as GetQuoteClientV2;
+
+        creat_server().await;
+
+        let channel = Endpoint::try_from("http://[::]:40081")
+            .unwrap()
+            .connect_with_connector(service_fn(|_: Uri| {
+                let path = "/tmp/quote-server.sock";
+                UnixStream::connect(path)
+            }))
+            .await
+            .unwrap();
+        let mut stream = GetQuoteClientV2::new(channel)
+            .watch_attestation(quote_server_v2::WatchAttestationRequest {
+                nonce_seed: b"seed".to_vec(),
+                interval_secs: 10,
+                user_data: b"abcdefg".to_vec(),
+                report_data_binding: quote_server_v2::ReportDataBinding::TranscriptSha512.into(),
+                include_event_logs: false,
+                event_log_format: 0,
+                include_ima_log: false,
+            })
+            .await
+            .unwrap()
+            .into_inner();
+
+        let response = stream.message().await.unwrap().unwrap();
+        assert_eq!(response.sequence, 0);
+        assert_eq!(response.trigger(), quote_server_v2::WatchTrigger::Start);
+        let nonce = quote_verifier::watch::watch_nonce(b"seed", 0);
+        assert_eq!(response.nonce, nonce.to_vec());
+        let expected_report_data = quote_verifier::binding::Binding::TranscriptSha512
+            .report_data(&nonce, b"abcdefg")
+            .unwrap();
+        let quote = quote_verifier::tdx::Quote::parse(&response.quote).unwrap();
+        assert_eq!(quote.report_data(), expected_report_data);
+    }
+
+    #[tokio::test]
+    #[serial]
+    //TDX ENV required: v2 responses carry the raw quote and its metadata
+    async fn request_to_server_v2() {
+        use crate::quote_server_v2::get_quote_client::GetQuoteClient as GetQuoteClientV2;
+
+        creat_server().await;
+
+        let channel = Endpoint::try_from("http://[::]:40081")
+            .unwrap()
+            .connect_with_connector(service_fn(|_: Uri| {
+                let path = "/tmp/quote-server.sock";
+                UnixStream::connect(path)
+            }))
+            .await
+            .unwrap();
+        let mut client = GetQuoteClientV2::new(channel);
+
+        let response = client
+            .get_quote(quote_server_v2::GetQuoteRequest {
+                user_data: b"abcdefg".to_vec(),
+                nonce: b"12345678".to_vec(),
+                report_data_binding: quote_server_v2::ReportDataBinding::TranscriptSha512.into(),
+            })
+            .await
+            .unwrap()
+            .into_inner();
+
+        let expected_report_data = quote_verifier::binding::Binding::TranscriptSha512
+            .report_data(b"12345678", b"abcdefg")
+            .unwrap();
+        let quote = quote_verifier::tdx::Quote::parse(&response.quote).unwrap();
+        assert_eq!(quote.report_data(), expected_report_data);
+        assert_ne!(response.tee_type(), quote_server_v2::TeeType::Unspecified);
+        assert_eq!(
+            response.report_data_binding(),
+            quote_server_v2::ReportDataBinding::TranscriptSha512
+        );
+        assert!(response.attestation_key_id.starts_with("sha256:"));
+        assert!(response.generated_at.is_some());
+    }
+
+    #[tokio::test]
+    #[serial]
+    //TDX ENV required: reports carry the report data and measurements
+    async fn request_report_to_server() {
+        use crate::quote_server_v2::get_quote_client::GetQuoteClient as GetQuoteClientV2;
+
+        creat_server().await;
+
+        let channel = Endpoint::try_from("http://[::]:40081")
+            .unwrap()
+            .connect_with_connector(service_fn(|_: Uri| {
+                let path = "/tmp/quote-server.sock";
+                UnixStream::connect(path)
+            }))
+            .await
+            .unwrap();
+        let mut client = GetQuoteClientV2::new(channel);
+
+        let response = client
+            .get_report(quote_server_v2::GetReportRequest {
+                user_data: vec![0xab; 32],
+                nonce: Vec::new(),
+                report_data_binding: quote_server_v2::ReportDataBinding::Raw.into(),
+            })
+            .await
+            .unwrap()
+            .into_inner();
+
+        assert_eq!(response.report.len(), quote_verifier::tdx::TD_REPORT_LEN);
+        let fields = response.td_report.unwrap();
+        assert_eq!(&fields.report_data[..32], &[0xab; 32]);
+        assert_eq!(fields.rtmrs.len(), 4);
+    }
 }
@@ -0,0 +1,87 @@
+/*
+* Copyright (c) 2023, Intel Corporation. All rights reserved.<BR>
+* SPDX-License-Identifier: Apache-2.0
+*/
+
+use anyhow::Result;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::watch;
+
+// How long NOT_SERVING is reported before new requests are refused, so that
+// health probes see it and clients are routed elsewhere
+pub const DEFAULT_PRE_STOP_DELAY: Duration = Duration::from_secs(5);
+// How long in-flight requests may take to finish after that, together with
+// the pre-stop delay below the default termination grace period of 30s in
+// Kubernetes
+pub const DEFAULT_DRAIN_TIMEOUT: Duration = Duration::from_secs(20);
+
+// Wait for SIGTERM or SIGINT, returning the name of the signal
+pub async fn wait_for_signal() -> Result<&'static str> {
+    let mut sigterm = signal(SignalKind::terminate())?;
+    let mut sigint = signal(SignalKind::interrupt())?;
+    tokio::select! {
+        _ = sigterm.recv() => Ok("SIGTERM"),
+        _ = sigint.recv() => Ok("SIGINT"),
+    }
+}
+
+// Tells every listener to stop accepting new requests
+#[derive(Clone)]
+pub struct Shutdown {
+    sender: Arc<watch::Sender<bool>>,
+    receiver: watch::Receiver<bool>,
+}
+
+impl Shutdown {
+    pub fn new() -> Self {
+        let (sender, receiver) = watch::channel(false);
+        Shutdown {
+            sender: Arc::new(sender),
+            receiver,
+        }
+    }
+
+    pub fn trigger(&self) {
+        let _ = self.sender.send(true);
+    }
+
+    // Resolves once shutdown has been triggered
+    pub async fn wait(mut self) {
+        while !*self.receiver.borrow() {
+            if self.receiver.changed().await.is_err() {
+                return;
+            }
+        }
+    }
+}
+
+impl Default for Shutdown {
+    fn default() -> Self {
+        Shutdown::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    //every waiter resolves on trigger, also those started afterwards
+    async fn trigger_wakes_waiters() {
+        let shutdown = Shutdown::new();
+        let waiter = tokio::spawn(shutdown.clone().wait());
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert!(!waiter.is_finished());
+
+        shutdown.trigger();
+        tokio::time::timeout(Duration::from_secs(1), waiter)
+            .await
+            .unwrap()
+            .unwrap();
+        tokio::time::timeout(Duration::from_secs(1), shutdown.wait())
+            .await
+            .unwrap();
+    }
+}
@@ -3,12 +3,20 @@
 * SPDX-License-Identifier: Apache-2.0
 */
 
+use crate::error::QuoteError;
 use anyhow::*;
-use sha2::{Digest, Sha512};
+use quote_verifier::binding::Binding;
+use quote_verifier::tdx::TdReport;
 use std::path::Path;
 use std::result::Result::Ok;
+use tdx_attest::TdxAttestError;
 
-#[derive(Debug, Clone)]
+// Maximum size in bytes of the decoded user_data accepted in a quote request
+pub const MAX_USER_DATA_SIZE: usize = 4096;
+// Maximum size in bytes of the decoded nonce accepted in a quote request
+pub const MAX_NONCE_SIZE: usize = 4096;
+
+#[derive(Debug, Clone, PartialEq)]
 pub enum TeeType {
     TDX,
     SEV,
@@ -16,6 +24,48 @@ pub enum TeeType {
     PLAIN,
 }
 
+// Version of the TDX module, told apart by the version in the REPORTTYPE of a
+// TDREPORT. The name of the guest device node only tells the driver apart.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TdxVersion {
+    V1_0,
+    V1_5,
+}
+
+// Device nodes of the TEEs quote-server knows about
+pub const TEE_DEVICES: [(TeeType, &str); 5] = [
+    (TeeType::TDX, "/dev/tdx_guest"),
+    (TeeType::TDX, "/dev/tdx-guest"),
+    (TeeType::TPM, "/dev/tpm0"),
+    (TeeType::SEV, "/dev/sev-guest"),
+    (TeeType::SEV, "/dev/sev"),
+];
+
+// Guest device node of the upstream TDX driver, the only one taking RTMR
+// extend requests
+const TDX_GUEST_DEVICE: &str = "/dev/tdx_guest";
+// Guest device node of the TDX 1.0 driver
+const TDX_1_0_GUEST_DEVICE: &str = "/dev/tdx-guest";
+
+// Version of the TDX module, None if no TDREPORT could be taken. It takes a
+// TDREPORT on the calling thread, so it is detected once at startup: the
+// module does not change while the TD runs.
+pub fn detect_tdx_version() -> Option<TdxVersion> {
+    get_tdx_report(base64::encode([0u8; 64]))
+        .ok()
+        .and_then(|report| tdx_version_of(&report))
+}
+
+// REPORTTYPE.VERSION is 0 on TDX 1.0 and 1 on TDX 1.5, whose TDINFO has the
+// SERVTD_HASH field
+fn tdx_version_of(report: &[u8]) -> Option<TdxVersion> {
+    match TdReport::parse(report).ok()?.report_type {
+        [0x81, _, 0, _] => Some(TdxVersion::V1_0),
+        [0x81, _, 1, _] => Some(TdxVersion::V1_5),
+        _ => None,
+    }
+}
+
 pub fn get_tee_type() -> TeeType {
     if Path::new("/dev/tpm0").exists() {
         TeeType::TPM
@@ -34,70 +84,174 @@ pub fn get_tee_type() -> TeeType {
     }
 }
 
-fn generate_tdx_report_data(
-    report_data: Option<String>,
-    nonce: String,
-) -> Result<String, anyhow::Error> {
-    let nonce_decoded = match base64::decode(nonce) {
+fn decode_field(field: &'static str, encoded: &str, limit: usize) -> Result<Vec<u8>> {
+    let decoded = match base64::decode(encoded) {
         Ok(v) => v,
         Err(e) => {
-            return Err(anyhow!(
-                "[generate_tdx_report_data] nonce is not base64 encoded: {:?}",
-                e
-            ))
+            return Err(QuoteError::InvalidEncoding {
+                field,
+                detail: format!("{:?}", e),
+            }
+            .into())
         }
     };
-    let mut hasher = Sha512::new();
-    hasher.update(nonce_decoded);
-    let _ret = match report_data {
-        Some(_encoded_report_data) => {
-            if _encoded_report_data.is_empty() {
-                hasher.update("")
-            } else {
-                let decoded_report_data = match base64::decode(_encoded_report_data) {
-                    Ok(v) => v,
-                    Err(e) => {
-                        return Err(anyhow!(
-                            "[generate_tdx_report_data] user data is not base64 encoded: {:?}",
-                            e
-                        ))
-                    }
-                };
-                hasher.update(decoded_report_data)
-            }
+    check_size(field, &decoded, limit)?;
+    Ok(decoded)
+}
+
+pub fn check_size(field: &'static str, value: &[u8], limit: usize) -> Result<()> {
+    if value.len() > limit {
+        return Err(QuoteError::TooLarge {
+            field,
+            size: value.len(),
+            limit,
         }
-        None => hasher.update(""),
+        .into());
+    }
+    Ok(())
+}
+
+fn generate_tdx_report_data(
+    report_data: Option<String>,
+    nonce: String,
+) -> Result<String, anyhow::Error> {
+    let nonce_decoded = decode_field("nonce", &nonce, MAX_NONCE_SIZE)?;
+    let user_data = match report_data {
+        Some(encoded) if !encoded.is_empty() => {
+            decode_field("user_data", &encoded, MAX_USER_DATA_SIZE)?
+        }
+        _ => Vec::new(),
     };
-    let hash_array: [u8; 64] = hasher
-        .finalize()
-        .as_slice()
-        .try_into()
-        .expect("[generate_tdx_report_data] Wrong length of report data");
-    Ok(base64::encode(hash_array))
+    let report_data = Binding::Sha512Concat.report_data(&nonce_decoded, &user_data)?;
+    Ok(base64::encode(report_data))
 }
 
 fn get_tdx_quote(report_data: Option<String>, nonce: String) -> Result<String> {
-    let tdx_report_data = match generate_tdx_report_data(report_data, nonce) {
-        Ok(v) => v,
-        Err(e) => {
-            return Err(anyhow!("[get_tdx_quote]: {:?}", e));
+    let tdx_report_data = generate_tdx_report_data(report_data, nonce)?;
+    encode_legacy_quote(&get_tdx_quote_with_report_data(tdx_report_data)?)
+}
+
+// tdx_attest reports a missing or unopenable device node as DeviceNotFound
+fn get_tdx_quote_with_report_data(tdx_report_data: String) -> Result<Vec<u8>> {
+    tdx_attest::get_tdx_quote(tdx_report_data).map_err(tdx_error)
+}
+
+// Quote error of a tdx_attest failure, by what failed: the device, the QGS
+// being unreachable or the QGS response
+fn tdx_error(e: anyhow::Error) -> anyhow::Error {
+    let detail = format!("{:#}", e);
+    match e.downcast_ref::<TdxAttestError>() {
+        Some(TdxAttestError::DeviceNotFound(_)) => QuoteError::DeviceNotFound(detail),
+        Some(TdxAttestError::QuoteServiceUnavailable(_)) => {
+            QuoteError::QuoteServiceUnavailable(detail)
         }
-    };
+        Some(TdxAttestError::QuoteServiceError(_)) => QuoteError::QuoteServiceError(detail),
+        Some(TdxAttestError::InvalidInput(_)) | Some(TdxAttestError::DeviceError(_)) | None => {
+            QuoteError::DeviceError(detail)
+        }
+    }
+    .into()
+}
 
-    let quote = match tdx_attest::get_tdx_quote(tdx_report_data) {
-        Err(e) => panic!("[get_tdx_quote] Fail to get TDX quote: {:?}", e),
-        Ok(q) => base64::encode(q),
-    };
+// Quote encoding of the quoteserver v1 API: base64, wrapped in a JSON string
+pub fn encode_legacy_quote(quote: &[u8]) -> Result<String> {
+    serde_json::to_string(&base64::encode(quote)).map_err(|e| anyhow!("[get_tdx_quote]: {:?}", e))
+}
+
+fn get_tdx_report(tdx_report_data: String) -> Result<Vec<u8>> {
+    tdx_attest::get_td_report(tdx_report_data).map_err(tdx_error)
+}
+
+// RTMR extend needs the upstream TDX guest driver, the TDX 1.0 driver takes no
+// extend requests
+fn extend_tdx_rtmr(index: u8, digest: &[u8]) -> Result<()> {
+    if !Path::new(TDX_GUEST_DEVICE).exists() && Path::new(TDX_1_0_GUEST_DEVICE).exists() {
+        return Err(QuoteError::Unsupported(format!(
+            "RTMR extend requires the {} device",
+            TDX_GUEST_DEVICE
+        ))
+        .into());
+    }
+    tdx_attest::extend_rtmr(index, digest).map_err(|e| {
+        let detail = format!("[extend_tdx_rtmr] Fail to extend RTMR: {:#}", e);
+        match e.downcast_ref::<TdxAttestError>() {
+            Some(TdxAttestError::DeviceNotFound(_)) => QuoteError::DeviceNotFound(detail),
+            _ => QuoteError::ExtendFailed(detail),
+        }
+        .into()
+    })
+}
+
+fn get_tpm_quote<T>() -> Result<T> {
+    Err(QuoteError::Unsupported("TPM to be supported!".to_string()).into())
+}
+
+fn get_sev_quote<T>() -> Result<T> {
+    Err(QuoteError::Unsupported("SEV to be supported!".to_string()).into())
+}
+
+// Report data binding the nonce and user data of a request, base64 encoded
+pub fn get_report_data(local_tee: TeeType, user_data: String, nonce: String) -> Result<String> {
+    match local_tee {
+        TeeType::TDX => generate_tdx_report_data(Some(user_data), nonce),
+        TeeType::TPM => get_tpm_quote(),
+        TeeType::SEV => get_sev_quote(),
+        _ => Err(QuoteError::DeviceNotFound("no TEE device found".to_string()).into()),
+    }
+}
+
+// Report data binding raw nonce and user data with the given scheme
+pub fn get_report_data_from_bytes(
+    local_tee: TeeType,
+    binding: Binding,
+    user_data: &[u8],
+    nonce: &[u8],
+) -> Result<Vec<u8>> {
+    check_size("user_data", user_data, MAX_USER_DATA_SIZE)?;
+    check_size("nonce", nonce, MAX_NONCE_SIZE)?;
+    match local_tee {
+        TeeType::TDX => binding
+            .report_data(nonce, user_data)
+            .map(|report_data| report_data.to_vec())
+            .map_err(|e| QuoteError::InvalidBinding(e.to_string()).into()),
+        TeeType::TPM => get_tpm_quote(),
+        TeeType::SEV => get_sev_quote(),
+        _ => Err(QuoteError::DeviceNotFound("no TEE device found".to_string()).into()),
+    }
+}
+
+// Raw quote over report data prepared by the caller, e.g. the root of a batch
+pub fn get_quote_with_report_data(local_tee: TeeType, report_data: String) -> Result<Vec<u8>> {
+    match local_tee {
+        TeeType::TDX => get_tdx_quote_with_report_data(report_data),
+        TeeType::TPM => get_tpm_quote(),
+        TeeType::SEV => get_sev_quote(),
+        _ => Err(QuoteError::DeviceNotFound("no TEE device found".to_string()).into()),
+    }
+}
 
-    serde_json::to_string(&quote).map_err(|e| anyhow!("[get_tdx_quote]: {:?}", e))
+// Local attestation report (TDREPORT for TDX) over base64 encoded report data
+pub fn get_report(local_tee: TeeType, report_data: String) -> Result<Vec<u8>> {
+    match local_tee {
+        TeeType::TDX => get_tdx_report(report_data),
+        TeeType::TPM => Err(QuoteError::Unsupported("TPM to be supported!".to_string()).into()),
+        TeeType::SEV => Err(QuoteError::Unsupported("SEV to be supported!".to_string()).into()),
+        _ => Err(QuoteError::DeviceNotFound("no TEE device found".to_string()).into()),
+    }
 }
 
-fn get_tpm_quote() -> Result<String> {
-    Err(anyhow!("TPM to be supported!"))
+// Extend a runtime measurement register (RTMR for TDX) with a SHA-384 digest
+pub fn extend_runtime_measurement(local_tee: TeeType, index: u8, digest: &[u8]) -> Result<()> {
+    match local_tee {
+        TeeType::TDX => extend_tdx_rtmr(index, digest),
+        TeeType::TPM => Err(QuoteError::Unsupported("TPM to be supported!".to_string()).into()),
+        TeeType::SEV => Err(QuoteError::Unsupported("SEV to be supported!".to_string()).into()),
+        _ => Err(QuoteError::DeviceNotFound("no TEE device found".to_string()).into()),
+    }
 }
 
-fn get_sev_quote() -> Result<String> {
-    Err(anyhow!("SEV to be supported!"))
+pub fn supports_runtime_measurement(local_tee: &TeeType) -> bool {
+    *local_tee == TeeType::TDX && Path::new(TDX_GUEST_DEVICE).exists()
 }
 
 pub fn get_quote(local_tee: TeeType, user_data: String, nonce: String) -> Result<String> {
@@ -105,7 +259,7 @@ pub fn get_quote(local_tee: TeeType, user_data: String, nonce: String) -> Result
         TeeType::TDX => get_tdx_quote(Some(user_data), nonce),
         TeeType::TPM => get_tpm_quote(),
         TeeType::SEV => get_sev_quote(),
-        _ => Err(anyhow!("Unexpected case!")),
+        _ => Err(QuoteError::DeviceNotFound("no TEE device found".to_string()).into()),
     }
 }
 
@@ -265,6 +419,84 @@ mod tests {
         assert_eq!(generated_hash_len, 64);
     }
 
+    #[test]
+    //generate_tdx_report_data allow user data of exactly MAX_USER_DATA_SIZE bytes
+    fn generate_tdx_report_data_report_data_at_limit() {
+        let result = generate_tdx_report_data(
+            Some(base64::encode(vec![0x5a; MAX_USER_DATA_SIZE])),
+            "IXUKoBO1XEFBPwopN4sY".to_string(),
+        );
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    //generate_tdx_report_data reject user data over MAX_USER_DATA_SIZE bytes
+    fn generate_tdx_report_data_report_data_too_large() {
+        let result = generate_tdx_report_data(
+            Some(base64::encode(vec![0x5a; MAX_USER_DATA_SIZE + 1])),
+            "IXUKoBO1XEFBPwopN4sY".to_string(),
+        );
+        match result.unwrap_err().downcast_ref::<QuoteError>() {
+            Some(QuoteError::TooLarge { field, .. }) => assert_eq!(*field, "user_data"),
+            e => panic!("unexpected error: {:?}", e),
+        }
+    }
+
+    #[test]
+    //generate_tdx_report_data reject nonce over MAX_NONCE_SIZE bytes
+    fn generate_tdx_report_data_nonce_too_large() {
+        let result = generate_tdx_report_data(
+            Some("YWJjZGVmZw==".to_string()),
+            base64::encode(vec![0x5a; MAX_NONCE_SIZE + 1]),
+        );
+        match result.unwrap_err().downcast_ref::<QuoteError>() {
+            Some(QuoteError::TooLarge { field, .. }) => assert_eq!(*field, "nonce"),
+            e => panic!("unexpected error: {:?}", e),
+        }
+    }
+
+    #[test]
+    //generate_tdx_report_data report which field is not base64 encoded
+    fn generate_tdx_report_data_invalid_encoding_field() {
+        let result = generate_tdx_report_data(
+            Some("XD^%*!x".to_string()),
+            "IXUKoBO1XEFBPwopN4sY".to_string(),
+        );
+        match result.unwrap_err().downcast_ref::<QuoteError>() {
+            Some(QuoteError::InvalidEncoding { field, .. }) => assert_eq!(*field, "user_data"),
+            e => panic!("unexpected error: {:?}", e),
+        }
+    }
+
+    #[test]
+    //tdx_attest failures keep apart the device, an unreachable QGS and QGS errors
+    fn tdx_error_reasons() {
+        let reason = |e: TdxAttestError| {
+            tdx_error(anyhow::Error::new(e).context("[get_tdx_quote]"))
+                .downcast_ref::<QuoteError>()
+                .unwrap()
+                .reason()
+        };
+        assert_eq!(
+            reason(TdxAttestError::DeviceNotFound("gone".to_string())),
+            "TEE_DEVICE_NOT_FOUND"
+        );
+        assert_eq!(
+            reason(TdxAttestError::DeviceError("EIO".to_string())),
+            "TEE_DEVICE_ERROR"
+        );
+        assert_eq!(
+            reason(TdxAttestError::QuoteServiceUnavailable(
+                "status".to_string()
+            )),
+            "QGS_UNAVAILABLE"
+        );
+        assert_eq!(
+            reason(TdxAttestError::QuoteServiceError("size".to_string())),
+            "QGS_ERROR"
+        );
+    }
+
     #[test]
     //TDX ENV required: tdx_get_quote allow empty nonce
     fn tdx_get_quote_empty_nonce() {
@@ -422,4 +654,18 @@ mod tests {
         );
         assert!(result.is_ok());
     }
+
+    #[test]
+    //the TDX module version is read from the REPORTTYPE of a TDREPORT
+    fn tdx_version_from_report() {
+        let mut report = vec![0u8; 1024];
+        assert_eq!(tdx_version_of(&report), None);
+        report[0] = 0x81;
+        assert_eq!(tdx_version_of(&report), Some(TdxVersion::V1_0));
+        report[2] = 1;
+        assert_eq!(tdx_version_of(&report), Some(TdxVersion::V1_5));
+        report[2] = 2;
+        assert_eq!(tdx_version_of(&report), None);
+        assert_eq!(tdx_version_of(&report[..512]), None);
+    }
 }
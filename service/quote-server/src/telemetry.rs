@@ -0,0 +1,82 @@
+/*
+* Copyright (c) 2023, Intel Corporation. All rights reserved.<BR>
+* SPDX-License-Identifier: Apache-2.0
+*/
+
+pub use ccnp_telemetry::Sensitive;
+use ccnp_telemetry::{TraceParent, TRACEPARENT};
+use serde::Deserialize;
+use std::time::Instant;
+use tonic::codegen::http;
+use tonic::Status;
+use tracing::field::Empty;
+use tracing::{info, info_span, warn, Span};
+use tracing_subscriber::EnvFilter;
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    #[default]
+    Text,
+    Json,
+}
+
+// Install the global subscriber. The level is taken from RUST_LOG and
+// defaults to info.
+pub fn init(format: LogFormat, log_sensitive: bool) {
+    ccnp_telemetry::set_log_sensitive(log_sensitive);
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
+    let builder = tracing_subscriber::fmt().with_env_filter(filter);
+    match format {
+        LogFormat::Text => builder.init(),
+        LogFormat::Json => builder.json().with_current_span(true).init(),
+    }
+}
+
+// Span of one gRPC request, carrying the caller's trace context. Caller,
+// TEE type, latency and outcome are recorded by the handler.
+pub fn request_span(request: &http::Request<()>) -> Span {
+    let span = info_span!(
+        "request",
+        method = %request.uri().path(),
+        trace_id = Empty,
+        parent_id = Empty,
+        caller = Empty,
+        tee = Empty,
+        latency_ms = Empty,
+        outcome = Empty,
+    );
+    let trace_parent = request
+        .headers()
+        .get(TRACEPARENT)
+        .and_then(|v| v.to_str().ok())
+        .and_then(TraceParent::parse);
+    if let Some(tp) = trace_parent {
+        span.record("trace_id", tp.trace_id.as_str());
+        span.record("parent_id", tp.parent_id.as_str());
+    }
+    span
+}
+
+// Record latency and outcome of the current request and log its completion
+pub fn finish<T>(start: Instant, result: &Result<T, Status>) {
+    let span = Span::current();
+    let latency_ms = start.elapsed().as_millis() as u64;
+    span.record("latency_ms", latency_ms);
+    match result {
+        Ok(_) => {
+            span.record("outcome", "OK");
+            info!(latency_ms, outcome = "OK", "request completed");
+        }
+        Err(status) => {
+            let outcome = format!("{:?}", status.code());
+            span.record("outcome", outcome.as_str());
+            warn!(
+                latency_ms,
+                outcome = outcome.as_str(),
+                error = status.message(),
+                "request failed"
+            );
+        }
+    }
+}
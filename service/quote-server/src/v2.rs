@@ -0,0 +1,966 @@
+/*
+* Copyright (c) 2023, Intel Corporation. All rights reserved.<BR>
+* SPDX-License-Identifier: Apache-2.0
+*/
+
+// quoteserver.v2.GetQuote, served by the same CCNPGetQuote as the v1 API
+
+use crate::batch::BatchedQuote;
+use crate::collateral::Collateral;
+use crate::error::{self, QuoteError};
+use crate::eventlog::{self, EventLog, EventLogKind};
+use crate::quote_server_v2::get_quote_server::GetQuote;
+use crate::quote_server_v2::{
+    BatchInclusionProof, CallerIdentity, Collateral as ApiCollateral, EventLog as ApiEventLog,
+    EventLogFormat, EventLogKind as ApiEventLogKind, EvidenceSource,
+    ExtendRuntimeMeasurementRequest, ExtendRuntimeMeasurementResponse, GetCapabilitiesRequest,
+    GetCapabilitiesResponse, GetChallengeRequest, GetChallengeResponse, GetEvidenceRequest,
+    GetEvidenceResponse, GetQuoteRequest, GetQuoteResponse, GetReportRequest, GetReportResponse,
+    ReportDataBinding, TdReport as ApiTdReport, TeeType as ApiTeeType, WatchAttestationRequest,
+    WatchAttestationResponse, WatchTrigger,
+};
+use crate::tee::{self, TdxVersion, TeeType};
+use crate::telemetry::{self, Sensitive};
+use crate::watch::{self, WatchPermit};
+use crate::{identity, CCNPGetQuote, GeneratedQuote};
+use quote_verifier::binding::Binding;
+use quote_verifier::evidence::evidence_digest;
+use quote_verifier::runtime::{RuntimeEvent, RUNTIME_RTMR};
+use quote_verifier::tdx::{attestation_key_id, Quote, TdReport, RTMR_COUNT};
+use quote_verifier::watch::watch_nonce;
+use std::path::Path;
+use std::time::{Duration, Instant, SystemTime};
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::{Request, Response, Status};
+use tracing::{debug, warn, Instrument};
+
+// TEE type and version reported in v2 responses
+pub fn api_tee_type(local_tee: &TeeType, tdx_version: Option<TdxVersion>) -> ApiTeeType {
+    match (local_tee, tdx_version) {
+        (TeeType::TDX, Some(TdxVersion::V1_0)) => ApiTeeType::Tdx10,
+        (TeeType::TDX, Some(TdxVersion::V1_5)) => ApiTeeType::Tdx15,
+        (TeeType::TDX, None) => ApiTeeType::Tdx,
+        (TeeType::SEV, _) => ApiTeeType::SevSnp,
+        (TeeType::TPM, _) => ApiTeeType::Tpm20,
+        _ => ApiTeeType::Unspecified,
+    }
+}
+
+// TEE devices present on the node. Only the TEE the server was started for
+// is served, and only TDX so far. The TDX version is the one detected at
+// startup.
+pub fn evidence_sources(
+    local_tee: &TeeType,
+    local_tdx_version: Option<TdxVersion>,
+) -> Vec<EvidenceSource> {
+    tee::TEE_DEVICES
+        .iter()
+        .filter(|(_, device)| Path::new(device).exists())
+        .map(|(tee_type, device)| {
+            let tdx_version = match tee_type {
+                TeeType::TDX => local_tdx_version,
+                _ => None,
+            };
+            evidence_source(local_tee, tee_type, device, tdx_version)
+        })
+        .collect()
+}
+
+fn evidence_source(
+    local_tee: &TeeType,
+    tee_type: &TeeType,
+    device: &str,
+    tdx_version: Option<TdxVersion>,
+) -> EvidenceSource {
+    let served = tee_type == local_tee && *tee_type == TeeType::TDX;
+    EvidenceSource {
+        tee_type: api_tee_type(tee_type, tdx_version).into(),
+        device: device.to_string(),
+        quote: served,
+        report: served,
+    }
+}
+
+// Binding schemes a request may select
+pub const REPORT_DATA_BINDINGS: [ReportDataBinding; 4] = [
+    ReportDataBinding::Sha512Concat,
+    ReportDataBinding::Raw,
+    ReportDataBinding::TranscriptSha512,
+    ReportDataBinding::TranscriptSha384,
+];
+
+pub const EVENT_LOG_FORMATS: [EventLogFormat; 3] = [
+    EventLogFormat::TcgPcClient,
+    EventLogFormat::CelJson,
+    EventLogFormat::CelCbor,
+];
+
+// Format of the event logs of a request, UNSPECIFIED selecting the format
+// they are recorded in
+pub fn log_format(value: i32) -> Result<&'static str, QuoteError> {
+    match EventLogFormat::from_i32(value) {
+        Some(EventLogFormat::Unspecified) | Some(EventLogFormat::TcgPcClient) => {
+            Ok(eventlog::FORMAT_TCG_PC_CLIENT)
+        }
+        Some(EventLogFormat::CelJson) => Ok(eventlog::FORMAT_CEL_JSON),
+        Some(EventLogFormat::CelCbor) => Ok(eventlog::FORMAT_CEL_CBOR),
+        None => Err(QuoteError::InvalidLogFormat(format!(
+            "unknown format {}",
+            value
+        ))),
+    }
+}
+
+// Binding scheme of a request, UNSPECIFIED selecting the v1 scheme
+pub fn binding(value: i32) -> Result<(Binding, ReportDataBinding), QuoteError> {
+    let binding = match ReportDataBinding::from_i32(value) {
+        Some(ReportDataBinding::Unspecified) | Some(ReportDataBinding::Sha512Concat) => {
+            Binding::Sha512Concat
+        }
+        Some(ReportDataBinding::Raw) => Binding::Raw,
+        Some(ReportDataBinding::TranscriptSha512) => Binding::TranscriptSha512,
+        Some(ReportDataBinding::TranscriptSha384) => Binding::TranscriptSha384,
+        None => {
+            return Err(QuoteError::InvalidBinding(format!(
+                "unknown binding {}",
+                value
+            )))
+        }
+    };
+    let echoed = match binding {
+        Binding::Raw => ReportDataBinding::Raw,
+        Binding::Sha512Concat => ReportDataBinding::Sha512Concat,
+        Binding::TranscriptSha512 => ReportDataBinding::TranscriptSha512,
+        Binding::TranscriptSha384 => ReportDataBinding::TranscriptSha384,
+    };
+    Ok((binding, echoed))
+}
+
+// ID of the key that signed the quote, empty if the quote cannot be parsed
+pub fn quote_key_id(local_tee: &TeeType, quote: &[u8]) -> String {
+    match local_tee {
+        TeeType::TDX => Quote::parse(quote)
+            .ok()
+            .and_then(|q| q.attestation_key())
+            .map(attestation_key_id)
+            .unwrap_or_default(),
+        _ => String::new(),
+    }
+}
+
+fn batch_proof(batched: BatchedQuote) -> BatchInclusionProof {
+    BatchInclusionProof {
+        leaf: batched.leaf,
+        leaf_index: batched.leaf_index,
+        batch_size: batched.batch_size,
+        audit_path: batched.audit_path.iter().map(|d| d.to_vec()).collect(),
+    }
+}
+
+fn api_event_log(log: EventLog) -> ApiEventLog {
+    let kind = match log.kind {
+        EventLogKind::Boot => ApiEventLogKind::Boot,
+        EventLogKind::Runtime => ApiEventLogKind::Runtime,
+        EventLogKind::Ima => ApiEventLogKind::Ima,
+    };
+    ApiEventLog {
+        kind: kind.into(),
+        format: log.format.to_string(),
+        data: log.data,
+    }
+}
+
+fn api_collateral(collateral: Collateral) -> ApiCollateral {
+    ApiCollateral {
+        pck_cert_chain: collateral.pck_cert_chain,
+        pck_crl: collateral.pck_crl,
+        pck_crl_issuer_chain: collateral.pck_crl_issuer_chain,
+        root_ca_crl: collateral.root_ca_crl,
+        tcb_info: collateral.tcb_info,
+        tcb_info_issuer_chain: collateral.tcb_info_issuer_chain,
+        qe_identity: collateral.qe_identity,
+        qe_identity_issuer_chain: collateral.qe_identity_issuer_chain,
+    }
+}
+
+// Event logs of evidence in `format`: the boot log, the runtime log once an
+// event was recorded, and with `include_ima` the IMA measurement list. The
+// logs are read and encoded on a blocking thread.
+async fn evidence_logs(
+    runtime_log: Option<EventLog>,
+    format: i32,
+    include_ima: bool,
+) -> Result<Vec<EventLog>, Status> {
+    let format = log_format(format).map_err(|e| Status::from(&e))?;
+    let logs = tokio::task::spawn_blocking(move || -> anyhow::Result<Vec<EventLog>> {
+        let ima_log = match include_ima {
+            true => eventlog::read_ima_log()?,
+            false => None,
+        };
+        Ok(eventlog::read_boot_log()?
+            .into_iter()
+            .chain(runtime_log)
+            .chain(ima_log)
+            .map(|log| eventlog::encode(log, format))
+            .collect())
+    })
+    .await
+    .map_err(|e| Status::internal(e.to_string()))?;
+    logs.map_err(|e| error::to_status(&e))
+}
+
+// RTMRs the event logs of evidence do not replay to, checked against a
+// TDREPORT taken before the quote. Each mismatch is logged with the first
+// event the RTMR does not reflect; logs that cannot be replayed flag every
+// RTMR. IMA entries not matching their template hash are logged as well.
+fn check_evidence(logs: &[EventLog], report: &[u8]) -> Vec<u32> {
+    for log in logs.iter().filter(|log| log.kind == EventLogKind::Ima) {
+        if let Err(e) = eventlog::parse_ima(log) {
+            warn!(error = ?e, "IMA measurement list of the evidence cannot be parsed");
+        }
+    }
+    match eventlog::replay_evidence(logs, report) {
+        Ok(replay) => replay
+            .rtmrs
+            .iter()
+            .enumerate()
+            .filter(|(_, replayed)| !replayed.matches)
+            .map(|(rtmr, replayed)| {
+                warn!(
+                    rtmr,
+                    events = replayed.events,
+                    divergence = ?replayed.divergence,
+                    "event logs do not match the RTMR of the TD"
+                );
+                rtmr as u32
+            })
+            .collect(),
+        Err(e) => {
+            warn!(error = ?e, "event logs of the evidence cannot be replayed");
+            (0..RTMR_COUNT as u32).collect()
+        }
+    }
+}
+
+fn logs_digest(user_data: &[u8], logs: &[EventLog]) -> [u8; 64] {
+    evidence_digest(
+        user_data,
+        &logs
+            .iter()
+            .map(|log| (log.kind.name(), log.data.as_slice()))
+            .collect::<Vec<_>>(),
+    )
+}
+
+// Parsed fields of a local report, None if it cannot be parsed
+pub fn report_fields(local_tee: &TeeType, report: &[u8]) -> Option<ApiTdReport> {
+    let report = match local_tee {
+        TeeType::TDX => TdReport::parse(report).ok()?,
+        _ => return None,
+    };
+    Some(ApiTdReport {
+        report_data: report.report_data.to_vec(),
+        mac: report.mac.to_vec(),
+        cpu_svn: report.cpu_svn.to_vec(),
+        tee_tcb_svn: report.tee_tcb_svn.to_vec(),
+        mr_seam: report.mr_seam.to_vec(),
+        mr_signer_seam: report.mr_signer_seam.to_vec(),
+        td_attributes: report.td_attributes.to_vec(),
+        xfam: report.xfam.to_vec(),
+        mr_td: report.mr_td.to_vec(),
+        mr_config_id: report.mr_config_id.to_vec(),
+        mr_owner: report.mr_owner.to_vec(),
+        mr_owner_config: report.mr_owner_config.to_vec(),
+        rtmrs: report.rtmrs.iter().map(|r| r.to_vec()).collect(),
+        servtd_hash: report.servtd_hash.to_vec(),
+    })
+}
+
+// Attempts to quote the event logs before giving up with RuntimeLogBusy
+const MAX_LOG_QUOTE_ATTEMPTS: usize = 3;
+
+// Event logs to include in evidence
+struct LogSelection {
+    format: i32,
+    include_ima: bool,
+}
+
+// Event logs as read and the quote over them
+struct QuotedLogs {
+    logs: Vec<EventLog>,
+    // RTMRs the logs do not replay to, see check_evidence
+    mismatched_rtmrs: Vec<u32>,
+    runtime_event_count: u64,
+    echoed: ReportDataBinding,
+    generated: GeneratedQuote,
+}
+
+impl CCNPGetQuote {
+    // Read the event logs selected, if any, and quote the report data derived
+    // from them. The runtime log is only locked while it is copied, not
+    // while the quote is generated, so extends do not wait for the QGS. If
+    // an event was extended meanwhile, the quote may reflect an event the
+    // logs miss, so the logs are read and quoted again. On TDX, the logs are
+    // replayed against a TDREPORT before the quote is requested. A challenge
+    // given as `nonce` is consumed once, before the first quote is generated.
+    async fn quote_logs<F>(
+        &self,
+        caller: &identity::CallerIdentity,
+        budget: Duration,
+        selection: Option<LogSelection>,
+        nonce: Option<&[u8]>,
+        mut report_data: F,
+    ) -> Result<QuotedLogs, Status>
+    where
+        F: FnMut(&[EventLog]) -> Result<(Vec<u8>, ReportDataBinding), Status>,
+    {
+        let deadline = Instant::now() + budget;
+        for attempt in 0..MAX_LOG_QUOTE_ATTEMPTS {
+            let (runtime_log, runtime_event_count) = {
+                let runtime_log = self.runtime_log.read().await;
+                (runtime_log.log(), runtime_log.events())
+            };
+            let logs = match &selection {
+                Some(selection) => {
+                    evidence_logs(runtime_log, selection.format, selection.include_ima).await?
+                }
+                None => Vec::new(),
+            };
+            let mismatched_rtmrs = match (&selection, &self.local_tee) {
+                (Some(_), TeeType::TDX) => {
+                    let remaining = deadline.saturating_duration_since(Instant::now());
+                    let report = self.report(caller, remaining, vec![0; 64]).await?;
+                    check_evidence(&logs, &report)
+                }
+                _ => Vec::new(),
+            };
+            let (bound, echoed) = report_data(&logs)?;
+            if let (0, Some(nonce)) = (attempt, nonce) {
+                self.check_nonce(nonce).await?;
+            }
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            let generated = self.generate(caller, remaining, bound).await?;
+            if selection.is_none() || self.runtime_log.read().await.events() == runtime_event_count
+            {
+                return Ok(QuotedLogs {
+                    logs,
+                    mismatched_rtmrs,
+                    runtime_event_count: runtime_event_count as u64,
+                    echoed,
+                    generated,
+                });
+            }
+            debug!("runtime event extended while quoting the event logs, quoting again");
+        }
+        Err(Status::from(&QuoteError::RuntimeLogBusy))
+    }
+
+    // Whether quotes can carry the user data as is
+    fn raw_binding(&self) -> bool {
+        !self.bind_identity && self.batcher.is_none()
+    }
+
+    // Report data of a v2 request under its binding scheme, consuming the
+    // nonce if it is a challenge. The challenge is consumed before the quote
+    // is generated, so concurrent requests cannot both use it.
+    async fn request_report_data(
+        &self,
+        user_data: &[u8],
+        nonce: &[u8],
+        report_data_binding: i32,
+    ) -> Result<(Vec<u8>, ReportDataBinding), Status> {
+        let bound = self.bound_report_data(user_data, nonce, report_data_binding)?;
+        self.check_nonce(nonce).await?;
+        Ok(bound)
+    }
+
+    // Report data under the binding scheme, for nonces that are no challenge
+    fn bound_report_data(
+        &self,
+        user_data: &[u8],
+        nonce: &[u8],
+        report_data_binding: i32,
+    ) -> Result<(Vec<u8>, ReportDataBinding), Status> {
+        let (binding, echoed) = binding(report_data_binding).map_err(|e| Status::from(&e))?;
+        // The caller identity and batching replace the report data of the
+        // quote, which would no longer be the raw user data
+        if binding == Binding::Raw && !self.raw_binding() {
+            return Err(Status::from(&QuoteError::InvalidBinding(
+                "raw report data are not available with caller identity binding or batching"
+                    .to_string(),
+            )));
+        }
+        let report_data =
+            tee::get_report_data_from_bytes(self.local_tee.clone(), binding, user_data, nonce)
+                .map_err(|e| error::to_status(&e))?;
+        Ok((report_data, echoed))
+    }
+
+    // Generate the quotes of a WatchAttestation stream until the client goes
+    // away, a quote fails or the server shuts down
+    async fn watch(
+        self,
+        caller: identity::CallerIdentity,
+        req: WatchAttestationRequest,
+        interval: Option<Duration>,
+        permit: WatchPermit,
+        sender: mpsc::Sender<Result<WatchAttestationResponse, Status>>,
+    ) {
+        let _permit = permit;
+        let mut measurements = self.watches.subscribe();
+        measurements.borrow_and_update();
+        let mut trigger = WatchTrigger::Start;
+        for sequence in 0u64.. {
+            let start = Instant::now();
+            let result = self.watch_quote(&caller, &req, sequence, trigger).await;
+            self.metrics
+                .record_request("v2.WatchAttestation.quote", start, &result);
+            let failed = result.is_err();
+            if sender.send(result).await.is_err() || failed {
+                return;
+            }
+
+            trigger = tokio::select! {
+                _ = sender.closed() => return,
+                _ = self.shutdown.clone().wait() => {
+                    let _ = sender
+                        .send(Err(Status::unavailable("server is shutting down")))
+                        .await;
+                    return;
+                }
+                _ = tokio::time::sleep(interval.unwrap_or_default()), if interval.is_some() => {
+                    WatchTrigger::Interval
+                }
+                changed = measurements.changed() => match changed {
+                    Ok(()) => WatchTrigger::RuntimeMeasurement,
+                    Err(_) => return,
+                },
+            };
+        }
+    }
+
+    async fn watch_quote(
+        &self,
+        caller: &identity::CallerIdentity,
+        req: &WatchAttestationRequest,
+        sequence: u64,
+        trigger: WatchTrigger,
+    ) -> Result<WatchAttestationResponse, Status> {
+        let nonce = watch_nonce(&req.nonce_seed, sequence);
+        let selection = req.include_event_logs.then_some(LogSelection {
+            format: req.event_log_format,
+            include_ima: req.include_ima_log,
+        });
+        let quoted = self
+            .quote_logs(
+                caller,
+                self.executor.timeout(),
+                selection,
+                None,
+                |logs| match req.include_event_logs {
+                    true => self.bound_report_data(
+                        &logs_digest(&req.user_data, logs),
+                        &nonce,
+                        req.report_data_binding,
+                    ),
+                    false => {
+                        self.bound_report_data(&req.user_data, &nonce, req.report_data_binding)
+                    }
+                },
+            )
+            .await?;
+        let generated = quoted.generated;
+
+        Ok(WatchAttestationResponse {
+            sequence,
+            trigger: trigger.into(),
+            nonce: nonce.to_vec(),
+            tee_type: api_tee_type(&self.local_tee, self.tdx_version).into(),
+            report_data_binding: quoted.echoed.into(),
+            event_logs: quoted.logs.into_iter().map(api_event_log).collect(),
+            attestation_key_id: quote_key_id(&self.local_tee, &generated.quote),
+            generated_at: Some(SystemTime::now().into()),
+            batch_proof: generated.batch.map(batch_proof),
+            caller_identity: self.api_caller_identity(caller),
+            runtime_event_count: quoted.runtime_event_count,
+            mismatched_rtmrs: quoted.mismatched_rtmrs,
+            quote: generated.quote,
+        })
+    }
+
+    fn api_caller_identity(&self, caller: &identity::CallerIdentity) -> Option<CallerIdentity> {
+        self.response_identity(caller).map(|id| CallerIdentity {
+            pod_uid: id.pod_uid,
+            container_id: id.container_id,
+            uid: id.uid,
+            gid: id.gid,
+            tls_client: id.tls_client,
+        })
+    }
+}
+
+#[tonic::async_trait]
+impl GetQuote for CCNPGetQuote {
+    async fn get_quote(
+        &self,
+        request: Request<GetQuoteRequest>,
+    ) -> Result<Response<GetQuoteResponse>, Status> {
+        let start = Instant::now();
+        let budget = self.executor.budget(request.metadata());
+        let mut caller = identity::caller_identity(&request);
+        let span = tracing::Span::current();
+        span.record("caller", caller.key().as_str());
+        span.record("tee", format!("{:?}", self.local_tee).as_str());
+
+        let result = async {
+            // v1 and v2 requests are the same action for the policy
+            self.authorize("GetQuote", &mut caller).await?;
+            let req = request.into_inner();
+            debug!(
+                user_data = %Sensitive(&req.user_data),
+                nonce = %Sensitive(&req.nonce),
+                "GetQuote request"
+            );
+            let (report_data, echoed) = self
+                .request_report_data(&req.user_data, &req.nonce, req.report_data_binding)
+                .await?;
+            let generated = self.generate(&caller, budget, report_data).await?;
+            Ok(GetQuoteResponse {
+                tee_type: api_tee_type(&self.local_tee, self.tdx_version).into(),
+                report_data_binding: echoed.into(),
+                attestation_key_id: quote_key_id(&self.local_tee, &generated.quote),
+                generated_at: Some(SystemTime::now().into()),
+                batch_proof: generated.batch.map(batch_proof),
+                caller_identity: self.api_caller_identity(&caller),
+                quote: generated.quote,
+            })
+        }
+        .await;
+        telemetry::finish(start, &result);
+        self.metrics.record_request("v2.GetQuote", start, &result);
+        result.map(Response::new)
+    }
+
+    async fn get_report(
+        &self,
+        request: Request<GetReportRequest>,
+    ) -> Result<Response<GetReportResponse>, Status> {
+        let start = Instant::now();
+        let budget = self.executor.budget(request.metadata());
+        let mut caller = identity::caller_identity(&request);
+        let span = tracing::Span::current();
+        span.record("caller", caller.key().as_str());
+        span.record("tee", format!("{:?}", self.local_tee).as_str());
+
+        let result = async {
+            self.authorize("GetReport", &mut caller).await?;
+            let req = request.into_inner();
+            debug!(
+                user_data = %Sensitive(&req.user_data),
+                nonce = %Sensitive(&req.nonce),
+                "GetReport request"
+            );
+            let (report_data, echoed) = self
+                .request_report_data(&req.user_data, &req.nonce, req.report_data_binding)
+                .await?;
+            let report = self.report(&caller, budget, report_data).await?;
+            Ok(GetReportResponse {
+                tee_type: api_tee_type(&self.local_tee, self.tdx_version).into(),
+                report_data_binding: echoed.into(),
+                td_report: report_fields(&self.local_tee, &report),
+                generated_at: Some(SystemTime::now().into()),
+                caller_identity: self.api_caller_identity(&caller),
+                report,
+            })
+        }
+        .await;
+        telemetry::finish(start, &result);
+        self.metrics.record_request("v2.GetReport", start, &result);
+        result.map(Response::new)
+    }
+
+    async fn get_capabilities(
+        &self,
+        request: Request<GetCapabilitiesRequest>,
+    ) -> Result<Response<GetCapabilitiesResponse>, Status> {
+        let start = Instant::now();
+        let mut caller = identity::caller_identity(&request);
+        tracing::Span::current().record("caller", caller.key().as_str());
+
+        let result = async {
+            self.authorize("GetCapabilities", &mut caller).await?;
+            Ok(GetCapabilitiesResponse {
+                server_version: env!("CARGO_PKG_VERSION").to_string(),
+                evidence_sources: evidence_sources(&self.local_tee, self.tdx_version),
+                report_data_bindings: REPORT_DATA_BINDINGS
+                    .iter()
+                    .filter(|b| **b != ReportDataBinding::Raw || self.raw_binding())
+                    .map(|b| *b as i32)
+                    .collect(),
+                max_user_data_size: tee::MAX_USER_DATA_SIZE as u32,
+                max_nonce_size: tee::MAX_NONCE_SIZE as u32,
+                batching: self.batcher.is_some(),
+                caller_identity_binding: self.bind_identity,
+                challenges: self.allows("GetChallenge", &caller),
+                strict_challenges: self.challenges.strict(),
+                challenge_ttl_secs: self.challenges.ttl().as_secs() as u32,
+                boot_event_log: self.local_tee == TeeType::TDX
+                    && eventlog::boot_log_path().is_some(),
+                runtime_event_log: tee::supports_runtime_measurement(&self.local_tee)
+                    && self.runtime_log.read().await.persistent(),
+                collateral: self.collateral.is_some(),
+                event_log_formats: EVENT_LOG_FORMATS.iter().map(|f| *f as i32).collect(),
+                ima_event_log: eventlog::ima_log_path().is_some(),
+            })
+        }
+        .await;
+        telemetry::finish(start, &result);
+        self.metrics
+            .record_request("v2.GetCapabilities", start, &result);
+        result.map(Response::new)
+    }
+
+    async fn get_evidence(
+        &self,
+        request: Request<GetEvidenceRequest>,
+    ) -> Result<Response<GetEvidenceResponse>, Status> {
+        let start = Instant::now();
+        let budget = self.executor.budget(request.metadata());
+        let mut caller = identity::caller_identity(&request);
+        let span = tracing::Span::current();
+        span.record("caller", caller.key().as_str());
+        span.record("tee", format!("{:?}", self.local_tee).as_str());
+
+        let result = async {
+            self.authorize("GetEvidence", &mut caller).await?;
+            let req = request.into_inner();
+            debug!(
+                user_data = %Sensitive(&req.user_data),
+                nonce = %Sensitive(&req.nonce),
+                include_collateral = req.include_collateral,
+                event_log_format = req.event_log_format,
+                include_ima_log = req.include_ima_log,
+                "GetEvidence request"
+            );
+            tee::check_size("user_data", &req.user_data, tee::MAX_USER_DATA_SIZE)
+                .map_err(|e| error::to_status(&e))?;
+            let collateral = match (&self.collateral, req.include_collateral) {
+                (Some(collateral), true) => Some(collateral),
+                (None, true) => return Err(Status::from(&QuoteError::CollateralNotConfigured)),
+                (_, false) => None,
+            };
+            // a failing collateral service is reported before the challenge
+            // is consumed and the QGS is asked for a quote
+            if let Some(collateral) = collateral {
+                match tokio::time::timeout(budget, collateral.prefetch()).await {
+                    Ok(result) => result.map_err(|e| error::to_status(&e))?,
+                    Err(_) => return Err((&QuoteError::DeadlineExceeded(budget)).into()),
+                }
+            }
+
+            // the logs are read before the quote is generated, so the quote
+            // covers at least the measurements they record, and quoted again
+            // if a runtime event is extended meanwhile, so that the quote
+            // covers exactly the runtime events of the log
+            let selection = LogSelection {
+                format: req.event_log_format,
+                include_ima: req.include_ima_log,
+            };
+            let quoted = self
+                .quote_logs(&caller, budget, Some(selection), Some(&req.nonce), |logs| {
+                    let digest = logs_digest(&req.user_data, logs);
+                    self.bound_report_data(&digest, &req.nonce, req.report_data_binding)
+                })
+                .await?;
+            let (logs, echoed, generated) = (quoted.logs, quoted.echoed, quoted.generated);
+            let mismatched_rtmrs = quoted.mismatched_rtmrs;
+
+            let collateral = match collateral {
+                Some(collateral) => {
+                    let remaining = budget.saturating_sub(start.elapsed());
+                    match tokio::time::timeout(remaining, collateral.fetch(&generated.quote)).await
+                    {
+                        Ok(c) => Some(c.map_err(|e| error::to_status(&e))?),
+                        Err(_) => return Err((&QuoteError::DeadlineExceeded(budget)).into()),
+                    }
+                }
+                None => None,
+            };
+            Ok(GetEvidenceResponse {
+                tee_type: api_tee_type(&self.local_tee, self.tdx_version).into(),
+                report_data_binding: echoed.into(),
+                event_logs: logs.into_iter().map(api_event_log).collect(),
+                collateral: collateral.map(api_collateral),
+                attestation_key_id: quote_key_id(&self.local_tee, &generated.quote),
+                generated_at: Some(SystemTime::now().into()),
+                batch_proof: generated.batch.map(batch_proof),
+                caller_identity: self.api_caller_identity(&caller),
+                mismatched_rtmrs,
+                quote: generated.quote,
+            })
+        }
+        .await;
+        telemetry::finish(start, &result);
+        self.metrics
+            .record_request("v2.GetEvidence", start, &result);
+        result.map(Response::new)
+    }
+
+    async fn extend_runtime_measurement(
+        &self,
+        request: Request<ExtendRuntimeMeasurementRequest>,
+    ) -> Result<Response<ExtendRuntimeMeasurementResponse>, Status> {
+        let start = Instant::now();
+        let mut caller = identity::caller_identity(&request);
+        let span = tracing::Span::current();
+        span.record("caller", caller.key().as_str());
+        span.record("tee", format!("{:?}", self.local_tee).as_str());
+
+        let result = async {
+            self.authorize("ExtendRuntimeMeasurement", &mut caller)
+                .await?;
+            let req = request.into_inner();
+            let event = RuntimeEvent {
+                domain: req.domain,
+                event_type: req.event_type,
+                content: req.content,
+            };
+            debug!(
+                domain = %event.domain,
+                event_type = %event.event_type,
+                content = %Sensitive(&event.content),
+                "ExtendRuntimeMeasurement request"
+            );
+            eventlog::check_event(&event).map_err(|e| error::to_status(&e))?;
+
+            // the log stays locked until the event is extended or dropped
+            // again, even if the request is cancelled meanwhile
+            let mut runtime_log = self.runtime_log.clone().write_owned().await;
+            // an in-memory log is lost on restart while RTMR3 keeps the
+            // extends, and the evidence would never replay again
+            if !runtime_log.persistent() {
+                return Err(Status::from(&QuoteError::RuntimeLogNotPersistent));
+            }
+            let local_tee = self.local_tee.clone();
+            let digest = event.digest();
+            let recorded = tokio::task::spawn_blocking(move || {
+                runtime_log.record(&event, RUNTIME_RTMR, |digest| {
+                    tee::extend_runtime_measurement(local_tee, RUNTIME_RTMR, digest)
+                })
+            })
+            .await
+            .map_err(|e| Status::internal(e.to_string()))?;
+            let event_index = recorded.map_err(|e| error::to_status(&e))?;
+            self.watches.measured(event_index + 1);
+            Ok(ExtendRuntimeMeasurementResponse {
+                rtmr_index: RUNTIME_RTMR.into(),
+                digest: digest.to_vec(),
+                event_index,
+            })
+        }
+        .await;
+        telemetry::finish(start, &result);
+        self.metrics
+            .record_request("v2.ExtendRuntimeMeasurement", start, &result);
+        result.map(Response::new)
+    }
+
+    type WatchAttestationStream = ReceiverStream<Result<WatchAttestationResponse, Status>>;
+
+    async fn watch_attestation(
+        &self,
+        request: Request<WatchAttestationRequest>,
+    ) -> Result<Response<Self::WatchAttestationStream>, Status> {
+        let start = Instant::now();
+        let mut caller = identity::caller_identity(&request);
+        let span = tracing::Span::current();
+        span.record("caller", caller.key().as_str());
+        span.record("tee", format!("{:?}", self.local_tee).as_str());
+
+        let result = async {
+            self.authorize("WatchAttestation", &mut caller).await?;
+            let req = request.into_inner();
+            debug!(
+                user_data = %Sensitive(&req.user_data),
+                nonce_seed = %Sensitive(&req.nonce_seed),
+                interval_secs = req.interval_secs,
+                include_event_logs = req.include_event_logs,
+                event_log_format = req.event_log_format,
+                include_ima_log = req.include_ima_log,
+                "WatchAttestation request"
+            );
+            let interval = watch::interval(req.interval_secs).map_err(|e| Status::from(&e))?;
+            // the request is checked before the seed is consumed
+            log_format(req.event_log_format).map_err(|e| Status::from(&e))?;
+            tee::check_size("user_data", &req.user_data, tee::MAX_USER_DATA_SIZE)
+                .and_then(|_| tee::check_size("nonce_seed", &req.nonce_seed, tee::MAX_NONCE_SIZE))
+                .map_err(|e| error::to_status(&e))?;
+            self.bound_report_data(
+                &[],
+                &watch_nonce(&req.nonce_seed, 0),
+                req.report_data_binding,
+            )?;
+            let permit = self.watches.acquire().map_err(|e| Status::from(&e))?;
+            self.check_nonce(&req.nonce_seed).await?;
+
+            let (sender, receiver) = mpsc::channel(1);
+            tokio::spawn(
+                self.clone()
+                    .watch(caller, req, interval, permit, sender)
+                    .in_current_span(),
+            );
+            Ok(ReceiverStream::new(receiver))
+        }
+        .await;
+        telemetry::finish(start, &result);
+        self.metrics
+            .record_request("v2.WatchAttestation", start, &result);
+        result.map(Response::new)
+    }
+
+    async fn get_challenge(
+        &self,
+        request: Request<GetChallengeRequest>,
+    ) -> Result<Response<GetChallengeResponse>, Status> {
+        let start = Instant::now();
+        let mut caller = identity::caller_identity(&request);
+        tracing::Span::current().record("caller", caller.key().as_str());
+
+        let result = async {
+            self.authorize("GetChallenge", &mut caller).await?;
+            let challenge = self.challenges.issue();
+            Ok(GetChallengeResponse {
+                nonce: challenge.nonce,
+                expires_at: Some(challenge.expires_at.into()),
+            })
+        }
+        .await;
+        telemetry::finish(start, &result);
+        self.metrics
+            .record_request("v2.GetChallenge", start, &result);
+        result.map(Response::new)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    //TDX nodes report their module version
+    fn tee_type_with_version() {
+        assert_eq!(
+            api_tee_type(&TeeType::TDX, Some(TdxVersion::V1_0)),
+            ApiTeeType::Tdx10
+        );
+        assert_eq!(
+            api_tee_type(&TeeType::TDX, Some(TdxVersion::V1_5)),
+            ApiTeeType::Tdx15
+        );
+        assert_eq!(api_tee_type(&TeeType::TDX, None), ApiTeeType::Tdx);
+        assert_eq!(api_tee_type(&TeeType::PLAIN, None), ApiTeeType::Unspecified);
+        assert_eq!(api_tee_type(&TeeType::TPM, None), ApiTeeType::Tpm20);
+    }
+
+    #[test]
+    //only the TDX device the server runs on serves evidence
+    fn evidence_source_of_device() {
+        let version = Some(TdxVersion::V1_0);
+        let source = evidence_source(&TeeType::TDX, &TeeType::TDX, "/dev/tdx_guest", version);
+        assert_eq!(source.tee_type(), ApiTeeType::Tdx10);
+        assert!(source.quote && source.report);
+        let source = evidence_source(&TeeType::TDX, &TeeType::TDX, "/dev/tdx_guest", None);
+        assert_eq!(source.tee_type(), ApiTeeType::Tdx);
+        let source = evidence_source(&TeeType::TDX, &TeeType::TPM, "/dev/tpm0", None);
+        assert_eq!(source.tee_type(), ApiTeeType::Tpm20);
+        assert!(!source.quote && !source.report);
+        // every selectable binding is accepted
+        for b in REPORT_DATA_BINDINGS {
+            assert_eq!(binding(b.into()).unwrap().1, b);
+        }
+    }
+
+    #[test]
+    //logs that do not replay to the TDREPORT flag their RTMRs
+    fn check_evidence_against_report() {
+        assert!(check_evidence(&[], &[0; 1024]).is_empty());
+        let mut report = vec![0; 1024];
+        report[864] = 1;
+        assert_eq!(check_evidence(&[], &report), vec![3]);
+        assert_eq!(check_evidence(&[], b"not a report"), vec![0, 1, 2, 3]);
+    }
+
+    #[test]
+    //unspecified bindings fall back to the v1 scheme, unknown ones are rejected
+    fn binding_of_request() {
+        assert_eq!(
+            binding(ReportDataBinding::Unspecified.into()).unwrap(),
+            (Binding::Sha512Concat, ReportDataBinding::Sha512Concat)
+        );
+        assert_eq!(
+            binding(ReportDataBinding::TranscriptSha384.into()).unwrap(),
+            (
+                Binding::TranscriptSha384,
+                ReportDataBinding::TranscriptSha384
+            )
+        );
+        let e = binding(42).unwrap_err();
+        assert_eq!(Status::from(&e).code(), tonic::Code::InvalidArgument);
+    }
+
+    #[tokio::test]
+    //raw report data are rejected when the identity or a batch replaces them
+    async fn raw_binding_needs_plain_quotes() {
+        let raw = ReportDataBinding::Raw.into();
+        let plain = CCNPGetQuote::new(TeeType::TDX);
+        assert!(plain.raw_binding());
+        for getquote in [
+            CCNPGetQuote::new(TeeType::TDX).with_identity_binding(),
+            CCNPGetQuote::new(TeeType::TDX).with_batching(crate::batch::BatchConfig {
+                window: Duration::from_millis(10),
+                max_size: 2,
+                max_queued: 2,
+            }),
+        ] {
+            let status = getquote
+                .bound_report_data(&[0xab; 32], &[], raw)
+                .unwrap_err();
+            assert_eq!(status.code(), tonic::Code::InvalidArgument);
+            assert_eq!(
+                tonic_types::StatusExt::get_error_details(&status)
+                    .error_info()
+                    .unwrap()
+                    .reason,
+                "INVALID_REPORT_DATA_BINDING"
+            );
+        }
+        // raw user data over 64 bytes do not fit the report data
+        let status = plain.bound_report_data(&[0xab; 65], &[], raw).unwrap_err();
+        assert_eq!(
+            tonic_types::StatusExt::get_error_details(&status)
+                .error_info()
+                .unwrap()
+                .reason,
+            "INVALID_REPORT_DATA_BINDING"
+        );
+    }
+
+    #[test]
+    //TDREPORT fields are returned as parsed, truncated reports have none
+    fn fields_of_report() {
+        let mut report = vec![0; quote_verifier::tdx::TD_REPORT_LEN];
+        report[128..192].fill(0xaa);
+        let fields = report_fields(&TeeType::TDX, &report).unwrap();
+        assert_eq!(fields.report_data, vec![0xaa; 64]);
+        assert_eq!(fields.rtmrs.len(), 4);
+        assert!(report_fields(&TeeType::TDX, &report[..512]).is_none());
+        assert!(report_fields(&TeeType::TPM, &report).is_none());
+    }
+
+    #[test]
+    //quotes that cannot be parsed have no key ID
+    fn key_id_of_invalid_quote() {
+        assert_eq!(quote_key_id(&TeeType::TDX, b"not a quote"), "");
+    }
+}
@@ -0,0 +1,129 @@
+/*
+* Copyright (c) 2023, Intel Corporation. All rights reserved.<BR>
+* SPDX-License-Identifier: Apache-2.0
+*/
+
+// Bookkeeping of WatchAttestation streams: how many are open, and when the
+// runtime measurements they watch change
+
+use crate::error::QuoteError;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::watch;
+
+// Streams open at the same time, each one generating a quote per interval
+pub const DEFAULT_MAX_WATCHES: usize = 64;
+// Shortest interval between quotes of a stream, so that streams cannot keep
+// the QGS busy
+pub const MIN_WATCH_INTERVAL: Duration = Duration::from_secs(10);
+
+#[derive(Clone)]
+pub struct Watches {
+    active: Arc<AtomicUsize>,
+    max: usize,
+    // number of runtime events recorded so far
+    measurements: Arc<watch::Sender<u64>>,
+}
+
+// Counts as an open stream until dropped
+pub struct WatchPermit {
+    active: Arc<AtomicUsize>,
+}
+
+impl Drop for WatchPermit {
+    fn drop(&mut self) {
+        self.active.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+impl Watches {
+    pub fn new(max: usize) -> Self {
+        let (sender, _) = watch::channel(0);
+        Watches {
+            active: Arc::new(AtomicUsize::new(0)),
+            max,
+            measurements: Arc::new(sender),
+        }
+    }
+
+    pub fn active(&self) -> usize {
+        self.active.load(Ordering::SeqCst)
+    }
+
+    pub fn acquire(&self) -> Result<WatchPermit, QuoteError> {
+        let admitted = self
+            .active
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |active| {
+                (active < self.max).then_some(active + 1)
+            });
+        match admitted {
+            Ok(_) => Ok(WatchPermit {
+                active: self.active.clone(),
+            }),
+            Err(_) => Err(QuoteError::TooManyWatches { limit: self.max }),
+        }
+    }
+
+    // Tell the open streams that the runtime event log now holds `events`
+    pub fn measured(&self, events: u64) {
+        self.measurements.send_replace(events);
+    }
+
+    pub fn subscribe(&self) -> watch::Receiver<u64> {
+        self.measurements.subscribe()
+    }
+}
+
+// Interval between the quotes of a stream, None for quotes on runtime
+// measurement changes only
+pub fn interval(secs: u32) -> Result<Option<Duration>, QuoteError> {
+    let interval = Duration::from_secs(secs.into());
+    match interval {
+        i if i.is_zero() => Ok(None),
+        i if i < MIN_WATCH_INTERVAL => Err(QuoteError::InvalidInterval {
+            min: MIN_WATCH_INTERVAL,
+        }),
+        i => Ok(Some(i)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    //streams over the limit are refused until an open one is closed
+    fn limit_open_streams() {
+        let watches = Watches::new(2);
+        let first = watches.acquire().unwrap();
+        let _second = watches.acquire().unwrap();
+        assert!(matches!(
+            watches.acquire(),
+            Err(QuoteError::TooManyWatches { limit: 2 })
+        ));
+        drop(first);
+        assert_eq!(watches.active(), 1);
+        assert!(watches.acquire().is_ok());
+    }
+
+    #[tokio::test]
+    //subscribers see the latest event count, bursts are coalesced
+    async fn notify_measurements() {
+        let watches = Watches::new(1);
+        let mut receiver = watches.subscribe();
+        watches.measured(1);
+        watches.measured(2);
+        receiver.changed().await.unwrap();
+        assert_eq!(*receiver.borrow_and_update(), 2);
+        assert!(!receiver.has_changed().unwrap());
+    }
+
+    #[test]
+    //intervals below the minimum are rejected, zero watches measurements only
+    fn watch_interval() {
+        assert_eq!(interval(0).unwrap(), None);
+        assert!(interval(1).is_err());
+        assert_eq!(interval(60).unwrap(), Some(Duration::from_secs(60)));
+    }
+}
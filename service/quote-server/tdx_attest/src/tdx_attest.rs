@@ -8,6 +8,7 @@
 use anyhow::*;
 use nix::*;
 use std::convert::TryInto;
+use std::fmt;
 use std::fs::File;
 use std::mem;
 use std::os::unix::io::AsRawFd;
@@ -31,6 +32,12 @@ pub struct tdx_1_5_report_req {
     tdreport: [u8; TDX_REPORT_LEN as usize], // User buffer to store TDREPORT output from TDCALL[TDG.MR.REPORT]
 }
 
+#[repr(C)]
+pub struct tdx_extend_rtmr_req {
+    data: [u8; RTMR_DATA_LEN as usize], // SHA-384 digest to extend the RTMR with
+    index: u8,                          // Index of the RTMR, 2 or 3 for the TD guest
+}
+
 #[repr(C)]
 pub struct qgs_msg_header {
     major_version: u16, // TDX major version
@@ -80,12 +87,56 @@ pub enum TdxVersion {
 pub enum TdxOperation {
     TDX_GET_TD_REPORT = 1,
     TDX_1_0_GET_QUOTE = 2,
+    TDX_EXTEND_RTMR = 3,
     TDX_1_5_GET_QUOTE = 4,
 }
 
+// Status of a quote request in tdx_quote_hdr, filled by the VMM
+const GET_QUOTE_SUCCESS: u64 = 0;
+const GET_QUOTE_IN_FLIGHT: u64 = 0xffff_ffff_ffff_ffff;
+const GET_QUOTE_SERVICE_UNAVAILABLE: u64 = 0x8000_0000_0000_0001;
+
 const REPORT_DATA_LEN: u32 = 64;
 const TDX_REPORT_LEN: u32 = 1024;
 const TDX_QUOTE_LEN: usize = 4 * 4096;
+// qgs_msg_header, selected_id_size and quote_size of a QGS response
+const QGS_MSG_RESP_FIXED_LEN: usize = 16 + 8;
+const RTMR_DATA_LEN: u32 = 48;
+
+// Failures of the TDX guest device and of the quote generation service (QGS).
+// They are returned as anyhow::Error; callers downcast them to tell them apart.
+#[derive(Debug)]
+pub enum TdxAttestError {
+    // No TDX guest device node is found or it cannot be opened
+    DeviceNotFound(String),
+    // The report data or RTMR digest passed in are not valid
+    InvalidInput(String),
+    // An ioctl of the TDX guest device failed
+    DeviceError(String),
+    // The VMM or the QGS did not serve the quote request, e.g. the QGS is not
+    // reachable
+    QuoteServiceUnavailable(String),
+    // The QGS answered with an error or a malformed response
+    QuoteServiceError(String),
+}
+
+impl fmt::Display for TdxAttestError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TdxAttestError::DeviceNotFound(m) => write!(f, "TDX device not found: {}", m),
+            TdxAttestError::InvalidInput(m) => write!(f, "invalid input: {}", m),
+            TdxAttestError::DeviceError(m) => write!(f, "TDX device error: {}", m),
+            TdxAttestError::QuoteServiceUnavailable(m) => {
+                write!(f, "quote generation service unavailable: {}", m)
+            }
+            TdxAttestError::QuoteServiceError(m) => {
+                write!(f, "quote generation service error: {}", m)
+            }
+        }
+    }
+}
+
+impl std::error::Error for TdxAttestError {}
 
 pub struct TdxInfo {
     tdx_version: TdxVersion,
@@ -101,78 +152,77 @@ impl TdxInfo {
     }
 }
 
-fn get_tdx_version() -> TdxVersion {
+fn get_tdx_version() -> Result<TdxVersion, anyhow::Error> {
     if Path::new("/dev/tdx-guest").exists() {
-        TdxVersion::TDX_1_0
+        Ok(TdxVersion::TDX_1_0)
     } else if Path::new("/dev/tdx_guest").exists() {
-        TdxVersion::TDX_1_5
+        Ok(TdxVersion::TDX_1_5)
     } else if Path::new("/dev/tdx-attest").exists() {
-        panic!("get_tdx_version: Deprecated device node /dev/tdx-attest, please upgrade to use /dev/tdx-guest or /dev/tdx_guest");
+        Err(TdxAttestError::DeviceNotFound("get_tdx_version: Deprecated device node /dev/tdx-attest, please upgrade to use /dev/tdx-guest or /dev/tdx_guest".to_string()).into())
     } else {
-        panic!("get_tdx_version: no TDX device found!");
+        Err(
+            TdxAttestError::DeviceNotFound("get_tdx_version: no TDX device found!".to_string())
+                .into(),
+        )
     }
 }
 
+fn open_device(caller: &str, path: &str) -> Result<File, anyhow::Error> {
+    File::options()
+        .read(true)
+        .write(true)
+        .open(path)
+        .map_err(|e| {
+            TdxAttestError::DeviceNotFound(format!("[{}] Fail to open {}: {:?}", caller, path, e))
+                .into()
+        })
+}
+
+fn report_data_array(report_data: String) -> Result<[u8; REPORT_DATA_LEN as usize], anyhow::Error> {
+    let report_data_bytes = match base64::decode(report_data) {
+        Ok(v) => v,
+        Err(e) => {
+            return Err(TdxAttestError::InvalidInput(format!(
+                "report data is not base64 encoded: {:?}",
+                e
+            ))
+            .into())
+        }
+    };
+    let len = report_data_bytes.len();
+    report_data_bytes.try_into().map_err(|_| {
+        TdxAttestError::InvalidInput(format!(
+            "report data must be {} bytes, got {}",
+            REPORT_DATA_LEN, len
+        ))
+        .into()
+    })
+}
+
 pub fn get_td_report(report_data: String) -> Result<Vec<u8>, anyhow::Error> {
     //detect TDX version
-    let tdx_info = match get_tdx_version() {
-        TdxVersion::TDX_1_0 => {
-            let device_node = match File::options()
-                .read(true)
-                .write(true)
-                .open("/dev/tdx-guest")
-            {
-                Err(e) => {
-                    return Err(anyhow!(
-                        "[get_td_report] Fail to open {}: {:?}",
-                        "/dev/tdx-guest",
-                        e
-                    ))
-                }
-                Ok(fd) => fd,
-            };
-            TdxInfo::new(TdxVersion::TDX_1_0, device_node)
-        }
-        TdxVersion::TDX_1_5 => {
-            let device_node = match File::options()
-                .read(true)
-                .write(true)
-                .open("/dev/tdx_guest")
-            {
-                Err(e) => {
-                    return Err(anyhow!(
-                        "[get_td_report] Fail to open {}: {:?}",
-                        "/dev/tdx_guest",
-                        e
-                    ))
-                }
-                Ok(fd) => fd,
-            };
-            TdxInfo::new(TdxVersion::TDX_1_5, device_node)
-        }
+    let tdx_info = match get_tdx_version()? {
+        TdxVersion::TDX_1_0 => TdxInfo::new(
+            TdxVersion::TDX_1_0,
+            open_device("get_td_report", "/dev/tdx-guest")?,
+        ),
+        TdxVersion::TDX_1_5 => TdxInfo::new(
+            TdxVersion::TDX_1_5,
+            open_device("get_td_report", "/dev/tdx_guest")?,
+        ),
     };
 
     match tdx_info.tdx_version {
-        TdxVersion::TDX_1_0 => match get_tdx_1_0_report(tdx_info.device_node, report_data) {
-            Err(e) => return Err(anyhow!("[get_td_report] Fail to get TDX report: {:?}", e)),
-            Ok(report) => Ok(report),
-        },
-        TdxVersion::TDX_1_5 => match get_tdx_1_5_report(tdx_info.device_node, report_data) {
-            Err(e) => return Err(anyhow!("[get_td_report] Fail to get TDX report: {:?}", e)),
-            Ok(report) => Ok(report),
-        },
+        TdxVersion::TDX_1_0 => get_tdx_1_0_report(tdx_info.device_node, report_data)
+            .context("[get_td_report] Fail to get TDX report"),
+        TdxVersion::TDX_1_5 => get_tdx_1_5_report(tdx_info.device_node, report_data)
+            .context("[get_td_report] Fail to get TDX report"),
     }
 }
 
 fn get_tdx_1_0_report(device_node: File, report_data: String) -> Result<Vec<u8>, anyhow::Error> {
-    let report_data_bytes = match base64::decode(report_data) {
-        Ok(v) => v,
-        Err(e) => return Err(anyhow!("report data is not base64 encoded: {:?}", e)),
-    };
-
     //prepare get TDX report request data
-    let mut report_data_array: [u8; REPORT_DATA_LEN as usize] = [0; REPORT_DATA_LEN as usize];
-    report_data_array.copy_from_slice(&report_data_bytes[0..]);
+    let report_data_array = report_data_array(report_data)?;
     let td_report: [u8; TDX_REPORT_LEN as usize] = [0; TDX_REPORT_LEN as usize];
 
     //build the request
@@ -197,10 +247,11 @@ fn get_tdx_1_0_report(device_node: File, report_data: String) -> Result<Vec<u8>,
         get_report_1_0_ioctl(device_node.as_raw_fd(), ptr::addr_of!(request) as *mut u64)
     } {
         Err(e) => {
-            return Err(anyhow!(
+            return Err(TdxAttestError::DeviceError(format!(
                 "[get_tdx_1_0_report] Fail to get TDX report: {:?}",
                 e
             ))
+            .into())
         }
         Ok(_) => (),
     };
@@ -209,17 +260,11 @@ fn get_tdx_1_0_report(device_node: File, report_data: String) -> Result<Vec<u8>,
 }
 
 fn get_tdx_1_5_report(device_node: File, report_data: String) -> Result<Vec<u8>, anyhow::Error> {
-    let report_data_bytes = match base64::decode(report_data) {
-        Ok(v) => v,
-        Err(e) => return Err(anyhow!("report data is not base64 encoded: {:?}", e)),
-    };
-
     //prepare get TDX report request data
-    let mut request = tdx_1_5_report_req {
-        reportdata: [0; REPORT_DATA_LEN as usize],
+    let request = tdx_1_5_report_req {
+        reportdata: report_data_array(report_data)?,
         tdreport: [0; TDX_REPORT_LEN as usize],
     };
-    request.reportdata.copy_from_slice(&report_data_bytes[0..]);
 
     //build the operator code
     ioctl_readwrite!(
@@ -237,10 +282,11 @@ fn get_tdx_1_5_report(device_node: File, report_data: String) -> Result<Vec<u8>,
         )
     } {
         Err(e) => {
-            return Err(anyhow!(
+            return Err(TdxAttestError::DeviceError(format!(
                 "[get_tdx_1_5_report] Fail to get TDX report: {:?}",
                 e
             ))
+            .into())
         }
         Ok(_) => (),
     };
@@ -273,53 +319,30 @@ fn generate_qgs_quote_msg(report: [u8; TDX_REPORT_LEN as usize]) -> qgs_msg_get_
 
 pub fn get_tdx_quote(report_data: String) -> Result<Vec<u8>, anyhow::Error> {
     //retrieve TDX report
-    let report_data_vec = match get_td_report(report_data) {
-        Err(e) => return Err(anyhow!("[get_tdx_quote] Fail to get TDX report: {:?}", e)),
-        Ok(report) => report,
-    };
+    let report_data_vec = get_td_report(report_data).context("[get_tdx_quote]")?;
     let report_data_array: [u8; TDX_REPORT_LEN as usize] = match report_data_vec.try_into() {
         Ok(r) => r,
-        Err(e) => return Err(anyhow!("[get_tdx_quote] Wrong TDX report format: {:?}", e)),
+        Err(e) => {
+            return Err(TdxAttestError::DeviceError(format!(
+                "[get_tdx_quote] Wrong TDX report format: {:?}",
+                e
+            ))
+            .into())
+        }
     };
 
     //build QGS request message
     let qgs_msg = generate_qgs_quote_msg(report_data_array);
 
-    let tdx_info = match get_tdx_version() {
-        TdxVersion::TDX_1_0 => {
-            let device_node = match File::options()
-                .read(true)
-                .write(true)
-                .open("/dev/tdx-guest")
-            {
-                Err(e) => {
-                    return Err(anyhow!(
-                        "[get_tdx_quote] Fail to open {}: {:?}",
-                        "/dev/tdx-guest",
-                        e
-                    ))
-                }
-                Ok(fd) => fd,
-            };
-            TdxInfo::new(TdxVersion::TDX_1_0, device_node)
-        }
-        TdxVersion::TDX_1_5 => {
-            let device_node = match File::options()
-                .read(true)
-                .write(true)
-                .open("/dev/tdx_guest")
-            {
-                Err(e) => {
-                    return Err(anyhow!(
-                        "[get_tdx_quote] Fail to open {}: {:?}",
-                        "/dev/tdx_guest",
-                        e
-                    ))
-                }
-                Ok(fd) => fd,
-            };
-            TdxInfo::new(TdxVersion::TDX_1_5, device_node)
-        }
+    let tdx_info = match get_tdx_version()? {
+        TdxVersion::TDX_1_0 => TdxInfo::new(
+            TdxVersion::TDX_1_0,
+            open_device("get_tdx_quote", "/dev/tdx-guest")?,
+        ),
+        TdxVersion::TDX_1_5 => TdxInfo::new(
+            TdxVersion::TDX_1_5,
+            open_device("get_tdx_quote", "/dev/tdx_guest")?,
+        ),
     };
 
     //build quote generation request header
@@ -359,7 +382,13 @@ pub fn get_tdx_quote(report_data: String) -> Result<Vec<u8>, anyhow::Error> {
                     ptr::addr_of!(request) as *mut u64,
                 )
             } {
-                Err(e) => return Err(anyhow!("[get_tdx_quote] Fail to get TDX quote: {:?}", e)),
+                Err(e) => {
+                    return Err(TdxAttestError::QuoteServiceUnavailable(format!(
+                        "[get_tdx_quote] Fail to get TDX quote: {:?}",
+                        e
+                    ))
+                    .into())
+                }
                 Ok(_r) => _r,
             };
         }
@@ -376,45 +405,256 @@ pub fn get_tdx_quote(report_data: String) -> Result<Vec<u8>, anyhow::Error> {
                     ptr::addr_of!(request) as *mut tdx_quote_req,
                 )
             } {
-                Err(e) => return Err(anyhow!("[get_tdx_quote] Fail to get TDX quote: {:?}", e)),
+                Err(e) => {
+                    return Err(TdxAttestError::QuoteServiceUnavailable(format!(
+                        "[get_tdx_quote] Fail to get TDX quote: {:?}",
+                        e
+                    ))
+                    .into())
+                }
                 Ok(_r) => _r,
             };
         }
     };
 
-    //inspect the response and retrive quote data
+    quote_from_response(&quote_header)
+}
+
+//inspect the response and retrive quote data
+fn quote_from_response(quote_header: &tdx_quote_hdr) -> Result<Vec<u8>, anyhow::Error> {
+    match quote_header.status {
+        GET_QUOTE_SUCCESS => (),
+        GET_QUOTE_IN_FLIGHT | GET_QUOTE_SERVICE_UNAVAILABLE => {
+            return Err(TdxAttestError::QuoteServiceUnavailable(format!(
+                "[get_tdx_quote] Fail to get TDX quote: status {:#x}",
+                quote_header.status
+            ))
+            .into())
+        }
+        status => {
+            return Err(TdxAttestError::QuoteServiceError(format!(
+                "[get_tdx_quote] Fail to get TDX quote: status {:#x}",
+                status
+            ))
+            .into())
+        }
+    }
+
     let out_len = quote_header.out_len;
-    let qgs_msg_resp_size =
-        unsafe { std::mem::transmute::<[u8; 4], u32>(quote_header.data_len_be_bytes) }.to_be();
+    let qgs_msg_resp_size = u32::from_be_bytes(quote_header.data_len_be_bytes);
+    if out_len.checked_sub(qgs_msg_resp_size) != Some(4) {
+        return Err(TdxAttestError::QuoteServiceError(
+            "[get_tdx_quote] Fail to get TDX quote: wrong TDX quote size!".to_string(),
+        )
+        .into());
+    }
 
-    let qgs_msg_resp = unsafe {
-        let raw_ptr = ptr::addr_of!(quote_header.data) as *mut qgs_msg_get_quote_resp;
-        raw_ptr.as_mut().unwrap() as &mut qgs_msg_get_quote_resp
+    // the QGS response is parsed from the bytes it occupies, a
+    // qgs_msg_get_quote_resp header and sizes followed by the selected id and
+    // the quote
+    let invalid = |reason: String| -> anyhow::Error {
+        TdxAttestError::QuoteServiceError(format!(
+            "[get_tdx_quote] Fail to get TDX quote: {}",
+            reason
+        ))
+        .into()
     };
-
-    if out_len - qgs_msg_resp_size != 4 {
-        return Err(anyhow!(
-            "[get_tdx_quote] Fail to get TDX quote: wrong TDX quote size!"
-        ));
+    let qgs_msg_resp = match quote_header.data.get(..qgs_msg_resp_size as usize) {
+        Some(resp) if resp.len() >= QGS_MSG_RESP_FIXED_LEN => resp,
+        _ => {
+            return Err(invalid(format!(
+                "QGS response size {} is out of bounds",
+                qgs_msg_resp_size
+            )))
+        }
+    };
+    let u16_at =
+        |offset: usize| u16::from_le_bytes(qgs_msg_resp[offset..offset + 2].try_into().unwrap());
+    let u32_at =
+        |offset: usize| u32::from_le_bytes(qgs_msg_resp[offset..offset + 4].try_into().unwrap());
+
+    let (major_version, minor_version, msg_type, error_code) =
+        (u16_at(0), u16_at(2), u32_at(4), u32_at(12));
+    if major_version != 1 || minor_version != 0 || msg_type != 1 || error_code != 0 {
+        return Err(invalid(format!("QGS response error {}", error_code)));
     }
 
-    if qgs_msg_resp.header.major_version != 1
-        || qgs_msg_resp.header.minor_version != 0
-        || qgs_msg_resp.header.msg_type != 1
-        || qgs_msg_resp.header.error_code != 0
+    let selected_id_size = u32_at(16) as usize;
+    let quote_size = u32_at(20) as usize;
+    let quote_start = QGS_MSG_RESP_FIXED_LEN + selected_id_size;
+    match quote_start
+        .checked_add(quote_size)
+        .and_then(|end| qgs_msg_resp.get(quote_start..end))
     {
-        return Err(anyhow!(
-            "[get_tdx_quote] Fail to get TDX quote: QGS response error!"
-        ));
+        Some(quote) => Ok(quote.to_vec()),
+        None => Err(invalid(format!(
+            "selected id size {} and quote size {} exceed the QGS response size {}",
+            selected_id_size, quote_size, qgs_msg_resp_size
+        ))),
+    }
+}
+
+pub fn extend_rtmr(index: u8, digest: &[u8]) -> Result<(), anyhow::Error> {
+    if digest.len() != RTMR_DATA_LEN as usize {
+        return Err(TdxAttestError::InvalidInput(format!(
+            "[extend_rtmr] RTMR extend data must be {} bytes, got {}",
+            RTMR_DATA_LEN,
+            digest.len()
+        ))
+        .into());
+    }
+
+    //only the TDX 1.5 guest driver takes extend requests
+    let device_node = match get_tdx_version()? {
+        TdxVersion::TDX_1_0 => {
+            return Err(TdxAttestError::DeviceError(format!(
+                "[extend_rtmr] RTMR extend is not supported by {}",
+                "/dev/tdx-guest"
+            ))
+            .into())
+        }
+        TdxVersion::TDX_1_5 => open_device("extend_rtmr", "/dev/tdx_guest")?,
+    };
+
+    //prepare RTMR extend request data
+    let mut request = tdx_extend_rtmr_req {
+        data: [0; RTMR_DATA_LEN as usize],
+        index,
+    };
+    request.data.copy_from_slice(digest);
+
+    //build the operator code
+    ioctl_write_ptr!(
+        extend_rtmr_ioctl,
+        b'T',
+        TdxOperation::TDX_EXTEND_RTMR,
+        tdx_extend_rtmr_req
+    );
+
+    //apply the ioctl command
+    if let Err(e) = unsafe { extend_rtmr_ioctl(device_node.as_raw_fd(), ptr::addr_of!(request)) } {
+        return Err(TdxAttestError::DeviceError(format!(
+            "[extend_rtmr] Fail to extend RTMR {}: {:?}",
+            index, e
+        ))
+        .into());
     }
 
-    Ok(qgs_msg_resp.id_quote[0..(qgs_msg_resp.quote_size as usize)].to_vec())
+    Ok(())
 }
 
 #[cfg(test)]
 mod tdx_attest_tests {
     use super::*;
 
+    // Quote request header as filled by the VMM with a QGS response carrying
+    // the given quote
+    fn response(status: u64, quote: &[u8]) -> Box<tdx_quote_hdr> {
+        let mut header = Box::new(tdx_quote_hdr {
+            version: 1,
+            status,
+            in_len: 0,
+            out_len: 0,
+            data_len_be_bytes: [0; 4],
+            data: [0; TDX_QUOTE_LEN],
+        });
+        let size = (16 + 8 + quote.len()) as u32;
+        header.data[0..2].copy_from_slice(&1u16.to_le_bytes());
+        header.data[4..8].copy_from_slice(&1u32.to_le_bytes());
+        header.data[8..12].copy_from_slice(&size.to_le_bytes());
+        header.data[20..24].copy_from_slice(&(quote.len() as u32).to_le_bytes());
+        header.data[24..24 + quote.len()].copy_from_slice(quote);
+        header.data_len_be_bytes = size.to_be_bytes();
+        header.out_len = size + 4;
+        header
+    }
+
+    fn attest_error(result: Result<Vec<u8>, anyhow::Error>) -> TdxAttestError {
+        result
+            .unwrap_err()
+            .downcast::<TdxAttestError>()
+            .expect("TdxAttestError")
+    }
+
+    #[test]
+    //the quote is taken from a successful QGS response
+    fn quote_from_qgs_response() {
+        let header = response(GET_QUOTE_SUCCESS, &[0xab; 32]);
+        assert_eq!(quote_from_response(&header).unwrap(), vec![0xab; 32]);
+    }
+
+    #[test]
+    //failed and short QGS responses are errors, not panics
+    fn quote_from_failed_qgs_response() {
+        let header = response(GET_QUOTE_SERVICE_UNAVAILABLE, &[]);
+        assert!(matches!(
+            attest_error(quote_from_response(&header)),
+            TdxAttestError::QuoteServiceUnavailable(_)
+        ));
+        let header = response(0x8000_0000_0000_0000, &[]);
+        assert!(matches!(
+            attest_error(quote_from_response(&header)),
+            TdxAttestError::QuoteServiceError(_)
+        ));
+
+        // out_len shorter than the response size
+        let mut header = response(GET_QUOTE_SUCCESS, &[0xab; 32]);
+        header.out_len = 2;
+        assert!(matches!(
+            attest_error(quote_from_response(&header)),
+            TdxAttestError::QuoteServiceError(_)
+        ));
+
+        // error code of the QGS
+        let mut header = response(GET_QUOTE_SUCCESS, &[]);
+        header.data[12..16].copy_from_slice(&0x12001u32.to_le_bytes());
+        assert!(matches!(
+            attest_error(quote_from_response(&header)),
+            TdxAttestError::QuoteServiceError(_)
+        ));
+
+        // quote size beyond the buffer
+        let mut header = response(GET_QUOTE_SUCCESS, &[]);
+        header.data[20..24].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(matches!(
+            attest_error(quote_from_response(&header)),
+            TdxAttestError::QuoteServiceError(_)
+        ));
+    }
+
+    #[test]
+    //quote sizes beyond the QGS response are rejected and the selected id is
+    //not returned as part of the quote
+    fn quote_bounded_by_qgs_response() {
+        // quote size within the buffer but beyond the response
+        let mut header = response(GET_QUOTE_SUCCESS, &[0xab; 32]);
+        header.data[20..24].copy_from_slice(&((TDX_QUOTE_LEN - 24) as u32).to_le_bytes());
+        assert!(matches!(
+            attest_error(quote_from_response(&header)),
+            TdxAttestError::QuoteServiceError(_)
+        ));
+
+        // response size beyond the buffer
+        let mut header = response(GET_QUOTE_SUCCESS, &[0xab; 32]);
+        let size = TDX_QUOTE_LEN as u32 + 1;
+        header.data_len_be_bytes = size.to_be_bytes();
+        header.out_len = size + 4;
+        assert!(matches!(
+            attest_error(quote_from_response(&header)),
+            TdxAttestError::QuoteServiceError(_)
+        ));
+
+        // selected id of 8 bytes followed by a quote of 24 bytes
+        let mut id_quote = vec![0xcd; 8];
+        id_quote.extend_from_slice(&[0xab; 24]);
+        let mut header = response(GET_QUOTE_SUCCESS, &id_quote);
+        header.data[16..20].copy_from_slice(&8u32.to_le_bytes());
+        header.data[20..24].copy_from_slice(&24u32.to_le_bytes());
+        assert_eq!(quote_from_response(&header).unwrap(), vec![0xab; 24]);
+        header.data[20..24].copy_from_slice(&32u32.to_le_bytes());
+        assert!(quote_from_response(&header).is_err());
+    }
+
     #[test]
     //TDX ENV required: call get_td_report and verify report data embedded in quote
     fn get_td_report_verify_report_data() {